@@ -0,0 +1,293 @@
+//! Asynchronous client for the TCSpecial command interpreter
+//!
+//! The same commands as the blocking client in tcsmoc, sent over a tokio UDP
+//! socket so a GUI or service can await replies instead of dedicating a
+//! thread to them.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{timeout_at, Instant};
+
+use tcslibgs::{
+    BufferPool, Command, CommandStatus, DHId, DHName, DHType, Fragment, FragmentReassembler, Framing, PingCommand,
+    PingTelemetry, PooledBuffer, ProtocolMessage, QueryDHCommand, QueryDHTelemetry, StartDHCommand, StartDHTelemetry,
+    StopDHCommand, TcsError, TcsResult, Telemetry, MAX_MESSAGE_SIZE,
+};
+
+use crate::connection::DEFAULT_MAX_TELEMETRY_SIZE;
+
+/// Default timeout for command responses
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait for the rest of a fragmented telemetry message
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Unsolicited telemetry kept for recv_async_telemetry; the oldest is
+/// dropped beyond this
+const UNSOLICITED_QUEUE_DEPTH: usize = 64;
+
+/// Client sending commands to TCSpecial over UDP without blocking
+pub struct AsyncTcsClient {
+    socket: UdpSocket,
+    remote_addr: SocketAddr,
+    sequence: u32,
+    timeout: Duration,
+    framing: Framing,
+    request_id: Option<u64>,
+    recv_buffer: PooledBuffer,
+    reassembler: FragmentReassembler,
+    unsolicited: VecDeque<Telemetry>,
+}
+
+impl AsyncTcsClient {
+    /// Create a client talking to the CI at remote_addr, which may be a
+    /// hostname
+    ///
+    /// The local socket is bound to an ephemeral port in the family of the
+    /// first address remote_addr resolves to.
+    pub async fn connect(remote_addr: &str) -> TcsResult<Self> {
+        let remote = lookup_host(remote_addr)
+            .await
+            .map_err(|e| TcsError::Config(format!("Unable to resolve address {}: {}", remote_addr, e)))?
+            .next()
+            .ok_or_else(|| TcsError::Config(format!("Address {} resolved to nothing", remote_addr)))?;
+        let local = if remote.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local).await?;
+
+        Ok(Self {
+            socket,
+            remote_addr: remote,
+            sequence: 1,
+            timeout: DEFAULT_TIMEOUT,
+            framing: Framing::Json,
+            request_id: None,
+            recv_buffer: BufferPool::global().take(MAX_MESSAGE_SIZE),
+            reassembler: FragmentReassembler::new(FRAGMENT_TIMEOUT, DEFAULT_MAX_TELEMETRY_SIZE),
+            unsolicited: VecDeque::new(),
+        })
+    }
+
+    /// Set the timeout for command responses
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Choose the framing commands are sent with
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Tag the commands sent from now on with an operator correlation id,
+    /// which TCSpecial echoes in each response; None stops tagging
+    pub fn set_request_id(&mut self, request_id: Option<u64>) {
+        self.request_id = request_id;
+    }
+
+    /// Get the sequence number the next command will use
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Get the locally bound address
+    pub fn local_addr(&self) -> TcsResult<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Get the next sequence number
+    fn next_sequence(&mut self) -> u32 {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Receive the next telemetry from the CI, reassembling fragments, until
+    /// the deadline
+    ///
+    /// Datagrams from other senders are skipped, so another node's packets
+    /// can't pass as our telemetry.
+    async fn receive_until(&mut self, deadline: Instant) -> TcsResult<Telemetry> {
+        loop {
+            let (size, addr) = timeout_at(deadline, self.socket.recv_from(&mut self.recv_buffer))
+                .await
+                .map_err(|_| TcsError::Timeout)??;
+            if addr != self.remote_addr {
+                continue;
+            }
+            let data = &self.recv_buffer[..size];
+
+            if !Fragment::is_fragment(data) {
+                return ProtocolMessage::from_bytes(data)?.into_telemetry();
+            }
+            if let Some(fragment) = Fragment::from_bytes(data) {
+                if let Some(message) = self.reassembler.add(fragment) {
+                    return ProtocolMessage::from_bytes(&message)?.into_telemetry();
+                }
+            }
+        }
+    }
+
+    /// Send a command and wait for the response
+    ///
+    /// Works as the blocking client does: telemetry that isn't the reply to
+    /// this command is skipped until the timeout elapses, unsolicited
+    /// telemetry is kept for recv_async_telemetry, and INVALID_COMMAND is
+    /// the reply whatever its sequence.
+    pub async fn send_command(&mut self, mut command: Command) -> TcsResult<Telemetry> {
+        command.set_request_id(self.request_id);
+        let sequence = command.sequence();
+        let data = ProtocolMessage::from_command(command).to_bytes_with(self.framing)?;
+        self.socket.send_to(&data, self.remote_addr).await?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.receive_until(deadline).await? {
+                Telemetry::InvalidCommand(tm) => return Err(TcsError::Command(tm.reason)),
+                telemetry if telemetry.is_unsolicited() => self.keep_unsolicited(telemetry),
+                telemetry if telemetry.sequence() == sequence => return Ok(telemetry),
+                _ => {}
+            }
+        }
+    }
+
+    /// Receive the next telemetry TCSpecial sent unprompted, such as a
+    /// beacon or DH_EVENT
+    ///
+    /// What arrived while waiting for command replies comes first, oldest
+    /// first. Command replies received meanwhile are skipped.
+    pub async fn recv_async_telemetry(&mut self, timeout: Duration) -> TcsResult<Telemetry> {
+        if let Some(telemetry) = self.unsolicited.pop_front() {
+            return Ok(telemetry);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let telemetry = self.receive_until(deadline).await?;
+            if telemetry.is_unsolicited() {
+                return Ok(telemetry);
+            }
+        }
+    }
+
+    /// Keep unsolicited telemetry for recv_async_telemetry
+    fn keep_unsolicited(&mut self, telemetry: Telemetry) {
+        if self.unsolicited.len() == UNSOLICITED_QUEUE_DEPTH {
+            self.unsolicited.pop_front();
+        }
+        self.unsolicited.push_back(telemetry);
+    }
+
+    /// Send a PING command
+    pub async fn ping(&mut self) -> TcsResult<PingTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::Ping(PingCommand::new(seq));
+
+        match self.send_command(cmd).await? {
+            Telemetry::Ping(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a START_DH command
+    pub async fn start_dh(&mut self, dh_id: DHId, dh_type: DHType, name: DHName) -> TcsResult<CommandStatus> {
+        Ok(self.start_dh_with_outcome(dh_id, dh_type, name).await?.header.status)
+    }
+
+    /// Send a START_DH command, getting whether it created, found or
+    /// reactivated the data handler as well as its status
+    pub async fn start_dh_with_outcome(
+        &mut self,
+        dh_id: DHId,
+        dh_type: DHType,
+        name: DHName,
+    ) -> TcsResult<StartDHTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::StartDH(StartDHCommand::new(seq, dh_id, dh_type, name));
+
+        match self.send_command(cmd).await? {
+            Telemetry::StartDH(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a STOP_DH command
+    pub async fn stop_dh(&mut self, dh_id: DHId) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::StopDH(StopDHCommand::new(seq, dh_id));
+
+        match self.send_command(cmd).await? {
+            Telemetry::StopDH(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a QUERY_DH command
+    pub async fn query_dh(&mut self, dh_id: DHId) -> TcsResult<QueryDHTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::QueryDH(QueryDHCommand::new(seq, dh_id));
+
+        match self.send_command(cmd).await? {
+            Telemetry::QueryDH(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcslibgs::{BeaconTelemetry, StopDHTelemetry};
+
+    /// Answer commands the way the CI would, sending a beacon and a stale
+    /// reply ahead of each real one
+    async fn mock_ci(socket: UdpSocket, commands: usize) {
+        let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+        for _ in 0..commands {
+            let (size, addr) = socket.recv_from(&mut buf).await.unwrap();
+            let command = ProtocolMessage::from_bytes(&buf[..size]).unwrap().into_command().unwrap();
+            let sequence = command.sequence();
+            let reply = match command {
+                Command::Ping(_) => Telemetry::Ping(PingTelemetry::new(sequence, CommandStatus::Success)),
+                Command::StopDH(_) => Telemetry::StopDH(StopDHTelemetry::new(sequence, CommandStatus::NotFound)),
+                other => panic!("Unexpected command {:?}", other),
+            };
+            let stale = Telemetry::Ping(PingTelemetry::new(sequence.wrapping_sub(100), CommandStatus::Success));
+
+            for telemetry in [Telemetry::Beacon(BeaconTelemetry::new()), stale, reply] {
+                let data = ProtocolMessage::from_telemetry(telemetry).to_bytes().unwrap();
+                socket.send_to(&data, addr).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_ping() {
+        let ci = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let ci_addr = ci.local_addr().unwrap();
+        let server = tokio::spawn(mock_ci(ci, 2));
+
+        let mut client = AsyncTcsClient::connect(&ci_addr.to_string()).await.unwrap();
+        let tm = client.ping().await.unwrap();
+        assert_eq!((tm.header.sequence, tm.header.status), (1, CommandStatus::Success));
+        assert_eq!(client.stop_dh(DHId(4)).await.unwrap(), CommandStatus::NotFound);
+        server.await.unwrap();
+
+        // The beacons sent ahead of the replies were kept
+        for _ in 0..2 {
+            let telemetry = client.recv_async_telemetry(Duration::from_millis(10)).await.unwrap();
+            assert!(matches!(telemetry, Telemetry::Beacon(_)));
+        }
+        assert!(matches!(client.recv_async_telemetry(Duration::from_millis(10)).await, Err(TcsError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout() {
+        // A CI that never answers
+        let ci = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut client = AsyncTcsClient::connect(&ci.local_addr().unwrap().to_string()).await.unwrap();
+        client.set_timeout(Duration::from_millis(50));
+        assert!(matches!(client.ping().await, Err(TcsError::Timeout)));
+    }
+}
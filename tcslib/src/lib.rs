@@ -5,7 +5,11 @@
 
 //pub mod client;
 pub mod connection;
+#[cfg(feature = "async")]
+pub mod async_client;
 
 //pub use client::*;
 pub use connection::*;
+#[cfg(feature = "async")]
+pub use async_client::*;
 pub use tcslibgs::*;
@@ -0,0 +1,120 @@
+//! Spacecraft clock
+//!
+//! The host's wall-clock time drifts and can't be trusted to be set, so the
+//! ground corrects it with SET_TIME. The correction is kept as an offset from
+//! the host time rather than by setting the host clock, which needs
+//! privileges and would disturb everything else on the host.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::types::Timestamp;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Host time corrected by an offset set from the ground
+///
+/// Clones share the offset, so a correction applied through one is seen by
+/// all of them.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// Nanoseconds added to the host time
+    offset_ns: Arc<AtomicI64>,
+    started: Instant,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            offset_ns: Arc::new(AtomicI64::new(0)),
+            started: Instant::now(),
+        }
+    }
+
+    /// Get the corrected time
+    pub fn now(&self) -> Timestamp {
+        from_nanos(host_nanos() + self.offset_ns.load(Ordering::SeqCst) as i128)
+    }
+
+    /// Correct the clock so it reads timestamp now, returning the time it
+    /// read before
+    pub fn set(&self, timestamp: Timestamp) -> Timestamp {
+        let host = host_nanos();
+        let offset = (to_nanos(timestamp) - host).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        let previous = self.offset_ns.swap(offset, Ordering::SeqCst);
+        from_nanos(host + previous as i128)
+    }
+
+    /// Get the correction applied to the host time, in nanoseconds
+    pub fn offset_ns(&self) -> i64 {
+        self.offset_ns.load(Ordering::SeqCst)
+    }
+
+    /// Get the time since the clock was created, unaffected by corrections
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn host_nanos() -> i128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i128
+}
+
+fn to_nanos(timestamp: Timestamp) -> i128 {
+    timestamp.seconds as i128 * NANOS_PER_SEC + timestamp.nanoseconds as i128
+}
+
+/// Times before the epoch read as the epoch
+fn from_nanos(nanos: i128) -> Timestamp {
+    let nanos = nanos.max(0);
+    Timestamp {
+        seconds: (nanos / NANOS_PER_SEC).min(u64::MAX as i128) as u64,
+        nanoseconds: (nanos % NANOS_PER_SEC) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_clock() {
+        let clock = Clock::new();
+        let shared = clock.clone();
+        assert_eq!(clock.offset_ns(), 0);
+
+        let host = Timestamp::now();
+        let target = Timestamp { seconds: 1_000_000_000, nanoseconds: 500_000_000 };
+        let previous = clock.set(target);
+        assert!(previous.seconds.abs_diff(host.seconds) <= 1);
+
+        // The clock reads the new time from now on, and clones follow it
+        let now = shared.now();
+        assert!(now.seconds.abs_diff(target.seconds) <= 1, "{:?}", now);
+        assert!(clock.offset_ns() < 0);
+
+        // Setting the time again replaces the correction rather than adding to it
+        let previous = clock.set(host);
+        assert!(previous.seconds.abs_diff(target.seconds) <= 1);
+        assert!(clock.offset_ns().abs() < NANOS_PER_SEC as i64);
+    }
+
+    #[test]
+    fn test_clock_limits() {
+        let clock = Clock::new();
+        clock.set(Timestamp { seconds: 0, nanoseconds: 0 });
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(clock.now().seconds, 0);
+
+        clock.set(Timestamp { seconds: u64::MAX, nanoseconds: 0 });
+        // The correction saturates rather than wrapping
+        assert!(clock.now().seconds > 200 * 365 * 24 * 3600);
+    }
+}
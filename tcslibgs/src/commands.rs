@@ -3,7 +3,9 @@
 //! Commands are sent from ground to space and are idempotent.
 
 use serde::{Deserialize, Serialize};
-use crate::types::{ArmKey, BeaconTime, DHId, DHName, DHType};
+use crate::log::LogLevel;
+use crate::protocol::Framing;
+use crate::types::{ArmKey, BeaconTime, CommandStatus, DHConfig, DHId, DHName, DHType, Timestamp};
 
 /// Command message header
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -12,6 +14,9 @@ pub struct CommandHeader {
     pub sequence: u32,
     /// Command type identifier
     pub cmd_type: CommandType,
+    /// Operator correlation id, echoed unchanged in the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
 }
 
 /// Command types
@@ -20,11 +25,31 @@ pub enum CommandType {
     Ping,
     RestartArm,
     Restart,
+    QueryDropped,
+    QueryEndpointSupport,
+    InjectFault,
+    QueryBeaconStatus,
+    Hello,
+    SetTime,
+    SetLogLevel,
     StartDH,
     StopDH,
     QueryDH,
+    SnapshotStats,
+    DHLoopback,
+    SubscribeDHStats,
+    UnsubscribeDHStats,
+    PauseAllDH,
+    ResumeAllDH,
+    ResetStats,
+    ListDH,
+    CheckpointDH,
+    DHControl,
+    QueryActivity,
     Config,
     ConfigDH,
+    ReconfigureDH,
+    ReloadConfig,
 }
 
 impl CommandType {
@@ -33,11 +58,31 @@ impl CommandType {
             CommandType::Ping => 0x01,
             CommandType::RestartArm => 0x02,
             CommandType::Restart => 0x03,
+            CommandType::QueryDropped => 0x04,
+            CommandType::QueryEndpointSupport => 0x05,
+            CommandType::InjectFault => 0x06,
+            CommandType::QueryBeaconStatus => 0x07,
+            CommandType::Hello => 0x08,
+            CommandType::SetTime => 0x09,
+            CommandType::SetLogLevel => 0x0A,
             CommandType::StartDH => 0x10,
             CommandType::StopDH => 0x11,
             CommandType::QueryDH => 0x12,
+            CommandType::SnapshotStats => 0x13,
+            CommandType::DHLoopback => 0x14,
+            CommandType::SubscribeDHStats => 0x15,
+            CommandType::UnsubscribeDHStats => 0x16,
+            CommandType::PauseAllDH => 0x17,
+            CommandType::ResumeAllDH => 0x18,
+            CommandType::ResetStats => 0x19,
+            CommandType::ListDH => 0x1A,
+            CommandType::CheckpointDH => 0x1B,
+            CommandType::DHControl => 0x1C,
+            CommandType::QueryActivity => 0x1D,
             CommandType::Config => 0x20,
             CommandType::ConfigDH => 0x21,
+            CommandType::ReconfigureDH => 0x22,
+            CommandType::ReloadConfig => 0x23,
         }
     }
 
@@ -46,11 +91,31 @@ impl CommandType {
             0x01 => Some(CommandType::Ping),
             0x02 => Some(CommandType::RestartArm),
             0x03 => Some(CommandType::Restart),
+            0x04 => Some(CommandType::QueryDropped),
+            0x05 => Some(CommandType::QueryEndpointSupport),
+            0x06 => Some(CommandType::InjectFault),
+            0x07 => Some(CommandType::QueryBeaconStatus),
+            0x08 => Some(CommandType::Hello),
+            0x09 => Some(CommandType::SetTime),
+            0x0A => Some(CommandType::SetLogLevel),
             0x10 => Some(CommandType::StartDH),
             0x11 => Some(CommandType::StopDH),
             0x12 => Some(CommandType::QueryDH),
+            0x13 => Some(CommandType::SnapshotStats),
+            0x14 => Some(CommandType::DHLoopback),
+            0x15 => Some(CommandType::SubscribeDHStats),
+            0x16 => Some(CommandType::UnsubscribeDHStats),
+            0x17 => Some(CommandType::PauseAllDH),
+            0x18 => Some(CommandType::ResumeAllDH),
+            0x19 => Some(CommandType::ResetStats),
+            0x1A => Some(CommandType::ListDH),
+            0x1B => Some(CommandType::CheckpointDH),
+            0x1C => Some(CommandType::DHControl),
+            0x1D => Some(CommandType::QueryActivity),
             0x20 => Some(CommandType::Config),
             0x21 => Some(CommandType::ConfigDH),
+            0x22 => Some(CommandType::ReconfigureDH),
+            0x23 => Some(CommandType::ReloadConfig),
             _ => None,
         }
     }
@@ -68,6 +133,7 @@ impl PingCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::Ping,
+                request_id: None,
             },
         }
     }
@@ -86,6 +152,7 @@ impl RestartArmCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::RestartArm,
+                request_id: None,
             },
             arm_key,
         }
@@ -105,12 +172,159 @@ impl RestartCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::Restart,
+                request_id: None,
             },
             arm_key,
         }
     }
 }
 
+/// QUERY_DROPPED command - report, and optionally clear, dropped message counts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryDroppedCommand {
+    pub header: CommandHeader,
+    /// Reset the counters after reporting them
+    pub clear: bool,
+}
+
+impl QueryDroppedCommand {
+    pub fn new(sequence: u32, clear: bool) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::QueryDropped,
+                request_id: None,
+            },
+            clear,
+        }
+    }
+}
+
+/// QUERY_ENDPOINT_SUPPORT command - report the endpoint types the CI can create
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryEndpointSupportCommand {
+    pub header: CommandHeader,
+}
+
+impl QueryEndpointSupportCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::QueryEndpointSupport,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// INJECT_FAULT command - make the next command of a given type fail
+///
+/// Only honored when the CI is configured to allow fault injection.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InjectFaultCommand {
+    pub header: CommandHeader,
+    /// Type of command to fail
+    pub target: CommandType,
+    /// Status the failed command reports
+    pub status: CommandStatus,
+}
+
+impl InjectFaultCommand {
+    pub fn new(sequence: u32, target: CommandType, status: CommandStatus) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::InjectFault,
+                request_id: None,
+            },
+            target,
+            status,
+        }
+    }
+}
+
+/// QUERY_BEACON_STATUS command - report beacon destinations and send counts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryBeaconStatusCommand {
+    pub header: CommandHeader,
+}
+
+impl QueryBeaconStatusCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::QueryBeaconStatus,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// HELLO command - offer the framings the ground supports, most preferred
+/// first, so TCSpecial can choose one both sides understand
+///
+/// Always sent as JSON, since no framing has been agreed yet.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HelloCommand {
+    pub header: CommandHeader,
+    pub framings: Vec<Framing>,
+}
+
+impl HelloCommand {
+    pub fn new(sequence: u32, framings: Vec<Framing>) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::Hello,
+                request_id: None,
+            },
+            framings,
+        }
+    }
+}
+
+/// SET_TIME command - correct the spacecraft clock to the given time
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SetTimeCommand {
+    pub header: CommandHeader,
+    pub timestamp: Timestamp,
+}
+
+impl SetTimeCommand {
+    pub fn new(sequence: u32, timestamp: Timestamp) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::SetTime,
+                request_id: None,
+            },
+            timestamp,
+        }
+    }
+}
+
+/// SET_LOG_LEVEL command - change how much TCSpecial logs while it runs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SetLogLevelCommand {
+    pub header: CommandHeader,
+    pub level: LogLevel,
+}
+
+impl SetLogLevelCommand {
+    pub fn new(sequence: u32, level: LogLevel) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::SetLogLevel,
+                request_id: None,
+            },
+            level,
+        }
+    }
+}
+
 /// START_DH command - start a data handler
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StartDHCommand {
@@ -126,6 +340,7 @@ impl StartDHCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::StartDH,
+                request_id: None,
             },
             dh_id,
             dh_type,
@@ -147,6 +362,7 @@ impl StopDHCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::StopDH,
+                request_id: None,
             },
             dh_id,
         }
@@ -166,8 +382,57 @@ impl QueryDHCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::QueryDH,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// SNAPSHOT_STATS command - downlink statistics for all data handlers at once
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SnapshotStatsCommand {
+    pub header: CommandHeader,
+}
+
+impl SnapshotStatsCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::SnapshotStats,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// DH_LOOPBACK command - send a token to a DH's payload and time its echo
+///
+/// The token goes through the DH's relay, so the DH must be active; the CI
+/// answers INVALID_PARAMETER if it isn't. The reply comes once the echo does
+/// or the timeout passes, with other commands answered meanwhile.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHLoopbackCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+    /// Value the payload is expected to echo back
+    pub token: u64,
+    /// How long to wait for the echo; the CI caps this at a few seconds
+    pub timeout_ms: u32,
+}
+
+impl DHLoopbackCommand {
+    pub fn new(sequence: u32, dh_id: DHId, token: u64, timeout_ms: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::DHLoopback,
+                request_id: None,
             },
             dh_id,
+            token,
+            timeout_ms,
         }
     }
 }
@@ -185,6 +450,7 @@ impl ConfigCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::Config,
+                request_id: None,
             },
             beacon_interval,
         }
@@ -192,11 +458,22 @@ impl ConfigCommand {
 }
 
 /// CONFIG_DH command - configure data handler values
+///
+/// Each value given replaces the data handler's current one and takes effect
+/// on its running relays; values left as None are unchanged. See
+/// ConduitOptions for what each means.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ConfigDHCommand {
     pub header: CommandHeader,
     pub dh_id: DHId,
-    // Additional configuration fields can be added here
+    #[serde(default)]
+    pub read_buffer_size: Option<usize>,
+    #[serde(default)]
+    pub write_buffer_size: Option<usize>,
+    #[serde(default)]
+    pub stream_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub rate_limit_bps: Option<u64>,
 }
 
 impl ConfigDHCommand {
@@ -205,8 +482,243 @@ impl ConfigDHCommand {
             header: CommandHeader {
                 sequence,
                 cmd_type: CommandType::ConfigDH,
+                request_id: None,
             },
             dh_id,
+            read_buffer_size: None,
+            write_buffer_size: None,
+            stream_delay_ms: None,
+            rate_limit_bps: None,
+        }
+    }
+}
+
+/// RECONFIGURE_DH command - replace a data handler's whole configuration
+///
+/// The new configuration takes effect in one step; an active data handler
+/// has its relays restarted and keeps its statistics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReconfigureDHCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+    pub config: DHConfig,
+}
+
+impl ReconfigureDHCommand {
+    pub fn new(sequence: u32, dh_id: DHId, config: DHConfig) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::ReconfigureDH,
+                request_id: None,
+            },
+            dh_id,
+            config,
+        }
+    }
+}
+
+/// RELOAD_CONFIG command - re-read the payload configuration file and apply
+/// the differences to the running data handlers
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReloadConfigCommand {
+    pub header: CommandHeader,
+}
+
+impl ReloadConfigCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::ReloadConfig,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// SUBSCRIBE_DH_STATS command - have the CI push a DH's statistics periodically
+///
+/// Updates are QUERY_DH telemetry carrying this command's sequence number and
+/// go to the address the command came from. A subscription lapses unless
+/// renewed by subscribing again.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SubscribeDHStatsCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+    /// Time between updates
+    pub interval_ms: u32,
+}
+
+impl SubscribeDHStatsCommand {
+    pub fn new(sequence: u32, dh_id: DHId, interval_ms: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::SubscribeDHStats,
+                request_id: None,
+            },
+            dh_id,
+            interval_ms,
+        }
+    }
+}
+
+/// UNSUBSCRIBE_DH_STATS command - stop pushing a DH's statistics
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnsubscribeDHStatsCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+}
+
+impl UnsubscribeDHStatsCommand {
+    pub fn new(sequence: u32, dh_id: DHId) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::UnsubscribeDHStats,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// PAUSE_ALL_DH command - pause every active data handler at once
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PauseAllDHCommand {
+    pub header: CommandHeader,
+}
+
+impl PauseAllDHCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::PauseAllDH,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// RESUME_ALL_DH command - resume every paused data handler at once
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeAllDHCommand {
+    pub header: CommandHeader,
+}
+
+impl ResumeAllDHCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::ResumeAllDH,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// RESET_STATS command - zero a data handler's counters, leaving it running
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResetStatsCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+}
+
+impl ResetStatsCommand {
+    pub fn new(sequence: u32, dh_id: DHId) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::ResetStats,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// LIST_DH command - list the data handlers TCSpecial has
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListDHCommand {
+    pub header: CommandHeader,
+}
+
+impl ListDHCommand {
+    pub fn new(sequence: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::ListDH,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// CHECKPOINT_DH command - keep a data handler's statistics so later
+/// QUERY_DH responses can report the change since now
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CheckpointDHCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+}
+
+impl CheckpointDHCommand {
+    pub fn new(sequence: u32, dh_id: DHId) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::CheckpointDH,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// DH_CONTROL command - write bytes straight to a DH's payload, apart from
+/// the traffic it relays, such as to start a payload sending telemetry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHControlCommand {
+    pub header: CommandHeader,
+    pub dh_id: DHId,
+    pub data: Vec<u8>,
+}
+
+impl DHControlCommand {
+    pub fn new(sequence: u32, dh_id: DHId, data: Vec<u8>) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::DHControl,
+                request_id: None,
+            },
+            dh_id,
+            data,
+        }
+    }
+}
+
+/// QUERY_ACTIVITY command - find which running data handlers have moved
+/// data within the last window_ms milliseconds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryActivityCommand {
+    pub header: CommandHeader,
+    pub window_ms: u32,
+}
+
+impl QueryActivityCommand {
+    pub fn new(sequence: u32, window_ms: u32) -> Self {
+        Self {
+            header: CommandHeader {
+                sequence,
+                cmd_type: CommandType::QueryActivity,
+                request_id: None,
+            },
+            window_ms,
         }
     }
 }
@@ -217,11 +729,31 @@ pub enum Command {
     Ping(PingCommand),
     RestartArm(RestartArmCommand),
     Restart(RestartCommand),
+    QueryDropped(QueryDroppedCommand),
+    QueryEndpointSupport(QueryEndpointSupportCommand),
+    InjectFault(InjectFaultCommand),
+    QueryBeaconStatus(QueryBeaconStatusCommand),
+    Hello(HelloCommand),
+    SetTime(SetTimeCommand),
+    SetLogLevel(SetLogLevelCommand),
     StartDH(StartDHCommand),
     StopDH(StopDHCommand),
     QueryDH(QueryDHCommand),
+    SnapshotStats(SnapshotStatsCommand),
+    DHLoopback(DHLoopbackCommand),
+    SubscribeDHStats(SubscribeDHStatsCommand),
+    UnsubscribeDHStats(UnsubscribeDHStatsCommand),
+    PauseAllDH(PauseAllDHCommand),
+    ResumeAllDH(ResumeAllDHCommand),
+    ResetStats(ResetStatsCommand),
+    ListDH(ListDHCommand),
+    CheckpointDH(CheckpointDHCommand),
+    DHControl(DHControlCommand),
+    QueryActivity(QueryActivityCommand),
     Config(ConfigCommand),
     ConfigDH(ConfigDHCommand),
+    ReconfigureDH(ReconfigureDHCommand),
+    ReloadConfig(ReloadConfigCommand),
 }
 
 impl Command {
@@ -230,11 +762,31 @@ impl Command {
             Command::Ping(cmd) => cmd.header.sequence,
             Command::RestartArm(cmd) => cmd.header.sequence,
             Command::Restart(cmd) => cmd.header.sequence,
+            Command::QueryDropped(cmd) => cmd.header.sequence,
+            Command::QueryEndpointSupport(cmd) => cmd.header.sequence,
+            Command::InjectFault(cmd) => cmd.header.sequence,
+            Command::QueryBeaconStatus(cmd) => cmd.header.sequence,
+            Command::Hello(cmd) => cmd.header.sequence,
+            Command::SetTime(cmd) => cmd.header.sequence,
+            Command::SetLogLevel(cmd) => cmd.header.sequence,
             Command::StartDH(cmd) => cmd.header.sequence,
             Command::StopDH(cmd) => cmd.header.sequence,
             Command::QueryDH(cmd) => cmd.header.sequence,
+            Command::SnapshotStats(cmd) => cmd.header.sequence,
+            Command::DHLoopback(cmd) => cmd.header.sequence,
+            Command::SubscribeDHStats(cmd) => cmd.header.sequence,
+            Command::UnsubscribeDHStats(cmd) => cmd.header.sequence,
+            Command::PauseAllDH(cmd) => cmd.header.sequence,
+            Command::ResumeAllDH(cmd) => cmd.header.sequence,
+            Command::ResetStats(cmd) => cmd.header.sequence,
+            Command::ListDH(cmd) => cmd.header.sequence,
+            Command::CheckpointDH(cmd) => cmd.header.sequence,
+            Command::DHControl(cmd) => cmd.header.sequence,
+            Command::QueryActivity(cmd) => cmd.header.sequence,
             Command::Config(cmd) => cmd.header.sequence,
             Command::ConfigDH(cmd) => cmd.header.sequence,
+            Command::ReconfigureDH(cmd) => cmd.header.sequence,
+            Command::ReloadConfig(cmd) => cmd.header.sequence,
         }
     }
 
@@ -243,13 +795,117 @@ impl Command {
             Command::Ping(cmd) => cmd.header.cmd_type,
             Command::RestartArm(cmd) => cmd.header.cmd_type,
             Command::Restart(cmd) => cmd.header.cmd_type,
+            Command::QueryDropped(cmd) => cmd.header.cmd_type,
+            Command::QueryEndpointSupport(cmd) => cmd.header.cmd_type,
+            Command::InjectFault(cmd) => cmd.header.cmd_type,
+            Command::QueryBeaconStatus(cmd) => cmd.header.cmd_type,
+            Command::Hello(cmd) => cmd.header.cmd_type,
+            Command::SetTime(cmd) => cmd.header.cmd_type,
+            Command::SetLogLevel(cmd) => cmd.header.cmd_type,
             Command::StartDH(cmd) => cmd.header.cmd_type,
             Command::StopDH(cmd) => cmd.header.cmd_type,
             Command::QueryDH(cmd) => cmd.header.cmd_type,
+            Command::SnapshotStats(cmd) => cmd.header.cmd_type,
+            Command::DHLoopback(cmd) => cmd.header.cmd_type,
+            Command::SubscribeDHStats(cmd) => cmd.header.cmd_type,
+            Command::UnsubscribeDHStats(cmd) => cmd.header.cmd_type,
+            Command::PauseAllDH(cmd) => cmd.header.cmd_type,
+            Command::ResumeAllDH(cmd) => cmd.header.cmd_type,
+            Command::ResetStats(cmd) => cmd.header.cmd_type,
+            Command::ListDH(cmd) => cmd.header.cmd_type,
+            Command::CheckpointDH(cmd) => cmd.header.cmd_type,
+            Command::DHControl(cmd) => cmd.header.cmd_type,
+            Command::QueryActivity(cmd) => cmd.header.cmd_type,
             Command::Config(cmd) => cmd.header.cmd_type,
             Command::ConfigDH(cmd) => cmd.header.cmd_type,
+            Command::ReconfigureDH(cmd) => cmd.header.cmd_type,
+            Command::ReloadConfig(cmd) => cmd.header.cmd_type,
         }
     }
+
+    /// Get the operator's correlation id, if any
+    pub fn request_id(&self) -> Option<u64> {
+        match self {
+            Command::Ping(cmd) => cmd.header.request_id,
+            Command::RestartArm(cmd) => cmd.header.request_id,
+            Command::Restart(cmd) => cmd.header.request_id,
+            Command::QueryDropped(cmd) => cmd.header.request_id,
+            Command::QueryEndpointSupport(cmd) => cmd.header.request_id,
+            Command::InjectFault(cmd) => cmd.header.request_id,
+            Command::QueryBeaconStatus(cmd) => cmd.header.request_id,
+            Command::Hello(cmd) => cmd.header.request_id,
+            Command::SetTime(cmd) => cmd.header.request_id,
+            Command::SetLogLevel(cmd) => cmd.header.request_id,
+            Command::StartDH(cmd) => cmd.header.request_id,
+            Command::StopDH(cmd) => cmd.header.request_id,
+            Command::QueryDH(cmd) => cmd.header.request_id,
+            Command::SnapshotStats(cmd) => cmd.header.request_id,
+            Command::DHLoopback(cmd) => cmd.header.request_id,
+            Command::SubscribeDHStats(cmd) => cmd.header.request_id,
+            Command::UnsubscribeDHStats(cmd) => cmd.header.request_id,
+            Command::PauseAllDH(cmd) => cmd.header.request_id,
+            Command::ResumeAllDH(cmd) => cmd.header.request_id,
+            Command::ResetStats(cmd) => cmd.header.request_id,
+            Command::ListDH(cmd) => cmd.header.request_id,
+            Command::CheckpointDH(cmd) => cmd.header.request_id,
+            Command::DHControl(cmd) => cmd.header.request_id,
+            Command::QueryActivity(cmd) => cmd.header.request_id,
+            Command::Config(cmd) => cmd.header.request_id,
+            Command::ConfigDH(cmd) => cmd.header.request_id,
+            Command::ReconfigureDH(cmd) => cmd.header.request_id,
+            Command::ReloadConfig(cmd) => cmd.header.request_id,
+        }
+    }
+
+    /// Tag the command with an operator correlation id
+    pub fn set_request_id(&mut self, request_id: Option<u64>) {
+        match self {
+            Command::Ping(cmd) => cmd.header.request_id = request_id,
+            Command::RestartArm(cmd) => cmd.header.request_id = request_id,
+            Command::Restart(cmd) => cmd.header.request_id = request_id,
+            Command::QueryDropped(cmd) => cmd.header.request_id = request_id,
+            Command::QueryEndpointSupport(cmd) => cmd.header.request_id = request_id,
+            Command::InjectFault(cmd) => cmd.header.request_id = request_id,
+            Command::QueryBeaconStatus(cmd) => cmd.header.request_id = request_id,
+            Command::Hello(cmd) => cmd.header.request_id = request_id,
+            Command::SetTime(cmd) => cmd.header.request_id = request_id,
+            Command::SetLogLevel(cmd) => cmd.header.request_id = request_id,
+            Command::StartDH(cmd) => cmd.header.request_id = request_id,
+            Command::StopDH(cmd) => cmd.header.request_id = request_id,
+            Command::QueryDH(cmd) => cmd.header.request_id = request_id,
+            Command::SnapshotStats(cmd) => cmd.header.request_id = request_id,
+            Command::DHLoopback(cmd) => cmd.header.request_id = request_id,
+            Command::SubscribeDHStats(cmd) => cmd.header.request_id = request_id,
+            Command::UnsubscribeDHStats(cmd) => cmd.header.request_id = request_id,
+            Command::PauseAllDH(cmd) => cmd.header.request_id = request_id,
+            Command::ResumeAllDH(cmd) => cmd.header.request_id = request_id,
+            Command::ResetStats(cmd) => cmd.header.request_id = request_id,
+            Command::ListDH(cmd) => cmd.header.request_id = request_id,
+            Command::CheckpointDH(cmd) => cmd.header.request_id = request_id,
+            Command::DHControl(cmd) => cmd.header.request_id = request_id,
+            Command::QueryActivity(cmd) => cmd.header.request_id = request_id,
+            Command::Config(cmd) => cmd.header.request_id = request_id,
+            Command::ConfigDH(cmd) => cmd.header.request_id = request_id,
+            Command::ReconfigureDH(cmd) => cmd.header.request_id = request_id,
+            Command::ReloadConfig(cmd) => cmd.header.request_id = request_id,
+        }
+    }
+
+    /// Check whether running the command twice has the same effect as
+    /// running it once, as it does for commands that only report
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Command::Ping(_)
+                | Command::QueryDropped(_)
+                | Command::QueryEndpointSupport(_)
+                | Command::QueryBeaconStatus(_)
+                | Command::Hello(_)
+                | Command::QueryDH(_)
+                | Command::ListDH(_)
+                | Command::QueryActivity(_)
+        )
+    }
 }
 
 #[cfg(test)]
@@ -277,4 +933,14 @@ mod tests {
         let deserialized: Command = serde_json::from_str(&json).unwrap();
         assert_eq!(cmd, deserialized);
     }
+
+    #[test]
+    fn test_reset_stats_serialization() {
+        let cmd = Command::ResetStats(ResetStatsCommand::new(9, DHId(3)));
+        let json = serde_json::to_string(&cmd).unwrap();
+        let deserialized: Command = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, cmd);
+        assert_eq!(deserialized.cmd_type(), CommandType::ResetStats);
+        assert_eq!(CommandType::from_u8(CommandType::ResetStats.to_u8()), Some(CommandType::ResetStats));
+    }
 }
@@ -14,6 +14,9 @@ pub enum TcsError {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
+
     #[error("Protocol error: {0}")]
     Protocol(String),
 
@@ -29,6 +32,9 @@ pub enum TcsError {
     #[error("Timeout")]
     Timeout,
 
+    #[error("Connection closed")]
+    ConnectionClosed,
+
     #[error("Not armed for restart")]
     NotArmed,
 
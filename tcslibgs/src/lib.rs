@@ -8,9 +8,17 @@ pub mod telemetry;
 pub mod types;
 pub mod protocol;
 pub mod error;
+pub mod pool;
+pub mod wire;
+pub mod clock;
+pub mod log;
 
 pub use commands::*;
 pub use telemetry::*;
 pub use types::*;
 pub use protocol::*;
 pub use error::*;
+pub use pool::*;
+pub use wire::*;
+pub use clock::*;
+pub use log::*;
@@ -0,0 +1,183 @@
+//! Logging with a level that can be changed while running
+//!
+//! Restarting TCSpecial to get more detail loses the state being debugged,
+//! so the ground raises and lowers the level with SET_LOG_LEVEL instead.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{TcsError, TcsResult};
+
+/// Environment variable holding the initial log level
+pub const LOG_LEVEL_ENV: &str = "RUST_LOG";
+
+/// How much is logged, from least to most
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Configuration names of all levels
+    pub const NAMES: [&'static str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+    const ALL: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+    fn from_u8(value: u8) -> LogLevel {
+        Self::ALL[(value as usize).min(Self::ALL.len() - 1)]
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        let lower = s.to_ascii_lowercase();
+        Self::NAMES
+            .iter()
+            .position(|name| *name == lower)
+            .map(|i| Self::ALL[i])
+            .ok_or_else(|| {
+                TcsError::Config(format!("Unknown log level '{}', expected one of: {}", s, Self::NAMES.join(", ")))
+            })
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Writes log messages at or below a level that can be changed at any time
+///
+/// Clones share the level and the destination, so a change made through one
+/// is seen by all of them.
+#[derive(Clone)]
+pub struct Logger {
+    level: Arc<AtomicU8>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Logger {
+    /// Create a logger writing to stderr
+    pub fn new(level: LogLevel) -> Self {
+        Self::with_writer(level, io::stderr())
+    }
+
+    /// Create a logger writing somewhere other than stderr
+    pub fn with_writer(level: LogLevel, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            level: Arc::new(AtomicU8::new(level as u8)),
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    /// Create a logger writing to stderr at the level named by RUST_LOG
+    ///
+    /// Only a plain level name is understood; anything else, such as a
+    /// per-module filter, leaves the default level.
+    pub fn from_env() -> Self {
+        let level = std::env::var(LOG_LEVEL_ENV)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        Self::new(level)
+    }
+
+    /// Get the current level
+    pub fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::SeqCst))
+    }
+
+    /// Change the level, returning the one it replaced
+    pub fn set_level(&self, level: LogLevel) -> LogLevel {
+        LogLevel::from_u8(self.level.swap(level as u8, Ordering::SeqCst))
+    }
+
+    /// Check whether messages at a level are written
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level()
+    }
+
+    /// Write a message if its level is enabled
+    ///
+    /// Logging must never take TCSpecial down, so write errors are dropped.
+    pub fn log(&self, level: LogLevel, args: fmt::Arguments<'_>) {
+        if !self.enabled(level) {
+            return;
+        }
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "[{}] {}", level, args);
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new(LogLevel::default())
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger").field("level", &self.level()).finish()
+    }
+}
+
+/// Log a formatted message at a level, e.g. tcs_log!(logger, Debug, "x {}", x)
+#[macro_export]
+macro_rules! tcs_log {
+    ($logger:expr, $level:ident, $($arg:tt)*) => {
+        $logger.log($crate::LogLevel::$level, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logger_level() {
+        let capture = Capture::default();
+        let logger = Logger::with_writer(LogLevel::Warn, capture.clone());
+        let shared = logger.clone();
+
+        tcs_log!(logger, Error, "error {}", 1);
+        tcs_log!(logger, Info, "info {}", 2);
+        assert_eq!(shared.set_level(LogLevel::Trace), LogLevel::Warn);
+        tcs_log!(logger, Trace, "trace {}", 3);
+        assert_eq!(String::from_utf8(capture.0.lock().unwrap().clone()).unwrap(), "[error] error 1\n[trace] trace 3\n");
+
+        assert_eq!("DEBUG".parse::<LogLevel>().unwrap(), LogLevel::Debug);
+        assert!(matches!("loud".parse::<LogLevel>(), Err(TcsError::Config(_))));
+        assert!(LogLevel::Error < LogLevel::Trace);
+    }
+}
@@ -0,0 +1,176 @@
+//! Shared pool of receive buffers
+//!
+//! Conduits, endpoints and connections take their buffers from a pool and
+//! return them when dropped, so buffers are reused as data handlers come and
+//! go instead of being freshly allocated each time.
+
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Default limit on the bytes held idle by the global pool
+pub const DEFAULT_POOL_CAPACITY: usize = 1024 * 1024;
+
+/// Buffer pool usage counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers handed out from the pool
+    pub hits: u64,
+    /// Buffers that had to be allocated
+    pub misses: u64,
+    /// Buffers freed because the pool was full
+    pub discards: u64,
+    /// Bytes currently held idle in the pool
+    pub retained_bytes: usize,
+}
+
+struct PoolInner {
+    capacity: usize,
+    free: BTreeMap<usize, Vec<Vec<u8>>>,
+    stats: PoolStats,
+}
+
+/// Pool of reusable byte buffers
+///
+/// Clones share the same buffers. Idle buffers are kept until they would
+/// exceed the capacity in bytes, after which returned buffers are freed.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                capacity,
+                free: BTreeMap::new(),
+                stats: PoolStats::default(),
+            })),
+        }
+    }
+
+    /// Get the process-wide pool
+    pub fn global() -> &'static BufferPool {
+        static GLOBAL: OnceLock<BufferPool> = OnceLock::new();
+        GLOBAL.get_or_init(|| BufferPool::new(DEFAULT_POOL_CAPACITY))
+    }
+
+    /// Set the limit on idle bytes, freeing buffers over the new limit
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        while inner.stats.retained_bytes > capacity {
+            let Some(mut entry) = inner.free.last_entry() else {
+                break;
+            };
+            let size = *entry.key();
+            entry.get_mut().pop();
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            inner.stats.retained_bytes -= size;
+            inner.stats.discards += 1;
+        }
+    }
+
+    /// Take a zeroed buffer of the given size
+    pub fn take(&self, size: usize) -> PooledBuffer {
+        let mut inner = self.inner.lock().unwrap();
+        let reused = inner.free.get_mut(&size).and_then(|buffers| buffers.pop());
+        let buffer = match reused {
+            Some(mut buffer) => {
+                inner.stats.hits += 1;
+                inner.stats.retained_bytes -= size;
+                buffer.fill(0);
+                buffer
+            }
+            None => {
+                inner.stats.misses += 1;
+                vec![0u8; size]
+            }
+        };
+
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Get the usage counters
+    pub fn stats(&self) -> PoolStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    fn give(&self, buffer: Vec<u8>) {
+        let mut inner = self.inner.lock().unwrap();
+        let size = buffer.len();
+        if inner.stats.retained_bytes + size > inner.capacity {
+            inner.stats.discards += 1;
+            return;
+        }
+        inner.stats.retained_bytes += size;
+        inner.free.entry(size).or_default().push(buffer);
+    }
+}
+
+/// Buffer borrowed from a BufferPool, returned to it when dropped
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_reuse() {
+        let pool = BufferPool::new(1024);
+        let mut buffer = pool.take(256);
+        buffer[0] = 0xff;
+        drop(buffer);
+
+        let buffer = pool.take(256);
+        assert_eq!(buffer[0], 0);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        let pool = BufferPool::new(300);
+        let first = pool.take(256);
+        let second = pool.take(256);
+        drop(first);
+        drop(second);
+
+        // Only one buffer fits under the cap
+        let stats = pool.stats();
+        assert_eq!(stats.retained_bytes, 256);
+        assert_eq!(stats.discards, 1);
+
+        pool.set_capacity(0);
+        assert_eq!(pool.stats().retained_bytes, 0);
+    }
+}
@@ -4,6 +4,14 @@
 //! address families, socket types, and protocols.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::commands::{Command, CommandType};
+use crate::error::{TcsError, TcsResult};
+use crate::telemetry::{Telemetry, TelemetryType};
 
 /// Canonical address family values
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -193,6 +201,98 @@ impl SocketConfig {
     }
 }
 
+/// Encoding used for commands and telemetry on the wire
+///
+/// The ground and TCSpecial agree on one with HELLO; JSON is used until
+/// then and whenever the other side does not understand HELLO.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Framing {
+    #[default]
+    Json,
+    Binary,
+}
+
+impl Framing {
+    /// Configuration names of all framings
+    pub const NAMES: [&'static str; 2] = ["json", "binary"];
+
+    /// Choose the first offered framing that is also supported, or JSON if
+    /// there is none
+    pub fn negotiate(offered: &[Framing], supported: &[Framing]) -> Framing {
+        offered
+            .iter()
+            .copied()
+            .find(|framing| supported.contains(framing))
+            .unwrap_or_default()
+    }
+
+    /// The framing a received ProtocolMessage was encoded with
+    ///
+    /// After the version byte, JSON messages start with '{' and binary ones
+    /// with a message type.
+    pub fn detect(bytes: &[u8]) -> Framing {
+        match bytes.get(1) {
+            Some(b'{') | None => Framing::Json,
+            Some(_) => Framing::Binary,
+        }
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Framing::Json => "json",
+            Framing::Binary => "binary",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Framing {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Framing::Json),
+            "binary" => Ok(Framing::Binary),
+            _ => Err(TcsError::Config(format!(
+                "Unknown framing '{}', expected one of: {}",
+                s,
+                Self::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3) lookup table
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Compute the CRC-32 (IEEE 802.3) of some bytes
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Size of the CRC trailer of a checked frame
+pub const FRAME_CRC_SIZE: usize = 4;
+
 /// Message framing for stream protocols
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MessageFrame {
@@ -200,44 +300,387 @@ pub struct MessageFrame {
     pub length: u32,
     /// Message data
     pub data: Vec<u8>,
+    /// Whether the frame ends with a CRC-32 of the length and data
+    #[serde(default)]
+    pub with_crc: bool,
 }
 
 impl MessageFrame {
     pub fn new(data: Vec<u8>) -> Self {
+        Self::with_crc(data, false)
+    }
+
+    /// Create a frame, optionally protected by a CRC trailer
+    pub fn with_crc(data: Vec<u8>, with_crc: bool) -> Self {
         Self {
             length: data.len() as u32,
             data,
+            with_crc,
         }
     }
 
-    /// Serialize the frame to bytes (length prefix + data)
+    /// Serialize the frame to bytes (length prefix + data + optional CRC)
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(4 + self.data.len());
+        let mut bytes = Vec::with_capacity(4 + self.data.len() + FRAME_CRC_SIZE);
         bytes.extend_from_slice(&self.length.to_be_bytes());
         bytes.extend_from_slice(&self.data);
+        if self.with_crc {
+            let crc = crc32(&bytes);
+            bytes.extend_from_slice(&crc.to_be_bytes());
+        }
         bytes
     }
 
-    /// Deserialize a frame from bytes
+    /// Deserialize a frame without a CRC trailer from bytes
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::from_bytes_with_crc(bytes, false).ok()
+    }
+
+    /// Deserialize a frame from bytes, checking its CRC trailer if it has one
+    pub fn from_bytes_with_crc(bytes: &[u8], with_crc: bool) -> TcsResult<Self> {
         if bytes.len() < 4 {
-            return None;
+            return Err(TcsError::Protocol("Frame too short for a length prefix".to_string()));
         }
         let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        if bytes.len() < 4 + length as usize {
+        let end = 4 + length as usize;
+        let trailer = if with_crc { FRAME_CRC_SIZE } else { 0 };
+        if bytes.len() < end + trailer {
+            return Err(TcsError::Protocol(format!(
+                "Frame truncated, {} of {} bytes",
+                bytes.len(),
+                end + trailer
+            )));
+        }
+        if with_crc {
+            let expected = u32::from_be_bytes(bytes[end..end + FRAME_CRC_SIZE].try_into().unwrap());
+            if crc32(&bytes[..end]) != expected {
+                return Err(TcsError::Protocol("CRC mismatch".to_string()));
+            }
+        }
+        Ok(Self {
+            length,
+            data: bytes[4..end].to_vec(),
+            with_crc,
+        })
+    }
+}
+
+/// Version of the command and telemetry layout, sent as the first byte of
+/// every ProtocolMessage
+///
+/// Bump this whenever a change to Command or Telemetry would make older
+/// software misread messages.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Largest datagram a ProtocolMessage, or one fragment of it, arrives in
+///
+/// Frames on a length-prefixed stream are held to the same size, whatever
+/// telemetry size limit is configured, so a corrupt or hostile length
+/// prefix can't make a reader allocate more.
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Contents of a ProtocolMessage
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MessagePayload {
+    Command(Command),
+    Telemetry(Telemetry),
+}
+
+/// Message exchanged between the ground and TCSpecial
+///
+/// Both sides encode and decode through this type so commands and telemetry
+/// always use the same framing on the wire. Serialized messages are the
+/// protocol version followed by the message in its framing.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProtocolMessage {
+    pub payload: MessagePayload,
+}
+
+impl ProtocolMessage {
+    pub fn from_command(command: Command) -> Self {
+        Self {
+            payload: MessagePayload::Command(command),
+        }
+    }
+
+    pub fn from_telemetry(telemetry: Telemetry) -> Self {
+        Self {
+            payload: MessagePayload::Telemetry(telemetry),
+        }
+    }
+
+    /// Serialize the message for sending as JSON
+    pub fn to_bytes(&self) -> TcsResult<Vec<u8>> {
+        self.to_bytes_with(Framing::Json)
+    }
+
+    /// Serialize the message for sending with the given framing
+    pub fn to_bytes_with(&self, framing: Framing) -> TcsResult<Vec<u8>> {
+        let mut bytes = vec![PROTOCOL_VERSION];
+        match framing {
+            Framing::Json => serde_json::to_writer(&mut bytes, self)?,
+            Framing::Binary => bytes.extend(match &self.payload {
+                MessagePayload::Command(command) => command.to_wire(),
+                MessagePayload::Telemetry(telemetry) => telemetry.to_wire(),
+            }),
+        }
+        Ok(bytes)
+    }
+
+    /// Deserialize a received message in either framing
+    ///
+    /// Messages from software speaking another protocol version are refused
+    /// rather than misread.
+    pub fn from_bytes(bytes: &[u8]) -> TcsResult<Self> {
+        match bytes.first() {
+            Some(&PROTOCOL_VERSION) => {}
+            Some(b'{') => {
+                return Err(TcsError::Protocol(format!(
+                    "Protocol version mismatch: message has no version, sender predates version {}",
+                    PROTOCOL_VERSION
+                )))
+            }
+            Some(version) => {
+                return Err(TcsError::Protocol(format!(
+                    "Protocol version mismatch: message is version {}, expected version {}",
+                    version, PROTOCOL_VERSION
+                )))
+            }
+            None => return Err(TcsError::Protocol("Empty message".to_string())),
+        }
+
+        let body = &bytes[1..];
+        if Framing::detect(bytes) == Framing::Json {
+            return Ok(serde_json::from_slice(body)?);
+        }
+
+        let tag = body[0];
+        if CommandType::from_u8(tag).is_some() {
+            Ok(Self::from_command(Command::from_wire(body)?))
+        } else if TelemetryType::from_u8(tag).is_some() {
+            Ok(Self::from_telemetry(Telemetry::from_wire(body)?))
+        } else {
+            Err(TcsError::Protocol(format!("Unknown message type 0x{:02X}", tag)))
+        }
+    }
+
+    /// Get the command, failing if the message carries telemetry
+    pub fn into_command(self) -> TcsResult<Command> {
+        match self.payload {
+            MessagePayload::Command(command) => Ok(command),
+            MessagePayload::Telemetry(_) => Err(TcsError::Protocol("Expected a command, got telemetry".to_string())),
+        }
+    }
+
+    /// Get the telemetry, failing if the message carries a command
+    pub fn into_telemetry(self) -> TcsResult<Telemetry> {
+        match self.payload {
+            MessagePayload::Telemetry(telemetry) => Ok(telemetry),
+            MessagePayload::Command(_) => Err(TcsError::Protocol("Expected telemetry, got a command".to_string())),
+        }
+    }
+}
+
+/// First byte of a fragment datagram, never a protocol version
+pub const FRAGMENT_MARKER: u8 = 0xFA;
+
+/// Size of the serialized fragment header, including the marker byte
+pub const FRAGMENT_HEADER_SIZE: usize = 9;
+
+/// Header identifying one piece of a fragmented message
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// Identifier shared by all fragments of one message
+    pub message_id: u32,
+    /// Position of this fragment, starting at zero
+    pub index: u16,
+    /// Total number of fragments in the message
+    pub total: u16,
+}
+
+/// One piece of a message too large to fit in a single datagram
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Fragment {
+    pub header: FragmentHeader,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    /// Split data into fragments carrying at most max_data bytes each
+    ///
+    /// Fails if the data needs more fragments than a header can number.
+    pub fn split(message_id: u32, data: &[u8], max_data: usize) -> TcsResult<Vec<Fragment>> {
+        let max_data = max_data.max(1);
+        let chunks: Vec<&[u8]> = data.chunks(max_data).collect();
+        let total = u16::try_from(chunks.len()).map_err(|_| {
+            TcsError::Protocol(format!(
+                "{} bytes need {} fragments of {} bytes, more than {}",
+                data.len(),
+                chunks.len(),
+                max_data,
+                u16::MAX
+            ))
+        })?;
+
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| Fragment {
+                header: FragmentHeader {
+                    message_id,
+                    index: index as u16,
+                    total,
+                },
+                data: chunk.to_vec(),
+            })
+            .collect())
+    }
+
+    /// Check whether a datagram carries a fragment
+    pub fn is_fragment(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&FRAGMENT_MARKER)
+    }
+
+    /// Serialize the fragment (marker + message_id + index + total + data)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FRAGMENT_HEADER_SIZE + self.data.len());
+        bytes.push(FRAGMENT_MARKER);
+        bytes.extend_from_slice(&self.header.message_id.to_be_bytes());
+        bytes.extend_from_slice(&self.header.index.to_be_bytes());
+        bytes.extend_from_slice(&self.header.total.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Deserialize a fragment from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FRAGMENT_HEADER_SIZE || !Self::is_fragment(bytes) {
+            return None;
+        }
+        let header = FragmentHeader {
+            message_id: u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            index: u16::from_be_bytes([bytes[5], bytes[6]]),
+            total: u16::from_be_bytes([bytes[7], bytes[8]]),
+        };
+        if header.total == 0 || header.index >= header.total {
             return None;
         }
         Some(Self {
-            length,
-            data: bytes[4..4 + length as usize].to_vec(),
+            header,
+            data: bytes[FRAGMENT_HEADER_SIZE..].to_vec(),
         })
     }
 }
 
+/// Most incomplete messages a FragmentReassembler holds at once
+pub const MAX_PENDING_MESSAGES: usize = 8;
+
+/// Fragments collected so far for one message
+struct PendingMessage {
+    first_seen: Instant,
+    total: u16,
+    fragments: HashMap<u16, Vec<u8>>,
+    /// Bytes held in fragments
+    size: usize,
+}
+
+/// Reassembles fragmented messages, discarding incomplete sets after a timeout
+///
+/// Memory is only taken for fragments as they arrive. A set growing past
+/// the largest message accepted is dropped, and when MAX_PENDING_MESSAGES
+/// sets are waiting the oldest makes way for a new one.
+pub struct FragmentReassembler {
+    timeout: Duration,
+    max_size: usize,
+    pending: HashMap<u32, PendingMessage>,
+}
+
+impl FragmentReassembler {
+    /// Create a reassembler for messages of at most max_size bytes
+    pub fn new(timeout: Duration, max_size: usize) -> Self {
+        Self {
+            timeout,
+            max_size,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Change the size of the largest message reassembled
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    /// Add a fragment, returning the complete message once all fragments
+    /// have arrived
+    pub fn add(&mut self, fragment: Fragment) -> Option<Vec<u8>> {
+        self.expire();
+
+        let header = fragment.header;
+        if !self.pending.contains_key(&header.message_id) && self.pending.len() >= MAX_PENDING_MESSAGES {
+            let oldest = self.pending.iter().min_by_key(|(_, p)| p.first_seen).map(|(id, _)| *id)?;
+            self.pending.remove(&oldest);
+        }
+        let pending = self.pending.entry(header.message_id).or_insert_with(|| PendingMessage {
+            first_seen: Instant::now(),
+            total: header.total,
+            fragments: HashMap::new(),
+            size: 0,
+        });
+
+        // A fragment that doesn't match the set already started is stale
+        if pending.total != header.total {
+            return None;
+        }
+
+        if !pending.fragments.contains_key(&header.index) {
+            pending.size += fragment.data.len();
+            pending.fragments.insert(header.index, fragment.data);
+        }
+
+        // A message this large would be refused anyway
+        if pending.size > self.max_size {
+            self.pending.remove(&header.message_id);
+            return None;
+        }
+
+        if pending.fragments.len() < header.total as usize {
+            return None;
+        }
+
+        let mut pending = self.pending.remove(&header.message_id)?;
+        let mut message = Vec::with_capacity(pending.size);
+        for index in 0..pending.total {
+            message.extend_from_slice(&pending.fragments.remove(&index)?);
+        }
+        Some(message)
+    }
+
+    /// Discard incomplete messages older than the timeout
+    pub fn expire(&mut self) {
+        let timeout = self.timeout;
+        self.pending.retain(|_, p| p.first_seen.elapsed() < timeout);
+    }
+
+    /// Number of incomplete messages being held
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_framing_negotiate() {
+        let both = [Framing::Json, Framing::Binary];
+        assert_eq!(Framing::negotiate(&[Framing::Binary, Framing::Json], &both), Framing::Binary);
+        assert_eq!(Framing::negotiate(&[Framing::Json, Framing::Binary], &both), Framing::Json);
+        assert_eq!(Framing::negotiate(&[Framing::Binary], &[Framing::Json]), Framing::Json);
+        assert_eq!("Binary".parse::<Framing>().unwrap(), Framing::Binary);
+        assert!("cbor".parse::<Framing>().is_err());
+    }
+
     #[test]
     fn test_address_family_conversion() {
         let af = AddressFamily::Inet;
@@ -261,4 +704,166 @@ mod tests {
         let parsed = MessageFrame::from_bytes(&bytes).unwrap();
         assert_eq!(parsed.data, data);
     }
+
+    #[test]
+    fn test_message_frame_crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let frame = MessageFrame::with_crc(b"telemetry".to_vec(), true);
+        let bytes = frame.to_bytes();
+        assert_eq!(bytes.len(), 4 + 9 + FRAME_CRC_SIZE);
+        assert_eq!(MessageFrame::from_bytes_with_crc(&bytes, true).unwrap(), frame);
+
+        // Any single corrupted byte, in the length, data or CRC, is caught
+        for i in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0x01;
+            let result = MessageFrame::from_bytes_with_crc(&corrupted, true);
+            assert!(matches!(result, Err(TcsError::Protocol(_))), "byte {}: {:?}", i, result);
+        }
+        let mut corrupted = bytes.clone();
+        corrupted[6] ^= 0x80;
+        match MessageFrame::from_bytes_with_crc(&corrupted, true) {
+            Err(TcsError::Protocol(msg)) => assert_eq!(msg, "CRC mismatch"),
+            other => panic!("Accepted corrupted frame {:?}", other),
+        }
+
+        // A frame without a CRC is unchanged on the wire
+        assert_eq!(MessageFrame::new(b"telemetry".to_vec()).to_bytes(), bytes[..bytes.len() - FRAME_CRC_SIZE]);
+        assert!(MessageFrame::from_bytes(&[0, 0]).is_none());
+        assert!(MessageFrame::from_bytes_with_crc(&[0, 0], false).is_err());
+    }
+
+    #[test]
+    fn test_protocol_message() {
+        use crate::commands::PingCommand;
+        use crate::telemetry::PingTelemetry;
+        use crate::types::CommandStatus;
+
+        let command = Command::Ping(PingCommand::new(7));
+        let bytes = ProtocolMessage::from_command(command.clone()).to_bytes().unwrap();
+        assert_ne!(bytes[0], FRAGMENT_MARKER);
+        assert_eq!(ProtocolMessage::from_bytes(&bytes).unwrap().into_command().unwrap(), command);
+
+        // A telemetry message is not accepted where a command is expected
+        let telemetry = Telemetry::Ping(PingTelemetry::new(7, CommandStatus::Success));
+        let bytes = ProtocolMessage::from_telemetry(telemetry.clone()).to_bytes().unwrap();
+        assert_eq!(ProtocolMessage::from_bytes(&bytes).unwrap().into_telemetry().unwrap(), telemetry);
+        let result = ProtocolMessage::from_bytes(&bytes).unwrap().into_command();
+        assert!(matches!(result, Err(TcsError::Protocol(_))));
+
+        // Nor is a command accepted where telemetry is expected
+        let bytes = ProtocolMessage::from_command(command).to_bytes().unwrap();
+        let result = ProtocolMessage::from_bytes(&bytes).unwrap().into_telemetry();
+        assert!(matches!(result, Err(TcsError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_protocol_message_framings() {
+        use crate::commands::StartDHCommand;
+        use crate::types::{DHId, DHName, DHType};
+
+        let command = Command::StartDH(StartDHCommand::new(4, DHId(2), DHType::Network, DHName::new("10.0.0.1:5000")));
+        let message = ProtocolMessage::from_command(command.clone());
+        for framing in [Framing::Json, Framing::Binary] {
+            let bytes = message.to_bytes_with(framing).unwrap();
+            assert_eq!(Framing::detect(&bytes), framing);
+            assert_ne!(bytes[0], FRAGMENT_MARKER);
+            assert_eq!(ProtocolMessage::from_bytes(&bytes).unwrap().into_command().unwrap(), command);
+        }
+
+        match ProtocolMessage::from_bytes(&[PROTOCOL_VERSION, 0x7F, 0, 0, 0, 1, 0]) {
+            Err(TcsError::Protocol(msg)) => assert!(msg.contains("0x7F"), "{}", msg),
+            other => panic!("Decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_protocol_version_mismatch() {
+        use crate::commands::PingCommand;
+
+        let message = ProtocolMessage::from_command(Command::Ping(PingCommand::new(1)));
+        for framing in [Framing::Json, Framing::Binary] {
+            let mut bytes = message.to_bytes_with(framing).unwrap();
+            assert_eq!(bytes[0], PROTOCOL_VERSION);
+
+            bytes[0] = PROTOCOL_VERSION + 1;
+            match ProtocolMessage::from_bytes(&bytes) {
+                Err(TcsError::Protocol(msg)) => {
+                    assert!(msg.contains("version mismatch"), "{}", msg);
+                    assert!(msg.contains(&format!("version {}", PROTOCOL_VERSION + 1)), "{}", msg);
+                }
+                other => panic!("Decoded {:?}", other),
+            }
+        }
+
+        // JSON from before versioning
+        let unversioned = serde_json::to_vec(&message).unwrap();
+        match ProtocolMessage::from_bytes(&unversioned) {
+            Err(TcsError::Protocol(msg)) => assert!(msg.contains("predates"), "{}", msg),
+            other => panic!("Decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fragment_reassembly() {
+        let data: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        let fragments = Fragment::split(9, &data, 100).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let mut reassembler = FragmentReassembler::new(Duration::from_secs(10), data.len());
+        let mut result = None;
+        for fragment in fragments.iter().rev() {
+            let parsed = Fragment::from_bytes(&fragment.to_bytes()).unwrap();
+            result = reassembler.add(parsed);
+        }
+        assert_eq!(result, Some(data));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_fragment_expiry() {
+        let fragments = Fragment::split(1, &[0u8; 30], 10).unwrap();
+        let mut reassembler = FragmentReassembler::new(Duration::from_millis(10), 30);
+        assert_eq!(reassembler.add(fragments[0].clone()), None);
+        assert_eq!(reassembler.pending(), 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+        reassembler.expire();
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_fragment_limits() {
+        // A lone fragment claiming the most fragments a header can number
+        // takes only what it carries, and a full set past max_size is dropped
+        let mut reassembler = FragmentReassembler::new(Duration::from_secs(10), 16);
+        let claim = |message_id: u32, index: u16, data: &[u8]| {
+            let header = FragmentHeader { message_id, index, total: u16::MAX };
+            Fragment::from_bytes(&Fragment { header, data: data.to_vec() }.to_bytes()).unwrap()
+        };
+        assert_eq!(reassembler.add(claim(1, 0, &[])), None);
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.add(claim(1, 1, &[0u8; 17])), None);
+        assert_eq!(reassembler.pending(), 0);
+
+        // Fresh message ids beyond the limit evict the oldest sets
+        for message_id in 0..MAX_PENDING_MESSAGES as u32 + 2 {
+            assert_eq!(reassembler.add(claim(message_id, 0, b"x")), None);
+        }
+        assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES);
+
+        // and a new message still gets through
+        let fragments = Fragment::split(100, b"complete", 4).unwrap();
+        assert_eq!(reassembler.add(fragments[0].clone()), None);
+        assert_eq!(reassembler.add(fragments[1].clone()), Some(b"complete".to_vec()));
+        assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES - 1);
+    }
+
+    #[test]
+    fn test_fragment_split_limit() {
+        let data = vec![0u8; u16::MAX as usize + 1];
+        assert!(matches!(Fragment::split(1, &data, 1), Err(TcsError::Protocol(_))));
+        assert_eq!(Fragment::split(1, &data[1..], 1).unwrap().len(), u16::MAX as usize);
+    }
 }
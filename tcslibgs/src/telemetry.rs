@@ -3,7 +3,13 @@
 //! Telemetry is sent from space to ground.
 
 use serde::{Deserialize, Serialize};
-use crate::types::{CommandStatus, DHId, Statistics, Timestamp};
+use std::net::SocketAddr;
+use crate::log::LogLevel;
+use crate::protocol::Framing;
+use crate::types::{
+    BeaconFormat, BeaconTime, CommandStatus, DHId, DHName, DHState, DHType, ErrorCode, NetworkProtocol, Port,
+    ReloadSummary, StartDHOutcome, StartReason, Statistics, Timestamp, WriteLatency,
+};
 
 /// Telemetry message header
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -14,6 +20,9 @@ pub struct TelemetryHeader {
     pub tm_type: TelemetryType,
     /// Command status
     pub status: CommandStatus,
+    /// Correlation id of the command that generated this response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
 }
 
 /// Telemetry types
@@ -22,12 +31,34 @@ pub enum TelemetryType {
     Ping,
     RestartArm,
     Restart,
+    QueryDropped,
+    QueryEndpointSupport,
+    InjectFault,
+    QueryBeaconStatus,
+    Hello,
+    SetTime,
+    SetLogLevel,
     StartDH,
     StopDH,
     QueryDH,
+    StatsSnapshot,
+    DHLoopback,
+    SubscribeDHStats,
+    UnsubscribeDHStats,
+    PauseAllDH,
+    ResumeAllDH,
+    ResetStats,
+    ListDH,
+    CheckpointDH,
+    DHControl,
+    QueryActivity,
     Config,
     ConfigDH,
+    ReconfigureDH,
+    ReloadConfig,
     Beacon,
+    InvalidCommand,
+    DHEvent,
 }
 
 impl TelemetryType {
@@ -36,12 +67,34 @@ impl TelemetryType {
             TelemetryType::Ping => 0x81,
             TelemetryType::RestartArm => 0x82,
             TelemetryType::Restart => 0x83,
+            TelemetryType::QueryDropped => 0x84,
+            TelemetryType::QueryEndpointSupport => 0x85,
+            TelemetryType::InjectFault => 0x86,
+            TelemetryType::QueryBeaconStatus => 0x87,
+            TelemetryType::Hello => 0x88,
+            TelemetryType::SetTime => 0x89,
+            TelemetryType::SetLogLevel => 0x8A,
             TelemetryType::StartDH => 0x90,
             TelemetryType::StopDH => 0x91,
             TelemetryType::QueryDH => 0x92,
+            TelemetryType::StatsSnapshot => 0x93,
+            TelemetryType::DHLoopback => 0x94,
+            TelemetryType::SubscribeDHStats => 0x95,
+            TelemetryType::UnsubscribeDHStats => 0x96,
+            TelemetryType::PauseAllDH => 0x97,
+            TelemetryType::ResumeAllDH => 0x98,
+            TelemetryType::ResetStats => 0x99,
+            TelemetryType::ListDH => 0x9A,
+            TelemetryType::CheckpointDH => 0x9B,
+            TelemetryType::DHControl => 0x9C,
+            TelemetryType::QueryActivity => 0x9D,
             TelemetryType::Config => 0xA0,
             TelemetryType::ConfigDH => 0xA1,
+            TelemetryType::ReconfigureDH => 0xA2,
+            TelemetryType::ReloadConfig => 0xA3,
             TelemetryType::Beacon => 0xF0,
+            TelemetryType::InvalidCommand => 0xF1,
+            TelemetryType::DHEvent => 0xF2,
         }
     }
 
@@ -50,12 +103,34 @@ impl TelemetryType {
             0x81 => Some(TelemetryType::Ping),
             0x82 => Some(TelemetryType::RestartArm),
             0x83 => Some(TelemetryType::Restart),
+            0x84 => Some(TelemetryType::QueryDropped),
+            0x85 => Some(TelemetryType::QueryEndpointSupport),
+            0x86 => Some(TelemetryType::InjectFault),
+            0x87 => Some(TelemetryType::QueryBeaconStatus),
+            0x88 => Some(TelemetryType::Hello),
+            0x89 => Some(TelemetryType::SetTime),
+            0x8A => Some(TelemetryType::SetLogLevel),
             0x90 => Some(TelemetryType::StartDH),
             0x91 => Some(TelemetryType::StopDH),
             0x92 => Some(TelemetryType::QueryDH),
+            0x93 => Some(TelemetryType::StatsSnapshot),
+            0x94 => Some(TelemetryType::DHLoopback),
+            0x95 => Some(TelemetryType::SubscribeDHStats),
+            0x96 => Some(TelemetryType::UnsubscribeDHStats),
+            0x97 => Some(TelemetryType::PauseAllDH),
+            0x98 => Some(TelemetryType::ResumeAllDH),
+            0x99 => Some(TelemetryType::ResetStats),
+            0x9A => Some(TelemetryType::ListDH),
+            0x9B => Some(TelemetryType::CheckpointDH),
+            0x9C => Some(TelemetryType::DHControl),
+            0x9D => Some(TelemetryType::QueryActivity),
             0xA0 => Some(TelemetryType::Config),
             0xA1 => Some(TelemetryType::ConfigDH),
+            0xA2 => Some(TelemetryType::ReconfigureDH),
+            0xA3 => Some(TelemetryType::ReloadConfig),
             0xF0 => Some(TelemetryType::Beacon),
+            0xF1 => Some(TelemetryType::InvalidCommand),
+            0xF2 => Some(TelemetryType::DHEvent),
             _ => None,
         }
     }
@@ -75,10 +150,17 @@ impl PingTelemetry {
                 sequence,
                 tm_type: TelemetryType::Ping,
                 status,
+                request_id: None,
             },
             timestamp: Timestamp::now(),
         }
     }
+
+    /// Replace the host time with the spacecraft clock's
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
 }
 
 /// RESTART_ARM telemetry response
@@ -94,6 +176,7 @@ impl RestartArmTelemetry {
                 sequence,
                 tm_type: TelemetryType::RestartArm,
                 status,
+                request_id: None,
             },
         }
     }
@@ -112,15 +195,225 @@ impl RestartTelemetry {
                 sequence,
                 tm_type: TelemetryType::Restart,
                 status,
+                request_id: None,
             },
         }
     }
 }
 
-/// START_DH telemetry response
+/// QUERY_DROPPED telemetry response
+///
+/// The counts are those before any clear requested by the command.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryDroppedTelemetry {
+    pub header: TelemetryHeader,
+    /// Telemetry discarded because the telemetry queue was full
+    pub telemetry_dropped: u64,
+    /// Commands discarded because they could not be decoded
+    pub commands_dropped: u64,
+    /// Telemetry discarded because it arrived on the command port
+    #[serde(default)]
+    pub unexpected_telemetry: u64,
+}
+
+impl QueryDroppedTelemetry {
+    pub fn new(
+        sequence: u32,
+        status: CommandStatus,
+        telemetry_dropped: u64,
+        commands_dropped: u64,
+        unexpected_telemetry: u64,
+    ) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::QueryDropped,
+                status,
+                request_id: None,
+            },
+            telemetry_dropped,
+            commands_dropped,
+            unexpected_telemetry,
+        }
+    }
+}
+
+/// QUERY_ENDPOINT_SUPPORT telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryEndpointSupportTelemetry {
+    pub header: TelemetryHeader,
+    /// Data handler types the CI can create
+    pub dh_types: Vec<DHType>,
+    /// Protocols the CI can create network data handlers for
+    pub protocols: Vec<NetworkProtocol>,
+}
+
+impl QueryEndpointSupportTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_types: Vec<DHType>, protocols: Vec<NetworkProtocol>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::QueryEndpointSupport,
+                status,
+                request_id: None,
+            },
+            dh_types,
+            protocols,
+        }
+    }
+}
+
+/// INJECT_FAULT telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InjectFaultTelemetry {
+    pub header: TelemetryHeader,
+}
+
+impl InjectFaultTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::InjectFault,
+                status,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// Beacon send counts for a single destination
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BeaconDestinationStatus {
+    pub address: SocketAddr,
+    /// Beacons sent without error
+    pub sent: u64,
+    /// Beacons that could not be sent
+    pub failed: u64,
+    /// Interval for this destination, if it doesn't use the CI's interval
+    #[serde(default)]
+    pub interval_override: Option<BeaconTime>,
+}
+
+impl BeaconDestinationStatus {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            sent: 0,
+            failed: 0,
+            interval_override: None,
+        }
+    }
+}
+
+/// QUERY_BEACON_STATUS telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryBeaconStatusTelemetry {
+    pub header: TelemetryHeader,
+    pub destinations: Vec<BeaconDestinationStatus>,
+}
+
+impl QueryBeaconStatusTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, destinations: Vec<BeaconDestinationStatus>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::QueryBeaconStatus,
+                status,
+                request_id: None,
+            },
+            destinations,
+        }
+    }
+}
+
+/// HELLO telemetry response, naming the framing TCSpecial chose
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HelloTelemetry {
+    pub header: TelemetryHeader,
+    pub framing: Framing,
+}
+
+impl HelloTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, framing: Framing) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::Hello,
+                status,
+                request_id: None,
+            },
+            framing,
+        }
+    }
+}
+
+/// SET_TIME telemetry response
+///
+/// The clock read previous just before it was set to applied, so the ground
+/// can work out the correction made.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SetTimeTelemetry {
+    pub header: TelemetryHeader,
+    pub applied: Timestamp,
+    pub previous: Timestamp,
+}
+
+impl SetTimeTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, applied: Timestamp, previous: Timestamp) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::SetTime,
+                status,
+                request_id: None,
+            },
+            applied,
+            previous,
+        }
+    }
+}
+
+/// SET_LOG_LEVEL telemetry response, with the level that was replaced
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SetLogLevelTelemetry {
+    pub header: TelemetryHeader,
+    pub level: LogLevel,
+    pub previous: LogLevel,
+}
+
+impl SetLogLevelTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, level: LogLevel, previous: LogLevel) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::SetLogLevel,
+                status,
+                request_id: None,
+            },
+            level,
+            previous,
+        }
+    }
+}
+
+/// START_DH telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StartDHTelemetry {
     pub header: TelemetryHeader,
+    /// What the command did, if it succeeded
+    #[serde(default)]
+    pub outcome: Option<StartDHOutcome>,
+    /// Port on the CI's address the data handler takes uplink on and sends
+    /// downlink from, if it is running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oc_port: Option<Port>,
+    /// Kind of error the command failed with, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorCode>,
+    /// Why the command failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 impl StartDHTelemetry {
@@ -130,15 +423,47 @@ impl StartDHTelemetry {
                 sequence,
                 tm_type: TelemetryType::StartDH,
                 status,
+                request_id: None,
             },
+            outcome: None,
+            oc_port: None,
+            error: None,
+            detail: None,
         }
     }
+
+    /// Add what the command did
+    pub fn with_outcome(mut self, outcome: StartDHOutcome) -> Self {
+        self.outcome = Some(outcome);
+        self
+    }
+
+    /// Add the port the data handler's OC endpoint is bound to
+    pub fn with_oc_port(mut self, oc_port: Port) -> Self {
+        self.oc_port = Some(oc_port);
+        self
+    }
+
+    /// Add the kind of error the command failed with
+    pub fn with_error(mut self, error: ErrorCode) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Add why the command failed
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
 }
 
 /// STOP_DH telemetry response
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StopDHTelemetry {
     pub header: TelemetryHeader,
+    /// Why the command failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 impl StopDHTelemetry {
@@ -148,17 +473,40 @@ impl StopDHTelemetry {
                 sequence,
                 tm_type: TelemetryType::StopDH,
                 status,
+                request_id: None,
             },
+            detail: None,
         }
     }
+
+    /// Add why the command failed
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
 }
 
 /// QUERY_DH telemetry response
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct QueryDHTelemetry {
     pub header: TelemetryHeader,
     pub dh_id: DHId,
     pub statistics: Statistics,
+    /// Set when dh_id does not name any data handler
+    #[serde(default)]
+    pub no_such_handler: bool,
+    /// Valid DH ids, if the CI is configured to report them
+    #[serde(default)]
+    pub valid_ids: Option<Vec<DHId>>,
+    /// State of the data handler, if it exists
+    #[serde(default)]
+    pub state: Option<DHState>,
+    /// Write latency, if the data handler tracks it
+    #[serde(default)]
+    pub write_latency: Option<WriteLatency>,
+    /// Change in statistics since the last CHECKPOINT_DH, if there was one
+    #[serde(default)]
+    pub since_checkpoint: Option<Statistics>,
 }
 
 impl QueryDHTelemetry {
@@ -168,9 +516,332 @@ impl QueryDHTelemetry {
                 sequence,
                 tm_type: TelemetryType::QueryDH,
                 status,
+                request_id: None,
             },
             dh_id,
             statistics,
+            no_such_handler: false,
+            valid_ids: None,
+            state: None,
+            write_latency: None,
+            since_checkpoint: None,
+        }
+    }
+
+    /// Add the state of the data handler
+    pub fn with_state(mut self, state: DHState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Add the write latency of the data handler
+    pub fn with_write_latency(mut self, write_latency: Option<WriteLatency>) -> Self {
+        self.write_latency = write_latency;
+        self
+    }
+
+    /// Add the change in statistics since the last checkpoint
+    pub fn with_since_checkpoint(mut self, since_checkpoint: Option<Statistics>) -> Self {
+        self.since_checkpoint = since_checkpoint;
+        self
+    }
+
+    /// Response for a QUERY_DH naming a data handler that does not exist
+    pub fn not_found(sequence: u32, dh_id: DHId, valid_ids: Option<Vec<DHId>>) -> Self {
+        Self {
+            no_such_handler: true,
+            valid_ids,
+            ..Self::new(sequence, CommandStatus::NotFound, dh_id, Statistics::new())
+        }
+    }
+}
+
+/// Statistics for a single data handler within a STATS_SNAPSHOT
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHStatistics {
+    pub dh_id: DHId,
+    pub statistics: Statistics,
+}
+
+/// STATS_SNAPSHOT telemetry response
+///
+/// All statistics carry the same capture timestamp so the ground sees a
+/// single point-in-time view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatsSnapshotTelemetry {
+    pub header: TelemetryHeader,
+    pub timestamp: Timestamp,
+    /// The data handlers' statistics added together
+    pub global: Statistics,
+    pub data_handlers: Vec<DHStatistics>,
+}
+
+impl StatsSnapshotTelemetry {
+    pub fn new(
+        sequence: u32,
+        status: CommandStatus,
+        timestamp: Timestamp,
+        global: Statistics,
+        data_handlers: Vec<DHStatistics>,
+    ) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::StatsSnapshot,
+                status,
+                request_id: None,
+            },
+            timestamp,
+            global,
+            data_handlers,
+        }
+    }
+}
+
+/// DH_LOOPBACK telemetry response
+///
+/// The status is TIMEOUT if the payload did not echo the token in time,
+/// including when it sent back something else.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHLoopbackTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+    pub token: u64,
+    /// Round-trip time through the payload, if the token came back
+    pub rtt_us: Option<u64>,
+}
+
+impl DHLoopbackTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId, token: u64, rtt_us: Option<u64>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::DHLoopback,
+                status,
+                request_id: None,
+            },
+            dh_id,
+            token,
+            rtt_us,
+        }
+    }
+}
+
+/// SUBSCRIBE_DH_STATS telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SubscribeDHStatsTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+}
+
+impl SubscribeDHStatsTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::SubscribeDHStats,
+                status,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// UNSUBSCRIBE_DH_STATS telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnsubscribeDHStatsTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+}
+
+impl UnsubscribeDHStatsTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::UnsubscribeDHStats,
+                status,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// PAUSE_ALL_DH telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PauseAllDHTelemetry {
+    pub header: TelemetryHeader,
+    /// Data handlers now paused, whether by this command or earlier
+    pub paused: u32,
+}
+
+impl PauseAllDHTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, paused: u32) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::PauseAllDH,
+                status,
+                request_id: None,
+            },
+            paused,
+        }
+    }
+}
+
+/// RESUME_ALL_DH telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeAllDHTelemetry {
+    pub header: TelemetryHeader,
+    /// Data handlers now active, whether by this command or earlier
+    pub resumed: u32,
+}
+
+impl ResumeAllDHTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, resumed: u32) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::ResumeAllDH,
+                status,
+                request_id: None,
+            },
+            resumed,
+        }
+    }
+}
+
+/// RESET_STATS telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResetStatsTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+}
+
+impl ResetStatsTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::ResetStats,
+                status,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// A data handler within a LIST_DH
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHListEntry {
+    pub dh_id: DHId,
+    pub dh_type: DHType,
+    pub name: DHName,
+    pub state: DHState,
+}
+
+/// LIST_DH telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListDHTelemetry {
+    pub header: TelemetryHeader,
+    /// Data handlers in id order
+    pub handlers: Vec<DHListEntry>,
+}
+
+impl ListDHTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, handlers: Vec<DHListEntry>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::ListDH,
+                status,
+                request_id: None,
+            },
+            handlers,
+        }
+    }
+}
+
+/// CHECKPOINT_DH telemetry response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CheckpointDHTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+}
+
+impl CheckpointDHTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::CheckpointDH,
+                status,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// DH_CONTROL telemetry response
+///
+/// The status is SUCCESS once the DH's relay has written the bytes to the
+/// payload. It is TIMEOUT if the relay didn't get to them in time, in which
+/// case none were written, and FAILURE if the payload would not take them
+/// all, in which case some may have been.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHControlTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+}
+
+impl DHControlTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::DHControl,
+                status,
+                request_id: None,
+            },
+            dh_id,
+        }
+    }
+}
+
+/// A running data handler within a QUERY_ACTIVITY
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHActivity {
+    pub dh_id: DHId,
+    /// Data moved within the window asked about
+    pub active: bool,
+    /// Milliseconds since data last moved, if it ever has
+    pub idle_ms: Option<u64>,
+}
+
+/// QUERY_ACTIVITY telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryActivityTelemetry {
+    pub header: TelemetryHeader,
+    pub window_ms: u32,
+    /// Running data handlers in id order
+    pub handlers: Vec<DHActivity>,
+}
+
+impl QueryActivityTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, window_ms: u32, handlers: Vec<DHActivity>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::QueryActivity,
+                status,
+                request_id: None,
+            },
+            window_ms,
+            handlers,
         }
     }
 }
@@ -179,6 +850,9 @@ impl QueryDHTelemetry {
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ConfigTelemetry {
     pub header: TelemetryHeader,
+    /// Beacon interval in effect after the command, if it was applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon_interval: Option<BeaconTime>,
 }
 
 impl ConfigTelemetry {
@@ -188,9 +862,17 @@ impl ConfigTelemetry {
                 sequence,
                 tm_type: TelemetryType::Config,
                 status,
+                request_id: None,
             },
+            beacon_interval: None,
         }
     }
+
+    /// Add the beacon interval now in effect
+    pub fn with_beacon_interval(mut self, beacon_interval: BeaconTime) -> Self {
+        self.beacon_interval = Some(beacon_interval);
+        self
+    }
 }
 
 /// CONFIG_DH telemetry response
@@ -206,29 +888,193 @@ impl ConfigDHTelemetry {
                 sequence,
                 tm_type: TelemetryType::ConfigDH,
                 status,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// RECONFIGURE_DH telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReconfigureDHTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+    /// Why the command failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ReconfigureDHTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus, dh_id: DHId) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::ReconfigureDH,
+                status,
+                request_id: None,
+            },
+            dh_id,
+            detail: None,
+        }
+    }
+
+    /// Add why the command failed
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// RELOAD_CONFIG telemetry response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReloadConfigTelemetry {
+    pub header: TelemetryHeader,
+    /// What the reload did, if the configuration could be read
+    #[serde(default)]
+    pub summary: Option<ReloadSummary>,
+}
+
+impl ReloadConfigTelemetry {
+    pub fn new(sequence: u32, status: CommandStatus) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::ReloadConfig,
+                status,
+                request_id: None,
+            },
+            summary: None,
+        }
+    }
+
+    /// Add what the reload did
+    pub fn with_summary(mut self, summary: ReloadSummary) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+}
+
+/// INVALID_COMMAND telemetry, sent in reply to a command TCSpecial could
+/// not decode
+///
+/// The sequence number is always 0, since it could not be read from the
+/// command either.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InvalidCommandTelemetry {
+    pub header: TelemetryHeader,
+    /// Why the command could not be decoded
+    pub reason: String,
+}
+
+impl InvalidCommandTelemetry {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence: 0,
+                tm_type: TelemetryType::InvalidCommand,
+                status: CommandStatus::InvalidCommand,
+                request_id: None,
             },
+            reason: reason.into(),
         }
     }
 }
 
+/// Spacecraft health carried in extended beacons
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BeaconHealth {
+    /// Data handlers currently relaying data
+    pub active_dh_count: u32,
+    /// Seconds since TCSpecial started
+    pub uptime_secs: u64,
+    /// Statistics summed over all data handlers
+    pub statistics: Statistics,
+}
+
 /// BEACON asynchronous telemetry
+///
+/// The optional fields are only present in the extended beacon format and
+/// are omitted entirely from the legacy format.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub struct BeaconTelemetry {
     pub header: TelemetryHeader,
     pub timestamp: Timestamp,
+    /// Count of beacons sent since startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon_sequence: Option<u32>,
+    /// Identifier of the sending node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+    /// Current beacon interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<BeaconTime>,
+    /// Why TCSpecial started, sent in the first beacon only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_reason: Option<StartReason>,
+    /// Milliseconds since startup, sent with start_reason
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_ms: Option<u64>,
+    /// Spacecraft health, if gathered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<BeaconHealth>,
 }
 
 impl BeaconTelemetry {
+    /// Create a legacy format beacon
     pub fn new() -> Self {
         Self {
             header: TelemetryHeader {
                 sequence: 0,
                 tm_type: TelemetryType::Beacon,
                 status: CommandStatus::Success,
+                request_id: None,
             },
             timestamp: Timestamp::now(),
+            beacon_sequence: None,
+            node_id: None,
+            interval: None,
+            start_reason: None,
+            uptime_ms: None,
+            health: None,
+        }
+    }
+
+    /// Create an extended format beacon
+    pub fn extended(beacon_sequence: u32, node_id: u32, interval: BeaconTime) -> Self {
+        Self {
+            beacon_sequence: Some(beacon_sequence),
+            node_id: Some(node_id),
+            interval: Some(interval),
+            ..Self::new()
         }
     }
+
+    /// Create a beacon in the given format
+    pub fn with_format(format: BeaconFormat, beacon_sequence: u32, node_id: u32, interval: BeaconTime) -> Self {
+        match format {
+            BeaconFormat::Legacy => Self::new(),
+            BeaconFormat::Extended => Self::extended(beacon_sequence, node_id, interval),
+        }
+    }
+
+    /// Add the reason for and time since startup
+    pub fn with_start(mut self, reason: StartReason, uptime_ms: u64) -> Self {
+        self.start_reason = Some(reason);
+        self.uptime_ms = Some(uptime_ms);
+        self
+    }
+
+    /// Replace the host time with the spacecraft clock's
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Add the spacecraft health
+    pub fn with_health(mut self, health: BeaconHealth) -> Self {
+        self.health = Some(health);
+        self
+    }
 }
 
 impl Default for BeaconTelemetry {
@@ -237,18 +1083,80 @@ impl Default for BeaconTelemetry {
     }
 }
 
+/// Change in a data handler's condition reported by a DH_EVENT
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DHEventKind {
+    /// The payload endpoint is open and relaying
+    Connected,
+    /// The payload closed its end of the connection
+    Disconnected,
+    /// The data handler stopped
+    Stopped,
+    /// The data handler faulted with the given kind of error
+    Error(ErrorCode),
+}
+
+/// DH_EVENT asynchronous telemetry
+///
+/// Sent unprompted when a data handler's condition changes. The sequence
+/// number counts events rather than answering a command, so gaps show the
+/// ground that events were lost.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DHEventTelemetry {
+    pub header: TelemetryHeader,
+    pub dh_id: DHId,
+    pub event: DHEventKind,
+}
+
+impl DHEventTelemetry {
+    pub fn new(sequence: u32, dh_id: DHId, event: DHEventKind) -> Self {
+        Self {
+            header: TelemetryHeader {
+                sequence,
+                tm_type: TelemetryType::DHEvent,
+                status: CommandStatus::Success,
+                request_id: None,
+            },
+            dh_id,
+            event,
+        }
+    }
+}
+
 /// Union of all telemetry types
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Telemetry {
     Ping(PingTelemetry),
     RestartArm(RestartArmTelemetry),
     Restart(RestartTelemetry),
+    QueryDropped(QueryDroppedTelemetry),
+    QueryEndpointSupport(QueryEndpointSupportTelemetry),
+    InjectFault(InjectFaultTelemetry),
+    QueryBeaconStatus(QueryBeaconStatusTelemetry),
+    Hello(HelloTelemetry),
+    SetTime(SetTimeTelemetry),
+    SetLogLevel(SetLogLevelTelemetry),
     StartDH(StartDHTelemetry),
     StopDH(StopDHTelemetry),
     QueryDH(QueryDHTelemetry),
+    StatsSnapshot(StatsSnapshotTelemetry),
+    DHLoopback(DHLoopbackTelemetry),
+    SubscribeDHStats(SubscribeDHStatsTelemetry),
+    UnsubscribeDHStats(UnsubscribeDHStatsTelemetry),
+    PauseAllDH(PauseAllDHTelemetry),
+    ResumeAllDH(ResumeAllDHTelemetry),
+    ResetStats(ResetStatsTelemetry),
+    ListDH(ListDHTelemetry),
+    CheckpointDH(CheckpointDHTelemetry),
+    DHControl(DHControlTelemetry),
+    QueryActivity(QueryActivityTelemetry),
     Config(ConfigTelemetry),
     ConfigDH(ConfigDHTelemetry),
+    ReconfigureDH(ReconfigureDHTelemetry),
+    ReloadConfig(ReloadConfigTelemetry),
     Beacon(BeaconTelemetry),
+    InvalidCommand(InvalidCommandTelemetry),
+    DHEvent(DHEventTelemetry),
 }
 
 impl Telemetry {
@@ -257,12 +1165,34 @@ impl Telemetry {
             Telemetry::Ping(tm) => tm.header.sequence,
             Telemetry::RestartArm(tm) => tm.header.sequence,
             Telemetry::Restart(tm) => tm.header.sequence,
+            Telemetry::QueryDropped(tm) => tm.header.sequence,
+            Telemetry::QueryEndpointSupport(tm) => tm.header.sequence,
+            Telemetry::InjectFault(tm) => tm.header.sequence,
+            Telemetry::QueryBeaconStatus(tm) => tm.header.sequence,
+            Telemetry::Hello(tm) => tm.header.sequence,
+            Telemetry::SetTime(tm) => tm.header.sequence,
+            Telemetry::SetLogLevel(tm) => tm.header.sequence,
             Telemetry::StartDH(tm) => tm.header.sequence,
             Telemetry::StopDH(tm) => tm.header.sequence,
             Telemetry::QueryDH(tm) => tm.header.sequence,
+            Telemetry::StatsSnapshot(tm) => tm.header.sequence,
+            Telemetry::DHLoopback(tm) => tm.header.sequence,
+            Telemetry::SubscribeDHStats(tm) => tm.header.sequence,
+            Telemetry::UnsubscribeDHStats(tm) => tm.header.sequence,
+            Telemetry::PauseAllDH(tm) => tm.header.sequence,
+            Telemetry::ResumeAllDH(tm) => tm.header.sequence,
+            Telemetry::ResetStats(tm) => tm.header.sequence,
+            Telemetry::ListDH(tm) => tm.header.sequence,
+            Telemetry::CheckpointDH(tm) => tm.header.sequence,
+            Telemetry::DHControl(tm) => tm.header.sequence,
+            Telemetry::QueryActivity(tm) => tm.header.sequence,
             Telemetry::Config(tm) => tm.header.sequence,
             Telemetry::ConfigDH(tm) => tm.header.sequence,
+            Telemetry::ReconfigureDH(tm) => tm.header.sequence,
+            Telemetry::ReloadConfig(tm) => tm.header.sequence,
             Telemetry::Beacon(tm) => tm.header.sequence,
+            Telemetry::InvalidCommand(tm) => tm.header.sequence,
+            Telemetry::DHEvent(tm) => tm.header.sequence,
         }
     }
 
@@ -271,12 +1201,34 @@ impl Telemetry {
             Telemetry::Ping(tm) => tm.header.tm_type,
             Telemetry::RestartArm(tm) => tm.header.tm_type,
             Telemetry::Restart(tm) => tm.header.tm_type,
+            Telemetry::QueryDropped(tm) => tm.header.tm_type,
+            Telemetry::QueryEndpointSupport(tm) => tm.header.tm_type,
+            Telemetry::InjectFault(tm) => tm.header.tm_type,
+            Telemetry::QueryBeaconStatus(tm) => tm.header.tm_type,
+            Telemetry::Hello(tm) => tm.header.tm_type,
+            Telemetry::SetTime(tm) => tm.header.tm_type,
+            Telemetry::SetLogLevel(tm) => tm.header.tm_type,
             Telemetry::StartDH(tm) => tm.header.tm_type,
             Telemetry::StopDH(tm) => tm.header.tm_type,
             Telemetry::QueryDH(tm) => tm.header.tm_type,
+            Telemetry::StatsSnapshot(tm) => tm.header.tm_type,
+            Telemetry::DHLoopback(tm) => tm.header.tm_type,
+            Telemetry::SubscribeDHStats(tm) => tm.header.tm_type,
+            Telemetry::UnsubscribeDHStats(tm) => tm.header.tm_type,
+            Telemetry::PauseAllDH(tm) => tm.header.tm_type,
+            Telemetry::ResumeAllDH(tm) => tm.header.tm_type,
+            Telemetry::ResetStats(tm) => tm.header.tm_type,
+            Telemetry::ListDH(tm) => tm.header.tm_type,
+            Telemetry::CheckpointDH(tm) => tm.header.tm_type,
+            Telemetry::DHControl(tm) => tm.header.tm_type,
+            Telemetry::QueryActivity(tm) => tm.header.tm_type,
             Telemetry::Config(tm) => tm.header.tm_type,
             Telemetry::ConfigDH(tm) => tm.header.tm_type,
+            Telemetry::ReconfigureDH(tm) => tm.header.tm_type,
+            Telemetry::ReloadConfig(tm) => tm.header.tm_type,
             Telemetry::Beacon(tm) => tm.header.tm_type,
+            Telemetry::InvalidCommand(tm) => tm.header.tm_type,
+            Telemetry::DHEvent(tm) => tm.header.tm_type,
         }
     }
 
@@ -285,12 +1237,136 @@ impl Telemetry {
             Telemetry::Ping(tm) => tm.header.status,
             Telemetry::RestartArm(tm) => tm.header.status,
             Telemetry::Restart(tm) => tm.header.status,
+            Telemetry::QueryDropped(tm) => tm.header.status,
+            Telemetry::QueryEndpointSupport(tm) => tm.header.status,
+            Telemetry::InjectFault(tm) => tm.header.status,
+            Telemetry::QueryBeaconStatus(tm) => tm.header.status,
+            Telemetry::Hello(tm) => tm.header.status,
+            Telemetry::SetTime(tm) => tm.header.status,
+            Telemetry::SetLogLevel(tm) => tm.header.status,
             Telemetry::StartDH(tm) => tm.header.status,
             Telemetry::StopDH(tm) => tm.header.status,
             Telemetry::QueryDH(tm) => tm.header.status,
+            Telemetry::StatsSnapshot(tm) => tm.header.status,
+            Telemetry::DHLoopback(tm) => tm.header.status,
+            Telemetry::SubscribeDHStats(tm) => tm.header.status,
+            Telemetry::UnsubscribeDHStats(tm) => tm.header.status,
+            Telemetry::PauseAllDH(tm) => tm.header.status,
+            Telemetry::ResumeAllDH(tm) => tm.header.status,
+            Telemetry::ResetStats(tm) => tm.header.status,
+            Telemetry::ListDH(tm) => tm.header.status,
+            Telemetry::CheckpointDH(tm) => tm.header.status,
+            Telemetry::DHControl(tm) => tm.header.status,
+            Telemetry::QueryActivity(tm) => tm.header.status,
             Telemetry::Config(tm) => tm.header.status,
             Telemetry::ConfigDH(tm) => tm.header.status,
+            Telemetry::ReconfigureDH(tm) => tm.header.status,
+            Telemetry::ReloadConfig(tm) => tm.header.status,
             Telemetry::Beacon(tm) => tm.header.status,
+            Telemetry::InvalidCommand(tm) => tm.header.status,
+            Telemetry::DHEvent(tm) => tm.header.status,
+        }
+    }
+
+    /// Get the correlation id echoed from the command, if any
+    pub fn request_id(&self) -> Option<u64> {
+        match self {
+            Telemetry::Ping(tm) => tm.header.request_id,
+            Telemetry::RestartArm(tm) => tm.header.request_id,
+            Telemetry::Restart(tm) => tm.header.request_id,
+            Telemetry::QueryDropped(tm) => tm.header.request_id,
+            Telemetry::QueryEndpointSupport(tm) => tm.header.request_id,
+            Telemetry::InjectFault(tm) => tm.header.request_id,
+            Telemetry::QueryBeaconStatus(tm) => tm.header.request_id,
+            Telemetry::Hello(tm) => tm.header.request_id,
+            Telemetry::SetTime(tm) => tm.header.request_id,
+            Telemetry::SetLogLevel(tm) => tm.header.request_id,
+            Telemetry::StartDH(tm) => tm.header.request_id,
+            Telemetry::StopDH(tm) => tm.header.request_id,
+            Telemetry::QueryDH(tm) => tm.header.request_id,
+            Telemetry::StatsSnapshot(tm) => tm.header.request_id,
+            Telemetry::DHLoopback(tm) => tm.header.request_id,
+            Telemetry::SubscribeDHStats(tm) => tm.header.request_id,
+            Telemetry::UnsubscribeDHStats(tm) => tm.header.request_id,
+            Telemetry::PauseAllDH(tm) => tm.header.request_id,
+            Telemetry::ResumeAllDH(tm) => tm.header.request_id,
+            Telemetry::ResetStats(tm) => tm.header.request_id,
+            Telemetry::ListDH(tm) => tm.header.request_id,
+            Telemetry::CheckpointDH(tm) => tm.header.request_id,
+            Telemetry::DHControl(tm) => tm.header.request_id,
+            Telemetry::QueryActivity(tm) => tm.header.request_id,
+            Telemetry::Config(tm) => tm.header.request_id,
+            Telemetry::ConfigDH(tm) => tm.header.request_id,
+            Telemetry::ReconfigureDH(tm) => tm.header.request_id,
+            Telemetry::ReloadConfig(tm) => tm.header.request_id,
+            Telemetry::Beacon(tm) => tm.header.request_id,
+            Telemetry::InvalidCommand(tm) => tm.header.request_id,
+            Telemetry::DHEvent(tm) => tm.header.request_id,
+        }
+    }
+
+    /// Get why the command failed, for telemetry that can say
+    pub fn detail(&self) -> Option<&str> {
+        match self {
+            Telemetry::StartDH(tm) => tm.detail.as_deref(),
+            Telemetry::StopDH(tm) => tm.detail.as_deref(),
+            Telemetry::ReconfigureDH(tm) => tm.detail.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// True for telemetry sent unprompted rather than in reply to a command
+    pub fn is_unsolicited(&self) -> bool {
+        matches!(self, Telemetry::Beacon(_) | Telemetry::DHEvent(_))
+    }
+
+    /// Get the statistics carried by the telemetry, if any
+    ///
+    /// Snapshots give the total over their data handlers and beacons the
+    /// totals from their health.
+    pub fn statistics(&self) -> Option<&Statistics> {
+        match self {
+            Telemetry::QueryDH(tm) => Some(&tm.statistics),
+            Telemetry::StatsSnapshot(tm) => Some(&tm.global),
+            Telemetry::Beacon(tm) => tm.health.as_ref().map(|health| &health.statistics),
+            _ => None,
+        }
+    }
+
+    /// Echo the correlation id of the command being answered
+    pub fn set_request_id(&mut self, request_id: Option<u64>) {
+        match self {
+            Telemetry::Ping(tm) => tm.header.request_id = request_id,
+            Telemetry::RestartArm(tm) => tm.header.request_id = request_id,
+            Telemetry::Restart(tm) => tm.header.request_id = request_id,
+            Telemetry::QueryDropped(tm) => tm.header.request_id = request_id,
+            Telemetry::QueryEndpointSupport(tm) => tm.header.request_id = request_id,
+            Telemetry::InjectFault(tm) => tm.header.request_id = request_id,
+            Telemetry::QueryBeaconStatus(tm) => tm.header.request_id = request_id,
+            Telemetry::Hello(tm) => tm.header.request_id = request_id,
+            Telemetry::SetTime(tm) => tm.header.request_id = request_id,
+            Telemetry::SetLogLevel(tm) => tm.header.request_id = request_id,
+            Telemetry::StartDH(tm) => tm.header.request_id = request_id,
+            Telemetry::StopDH(tm) => tm.header.request_id = request_id,
+            Telemetry::QueryDH(tm) => tm.header.request_id = request_id,
+            Telemetry::StatsSnapshot(tm) => tm.header.request_id = request_id,
+            Telemetry::DHLoopback(tm) => tm.header.request_id = request_id,
+            Telemetry::SubscribeDHStats(tm) => tm.header.request_id = request_id,
+            Telemetry::UnsubscribeDHStats(tm) => tm.header.request_id = request_id,
+            Telemetry::PauseAllDH(tm) => tm.header.request_id = request_id,
+            Telemetry::ResumeAllDH(tm) => tm.header.request_id = request_id,
+            Telemetry::ResetStats(tm) => tm.header.request_id = request_id,
+            Telemetry::ListDH(tm) => tm.header.request_id = request_id,
+            Telemetry::CheckpointDH(tm) => tm.header.request_id = request_id,
+            Telemetry::DHControl(tm) => tm.header.request_id = request_id,
+            Telemetry::QueryActivity(tm) => tm.header.request_id = request_id,
+            Telemetry::Config(tm) => tm.header.request_id = request_id,
+            Telemetry::ConfigDH(tm) => tm.header.request_id = request_id,
+            Telemetry::ReconfigureDH(tm) => tm.header.request_id = request_id,
+            Telemetry::ReloadConfig(tm) => tm.header.request_id = request_id,
+            Telemetry::Beacon(tm) => tm.header.request_id = request_id,
+            Telemetry::InvalidCommand(tm) => tm.header.request_id = request_id,
+            Telemetry::DHEvent(tm) => tm.header.request_id = request_id,
         }
     }
 }
@@ -319,6 +1395,78 @@ mod tests {
         assert_eq!(tm.header.tm_type, TelemetryType::Beacon);
     }
 
+    #[test]
+    fn test_beacon_formats() {
+        let legacy = serde_json::to_value(BeaconTelemetry::with_format(
+            BeaconFormat::Legacy, 3, 7, BeaconTime(1000))).unwrap();
+        let mut keys: Vec<&String> = legacy.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["header", "timestamp"]);
+
+        let extended = BeaconTelemetry::with_format(BeaconFormat::Extended, 3, 7, BeaconTime(1000));
+        let json = serde_json::to_string(&extended).unwrap();
+        let deserialized: BeaconTelemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.beacon_sequence, Some(3));
+        assert_eq!(deserialized.node_id, Some(7));
+        assert_eq!(deserialized.interval, Some(BeaconTime(1000)));
+        assert_eq!(deserialized.start_reason, None);
+
+        let first = BeaconTelemetry::new().with_start(StartReason::CommandedRestart, 12);
+        let json = serde_json::to_string(&first).unwrap();
+        let deserialized: BeaconTelemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.start_reason, Some(StartReason::CommandedRestart));
+        assert_eq!(deserialized.uptime_ms, Some(12));
+    }
+
+    #[test]
+    fn test_beacon_health_serialization() {
+        let health = BeaconHealth {
+            active_dh_count: 2,
+            uptime_secs: 86_400,
+            statistics: Statistics { bytes_sent: 1000, bytes_received: 24, ..Statistics::new() },
+        };
+        let beacon = BeaconTelemetry::extended(5, 1, BeaconTime(1000)).with_health(health);
+        let json = serde_json::to_string(&Telemetry::Beacon(beacon)).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            Telemetry::Beacon(deserialized) => assert_eq!(deserialized.health, Some(health)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        // A spacecraft with no data handlers reports zeros rather than nothing
+        let beacon = BeaconTelemetry::extended(6, 1, BeaconTime(1000)).with_health(BeaconHealth::default());
+        let deserialized: BeaconTelemetry = serde_json::from_str(&serde_json::to_string(&beacon).unwrap()).unwrap();
+        assert_eq!(deserialized.health, Some(BeaconHealth::default()));
+
+        // Beacons without health, such as legacy ones, read back without it
+        let deserialized: BeaconTelemetry =
+            serde_json::from_str(&serde_json::to_string(&BeaconTelemetry::new()).unwrap()).unwrap();
+        assert_eq!(deserialized.health, None);
+    }
+
+    #[test]
+    fn test_dh_event_serialization() {
+        for event in [DHEventKind::Connected, DHEventKind::Disconnected, DHEventKind::Stopped, DHEventKind::Error(ErrorCode::Io)] {
+            let tm = Telemetry::DHEvent(DHEventTelemetry::new(4, DHId(2), event));
+            assert!(tm.is_unsolicited());
+            let json = serde_json::to_string(&tm).unwrap();
+            assert_eq!(serde_json::from_str::<Telemetry>(&json).unwrap(), tm);
+        }
+        assert!(!Telemetry::Ping(PingTelemetry::new(4, CommandStatus::Success)).is_unsolicited());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let statistics = Statistics { bytes_sent: 10, messages_sent: 2, ..Statistics::new() };
+        let tm = Telemetry::QueryDH(QueryDHTelemetry::new(1, CommandStatus::Success, DHId(3), statistics));
+        assert_eq!(tm.statistics(), Some(&statistics));
+
+        let tm = Telemetry::StatsSnapshot(StatsSnapshotTelemetry::new(2, CommandStatus::Success, Timestamp::now(), statistics, vec![]));
+        assert_eq!(tm.statistics(), Some(&statistics));
+
+        assert_eq!(Telemetry::Ping(PingTelemetry::new(3, CommandStatus::Success)).statistics(), None);
+        assert_eq!(Telemetry::Beacon(BeaconTelemetry::new()).statistics(), None);
+    }
+
     #[test]
     fn test_telemetry_serialization() {
         let tm = Telemetry::Ping(PingTelemetry::new(42, CommandStatus::Success));
@@ -326,4 +1474,44 @@ mod tests {
         let deserialized: Telemetry = serde_json::from_str(&json).unwrap();
         assert_eq!(tm.sequence(), deserialized.sequence());
     }
+
+    #[test]
+    fn test_reset_stats_serialization() {
+        let tm = Telemetry::ResetStats(ResetStatsTelemetry::new(9, CommandStatus::NotFound, DHId(3)));
+        let json = serde_json::to_string(&tm).unwrap();
+        let deserialized: Telemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, tm);
+        assert_eq!(deserialized.tm_type(), TelemetryType::ResetStats);
+    }
+
+    #[test]
+    fn test_reload_config_serialization() {
+        let summary = ReloadSummary {
+            added: vec![DHId(2)],
+            removed: vec![DHId(0)],
+            ..ReloadSummary::default()
+        };
+        let tm = Telemetry::ReloadConfig(ReloadConfigTelemetry::new(4, CommandStatus::Success).with_summary(summary));
+        let json = serde_json::to_string(&tm).unwrap();
+        let deserialized: Telemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, tm);
+        assert_eq!(TelemetryType::from_u8(TelemetryType::ReloadConfig.to_u8()), Some(TelemetryType::ReloadConfig));
+    }
+
+    #[test]
+    fn test_detail_serialization() {
+        let tm = Telemetry::StartDH(
+            StartDHTelemetry::new(5, CommandStatus::NotFound).with_detail("Data handler not found: 7"),
+        );
+        let json = serde_json::to_string(&tm).unwrap();
+        let deserialized: Telemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.detail(), Some("Data handler not found: 7"));
+
+        // Telemetry without a detail leaves it out, and reads back without one
+        let tm = Telemetry::StopDH(StopDHTelemetry::new(6, CommandStatus::Success));
+        let json = serde_json::to_string(&tm).unwrap();
+        assert!(!json.contains("detail"), "{}", json);
+        let deserialized: Telemetry = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.detail(), None);
+    }
 }
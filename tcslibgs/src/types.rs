@@ -2,7 +2,15 @@
 
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{TcsError, TcsResult};
+use crate::pool::DEFAULT_POOL_CAPACITY;
+use crate::protocol::Framing;
 
 /// Timestamp type for spacecraft time
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -51,6 +59,68 @@ pub enum DHType {
     Device,
 }
 
+/// Data handler lifecycle state, as reported to the ground
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DHState {
+    /// Created but not activated
+    Created,
+    /// Active and conduiting data
+    Active,
+    /// Active but not currently conduiting data
+    Paused,
+    /// Stopped
+    Stopped,
+    /// Stopped because of an error
+    Faulted,
+}
+
+/// What a START_DH command did
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StartDHOutcome {
+    /// A new data handler was created
+    Created,
+    /// The data handler already existed and had not been stopped
+    AlreadyActive,
+    /// A stopped or faulted data handler was replaced by a new one
+    Reactivated,
+}
+
+/// Kind of error reported in telemetry
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ErrorCode {
+    Io,
+    Config,
+    Protocol,
+    Endpoint,
+    DataHandler,
+    Timeout,
+    Other,
+    /// The configuration names something this host doesn't have, such as a
+    /// local address to bind
+    InvalidConfiguration,
+}
+
+impl From<&TcsError> for ErrorCode {
+    fn from(error: &TcsError) -> Self {
+        match error {
+            TcsError::Io(_) | TcsError::ConnectionClosed => ErrorCode::Io,
+            TcsError::Config(_) => ErrorCode::Config,
+            TcsError::InvalidConfiguration(_) => ErrorCode::InvalidConfiguration,
+            TcsError::Json(_) | TcsError::Protocol(_) => ErrorCode::Protocol,
+            TcsError::Endpoint(_) => ErrorCode::Endpoint,
+            TcsError::DataHandler(_) | TcsError::DHNotFound(_) | TcsError::DHExists(_) => ErrorCode::DataHandler,
+            TcsError::Timeout => ErrorCode::Timeout,
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+impl fmt::Display for DHState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 /// Data handler name
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DHName(pub String);
@@ -59,6 +129,138 @@ impl DHName {
     pub fn new(name: impl Into<String>) -> Self {
         Self(name.into())
     }
+
+    /// Parse a name of the form host:port:protocol
+    pub fn address(&self) -> TcsResult<DHAddress> {
+        self.0.parse()
+    }
+
+    /// Infer the data handler type from the shape of the name
+    ///
+    /// Absolute paths are devices, and host:port or host:port:protocol names
+    /// are networks. Anything else, including Windows-style paths whose drive
+    /// letter looks like a host, is an error rather than a guess.
+    pub fn infer_type(&self) -> TcsResult<DHType> {
+        let name = self.0.as_str();
+        if name.starts_with('/') {
+            return Ok(DHType::Device);
+        }
+
+        let bytes = name.as_bytes();
+        let windows_path = name.contains('\\')
+            || (bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'/');
+        if windows_path {
+            return Err(TcsError::Config(format!(
+                "DH name '{}' looks like a Windows path; device paths must be absolute Unix paths", name
+            )));
+        }
+
+        // host:port, optionally followed by :protocol
+        let mut fields = name.rsplitn(2, ':');
+        let network = match (fields.next(), fields.next()) {
+            (Some(port), Some(host)) if !host.is_empty() && port.parse::<Port>().is_ok() => true,
+            _ => self.address().is_ok(),
+        };
+        if network {
+            Ok(DHType::Network)
+        } else {
+            Err(TcsError::Config(format!(
+                "Can't infer a DH type from name '{}'; use a path or host:port, or choose the type", name
+            )))
+        }
+    }
+}
+
+/// Network port number
+///
+/// Port 0 asks the OS to choose a port when binding, and is never a port
+/// that can be connected to. Port::new and parsing refuse it, so names and
+/// addresses given by operators can't carry it; Port::ANY is for binding.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Port(pub u16);
+
+impl Port {
+    /// Let the OS choose the port when binding
+    pub const ANY: Port = Port(0);
+
+    /// Create a port that can be connected to
+    pub fn new(port: u16) -> TcsResult<Self> {
+        Port(port).connectable()
+    }
+
+    /// Check the port can be connected to, rather than only bound
+    pub fn connectable(self) -> TcsResult<Self> {
+        if self.is_any() {
+            return Err(TcsError::Config("Port 0 can't be connected to".to_string()));
+        }
+        Ok(self)
+    }
+
+    /// Check whether the OS chooses the port
+    pub fn is_any(&self) -> bool {
+        *self == Self::ANY
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Port {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        let port = s.parse().map_err(|_| TcsError::Config(format!("Invalid port '{}'", s)))?;
+        Port::new(port)
+    }
+}
+
+/// Network address given by a DH name in host:port:protocol form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DHAddress {
+    pub host: String,
+    pub port: Port,
+    pub protocol: NetworkProtocol,
+}
+
+impl DHAddress {
+    pub fn to_network_config(&self) -> NetworkConfig {
+        NetworkConfig {
+            protocol: self.protocol,
+            address: self.host.clone(),
+            port: self.port,
+            udp_mode: UdpMode::default(),
+        }
+    }
+}
+
+impl FromStr for DHAddress {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        // Split from the right so IPv6 hosts may contain colons
+        let mut fields = s.rsplitn(3, ':');
+        let (protocol, port, host) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(protocol), Some(port), Some(host)) if !host.is_empty() => (protocol, port, host),
+            _ => {
+                return Err(TcsError::Config(format!(
+                    "DH name '{}' is not of the form host:port:protocol", s
+                )))
+            }
+        };
+
+        let port = port
+            .parse::<Port>()
+            .map_err(|_| TcsError::Config(format!("Invalid port '{}' in DH name '{}'", port, s)))?;
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            protocol: protocol.parse()?,
+        })
+    }
 }
 
 /// Arm key for restart commands
@@ -75,6 +277,65 @@ impl Default for BeaconTime {
     }
 }
 
+/// Format of BEACON telemetry
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BeaconFormat {
+    /// Header and timestamp only, understood by all ground software
+    #[default]
+    Legacy,
+    /// Legacy fields plus beacon sequence, node id and interval
+    Extended,
+}
+
+/// Why TCSpecial most recently started
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StartReason {
+    /// Power-on or any other uncommanded start
+    #[default]
+    ColdStart,
+    /// Start following a successful RESTART command
+    CommandedRestart,
+}
+
+/// Event that sends a beacon straight away rather than at the next interval
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BeaconTrigger {
+    /// A data handler faulted
+    DHFaulted,
+    /// A data handler lost its payload
+    DHDisconnected,
+    /// RESTART_ARM armed a restart
+    RestartArmed,
+}
+
+impl BeaconTrigger {
+    /// Configuration names of all triggers
+    pub const NAMES: [&'static str; 3] = ["dh_faulted", "dh_disconnected", "restart_armed"];
+}
+
+impl FromStr for BeaconTrigger {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "dh_faulted" => Ok(BeaconTrigger::DHFaulted),
+            "dh_disconnected" => Ok(BeaconTrigger::DHDisconnected),
+            "restart_armed" => Ok(BeaconTrigger::RestartArmed),
+            _ => Err(TcsError::Config(format!(
+                "Unknown beacon trigger '{}', expected one of: {}",
+                s,
+                Self::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+/// Offset of the first counter in encoded statistics
+const STATISTICS_COUNTERS_OFFSET: usize = 13;
+
+/// Size of statistics in the compact binary form
+pub const STATISTICS_ENCODED_SIZE: usize = STATISTICS_COUNTERS_OFFSET + 9 * 8;
+
 /// Statistics for data handler operations
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Statistics {
@@ -92,6 +353,43 @@ pub struct Statistics {
     pub writes_completed: u64,
     /// Number of failed write operations
     pub writes_failed: u64,
+    /// Number of messages (datagrams) received
+    #[serde(default)]
+    pub messages_received: u64,
+    /// Number of messages (datagrams) sent
+    #[serde(default)]
+    pub messages_sent: u64,
+    /// Time the data handler has been active, in milliseconds
+    #[serde(default)]
+    pub active_duration_ms: u64,
+}
+
+/// Distribution of how long a data handler's writes took, in microseconds
+///
+/// Percentiles are approximate, to within an eighth of the value.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WriteLatency {
+    /// Number of writes timed
+    pub samples: u64,
+    pub min_us: u64,
+    pub max_us: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+}
+
+/// What reloading the payload configuration did to the data handlers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReloadSummary {
+    /// Data handlers new to the configuration
+    pub added: Vec<DHId>,
+    /// Data handlers no longer in the configuration, now stopped
+    pub removed: Vec<DHId>,
+    /// Data handlers whose configuration changed
+    pub reconfigured: Vec<DHId>,
+    /// Data handlers left as they were
+    pub unchanged: Vec<DHId>,
+    /// Data handlers whose change could not be applied
+    pub failed: Vec<DHId>,
 }
 
 impl Statistics {
@@ -99,10 +397,115 @@ impl Statistics {
         Self::default()
     }
 
-    pub fn with_timestamp(mut self) -> Self {
-        self.timestamp = Some(Timestamp::now());
+    /// Record when the statistics were taken
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
         self
     }
+
+    /// Add the counters from another set of statistics to this one
+    pub fn accumulate(&mut self, other: &Statistics) {
+        self.bytes_received += other.bytes_received;
+        self.reads_completed += other.reads_completed;
+        self.reads_failed += other.reads_failed;
+        self.bytes_sent += other.bytes_sent;
+        self.writes_completed += other.writes_completed;
+        self.writes_failed += other.writes_failed;
+        self.messages_received += other.messages_received;
+        self.messages_sent += other.messages_sent;
+    }
+
+    /// Get the change in the counters since earlier statistics
+    ///
+    /// Counters that went backwards, as after a reset, read as zero. The
+    /// timestamp is this one's.
+    pub fn diff(&self, earlier: &Statistics) -> Statistics {
+        Statistics {
+            timestamp: self.timestamp,
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+            reads_completed: self.reads_completed.saturating_sub(earlier.reads_completed),
+            reads_failed: self.reads_failed.saturating_sub(earlier.reads_failed),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            writes_completed: self.writes_completed.saturating_sub(earlier.writes_completed),
+            writes_failed: self.writes_failed.saturating_sub(earlier.writes_failed),
+            messages_received: self.messages_received.saturating_sub(earlier.messages_received),
+            messages_sent: self.messages_sent.saturating_sub(earlier.messages_sent),
+            active_duration_ms: self.active_duration_ms.saturating_sub(earlier.active_duration_ms),
+        }
+    }
+
+    /// Counters in their encoded order
+    fn counters(&self) -> [u64; 9] {
+        [
+            self.bytes_received,
+            self.reads_completed,
+            self.reads_failed,
+            self.bytes_sent,
+            self.writes_completed,
+            self.writes_failed,
+            self.messages_received,
+            self.messages_sent,
+            self.active_duration_ms,
+        ]
+    }
+
+    /// Encode in the compact fixed-size binary form
+    ///
+    /// The layout is a timestamp-present flag byte, the timestamp seconds
+    /// (u64) and nanoseconds (u32), zero if absent, then each counter as a
+    /// u64. All values are big-endian.
+    pub fn encode(&self) -> [u8; STATISTICS_ENCODED_SIZE] {
+        let mut bytes = [0u8; STATISTICS_ENCODED_SIZE];
+        if let Some(timestamp) = self.timestamp {
+            bytes[0] = 1;
+            bytes[1..9].copy_from_slice(&timestamp.seconds.to_be_bytes());
+            bytes[9..13].copy_from_slice(&timestamp.nanoseconds.to_be_bytes());
+        }
+        for (i, counter) in self.counters().iter().enumerate() {
+            let offset = STATISTICS_COUNTERS_OFFSET + i * 8;
+            bytes[offset..offset + 8].copy_from_slice(&counter.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Decode statistics encoded by encode()
+    pub fn decode(bytes: &[u8]) -> TcsResult<Self> {
+        if bytes.len() != STATISTICS_ENCODED_SIZE {
+            return Err(TcsError::Protocol(format!(
+                "Encoded statistics are {} bytes, expected {}",
+                bytes.len(),
+                STATISTICS_ENCODED_SIZE
+            )));
+        }
+
+        let timestamp = match bytes[0] {
+            0 => None,
+            1 => Some(Timestamp {
+                seconds: u64::from_be_bytes(bytes[1..9].try_into().unwrap()),
+                nanoseconds: u32::from_be_bytes(bytes[9..13].try_into().unwrap()),
+            }),
+            flag => return Err(TcsError::Protocol(format!("Invalid statistics timestamp flag {}", flag))),
+        };
+
+        let mut counters = [0u64; 9];
+        for (i, counter) in counters.iter_mut().enumerate() {
+            let offset = STATISTICS_COUNTERS_OFFSET + i * 8;
+            *counter = u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        }
+
+        Ok(Self {
+            timestamp,
+            bytes_received: counters[0],
+            reads_completed: counters[1],
+            reads_failed: counters[2],
+            bytes_sent: counters[3],
+            writes_completed: counters[4],
+            writes_failed: counters[5],
+            messages_received: counters[6],
+            messages_sent: counters[7],
+            active_duration_ms: counters[8],
+        })
+    }
 }
 
 /// Network protocol type
@@ -114,12 +517,88 @@ pub enum NetworkProtocol {
     UnixDgram,
 }
 
+impl NetworkProtocol {
+    /// Configuration names of all protocols
+    pub const NAMES: [&'static str; 4] = ["tcp", "udp", "unix_stream", "unix_dgram"];
+}
+
+impl fmt::Display for NetworkProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NetworkProtocol::Tcp => "tcp",
+            NetworkProtocol::Udp => "udp",
+            NetworkProtocol::UnixStream => "unix_stream",
+            NetworkProtocol::UnixDgram => "unix_dgram",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for NetworkProtocol {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(NetworkProtocol::Tcp),
+            "udp" => Ok(NetworkProtocol::Udp),
+            "unix_stream" => Ok(NetworkProtocol::UnixStream),
+            "unix_dgram" => Ok(NetworkProtocol::UnixDgram),
+            _ => Err(TcsError::Config(format!(
+                "Unknown protocol '{}', expected one of: {}",
+                s,
+                Self::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+/// How a UDP endpoint addresses its peer
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UdpMode {
+    /// Socket is connected to one peer and uses send/recv
+    #[default]
+    Connected,
+    /// Socket uses send_to/recv_from, replying to the last sender
+    Unconnected,
+}
+
+/// How a conduit waits for payload I/O
+///
+/// In blocking mode a conduit sleeps until data arrives or it is told to
+/// stop, instead of waking periodically. Fair scheduling always polls since
+/// its single thread must not wait on one direction.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IoMode {
+    /// Non-blocking reads, waking periodically to check for commands
+    #[default]
+    NonBlocking,
+    /// Blocking reads, woken only by data or the command pipe
+    Blocking,
+}
+
+/// How a conduit orders its reads and writes to a stream
+///
+/// Datagrams are always sent whole before the next read, and fair scheduling
+/// always drains.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WriteOrdering {
+    /// Each read is written out in full before the next read, so no more
+    /// than one read's worth of data is ever in flight
+    #[default]
+    Drain,
+    /// Reads carry on while the stream is slow to take data, holding what it
+    /// hasn't taken, up to a limit, to finish writing between reads
+    Overlap,
+}
+
 /// Configuration for a network endpoint
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NetworkConfig {
     pub protocol: NetworkProtocol,
     pub address: String,
-    pub port: u16,
+    pub port: Port,
+    #[serde(default)]
+    pub udp_mode: UdpMode,
 }
 
 /// Configuration for a device endpoint
@@ -128,21 +607,134 @@ pub struct DeviceConfig {
     pub path: String,
 }
 
+/// Parity bit of a serial port
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+impl FromStr for Parity {
+    type Err = TcsError;
+
+    fn from_str(s: &str) -> TcsResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Parity::None),
+            "even" => Ok(Parity::Even),
+            "odd" => Ok(Parity::Odd),
+            _ => Err(TcsError::Config(format!("Unknown parity '{}', expected one of: none, even, odd", s))),
+        }
+    }
+}
+
+/// Configuration for a serial port endpoint
+///
+/// The port is set to raw mode with eight data bits.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub path: String,
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub parity: Parity,
+    /// One or two
+    #[serde(default = "default_stop_bits")]
+    pub stop_bits: u8,
+}
+
+fn default_stop_bits() -> u8 {
+    1
+}
+
+/// Kind of Unix domain socket
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UnixMode {
+    #[default]
+    Stream,
+    Datagram,
+}
+
+/// Configuration for a Unix domain socket endpoint
+///
+/// The data handler binds the socket at path and the payload connects to it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnixConfig {
+    pub path: String,
+    #[serde(default)]
+    pub mode: UnixMode,
+}
+
 /// Endpoint configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum EndpointConfig {
     Network(NetworkConfig),
     Device(DeviceConfig),
+    Serial(SerialConfig),
+    Unix(UnixConfig),
+}
+
+impl EndpointConfig {
+    /// Type of data handler the endpoint belongs to
+    pub fn dh_type(&self) -> DHType {
+        match self {
+            EndpointConfig::Network(_) | EndpointConfig::Unix(_) => DHType::Network,
+            EndpointConfig::Device(_) | EndpointConfig::Serial(_) => DHType::Device,
+        }
+    }
+}
+
+/// Options controlling how a data handler's conduits move data
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConduitOptions {
+    /// Service both directions from one thread, alternating between them
+    #[serde(default)]
+    pub fair_scheduling: bool,
+    /// Consecutive I/O errors before the data handler is faulted
+    #[serde(default)]
+    pub fault_threshold: Option<u32>,
+    /// Least time errors must persist before the data handler is faulted
+    #[serde(default)]
+    pub fault_window_ms: Option<u64>,
+    /// Whether the conduits use blocking reads
+    #[serde(default)]
+    pub io_mode: IoMode,
+    /// Time each write so QUERY_DH can report write latency
+    #[serde(default)]
+    pub track_write_latency: bool,
+    /// Most bytes taken in one read, if fewer than the buffer holds
+    #[serde(default)]
+    pub read_buffer_size: Option<usize>,
+    /// Most bytes given to a stream in one write; datagrams are sent whole
+    #[serde(default)]
+    pub write_buffer_size: Option<usize>,
+    /// Time to wait after each write to a stream, for payloads that can't
+    /// keep up
+    #[serde(default)]
+    pub stream_delay_ms: Option<u64>,
+    /// Quiet time a stream read waits for more bytes before relaying what
+    /// it has, so a trickling source makes fewer, larger writes
+    #[serde(default)]
+    pub coalesce_ms: Option<u64>,
+    /// Cap on the data handler's throughput in bytes per second, both
+    /// directions together
+    #[serde(default)]
+    pub rate_limit_bps: Option<u64>,
+    /// Whether reads wait for slow stream writes to finish
+    #[serde(default)]
+    pub write_ordering: WriteOrdering,
 }
 
 /// Data handler configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DHConfig {
     pub dh_id: DHId,
     pub name: DHName,
     pub endpoint: EndpointConfig,
     pub packet_size: usize,
     pub packet_interval_ms: u32,
+    #[serde(default)]
+    pub conduit: ConduitOptions,
 }
 
 /// Payload configuration file structure
@@ -169,30 +761,62 @@ pub struct DHConfigJson {
     pub port: Option<u16>,
     #[serde(default)]
     pub path: Option<String>,
+    #[serde(default)]
+    pub udp_mode: Option<String>,
+    #[serde(default)]
+    pub baud_rate: Option<u32>,
+    #[serde(default)]
+    pub parity: Option<String>,
+    #[serde(default)]
+    pub stop_bits: Option<u8>,
     pub packet_size: usize,
     pub packet_interval_ms: u32,
+    #[serde(default, flatten)]
+    pub conduit: ConduitOptions,
 }
 
 impl DHConfigJson {
     pub fn to_dh_config(&self) -> Result<DHConfig, String> {
         let endpoint = match self.dh_type.as_str() {
             "network" => {
-                let protocol = match self.protocol.as_deref() {
-                    Some("tcp") => NetworkProtocol::Tcp,
-                    Some("udp") => NetworkProtocol::Udp,
-                    Some("unix_stream") => NetworkProtocol::UnixStream,
-                    Some("unix_dgram") => NetworkProtocol::UnixDgram,
-                    _ => return Err("Invalid or missing protocol".to_string()),
-                };
-                EndpointConfig::Network(NetworkConfig {
-                    protocol,
-                    address: self.address.clone().ok_or("Missing address")?,
-                    port: self.port.ok_or("Missing port")?,
-                })
+                let protocol = self
+                    .protocol
+                    .as_deref()
+                    .ok_or("Missing protocol")?
+                    .parse::<NetworkProtocol>()
+                    .map_err(|e| e.to_string())?;
+                match protocol {
+                    NetworkProtocol::UnixStream | NetworkProtocol::UnixDgram => EndpointConfig::Unix(UnixConfig {
+                        path: self.path.clone().ok_or("Missing path")?,
+                        mode: if protocol == NetworkProtocol::UnixDgram { UnixMode::Datagram } else { UnixMode::Stream },
+                    }),
+                    NetworkProtocol::Tcp | NetworkProtocol::Udp => {
+                        let udp_mode = match self.udp_mode.as_deref() {
+                            None | Some("connected") => UdpMode::Connected,
+                            Some("unconnected") => UdpMode::Unconnected,
+                            Some(mode) => return Err(format!("Invalid UDP mode: {}", mode)),
+                        };
+                        EndpointConfig::Network(NetworkConfig {
+                            protocol,
+                            address: self.address.clone().ok_or("Missing address")?,
+                            port: self.port.map(Port).ok_or("Missing port")?,
+                            udp_mode,
+                        })
+                    }
+                }
             }
             "device" => EndpointConfig::Device(DeviceConfig {
                 path: self.path.clone().ok_or("Missing path")?,
             }),
+            "serial" => EndpointConfig::Serial(SerialConfig {
+                path: self.path.clone().ok_or("Missing path")?,
+                baud_rate: self.baud_rate.ok_or("Missing baud rate")?,
+                parity: match self.parity.as_deref() {
+                    Some(parity) => parity.parse::<Parity>().map_err(|e| e.to_string())?,
+                    None => Parity::None,
+                },
+                stop_bits: self.stop_bits.unwrap_or_else(default_stop_bits),
+            }),
             _ => return Err(format!("Invalid DH type: {}", self.dh_type)),
         };
 
@@ -202,6 +826,7 @@ impl DHConfigJson {
             endpoint,
             packet_size: self.packet_size,
             packet_interval_ms: self.packet_interval_ms,
+            conduit: self.conduit.clone(),
         })
     }
 }
@@ -213,15 +838,105 @@ pub struct CIConfigJson {
     pub port: u16,
     pub protocol: String,
     pub beacon_interval_ms: u32,
+    #[serde(default)]
+    pub query_dh_valid_ids: bool,
+    #[serde(default)]
+    pub beacon_format: Option<String>,
+    #[serde(default)]
+    pub node_id: u32,
+    #[serde(default)]
+    pub buffer_pool_bytes: Option<usize>,
+    #[serde(default)]
+    pub restart_marker: Option<String>,
+    #[serde(default)]
+    pub self_poll_interval_ms: Option<u32>,
+    #[serde(default)]
+    pub arm_keys: Option<Vec<u64>>,
+    #[serde(default)]
+    pub fault_injection: bool,
+    #[serde(default)]
+    pub start_dh_exclusive: bool,
+    #[serde(default)]
+    pub beacon_destinations: Option<Vec<String>>,
+    #[serde(default)]
+    pub reuse_address: Option<bool>,
+    #[serde(default)]
+    pub downlink_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    pub beacon_intervals_ms: Option<BTreeMap<String, u32>>,
+    #[serde(default)]
+    pub framings: Option<Vec<String>>,
+    #[serde(default)]
+    pub reply_invalid_commands: Option<bool>,
+    #[serde(default)]
+    pub unexpected_telemetry_warning: Option<u64>,
+    #[serde(default)]
+    pub beacon_required: bool,
+    #[serde(default)]
+    pub max_wait_ms: Option<u32>,
+    #[serde(default)]
+    pub max_data_handlers: Option<u32>,
+    #[serde(default)]
+    pub beacon_triggers: Option<Vec<String>>,
 }
 
+/// Default file used to recognize a commanded restart
+pub const DEFAULT_RESTART_MARKER: &str = "/tmp/tcspecial.restart";
+
+/// Default number of telemetry messages received on the command port before
+/// the CI warns of a misconfigured peer
+pub const DEFAULT_UNEXPECTED_TELEMETRY_WARNING: u64 = 10;
+
 /// Command interpreter configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CIConfig {
     pub address: String,
-    pub port: u16,
+    pub port: Port,
     pub protocol: NetworkProtocol,
     pub beacon_interval: BeaconTime,
+    /// Include the list of valid DH ids when QUERY_DH names an unknown DH
+    pub query_dh_valid_ids: bool,
+    pub beacon_format: BeaconFormat,
+    /// Identifier reported in extended beacons
+    pub node_id: u32,
+    /// Limit on bytes held idle in the shared buffer pool
+    pub buffer_pool_bytes: usize,
+    /// File recording a commanded restart across the restart, if any
+    pub restart_marker: Option<String>,
+    /// How often the CI checks its own DHs for progress, if at all
+    pub self_poll_interval: Option<Duration>,
+    /// Keys accepted by RESTART_ARM; any key is accepted if None
+    pub arm_keys: Option<Vec<ArmKey>>,
+    /// Honor INJECT_FAULT commands; for testing ground error handling only
+    pub fault_injection: bool,
+    /// Report ALREADY_EXISTS for START_DH of an existing DH rather than success
+    pub start_dh_exclusive: bool,
+    /// Addresses beacons are sent to; the CI's default if empty
+    pub beacon_destinations: Vec<SocketAddr>,
+    /// Set SO_REUSEADDR on the command socket so a restart can rebind at once
+    pub reuse_address: bool,
+    /// Cap on the combined payload-to-ground throughput of all DHs, if any
+    pub downlink_bytes_per_sec: Option<u64>,
+    /// Beacon destinations sent to at their own interval
+    pub beacon_intervals: BTreeMap<SocketAddr, Duration>,
+    /// Framings HELLO may choose; JSON is always understood
+    pub framings: Vec<Framing>,
+    /// Answer commands that can't be decoded with INVALID_COMMAND telemetry
+    /// rather than dropping them silently
+    pub reply_invalid_commands: bool,
+    /// Telemetry messages received on the command port before the CI warns
+    /// that a peer is probably misconfigured
+    pub unexpected_telemetry_warning: u64,
+    /// Stop the CI, rather than carry on without beacons, if beaconing
+    /// can't be started
+    pub beacon_required: bool,
+    /// Longest the CI waits for a command when it has no periodic work due
+    /// sooner; the CI's default if None
+    pub max_wait: Option<Duration>,
+    /// Most data handlers running at once; unlimited if None
+    pub max_data_handlers: Option<usize>,
+    /// Events that send a beacon at once, outside the beacon schedule
+    pub beacon_triggers: Vec<BeaconTrigger>,
 }
 
 impl CIConfigJson {
@@ -232,11 +947,83 @@ impl CIConfigJson {
             _ => return Err(format!("Invalid protocol: {}", self.protocol)),
         };
 
+        let beacon_format = match self.beacon_format.as_deref() {
+            None | Some("legacy") => BeaconFormat::Legacy,
+            Some("extended") => BeaconFormat::Extended,
+            Some(format) => return Err(format!("Invalid beacon format: {}", format)),
+        };
+
+        let beacon_destinations = self
+            .beacon_destinations
+            .iter()
+            .flatten()
+            .map(|dest| dest.parse().map_err(|e| format!("Invalid beacon destination {}: {}", dest, e)))
+            .collect::<Result<Vec<SocketAddr>, String>>()?;
+
+        let beacon_intervals = self
+            .beacon_intervals_ms
+            .iter()
+            .flatten()
+            .map(|(dest, ms)| match dest.parse() {
+                Ok(_) if *ms == 0 => Err(format!("Beacon interval for {} must not be zero", dest)),
+                Ok(addr) => Ok((addr, Duration::from_millis(*ms as u64))),
+                Err(e) => Err(format!("Invalid beacon destination {}: {}", dest, e)),
+            })
+            .collect::<Result<BTreeMap<SocketAddr, Duration>, String>>()?;
+
+        let framings = match &self.framings {
+            None => vec![Framing::Json],
+            Some(names) => names
+                .iter()
+                .map(|name| name.parse::<Framing>().map_err(|e| e.to_string()))
+                .collect::<Result<Vec<Framing>, String>>()?,
+        };
+
+        let beacon_triggers = self
+            .beacon_triggers
+            .iter()
+            .flatten()
+            .map(|name| name.parse::<BeaconTrigger>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<BeaconTrigger>, String>>()?;
+
         Ok(CIConfig {
             address: self.address.clone(),
-            port: self.port,
+            port: Port(self.port),
             protocol,
             beacon_interval: BeaconTime(self.beacon_interval_ms),
+            query_dh_valid_ids: self.query_dh_valid_ids,
+            beacon_format,
+            node_id: self.node_id,
+            buffer_pool_bytes: self.buffer_pool_bytes.unwrap_or(DEFAULT_POOL_CAPACITY),
+            restart_marker: Some(
+                self.restart_marker.clone().unwrap_or_else(|| DEFAULT_RESTART_MARKER.to_string()),
+            ),
+            self_poll_interval: self.self_poll_interval_ms.map(|ms| Duration::from_millis(ms as u64)),
+            arm_keys: self.arm_keys.as_ref().map(|keys| keys.iter().copied().map(ArmKey).collect()),
+            fault_injection: self.fault_injection,
+            start_dh_exclusive: self.start_dh_exclusive,
+            beacon_destinations,
+            reuse_address: self.reuse_address.unwrap_or(true),
+            downlink_bytes_per_sec: match self.downlink_bytes_per_sec {
+                Some(0) => return Err("Downlink rate must not be zero".to_string()),
+                rate => rate,
+            },
+            beacon_intervals,
+            framings,
+            reply_invalid_commands: self.reply_invalid_commands.unwrap_or(true),
+            unexpected_telemetry_warning: self
+                .unexpected_telemetry_warning
+                .unwrap_or(DEFAULT_UNEXPECTED_TELEMETRY_WARNING),
+            beacon_required: self.beacon_required,
+            max_wait: match self.max_wait_ms {
+                Some(0) => return Err("Maximum wait must not be zero".to_string()),
+                ms => ms.map(|ms| Duration::from_millis(ms as u64)),
+            },
+            max_data_handlers: match self.max_data_handlers {
+                Some(0) => return Err("Maximum data handlers must not be zero".to_string()),
+                max => max.map(|max| max as usize),
+            },
+            beacon_triggers,
         })
     }
 }
@@ -249,6 +1036,7 @@ pub enum CommandStatus {
     InvalidCommand,
     InvalidParameter,
     NotArmed,
+    InvalidArmKey,
     NotFound,
     AlreadyExists,
     Timeout,
@@ -264,6 +1052,56 @@ impl CommandStatus {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dh_name_infer_type() {
+        let infer = |name: &str| DHName::new(name).infer_type();
+
+        assert_eq!(infer("/dev/ttyUSB0").unwrap(), DHType::Device);
+        assert_eq!(infer("10.0.0.1:5000").unwrap(), DHType::Network);
+        assert_eq!(infer("payload.local:5000:udp").unwrap(), DHType::Network);
+        assert_eq!(infer("[::1]:5000").unwrap(), DHType::Network);
+
+        for ambiguous in ["C:\\dev\\com1", "C:/dev/com1", "DH0", "10.0.0.1", "host:port"] {
+            match infer(ambiguous) {
+                Err(TcsError::Config(msg)) => assert!(msg.contains(ambiguous), "{}", msg),
+                other => panic!("Inferred {:?} from {}", other, ambiguous),
+            }
+        }
+    }
+
+    #[test]
+    fn test_statistics_encoding() {
+        let mut stats = Statistics {
+            bytes_received: 0x0102_0304_0506_0708,
+            reads_completed: 2,
+            reads_failed: 3,
+            bytes_sent: 4,
+            writes_completed: 5,
+            writes_failed: 6,
+            messages_received: 7,
+            messages_sent: 8,
+            active_duration_ms: u64::MAX,
+            ..Statistics::new()
+        };
+
+        let bytes = stats.encode();
+        assert_eq!(bytes[0], 0);
+        assert_eq!(&bytes[1..13], &[0u8; 12]);
+        assert_eq!(&bytes[13..21], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Statistics::decode(&bytes).unwrap(), stats);
+
+        stats.timestamp = Some(Timestamp { seconds: 0x1122_3344, nanoseconds: 999_999_999 });
+        let bytes = stats.encode();
+        assert_eq!(&bytes[..13], &[1, 0, 0, 0, 0, 0x11, 0x22, 0x33, 0x44, 0x3b, 0x9a, 0xc9, 0xff]);
+        assert_eq!(Statistics::decode(&bytes).unwrap(), stats);
+        assert_eq!(Statistics::decode(&bytes).unwrap().encode(), bytes);
+
+        assert!(matches!(Statistics::decode(&bytes[1..]), Err(TcsError::Protocol(_))));
+        let mut bad_flag = bytes;
+        bad_flag[0] = 2;
+        assert!(matches!(Statistics::decode(&bad_flag), Err(TcsError::Protocol(_))));
+    }
+
     #[test]
     fn test_dhid_ordering() {
         let id1 = DHId(1);
@@ -277,9 +1115,108 @@ mod tests {
         assert!(ts.seconds > 0);
     }
 
+    #[test]
+    fn test_dh_state_serialization() {
+        for state in [DHState::Created, DHState::Active, DHState::Paused, DHState::Stopped, DHState::Faulted] {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(json, format!("\"{}\"", state));
+            assert_eq!(serde_json::from_str::<DHState>(&json).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn test_port() {
+        assert_eq!(Port::new(5000).unwrap(), Port(5000));
+        assert!(Port::ANY.is_any());
+        assert!(matches!(Port::new(0), Err(TcsError::Config(_))));
+        assert!(matches!(Port::ANY.connectable(), Err(TcsError::Config(_))));
+
+        assert_eq!("4000".parse::<Port>().unwrap(), Port(4000));
+        assert_eq!(Port(4000).to_string(), "4000");
+        for bad in ["0", "65536", "-1", "http", ""] {
+            assert!(matches!(bad.parse::<Port>(), Err(TcsError::Config(_))), "{} parsed", bad);
+        }
+        assert!(matches!(DHName::new("payload.local:0:udp").address(), Err(TcsError::Config(_))));
+
+        // Ports are plain numbers in JSON
+        assert_eq!(serde_json::to_string(&Port(5000)).unwrap(), "5000");
+        assert_eq!(serde_json::from_str::<Port>("5000").unwrap(), Port(5000));
+    }
+
+    #[test]
+    fn test_dh_address() {
+        let address = DHName::new("payload.local:5000:tcp").address().unwrap();
+        assert_eq!(address.host, "payload.local");
+        assert_eq!(address.port, Port(5000));
+        assert_eq!(address.protocol, NetworkProtocol::Tcp);
+
+        let address = DHName::new("::1:5001:UDP").address().unwrap();
+        assert_eq!((address.host.as_str(), address.protocol), ("::1", NetworkProtocol::Udp));
+
+        assert!(matches!(DHName::new("localhost:5000").address(), Err(TcsError::Config(_))));
+
+        match DHName::new("localhost:5000:sctp").address() {
+            Err(TcsError::Config(msg)) => {
+                assert!(msg.contains("'sctp'"));
+                assert!(msg.contains("tcp, udp, unix_stream, unix_dgram"));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
     #[test]
     fn test_statistics_with_timestamp() {
-        let stats = Statistics::new().with_timestamp();
+        let stats = Statistics::new().with_timestamp(Timestamp::now());
         assert!(stats.timestamp.is_some());
     }
+
+    #[test]
+    fn test_serial_config_json() {
+        let json = r#"{"dh_id": 3, "name": "radio", "type": "serial", "path": "/dev/ttyS0",
+            "baud_rate": 115200, "parity": "even", "packet_size": 64, "packet_interval_ms": 10}"#;
+        let config = serde_json::from_str::<DHConfigJson>(json).unwrap().to_dh_config().unwrap();
+        assert_eq!(config.endpoint, EndpointConfig::Serial(SerialConfig {
+            path: "/dev/ttyS0".to_string(),
+            baud_rate: 115200,
+            parity: Parity::Even,
+            stop_bits: 1,
+        }));
+        assert_eq!(config.endpoint.dh_type(), DHType::Device);
+
+        let missing = json.replace(r#""baud_rate": 115200,"#, "");
+        assert!(serde_json::from_str::<DHConfigJson>(&missing).unwrap().to_dh_config().is_err());
+        let bad = json.replace("even", "mark");
+        assert!(serde_json::from_str::<DHConfigJson>(&bad).unwrap().to_dh_config().unwrap_err().contains("mark"));
+    }
+
+    #[test]
+    fn test_unix_config_json() {
+        let json = r#"{"dh_id": 4, "name": "camera", "type": "network", "protocol": "unix_dgram",
+            "path": "/run/camera.sock", "packet_size": 64, "packet_interval_ms": 10}"#;
+        let config = serde_json::from_str::<DHConfigJson>(json).unwrap().to_dh_config().unwrap();
+        assert_eq!(config.endpoint, EndpointConfig::Unix(UnixConfig {
+            path: "/run/camera.sock".to_string(),
+            mode: UnixMode::Datagram,
+        }));
+        assert_eq!(config.endpoint.dh_type(), DHType::Network);
+
+        let stream = json.replace("unix_dgram", "unix_stream");
+        let config = serde_json::from_str::<DHConfigJson>(&stream).unwrap().to_dh_config().unwrap();
+        assert!(matches!(config.endpoint, EndpointConfig::Unix(UnixConfig { mode: UnixMode::Stream, .. })));
+        let missing = json.replace(r#""path": "/run/camera.sock","#, "");
+        assert!(serde_json::from_str::<DHConfigJson>(&missing).unwrap().to_dh_config().is_err());
+    }
+
+    #[test]
+    fn test_statistics_diff() {
+        let earlier = Statistics { bytes_received: 100, reads_completed: 4, bytes_sent: 50, ..Statistics::new() };
+        let later = Statistics { bytes_received: 160, reads_completed: 7, bytes_sent: 40, ..Statistics::new() }
+            .with_timestamp(Timestamp::now());
+
+        let delta = later.diff(&earlier);
+        assert_eq!((delta.bytes_received, delta.reads_completed), (60, 3));
+        // Counters that went backwards don't wrap
+        assert_eq!(delta.bytes_sent, 0);
+        assert_eq!(delta.timestamp, later.timestamp);
+    }
 }
@@ -0,0 +1,890 @@
+//! Compact binary encoding of commands and telemetry
+//!
+//! Every message starts with its CommandType or TelemetryType code as a
+//! one-byte tag, followed by the rest of its header and then its fields in
+//! declaration order. Integers are fixed-size and big-endian, booleans and
+//! Options take a flag byte, unit enums take one byte, and strings and
+//! vectors are prefixed with a u32 length. Statistics use their own fixed
+//! encoding.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::commands::*;
+use crate::error::{TcsError, TcsResult};
+use crate::log::LogLevel;
+use crate::protocol::Framing;
+use crate::telemetry::*;
+use crate::types::*;
+
+/// A value with a binary wire encoding
+pub trait Wire: Sized {
+    /// Append the encoded value
+    fn write_wire(&self, out: &mut Vec<u8>);
+
+    /// Decode a value, consuming its bytes from the reader
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self>;
+}
+
+/// Cursor over an encoded message
+pub struct WireReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    /// Take the next len bytes
+    pub fn take(&mut self, len: usize) -> TcsResult<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(TcsError::Protocol(format!(
+                "Message truncated at byte {}, needed {} more",
+                self.offset, len
+            )));
+        }
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    /// Take the next len bytes as a fixed-size array
+    fn take_array<const N: usize>(&mut self) -> TcsResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// Number of bytes not yet consumed
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    /// Fail if there are bytes left over after a message
+    pub fn finish(&self) -> TcsResult<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            extra => Err(TcsError::Protocol(format!("{} unexpected bytes after message", extra))),
+        }
+    }
+}
+
+macro_rules! wire_int {
+    ($($int:ty),*) => {
+        $(
+            impl Wire for $int {
+                fn write_wire(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+                    Ok(<$int>::from_be_bytes(reader.take_array()?))
+                }
+            }
+        )*
+    };
+}
+
+wire_int!(u8, u16, u32, u64);
+
+/// Encode a struct as its fields in the order listed
+macro_rules! wire_struct {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl Wire for $name {
+            fn write_wire(&self, _out: &mut Vec<u8>) {
+                $(self.$field.write_wire(_out);)*
+            }
+
+            fn read_wire(_reader: &mut WireReader<'_>) -> TcsResult<Self> {
+                Ok(Self {
+                    $($field: Wire::read_wire(_reader)?,)*
+                })
+            }
+        }
+    };
+}
+
+/// Encode a unit enum as the one-byte index of its variant in the list
+macro_rules! wire_enum {
+    ($name:ident { $($variant:ident),* $(,)? }) => {
+        impl Wire for $name {
+            fn write_wire(&self, out: &mut Vec<u8>) {
+                const VARIANTS: &[$name] = &[$($name::$variant),*];
+                let index = VARIANTS.iter().position(|v| v == self).unwrap();
+                out.push(index as u8);
+            }
+
+            fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+                const VARIANTS: &[$name] = &[$($name::$variant),*];
+                let index = u8::read_wire(reader)?;
+                VARIANTS.get(index as usize).copied().ok_or_else(|| {
+                    TcsError::Protocol(format!("Invalid {} {}", stringify!($name), index))
+                })
+            }
+        }
+    };
+}
+
+impl Wire for bool {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        match u8::read_wire(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            flag => Err(TcsError::Protocol(format!("Invalid boolean {}", flag))),
+        }
+    }
+}
+
+impl Wire for usize {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        (*self as u64).write_wire(out);
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let value = u64::read_wire(reader)?;
+        usize::try_from(value).map_err(|_| TcsError::Protocol(format!("Size {} out of range", value)))
+    }
+}
+
+/// Read a u32 length prefix
+fn read_len(reader: &mut WireReader<'_>) -> TcsResult<usize> {
+    Ok(u32::read_wire(reader)? as usize)
+}
+
+impl Wire for String {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).write_wire(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let len = read_len(reader)?;
+        String::from_utf8(reader.take(len)?.to_vec())
+            .map_err(|e| TcsError::Protocol(format!("Invalid string: {}", e)))
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        self.is_some().write_wire(out);
+        if let Some(value) = self {
+            value.write_wire(out);
+        }
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        match bool::read_wire(reader)? {
+            true => Ok(Some(T::read_wire(reader)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).write_wire(out);
+        for item in self {
+            item.write_wire(out);
+        }
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let len = read_len(reader)?;
+        // Every item takes at least a byte, so a bogus length can't make
+        // us allocate more than the message holds
+        let mut items = Vec::with_capacity(len.min(reader.remaining()));
+        for _ in 0..len {
+            items.push(T::read_wire(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Wire for SocketAddr {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        match self.ip() {
+            IpAddr::V4(ip) => {
+                out.push(4);
+                out.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                out.push(6);
+                out.extend_from_slice(&ip.octets());
+            }
+        }
+        self.port().write_wire(out);
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let ip = match u8::read_wire(reader)? {
+            4 => IpAddr::V4(Ipv4Addr::from(reader.take_array::<4>()?)),
+            6 => IpAddr::V6(Ipv6Addr::from(reader.take_array::<16>()?)),
+            version => return Err(TcsError::Protocol(format!("Invalid IP version {}", version))),
+        };
+        Ok(SocketAddr::new(ip, u16::read_wire(reader)?))
+    }
+}
+
+impl Wire for CommandType {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        out.push(self.to_u8());
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let tag = u8::read_wire(reader)?;
+        CommandType::from_u8(tag).ok_or_else(|| TcsError::Protocol(format!("Unknown command type 0x{:02X}", tag)))
+    }
+}
+
+impl Wire for TelemetryType {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        out.push(self.to_u8());
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        let tag = u8::read_wire(reader)?;
+        TelemetryType::from_u8(tag).ok_or_else(|| TcsError::Protocol(format!("Unknown telemetry type 0x{:02X}", tag)))
+    }
+}
+
+impl Wire for Statistics {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.encode());
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        Statistics::decode(reader.take(STATISTICS_ENCODED_SIZE)?)
+    }
+}
+
+impl Wire for EndpointConfig {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        match self {
+            EndpointConfig::Network(config) => {
+                out.push(0);
+                config.write_wire(out);
+            }
+            EndpointConfig::Device(config) => {
+                out.push(1);
+                config.write_wire(out);
+            }
+            EndpointConfig::Serial(config) => {
+                out.push(2);
+                config.write_wire(out);
+            }
+            EndpointConfig::Unix(config) => {
+                out.push(3);
+                config.write_wire(out);
+            }
+        }
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        match u8::read_wire(reader)? {
+            0 => Ok(EndpointConfig::Network(NetworkConfig::read_wire(reader)?)),
+            1 => Ok(EndpointConfig::Device(DeviceConfig::read_wire(reader)?)),
+            2 => Ok(EndpointConfig::Serial(SerialConfig::read_wire(reader)?)),
+            3 => Ok(EndpointConfig::Unix(UnixConfig::read_wire(reader)?)),
+            kind => Err(TcsError::Protocol(format!("Invalid endpoint kind {}", kind))),
+        }
+    }
+}
+
+impl Wire for DHEventKind {
+    fn write_wire(&self, out: &mut Vec<u8>) {
+        match self {
+            DHEventKind::Connected => out.push(0),
+            DHEventKind::Disconnected => out.push(1),
+            DHEventKind::Stopped => out.push(2),
+            DHEventKind::Error(code) => {
+                out.push(3);
+                code.write_wire(out);
+            }
+        }
+    }
+
+    fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+        match u8::read_wire(reader)? {
+            0 => Ok(DHEventKind::Connected),
+            1 => Ok(DHEventKind::Disconnected),
+            2 => Ok(DHEventKind::Stopped),
+            3 => Ok(DHEventKind::Error(ErrorCode::read_wire(reader)?)),
+            kind => Err(TcsError::Protocol(format!("Invalid DH event {}", kind))),
+        }
+    }
+}
+
+/// Encode a single-field tuple struct as its field
+macro_rules! wire_newtype {
+    ($($name:ident),*) => {
+        $(
+            impl Wire for $name {
+                fn write_wire(&self, out: &mut Vec<u8>) {
+                    self.0.write_wire(out);
+                }
+
+                fn read_wire(reader: &mut WireReader<'_>) -> TcsResult<Self> {
+                    Ok(Self(Wire::read_wire(reader)?))
+                }
+            }
+        )*
+    };
+}
+
+wire_newtype!(DHId, DHName, ArmKey, BeaconTime, Port);
+
+wire_enum!(CommandStatus {
+    Success, Failure, InvalidCommand, InvalidParameter, NotArmed, InvalidArmKey, NotFound, AlreadyExists, Timeout,
+});
+wire_enum!(DHType { Network, Device });
+wire_enum!(DHState { Created, Active, Paused, Stopped, Faulted });
+wire_enum!(NetworkProtocol { Tcp, Udp, UnixStream, UnixDgram });
+wire_enum!(UdpMode { Connected, Unconnected });
+wire_enum!(Parity { None, Even, Odd });
+wire_enum!(UnixMode { Stream, Datagram });
+wire_enum!(IoMode { NonBlocking, Blocking });
+wire_enum!(WriteOrdering { Drain, Overlap });
+wire_enum!(StartReason { ColdStart, CommandedRestart });
+wire_enum!(StartDHOutcome { Created, AlreadyActive, Reactivated });
+wire_enum!(Framing { Json, Binary });
+wire_enum!(LogLevel { Error, Warn, Info, Debug, Trace });
+wire_enum!(ErrorCode { Io, Config, Protocol, Endpoint, DataHandler, Timeout, Other, InvalidConfiguration });
+
+wire_struct!(Timestamp { seconds, nanoseconds });
+wire_struct!(NetworkConfig { protocol, address, port, udp_mode });
+wire_struct!(DeviceConfig { path });
+wire_struct!(SerialConfig { path, baud_rate, parity, stop_bits });
+wire_struct!(UnixConfig { path, mode });
+wire_struct!(ConduitOptions {
+    fair_scheduling,
+    fault_threshold,
+    fault_window_ms,
+    io_mode,
+    track_write_latency,
+    read_buffer_size,
+    write_buffer_size,
+    stream_delay_ms,
+    coalesce_ms,
+    rate_limit_bps,
+    write_ordering
+});
+wire_struct!(DHConfig { dh_id, name, endpoint, packet_size, packet_interval_ms, conduit });
+
+// The type comes first so it is the message tag
+wire_struct!(CommandHeader { cmd_type, sequence, request_id });
+wire_struct!(PingCommand { header });
+wire_struct!(RestartArmCommand { header, arm_key });
+wire_struct!(RestartCommand { header, arm_key });
+wire_struct!(QueryDroppedCommand { header, clear });
+wire_struct!(QueryEndpointSupportCommand { header });
+wire_struct!(InjectFaultCommand { header, target, status });
+wire_struct!(QueryBeaconStatusCommand { header });
+wire_struct!(HelloCommand { header, framings });
+wire_struct!(SetTimeCommand { header, timestamp });
+wire_struct!(SetLogLevelCommand { header, level });
+wire_struct!(StartDHCommand { header, dh_id, dh_type, name });
+wire_struct!(StopDHCommand { header, dh_id });
+wire_struct!(QueryDHCommand { header, dh_id });
+wire_struct!(SnapshotStatsCommand { header });
+wire_struct!(DHLoopbackCommand { header, dh_id, token, timeout_ms });
+wire_struct!(SubscribeDHStatsCommand { header, dh_id, interval_ms });
+wire_struct!(UnsubscribeDHStatsCommand { header, dh_id });
+wire_struct!(PauseAllDHCommand { header });
+wire_struct!(ResumeAllDHCommand { header });
+wire_struct!(ResetStatsCommand { header, dh_id });
+wire_struct!(ListDHCommand { header });
+wire_struct!(CheckpointDHCommand { header, dh_id });
+wire_struct!(DHControlCommand { header, dh_id, data });
+wire_struct!(QueryActivityCommand { header, window_ms });
+wire_struct!(ConfigCommand { header, beacon_interval });
+wire_struct!(ConfigDHCommand { header, dh_id, read_buffer_size, write_buffer_size, stream_delay_ms, rate_limit_bps });
+wire_struct!(ReconfigureDHCommand { header, dh_id, config });
+wire_struct!(ReloadConfigCommand { header });
+
+wire_struct!(TelemetryHeader { tm_type, sequence, status, request_id });
+wire_struct!(PingTelemetry { header, timestamp });
+wire_struct!(RestartArmTelemetry { header });
+wire_struct!(RestartTelemetry { header });
+wire_struct!(QueryDroppedTelemetry { header, telemetry_dropped, commands_dropped, unexpected_telemetry });
+wire_struct!(QueryEndpointSupportTelemetry { header, dh_types, protocols });
+wire_struct!(InjectFaultTelemetry { header });
+wire_struct!(BeaconDestinationStatus { address, sent, failed, interval_override });
+wire_struct!(QueryBeaconStatusTelemetry { header, destinations });
+wire_struct!(HelloTelemetry { header, framing });
+wire_struct!(SetTimeTelemetry { header, applied, previous });
+wire_struct!(SetLogLevelTelemetry { header, level, previous });
+wire_struct!(StartDHTelemetry { header, outcome, oc_port, error, detail });
+wire_struct!(StopDHTelemetry { header, detail });
+wire_struct!(QueryDHTelemetry { header, dh_id, statistics, no_such_handler, valid_ids, state, write_latency, since_checkpoint });
+wire_struct!(WriteLatency { samples, min_us, max_us, p50_us, p99_us });
+wire_struct!(DHStatistics { dh_id, statistics });
+wire_struct!(StatsSnapshotTelemetry { header, timestamp, global, data_handlers });
+wire_struct!(DHLoopbackTelemetry { header, dh_id, token, rtt_us });
+wire_struct!(SubscribeDHStatsTelemetry { header, dh_id });
+wire_struct!(UnsubscribeDHStatsTelemetry { header, dh_id });
+wire_struct!(PauseAllDHTelemetry { header, paused });
+wire_struct!(ResumeAllDHTelemetry { header, resumed });
+wire_struct!(ResetStatsTelemetry { header, dh_id });
+wire_struct!(ListDHTelemetry { header, handlers });
+wire_struct!(CheckpointDHTelemetry { header, dh_id });
+wire_struct!(DHControlTelemetry { header, dh_id });
+wire_struct!(QueryActivityTelemetry { header, window_ms, handlers });
+wire_struct!(DHActivity { dh_id, active, idle_ms });
+wire_struct!(DHListEntry { dh_id, dh_type, name, state });
+wire_struct!(ConfigTelemetry { header, beacon_interval });
+wire_struct!(ConfigDHTelemetry { header });
+wire_struct!(ReconfigureDHTelemetry { header, dh_id, detail });
+wire_struct!(ReloadConfigTelemetry { header, summary });
+wire_struct!(ReloadSummary { added, removed, reconfigured, unchanged, failed });
+wire_struct!(InvalidCommandTelemetry { header, reason });
+wire_struct!(BeaconHealth { active_dh_count, uptime_secs, statistics });
+wire_struct!(DHEventTelemetry { header, dh_id, event });
+wire_struct!(BeaconTelemetry { header, timestamp, beacon_sequence, node_id, interval, start_reason, uptime_ms, health });
+
+/// Peek at a message's tag without consuming it
+fn peek_tag(bytes: &[u8]) -> TcsResult<u8> {
+    bytes
+        .first()
+        .copied()
+        .ok_or_else(|| TcsError::Protocol("Empty message".to_string()))
+}
+
+/// Decode a whole message, failing on leftover bytes
+fn decode_all<T: Wire>(bytes: &[u8]) -> TcsResult<T> {
+    let mut reader = WireReader::new(bytes);
+    let value = T::read_wire(&mut reader)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+impl Command {
+    /// Encode the command in the binary wire format
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Command::Ping(cmd) => cmd.write_wire(&mut out),
+            Command::RestartArm(cmd) => cmd.write_wire(&mut out),
+            Command::Restart(cmd) => cmd.write_wire(&mut out),
+            Command::QueryDropped(cmd) => cmd.write_wire(&mut out),
+            Command::QueryEndpointSupport(cmd) => cmd.write_wire(&mut out),
+            Command::InjectFault(cmd) => cmd.write_wire(&mut out),
+            Command::QueryBeaconStatus(cmd) => cmd.write_wire(&mut out),
+            Command::Hello(cmd) => cmd.write_wire(&mut out),
+            Command::SetTime(cmd) => cmd.write_wire(&mut out),
+            Command::SetLogLevel(cmd) => cmd.write_wire(&mut out),
+            Command::StartDH(cmd) => cmd.write_wire(&mut out),
+            Command::StopDH(cmd) => cmd.write_wire(&mut out),
+            Command::QueryDH(cmd) => cmd.write_wire(&mut out),
+            Command::SnapshotStats(cmd) => cmd.write_wire(&mut out),
+            Command::DHLoopback(cmd) => cmd.write_wire(&mut out),
+            Command::SubscribeDHStats(cmd) => cmd.write_wire(&mut out),
+            Command::UnsubscribeDHStats(cmd) => cmd.write_wire(&mut out),
+            Command::PauseAllDH(cmd) => cmd.write_wire(&mut out),
+            Command::ResumeAllDH(cmd) => cmd.write_wire(&mut out),
+            Command::ResetStats(cmd) => cmd.write_wire(&mut out),
+            Command::ListDH(cmd) => cmd.write_wire(&mut out),
+            Command::CheckpointDH(cmd) => cmd.write_wire(&mut out),
+            Command::DHControl(cmd) => cmd.write_wire(&mut out),
+            Command::QueryActivity(cmd) => cmd.write_wire(&mut out),
+            Command::Config(cmd) => cmd.write_wire(&mut out),
+            Command::ConfigDH(cmd) => cmd.write_wire(&mut out),
+            Command::ReconfigureDH(cmd) => cmd.write_wire(&mut out),
+            Command::ReloadConfig(cmd) => cmd.write_wire(&mut out),
+        }
+        out
+    }
+
+    /// Decode a command encoded by to_wire()
+    pub fn from_wire(bytes: &[u8]) -> TcsResult<Command> {
+        let tag = peek_tag(bytes)?;
+        let cmd_type = CommandType::from_u8(tag)
+            .ok_or_else(|| TcsError::Protocol(format!("Unknown command type 0x{:02X}", tag)))?;
+        Ok(match cmd_type {
+            CommandType::Ping => Command::Ping(decode_all(bytes)?),
+            CommandType::RestartArm => Command::RestartArm(decode_all(bytes)?),
+            CommandType::Restart => Command::Restart(decode_all(bytes)?),
+            CommandType::QueryDropped => Command::QueryDropped(decode_all(bytes)?),
+            CommandType::QueryEndpointSupport => Command::QueryEndpointSupport(decode_all(bytes)?),
+            CommandType::InjectFault => Command::InjectFault(decode_all(bytes)?),
+            CommandType::QueryBeaconStatus => Command::QueryBeaconStatus(decode_all(bytes)?),
+            CommandType::Hello => Command::Hello(decode_all(bytes)?),
+            CommandType::SetTime => Command::SetTime(decode_all(bytes)?),
+            CommandType::SetLogLevel => Command::SetLogLevel(decode_all(bytes)?),
+            CommandType::StartDH => Command::StartDH(decode_all(bytes)?),
+            CommandType::StopDH => Command::StopDH(decode_all(bytes)?),
+            CommandType::QueryDH => Command::QueryDH(decode_all(bytes)?),
+            CommandType::SnapshotStats => Command::SnapshotStats(decode_all(bytes)?),
+            CommandType::DHLoopback => Command::DHLoopback(decode_all(bytes)?),
+            CommandType::SubscribeDHStats => Command::SubscribeDHStats(decode_all(bytes)?),
+            CommandType::UnsubscribeDHStats => Command::UnsubscribeDHStats(decode_all(bytes)?),
+            CommandType::PauseAllDH => Command::PauseAllDH(decode_all(bytes)?),
+            CommandType::ResumeAllDH => Command::ResumeAllDH(decode_all(bytes)?),
+            CommandType::ResetStats => Command::ResetStats(decode_all(bytes)?),
+            CommandType::ListDH => Command::ListDH(decode_all(bytes)?),
+            CommandType::CheckpointDH => Command::CheckpointDH(decode_all(bytes)?),
+            CommandType::DHControl => Command::DHControl(decode_all(bytes)?),
+            CommandType::QueryActivity => Command::QueryActivity(decode_all(bytes)?),
+            CommandType::Config => Command::Config(decode_all(bytes)?),
+            CommandType::ConfigDH => Command::ConfigDH(decode_all(bytes)?),
+            CommandType::ReconfigureDH => Command::ReconfigureDH(decode_all(bytes)?),
+            CommandType::ReloadConfig => Command::ReloadConfig(decode_all(bytes)?),
+        })
+    }
+}
+
+impl Telemetry {
+    /// Encode the telemetry in the binary wire format
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Telemetry::Ping(tm) => tm.write_wire(&mut out),
+            Telemetry::RestartArm(tm) => tm.write_wire(&mut out),
+            Telemetry::Restart(tm) => tm.write_wire(&mut out),
+            Telemetry::QueryDropped(tm) => tm.write_wire(&mut out),
+            Telemetry::QueryEndpointSupport(tm) => tm.write_wire(&mut out),
+            Telemetry::InjectFault(tm) => tm.write_wire(&mut out),
+            Telemetry::QueryBeaconStatus(tm) => tm.write_wire(&mut out),
+            Telemetry::Hello(tm) => tm.write_wire(&mut out),
+            Telemetry::SetTime(tm) => tm.write_wire(&mut out),
+            Telemetry::SetLogLevel(tm) => tm.write_wire(&mut out),
+            Telemetry::StartDH(tm) => tm.write_wire(&mut out),
+            Telemetry::StopDH(tm) => tm.write_wire(&mut out),
+            Telemetry::QueryDH(tm) => tm.write_wire(&mut out),
+            Telemetry::StatsSnapshot(tm) => tm.write_wire(&mut out),
+            Telemetry::DHLoopback(tm) => tm.write_wire(&mut out),
+            Telemetry::SubscribeDHStats(tm) => tm.write_wire(&mut out),
+            Telemetry::UnsubscribeDHStats(tm) => tm.write_wire(&mut out),
+            Telemetry::PauseAllDH(tm) => tm.write_wire(&mut out),
+            Telemetry::ResumeAllDH(tm) => tm.write_wire(&mut out),
+            Telemetry::ResetStats(tm) => tm.write_wire(&mut out),
+            Telemetry::ListDH(tm) => tm.write_wire(&mut out),
+            Telemetry::CheckpointDH(tm) => tm.write_wire(&mut out),
+            Telemetry::DHControl(tm) => tm.write_wire(&mut out),
+            Telemetry::QueryActivity(tm) => tm.write_wire(&mut out),
+            Telemetry::Config(tm) => tm.write_wire(&mut out),
+            Telemetry::ConfigDH(tm) => tm.write_wire(&mut out),
+            Telemetry::ReconfigureDH(tm) => tm.write_wire(&mut out),
+            Telemetry::ReloadConfig(tm) => tm.write_wire(&mut out),
+            Telemetry::Beacon(tm) => tm.write_wire(&mut out),
+            Telemetry::InvalidCommand(tm) => tm.write_wire(&mut out),
+            Telemetry::DHEvent(tm) => tm.write_wire(&mut out),
+        }
+        out
+    }
+
+    /// Decode telemetry encoded by to_wire()
+    pub fn from_wire(bytes: &[u8]) -> TcsResult<Telemetry> {
+        let tag = peek_tag(bytes)?;
+        let tm_type = TelemetryType::from_u8(tag)
+            .ok_or_else(|| TcsError::Protocol(format!("Unknown telemetry type 0x{:02X}", tag)))?;
+        Ok(match tm_type {
+            TelemetryType::Ping => Telemetry::Ping(decode_all(bytes)?),
+            TelemetryType::RestartArm => Telemetry::RestartArm(decode_all(bytes)?),
+            TelemetryType::Restart => Telemetry::Restart(decode_all(bytes)?),
+            TelemetryType::QueryDropped => Telemetry::QueryDropped(decode_all(bytes)?),
+            TelemetryType::QueryEndpointSupport => Telemetry::QueryEndpointSupport(decode_all(bytes)?),
+            TelemetryType::InjectFault => Telemetry::InjectFault(decode_all(bytes)?),
+            TelemetryType::QueryBeaconStatus => Telemetry::QueryBeaconStatus(decode_all(bytes)?),
+            TelemetryType::Hello => Telemetry::Hello(decode_all(bytes)?),
+            TelemetryType::SetTime => Telemetry::SetTime(decode_all(bytes)?),
+            TelemetryType::SetLogLevel => Telemetry::SetLogLevel(decode_all(bytes)?),
+            TelemetryType::StartDH => Telemetry::StartDH(decode_all(bytes)?),
+            TelemetryType::StopDH => Telemetry::StopDH(decode_all(bytes)?),
+            TelemetryType::QueryDH => Telemetry::QueryDH(decode_all(bytes)?),
+            TelemetryType::StatsSnapshot => Telemetry::StatsSnapshot(decode_all(bytes)?),
+            TelemetryType::DHLoopback => Telemetry::DHLoopback(decode_all(bytes)?),
+            TelemetryType::SubscribeDHStats => Telemetry::SubscribeDHStats(decode_all(bytes)?),
+            TelemetryType::UnsubscribeDHStats => Telemetry::UnsubscribeDHStats(decode_all(bytes)?),
+            TelemetryType::PauseAllDH => Telemetry::PauseAllDH(decode_all(bytes)?),
+            TelemetryType::ResumeAllDH => Telemetry::ResumeAllDH(decode_all(bytes)?),
+            TelemetryType::ResetStats => Telemetry::ResetStats(decode_all(bytes)?),
+            TelemetryType::ListDH => Telemetry::ListDH(decode_all(bytes)?),
+            TelemetryType::CheckpointDH => Telemetry::CheckpointDH(decode_all(bytes)?),
+            TelemetryType::DHControl => Telemetry::DHControl(decode_all(bytes)?),
+            TelemetryType::QueryActivity => Telemetry::QueryActivity(decode_all(bytes)?),
+            TelemetryType::Config => Telemetry::Config(decode_all(bytes)?),
+            TelemetryType::ConfigDH => Telemetry::ConfigDH(decode_all(bytes)?),
+            TelemetryType::ReconfigureDH => Telemetry::ReconfigureDH(decode_all(bytes)?),
+            TelemetryType::ReloadConfig => Telemetry::ReloadConfig(decode_all(bytes)?),
+            TelemetryType::Beacon => Telemetry::Beacon(decode_all(bytes)?),
+            TelemetryType::InvalidCommand => Telemetry::InvalidCommand(decode_all(bytes)?),
+            TelemetryType::DHEvent => Telemetry::DHEvent(decode_all(bytes)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> Statistics {
+        Statistics {
+            bytes_received: 123_456,
+            reads_completed: 1_000,
+            reads_failed: 2,
+            bytes_sent: 654_321,
+            writes_completed: 999,
+            writes_failed: 1,
+            messages_received: 1_000,
+            messages_sent: 999,
+            active_duration_ms: 3_600_000,
+            ..Statistics::new()
+        }
+        .with_timestamp(Timestamp::now())
+    }
+
+    fn all_commands() -> Vec<Command> {
+        let dh_config = DHConfig {
+            dh_id: DHId(3),
+            name: DHName::new("payload"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Udp,
+                address: "10.0.0.9".to_string(),
+                port: Port(5003),
+                udp_mode: UdpMode::Unconnected,
+            }),
+            packet_size: 1024,
+            packet_interval_ms: 10,
+            conduit: ConduitOptions {
+                fair_scheduling: true,
+                fault_threshold: Some(5),
+                fault_window_ms: None,
+                io_mode: IoMode::Blocking,
+                track_write_latency: true,
+                read_buffer_size: Some(512),
+                write_buffer_size: None,
+                stream_delay_ms: Some(5),
+                coalesce_ms: Some(10),
+                rate_limit_bps: Some(1_000_000),
+                write_ordering: WriteOrdering::Overlap,
+            },
+        };
+        let mut ping = PingCommand::new(1);
+        ping.header.request_id = Some(0xDEAD_BEEF_0000_0001);
+
+        vec![
+            Command::Ping(ping),
+            Command::RestartArm(RestartArmCommand::new(2, ArmKey(42))),
+            Command::Restart(RestartCommand::new(3, ArmKey(42))),
+            Command::QueryDropped(QueryDroppedCommand::new(4, true)),
+            Command::QueryEndpointSupport(QueryEndpointSupportCommand::new(5)),
+            Command::InjectFault(InjectFaultCommand::new(6, CommandType::QueryDH, CommandStatus::Timeout)),
+            Command::QueryBeaconStatus(QueryBeaconStatusCommand::new(7)),
+            Command::Hello(HelloCommand::new(8, vec![Framing::Binary, Framing::Json])),
+            Command::SetTime(SetTimeCommand::new(9, Timestamp { seconds: 1_700_000_000, nanoseconds: 999_999_999 })),
+            Command::SetLogLevel(SetLogLevelCommand::new(10, LogLevel::Debug)),
+            Command::StartDH(StartDHCommand::new(9, DHId(1), DHType::Network, DHName::new("10.0.0.1:5000:udp"))),
+            Command::StartDH(StartDHCommand::new(9, DHId(2), DHType::Device, DHName::new("/dev/ttyS0 \u{2603}"))),
+            Command::StopDH(StopDHCommand::new(10, DHId(1))),
+            Command::QueryDH(QueryDHCommand::new(11, DHId(1))),
+            Command::SnapshotStats(SnapshotStatsCommand::new(12)),
+            Command::DHLoopback(DHLoopbackCommand::new(13, DHId(1), u64::MAX, 500)),
+            Command::SubscribeDHStats(SubscribeDHStatsCommand::new(14, DHId(1), 1000)),
+            Command::UnsubscribeDHStats(UnsubscribeDHStatsCommand::new(15, DHId(1))),
+            Command::PauseAllDH(PauseAllDHCommand::new(16)),
+            Command::ResumeAllDH(ResumeAllDHCommand::new(17)),
+            Command::ResetStats(ResetStatsCommand::new(18, DHId(2))),
+            Command::ListDH(ListDHCommand::new(18)),
+            Command::CheckpointDH(CheckpointDHCommand::new(19, DHId(2))),
+            Command::DHControl(DHControlCommand::new(20, DHId(3), b"start\r\n".to_vec())),
+            Command::QueryActivity(QueryActivityCommand::new(21, 500)),
+            Command::Config(ConfigCommand::new(18, BeaconTime(2500))),
+            Command::ConfigDH(ConfigDHCommand::new(19, DHId(1))),
+            Command::ConfigDH(ConfigDHCommand {
+                read_buffer_size: Some(256),
+                rate_limit_bps: Some(u64::MAX),
+                ..ConfigDHCommand::new(19, DHId(2))
+            }),
+            Command::ReconfigureDH(ReconfigureDHCommand::new(20, DHId(3), dh_config.clone())),
+            Command::ReconfigureDH(ReconfigureDHCommand::new(20, DHId(4), DHConfig {
+                endpoint: EndpointConfig::Serial(SerialConfig {
+                    path: "/dev/ttyS1".to_string(),
+                    baud_rate: 57600,
+                    parity: Parity::Odd,
+                    stop_bits: 2,
+                }),
+                ..dh_config.clone()
+            })),
+            Command::ReconfigureDH(ReconfigureDHCommand::new(20, DHId(5), DHConfig {
+                endpoint: EndpointConfig::Unix(UnixConfig {
+                    path: "/run/tcspecial/camera.sock".to_string(),
+                    mode: UnixMode::Datagram,
+                }),
+                ..dh_config
+            })),
+            Command::ReloadConfig(ReloadConfigCommand::new(21)),
+        ]
+    }
+
+    fn all_telemetry() -> Vec<Telemetry> {
+        let ok = CommandStatus::Success;
+        let dh_entry = |id, dh_type, name: &str, state| DHListEntry {
+            dh_id: DHId(id),
+            dh_type,
+            name: DHName::new(name),
+            state,
+        };
+        let mut ping = PingTelemetry::new(1, ok);
+        ping.header.request_id = Some(77);
+
+        vec![
+            Telemetry::Ping(ping),
+            Telemetry::RestartArm(RestartArmTelemetry::new(2, CommandStatus::NotArmed)),
+            Telemetry::Restart(RestartTelemetry::new(3, CommandStatus::InvalidArmKey)),
+            Telemetry::QueryDropped(QueryDroppedTelemetry::new(4, ok, 10, 20, 30)),
+            Telemetry::QueryEndpointSupport(QueryEndpointSupportTelemetry::new(
+                5, ok, vec![DHType::Network, DHType::Device], vec![NetworkProtocol::Tcp, NetworkProtocol::UnixDgram])),
+            Telemetry::InjectFault(InjectFaultTelemetry::new(6, ok)),
+            Telemetry::QueryBeaconStatus(QueryBeaconStatusTelemetry::new(7, ok, vec![
+                BeaconDestinationStatus {
+                    address: "127.0.0.1:4001".parse().unwrap(),
+                    sent: 5,
+                    failed: 0,
+                    interval_override: Some(BeaconTime(1000)),
+                },
+                BeaconDestinationStatus {
+                    address: "[::1]:4002".parse().unwrap(),
+                    sent: 0,
+                    failed: 3,
+                    interval_override: None,
+                },
+            ])),
+            Telemetry::Hello(HelloTelemetry::new(8, ok, Framing::Binary)),
+            Telemetry::SetTime(SetTimeTelemetry::new(
+                9,
+                ok,
+                Timestamp { seconds: 1_700_000_000, nanoseconds: 0 },
+                Timestamp { seconds: 1_699_999_000, nanoseconds: 12 },
+            )),
+            Telemetry::SetLogLevel(SetLogLevelTelemetry::new(10, ok, LogLevel::Trace, LogLevel::Info)),
+            Telemetry::StartDH(
+                StartDHTelemetry::new(9, CommandStatus::AlreadyExists)
+                    .with_error(ErrorCode::DataHandler)
+                    .with_detail("Data handler already exists: 1"),
+            ),
+            Telemetry::StartDH(
+                StartDHTelemetry::new(9, ok).with_outcome(StartDHOutcome::Reactivated).with_oc_port(Port(40000)),
+            ),
+            Telemetry::StopDH(StopDHTelemetry::new(10, ok)),
+            Telemetry::StopDH(StopDHTelemetry::new(10, CommandStatus::Failure).with_detail("Thread join failed")),
+            Telemetry::QueryDH(QueryDHTelemetry::new(11, ok, DHId(1), stats()).with_state(DHState::Paused)),
+            Telemetry::QueryDH(QueryDHTelemetry::not_found(11, DHId(9), Some(vec![DHId(1), DHId(2)]))),
+            Telemetry::QueryDH(QueryDHTelemetry::new(11, ok, DHId(1), stats()).with_write_latency(Some(WriteLatency {
+                samples: 100,
+                min_us: 3,
+                max_us: 20_000,
+                p50_us: 4,
+                p99_us: 18_431,
+            }))),
+            Telemetry::QueryDH(QueryDHTelemetry::new(11, ok, DHId(1), stats()).with_since_checkpoint(Some(Statistics {
+                bytes_received: 10,
+                ..Statistics::new()
+            }))),
+            Telemetry::StatsSnapshot(StatsSnapshotTelemetry::new(12, ok, Timestamp::now(), stats(), vec![
+                DHStatistics { dh_id: DHId(1), statistics: stats() },
+                DHStatistics { dh_id: DHId(2), statistics: Statistics::new() },
+            ])),
+            Telemetry::DHLoopback(DHLoopbackTelemetry::new(13, ok, DHId(1), 99, Some(1500))),
+            Telemetry::SubscribeDHStats(SubscribeDHStatsTelemetry::new(14, ok, DHId(1))),
+            Telemetry::UnsubscribeDHStats(UnsubscribeDHStatsTelemetry::new(15, CommandStatus::NotFound, DHId(1))),
+            Telemetry::PauseAllDH(PauseAllDHTelemetry::new(16, ok, 3)),
+            Telemetry::ResumeAllDH(ResumeAllDHTelemetry::new(17, ok, 3)),
+            Telemetry::ResetStats(ResetStatsTelemetry::new(18, CommandStatus::NotFound, DHId(2))),
+            Telemetry::ListDH(ListDHTelemetry::new(18, ok, vec![dh_entry(0, DHType::Network, "uplink", DHState::Active)])),
+            Telemetry::ListDH(ListDHTelemetry::new(
+                18,
+                ok,
+                vec![dh_entry(0, DHType::Network, "", DHState::Paused), dh_entry(3, DHType::Device, "tty", DHState::Faulted)],
+            )),
+            Telemetry::CheckpointDH(CheckpointDHTelemetry::new(19, CommandStatus::NotFound, DHId(2))),
+            Telemetry::DHControl(DHControlTelemetry::new(20, CommandStatus::Timeout, DHId(3))),
+            Telemetry::QueryActivity(QueryActivityTelemetry::new(
+                21,
+                ok,
+                500,
+                vec![
+                    DHActivity { dh_id: DHId(0), active: true, idle_ms: Some(12) },
+                    DHActivity { dh_id: DHId(4), active: false, idle_ms: None },
+                ],
+            )),
+            Telemetry::Config(ConfigTelemetry::new(18, ok).with_beacon_interval(BeaconTime(2500))),
+            Telemetry::ConfigDH(ConfigDHTelemetry::new(19, ok)),
+            Telemetry::ReconfigureDH(
+                ReconfigureDHTelemetry::new(20, CommandStatus::InvalidParameter, DHId(3))
+                    .with_detail("Configuration error: Empty device path"),
+            ),
+            Telemetry::ReloadConfig(ReloadConfigTelemetry::new(21, CommandStatus::Failure)),
+            Telemetry::ReloadConfig(ReloadConfigTelemetry::new(22, ok).with_summary(ReloadSummary {
+                added: vec![DHId(4)],
+                removed: vec![DHId(0), DHId(1)],
+                reconfigured: vec![],
+                unchanged: vec![DHId(2)],
+                failed: vec![DHId(3)],
+            })),
+            Telemetry::Beacon(BeaconTelemetry::new()),
+            Telemetry::Beacon(BeaconTelemetry::extended(41, 7, BeaconTime(5000)).with_start(StartReason::CommandedRestart, 12_345)),
+            Telemetry::Beacon(BeaconTelemetry::extended(42, 7, BeaconTime(5000)).with_health(BeaconHealth {
+                active_dh_count: 3,
+                uptime_secs: 12,
+                statistics: stats(),
+            })),
+            Telemetry::Beacon(BeaconTelemetry::extended(43, 7, BeaconTime(5000)).with_health(BeaconHealth::default())),
+            Telemetry::InvalidCommand(InvalidCommandTelemetry::new("Undecodable command")),
+            Telemetry::DHEvent(DHEventTelemetry::new(1, DHId(4), DHEventKind::Disconnected)),
+            Telemetry::DHEvent(DHEventTelemetry::new(2, DHId(4), DHEventKind::Error(ErrorCode::Endpoint))),
+        ]
+    }
+
+    #[test]
+    fn test_command_wire_round_trip() {
+        for command in all_commands() {
+            let bytes = command.to_wire();
+            assert_eq!(bytes[0], command.cmd_type().to_u8());
+            assert_eq!(Command::from_wire(&bytes).unwrap(), command);
+
+            // Truncated messages and trailing bytes are rejected
+            assert!(Command::from_wire(&bytes[..bytes.len() - 1]).is_err());
+            let mut padded = bytes.clone();
+            padded.push(0);
+            assert!(Command::from_wire(&padded).is_err());
+        }
+    }
+
+    #[test]
+    fn test_telemetry_wire_round_trip() {
+        for telemetry in all_telemetry() {
+            let bytes = telemetry.to_wire();
+            assert_eq!(bytes[0], telemetry.tm_type().to_u8());
+            assert_eq!(Telemetry::from_wire(&bytes).unwrap(), telemetry);
+            assert!(Telemetry::from_wire(&bytes[..bytes.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_wire_rejects_unknown_tags() {
+        match Command::from_wire(&[0x7F, 0, 0, 0, 1, 0]) {
+            Err(TcsError::Protocol(msg)) => assert!(msg.contains("0x7F"), "{}", msg),
+            other => panic!("Decoded {:?}", other),
+        }
+        assert!(Command::from_wire(&Telemetry::Ping(PingTelemetry::new(1, CommandStatus::Success)).to_wire()).is_err());
+        assert!(Telemetry::from_wire(&Command::Ping(PingCommand::new(1)).to_wire()).is_err());
+        assert!(Command::from_wire(&[]).is_err());
+
+        // A bad variant index inside a message is caught too
+        let mut bytes = Command::StartDH(StartDHCommand::new(1, DHId(1), DHType::Device, DHName::new("/dev/x"))).to_wire();
+        bytes[10] = 9;
+        assert!(matches!(Command::from_wire(&bytes), Err(TcsError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_wire_smaller_than_json() {
+        let telemetry = Telemetry::QueryDH(QueryDHTelemetry::new(11, CommandStatus::Success, DHId(1), stats())
+            .with_state(DHState::Active));
+        let binary = telemetry.to_wire();
+        let json = serde_json::to_vec(&telemetry).unwrap();
+        assert!(binary.len() * 3 < json.len(), "binary {} bytes, JSON {} bytes", binary.len(), json.len());
+    }
+}
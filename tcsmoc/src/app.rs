@@ -1,7 +1,7 @@
 //! Application state and logic for tcsmoc
 
 use std::time::{SystemTime, UNIX_EPOCH};
-use tcslibgs::Statistics;
+use tcslibgs::{DHState, Statistics};
 
 /// Format a timestamp for display
 pub fn format_timestamp(seconds: u64, _nanos: u32) -> String {
@@ -54,7 +54,7 @@ impl DHDisplayState {
     pub fn new(dh_id: u32) -> Self {
         Self {
             dh_id,
-            status: "Stopped".to_string(),
+            status: DHState::Stopped.to_string(),
             last_sent_time: "--:--:--".to_string(),
             last_sent_data: String::new(),
             last_recv_time: "--:--:--".to_string(),
@@ -2,14 +2,17 @@
  * Receive beacon messages
  */
 
-use std::net::UdpSocket;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
-use std::time::{Duration, SystemTime};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 use slint::{Color, Weak};
 
 use crate::MainWindow;
-use tcslibgs::TcsResult;
+use tcslibgs::{BeaconTelemetry, ProtocolMessage, TcsResult, Telemetry};
+use tcspecial::endpoint::bind_udp;
 
 const DEBUG_BEACON: bool = false;
 
@@ -166,57 +169,218 @@ impl IndicatorStates {
 }
 
 /*
+ * Counts of received beacons
+ * received         Beacon messages received
+ * lost             Beacons estimated lost from gaps in the beacon sequence
+ * last_interval    Time between the last two beacons
+ * total_interval   Sum of the times between beacons, for the average
+ * last_sequence    Sequence number of the last extended beacon
+ * last_arrival     Time the last beacon arrived
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BeaconStats {
+    pub received:       u64,
+    pub lost:           u64,
+    pub last_interval:  Option<Duration>,
+    total_interval:     Duration,
+    last_sequence:      Option<u32>,
+    last_arrival:       Option<Instant>,
+}
+
+impl BeaconStats {
+    /*
+     * Record a beacon arriving at now. sequence is None for legacy beacons,
+     * which can't show loss. A sequence that doesn't advance means the sender
+     * restarted, so it starts a new run rather than counting as loss.
+     */
+    fn record(&mut self, sequence: Option<u32>, now: Instant) {
+        if let Some(last) = self.last_arrival {
+            let interval = now.saturating_duration_since(last);
+            self.last_interval = Some(interval);
+            self.total_interval = self.total_interval.saturating_add(interval);
+        }
+        self.received += 1;
+        self.last_arrival = Some(now);
+
+        if let Some(sequence) = sequence {
+            if let Some(last) = self.last_sequence {
+                if sequence > last {
+                    self.lost += (sequence - last - 1) as u64;
+                }
+            }
+            self.last_sequence = Some(sequence);
+        }
+    }
+
+    /*
+     * Average time between beacons, None until two have been received
+     */
+    pub fn average_interval(&self) -> Option<Duration> {
+        match self.received {
+            0 | 1 => None,
+            n => Some(self.total_interval / (n - 1).min(u32::MAX as u64) as u32),
+        }
+    }
+
+    /*
+     * Fraction of beacons lost, from 0.0 to 1.0
+     */
+    pub fn loss_rate(&self) -> f64 {
+        let expected = self.received + self.lost;
+        if expected == 0 {
+            0.0
+        } else {
+            self.lost as f64 / expected as f64
+        }
+    }
+}
+
+/*
+ * Summary for display, e.g. "12 rcvd, 1 lost (7.7%), every 1.0s, last 1.0s"
+ */
+impl fmt::Display for BeaconStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rcvd, {} lost ({:.1}%)", self.received, self.lost, self.loss_rate() * 100.0)?;
+        if let Some(average) = self.average_interval() {
+            write!(f, ", every {:.1}s", average.as_secs_f64())?;
+        }
+        if let Some(last) = self.last_interval {
+            write!(f, ", last {:.1}s", last.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Receive thread handle. Stopping or dropping it stops and joins the thread.
+ * stop         Set to ask the receive thread to exit
+ * wake_addr    Address to send to so the receive thread notices stop
+ * handle       Receive thread
+ * stats        Counts of received beacons, updated by the receive thread
+ */
+pub struct BeaconReceive {
+    stop:               Arc<AtomicBool>,
+    wake_addr:          SocketAddr,
+    handle:             Option<JoinHandle<()>>,
+    stats:              Arc<Mutex<BeaconStats>>,
+}
+
+/*
+ * State used by the receive thread
  * last_beacon  Time of last received beacon message
- * src_addr     Address from which to receive beacon messages
  * ui_weak      Slint window with beacon information
  * indicators   Indicator state configuration
+ * stop         Set when the thread should exit
+ * stats        Counts of received beacons
  */
 #[derive(Clone)]
-pub struct BeaconReceive {
+struct BeaconReceiver {
     last_beacon:        ArcCondPair<Option<SystemTime>>,
-    src_addr:           std::net::SocketAddr,
     ui_weak:            Weak<MainWindow>,
     indicator_states:   IndicatorStates,
+    stop:               Arc<AtomicBool>,
+    stats:              Arc<Mutex<BeaconStats>>,
 }
 
 impl BeaconReceive {
+    /*
+     * Bind src_addr and start receiving beacons on it. Returns None if the
+     * address can't be bound. reuse_address sets SO_REUSEADDR so a restarted
+     * tcsmoc can rebind immediately.
+     */
     pub fn new(
         ui_weak:            Weak<MainWindow>,
-        src_addr:           std::net::SocketAddr,
+        src_addr:           SocketAddr,
         indicator_states:   IndicatorStates,
+        reuse_address:      bool,
     ) -> Option<BeaconReceive> {
-        let last_beacon = Arc::new(CondPair {
-            lock: Mutex::new(None),
-            cvar: Condvar::new(),
-        });
+        let socket = match bind_udp(src_addr, reuse_address) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("BeaconReceive: unable to bind {}: {}", src_addr, e);
+                return None;
+            }
+        };
+
+        // A wildcard bind is woken through the loopback address
+        let mut wake_addr = socket.local_addr().ok()?;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
 
-        let b = BeaconReceive {
-            last_beacon,
-            src_addr,
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(Mutex::new(BeaconStats::default()));
+        let receiver = BeaconReceiver {
+            last_beacon: Arc::new(CondPair {
+                lock: Mutex::new(None),
+                cvar: Condvar::new(),
+            }),
             ui_weak,
             indicator_states,
+            stop: stop.clone(),
+            stats: stats.clone(),
         };
 
-        let b_clone = b.clone();
-        thread::spawn(move || {
-            if let Err(e) = b_clone.receive_beacon() {
-                panic!("Beacon receive error: {}", e);
+        let handle = thread::spawn(move || {
+            if let Err(e) = receiver.receive_beacon(socket) {
+                eprintln!("Beacon receive error: {}", e);
             }
         });
 
-        Some(b)
+        Some(BeaconReceive {
+            stop,
+            wake_addr,
+            handle: Some(handle),
+            stats,
+        })
+    }
+
+    /*
+     * Get a snapshot of the beacon counts
+     */
+    pub fn stats(&self) -> BeaconStats {
+        *self.stats.lock().unwrap()
     }
 
     /*
-     * Receive beacon messages in a loop
+     * Stop the receive thread and wait for it to exit
      */
-    fn receive_beacon(&self) -> TcsResult<()> {
-        // Bind to a local address to receive messages
-        let socket = UdpSocket::bind(self.src_addr)?;
+    pub fn stop(&mut self) {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        self.stop.store(true, Ordering::SeqCst);
+
+        // Wake the thread if it is blocked waiting for a beacon
+        if let Ok(socket) = UdpSocket::bind(SocketAddr::new(self.wake_addr.ip(), 0)) {
+            let _ = socket.send_to(&[], self.wake_addr);
+        }
 
+        if handle.join().is_err() {
+            eprintln!("BeaconReceive: receive thread panicked");
+        }
+    }
+}
+
+impl Drop for BeaconReceive {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl BeaconReceiver {
+    /*
+     * Receive beacon messages in a loop until asked to stop
+     */
+    fn receive_beacon(&self, socket: UdpSocket) -> TcsResult<()> {
         let mut buf = [0u8; 65535];
 
-        loop {
+        while !self.stop.load(Ordering::SeqCst) {
             // Get current color and timeout duration
             let last_beacon_guard = self.last_beacon.lock.lock().unwrap();
             let last_beacon_value = *last_beacon_guard;
@@ -232,7 +396,10 @@ eprintln!("First: timeout {:?} color {:?}", timeout, color);
 
             // Receive beacon data from socket (or timeout)
             let status = socket.recv_from(&mut buf);
-            
+            if self.stop.load(Ordering::SeqCst) {
+                break;
+            }
+
             let new_color = match status {
                 Ok((size, addr)) => {
 //eprintln!("beacon received {} bytes from {}", size, addr);
@@ -243,6 +410,32 @@ eprintln!("First: timeout {:?} color {:?}", timeout, color);
                     let last_beacon_value = *last_beacon_guard;
                     drop(last_beacon_guard);
 
+                    let beacon = beacon(&buf[..size]);
+                    if let Some(beacon) = &beacon {
+                        let mut stats = self.stats.lock().unwrap();
+                        stats.record(beacon.beacon_sequence, Instant::now());
+                        let summary = stats.to_string();
+                        drop(stats);
+
+                        let ui_weak = self.ui_weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_weak.upgrade() {
+                                ui.set_beacon_stats(summary.into());
+                            }
+                        });
+                    }
+
+                    // Show the spacecraft uptime from extended beacons
+                    if let Some(health) = beacon.and_then(|beacon| beacon.health) {
+                        let uptime = format_uptime(health.uptime_secs);
+                        let ui_weak = self.ui_weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_weak.upgrade() {
+                                ui.set_beacon_uptime(uptime.into());
+                            }
+                        });
+                    }
+
                     // Recalculate color after receiving
                     let (_, color) = self.indicator_states.delay_and_color(&last_beacon_value);
 if DEBUG_BEACON {
@@ -281,6 +474,8 @@ eprintln!("Timedout: timeout {:?} color {:?}", timeout, color);
                 });
             }
         }
+
+        Ok(())
     }
 
 /*
@@ -349,9 +544,121 @@ use crate::config::constants::BEACON_INDICATOR; // Adjust path as needed
 */
 }
 
+/*
+ * Decode a beacon message. Returns None for anything that isn't a beacon.
+ */
+fn beacon(data: &[u8]) -> Option<BeaconTelemetry> {
+    match ProtocolMessage::from_bytes(data).ok()?.into_telemetry().ok()? {
+        Telemetry::Beacon(beacon) => Some(beacon),
+        _ => None,
+    }
+}
+
+/*
+ * Format an uptime in seconds as [days d ]hh:mm:ss
+ */
+fn format_uptime(secs: u64) -> String {
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    let hms = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    if days == 0 {
+        hms
+    } else {
+        format!("{}d {}", days, hms)
+    }
+}
+
 type ArcCondPair<T> = Arc<CondPair<T>>;
 
 struct CondPair<T> {
     lock: Mutex<T>,
     cvar: Condvar,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beacon_receive_stops_on_drop() {
+        let indicators = IndicatorStates::new(Color::from_rgb_u8(0, 0, 0), vec![]);
+        let receive = BeaconReceive::new(Weak::default(), "127.0.0.1:0".parse().unwrap(), indicators, true)
+            .unwrap();
+        let stop = receive.stop.clone();
+
+        // With no beacons the thread is blocked in recv_from; drop must wake and join it
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            drop(receive);
+            let _ = done_tx.send(());
+        });
+
+        assert!(done_rx.recv_timeout(Duration::from_secs(2)).is_ok());
+        assert!(stop.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_beacon_health() {
+        use tcslibgs::{BeaconHealth, BeaconTelemetry, BeaconTime};
+
+        let health = BeaconHealth { active_dh_count: 1, uptime_secs: 90_061, ..BeaconHealth::default() };
+        let extended = BeaconTelemetry::extended(1, 0, BeaconTime(1000)).with_health(health);
+        let data = ProtocolMessage::from_telemetry(Telemetry::Beacon(extended)).to_bytes().unwrap();
+        assert_eq!(beacon(&data).and_then(|beacon| beacon.health), Some(health));
+        assert_eq!(format_uptime(health.uptime_secs), "1d 01:01:01");
+        assert_eq!(format_uptime(59), "00:00:59");
+
+        let legacy = ProtocolMessage::from_telemetry(Telemetry::Beacon(BeaconTelemetry::new())).to_bytes().unwrap();
+        assert_eq!(beacon(&legacy).and_then(|beacon| beacon.health), None);
+        assert!(beacon(&[0xff]).is_none());
+    }
+
+    #[test]
+    fn test_beacon_stats() {
+        use tcslibgs::BeaconTime;
+
+        let start = Instant::now();
+        let mut stats = BeaconStats::default();
+        assert_eq!(stats.average_interval(), None);
+        assert_eq!(stats.loss_rate(), 0.0);
+
+        // Sequence 3 is missing
+        for (i, sequence) in [1, 2, 4, 5].into_iter().enumerate() {
+            stats.record(Some(sequence), start + Duration::from_millis(100 * i as u64));
+        }
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.loss_rate(), 0.2);
+        assert_eq!(stats.last_interval, Some(Duration::from_millis(100)));
+        assert_eq!(stats.average_interval(), Some(Duration::from_millis(100)));
+
+        // A sender restart and legacy beacons don't count as loss
+        stats.record(Some(0), start + Duration::from_millis(400));
+        stats.record(None, start + Duration::from_millis(600));
+        assert_eq!(stats.received, 6);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.last_interval, Some(Duration::from_millis(200)));
+        assert_eq!(stats.average_interval(), Some(Duration::from_millis(120)));
+        assert_eq!(stats.to_string(), "6 rcvd, 1 lost (14.3%), every 0.1s, last 0.2s");
+        assert_eq!(BeaconStats::default().to_string(), "0 rcvd, 0 lost (0.0%)");
+
+        // Beacons over the network are counted by the receive thread
+        let indicators = IndicatorStates::new(Color::from_rgb_u8(0, 0, 0), vec![]);
+        let receive = BeaconReceive::new(Weak::default(), "127.0.0.1:0".parse().unwrap(), indicators, true)
+            .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for sequence in [7, 8, 10] {
+            let beacon = BeaconTelemetry::extended(sequence, 0, BeaconTime(1000));
+            let data = ProtocolMessage::from_telemetry(Telemetry::Beacon(beacon)).to_bytes().unwrap();
+            sender.send_to(&data, receive.wake_addr).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while receive.stats().received < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = receive.stats();
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.lost, 1);
+        assert!(stats.last_interval.is_some());
+    }
+}
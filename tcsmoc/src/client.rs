@@ -1,33 +1,453 @@
 //! TCSpecial client for ground software integration
 
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tcslibgs::{
-    ArmKey, BeaconTime, Command, CommandStatus, ConfigCommand, DHId, DHName, DHType,
-    PingCommand, QueryDHCommand, RestartArmCommand, RestartCommand, StartDHCommand,
-    Statistics, StopDHCommand, TcsError, TcsResult, Telemetry,
+    ArmKey, BeaconTelemetry, BeaconTime, Command, CommandStatus, CommandType, ConfigCommand, ConfigDHCommand, DHConfig, DHId,
+    CheckpointDHCommand, DHActivity, DHControlCommand, DHListEntry, DHLoopbackCommand, DHLoopbackTelemetry, DHName, DHState, DHType, Framing, HelloCommand,
+    InjectFaultCommand, ListDHCommand, LogLevel, MAX_MESSAGE_SIZE, NetworkProtocol, PauseAllDHCommand, PingCommand, Port,
+    ProtocolMessage, QueryBeaconStatusCommand, QueryActivityCommand, QueryBeaconStatusTelemetry, QueryDHCommand, QueryDHTelemetry, QueryDroppedCommand, QueryDroppedTelemetry,
+    QueryEndpointSupportCommand, QueryEndpointSupportTelemetry, ReconfigureDHCommand, ReloadConfigCommand,
+    ReloadConfigTelemetry, ResetStatsCommand, RestartArmCommand, RestartCommand, ResumeAllDHCommand, SetLogLevelCommand,
+    SetLogLevelTelemetry, SetTimeCommand, SetTimeTelemetry, SnapshotStatsCommand, StartDHCommand, StartDHTelemetry,
+    Statistics, StatsSnapshotTelemetry, StopDHCommand, SubscribeDHStatsCommand, TcsError, TcsResult, Telemetry, Timestamp, UnsubscribeDHStatsCommand,
 };
 
-use tcslib::Connection;
+use tcslib::{Connection, TcpConnection, UdpConnection};
+use tcspecial::config::load_payload_config;
+use tcspecial::endpoint::bind_udp;
 
 /// Default timeout for command responses
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// How often provision_dh queries a DH while waiting for it to come up
+const PROVISION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Unsolicited telemetry kept for recv_async_telemetry; the oldest is
+/// dropped beyond this
+const UNSOLICITED_QUEUE_DEPTH: usize = 64;
+
+/// How often the background receiver checks the connection for telemetry,
+/// and the longest it holds the connection for each read
+const RECEIVER_POLL: Duration = Duration::from_millis(20);
+
+/// Telemetry the background receiver holds for the client; the oldest is
+/// dropped beyond this
+const INBOX_DEPTH: usize = 256;
+
+/// CI port used when a URL does not give one
+pub const DEFAULT_CI_PORT: u16 = 4000;
+
+/// Sequence number for a new session, taken from the clock so that it is
+/// unlikely to reuse the recent sequence numbers of an earlier session
+pub fn time_seeded_sequence() -> u32 {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    millis as u32
+}
+
+/// Split a URL such as "udp://host:4000" into its transport and address
+///
+/// The scheme defaults to udp and the port to DEFAULT_CI_PORT. IPv6 hosts
+/// must be bracketed, as in "tcp://[::1]:4000".
+pub fn parse_url(url: &str) -> TcsResult<(NetworkProtocol, String)> {
+    let (protocol, rest) = match url.split_once("://") {
+        Some(("udp", rest)) => (NetworkProtocol::Udp, rest),
+        Some(("tcp", rest)) => (NetworkProtocol::Tcp, rest),
+        Some((scheme, _)) => return Err(TcsError::Config(format!("Unsupported URL scheme: {}", scheme))),
+        None => (NetworkProtocol::Udp, url),
+    };
+
+    let host = rest.trim_end_matches('/');
+    if host.is_empty() {
+        return Err(TcsError::Config(format!("Missing host in URL: {}", url)));
+    }
+
+    let has_port = match host.strip_prefix('[') {
+        Some(bracketed) => !bracketed.ends_with(']'),
+        None => host.contains(':'),
+    };
+    let address = if has_port {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, DEFAULT_CI_PORT)
+    };
+
+    Ok((protocol, address))
+}
+
+/// High-level description of a data handler to start
+///
+/// Builds a DHName in the grammar tcspecial expects, validating it first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhSpec {
+    name: DHName,
+    dh_type: DHType,
+}
+
+impl DhSpec {
+    /// Describe a network payload reached at host:port
+    pub fn network(host: &str, port: u16, protocol: NetworkProtocol) -> TcsResult<Self> {
+        if host.is_empty() {
+            return Err(TcsError::Config("DH host must not be empty".to_string()));
+        }
+        let port = Port::new(port).map_err(|_| TcsError::Config(format!("Invalid port {} for DH host {}", port, host)))?;
+
+        let name = DHName::new(format!("{}:{}:{}", host, port, protocol));
+        name.address()?;
+        Ok(Self {
+            name,
+            dh_type: DHType::Network,
+        })
+    }
+
+    /// Describe a device payload at an absolute path
+    pub fn device(path: &str) -> TcsResult<Self> {
+        if !path.starts_with('/') {
+            return Err(TcsError::Config(format!("Device path {} must be absolute", path)));
+        }
+
+        Ok(Self {
+            name: DHName::new(path),
+            dh_type: DHType::Device,
+        })
+    }
+
+    pub fn name(&self) -> &DHName {
+        &self.name
+    }
+
+    pub fn dh_type(&self) -> DHType {
+        self.dh_type
+    }
+}
+
+/// Bounded history of the most recently received telemetry
+///
+/// Clones share the same history, so one can be handed to a receive thread
+/// while another is read by the UI.
+#[derive(Clone)]
+pub struct TelemetryHistory {
+    capacity: usize,
+    items: Arc<Mutex<VecDeque<Telemetry>>>,
+}
+
+impl TelemetryHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Record telemetry, discarding the oldest item if full
+    pub fn push(&self, telemetry: Telemetry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut items = self.items.lock().unwrap();
+        if items.len() == self.capacity {
+            items.pop_front();
+        }
+        items.push_back(telemetry);
+    }
+
+    /// Get the recorded telemetry, newest first
+    pub fn recent(&self) -> Vec<Telemetry> {
+        self.items.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+/// Called with each beacon the background receiver gets
+pub type BeaconCallback = Box<dyn FnMut(BeaconTelemetry) + Send>;
+
+/// Called each time an interval passes without a beacon
+pub type BeaconTimeoutCallback = Box<dyn FnMut() + Send>;
+
+/// What the background receiver does with beacons
+///
+/// The callbacks are shared so they can be called without the handlers
+/// locked, leaving a callback free to register others.
+struct BeaconHandlers {
+    beacon: Option<Arc<Mutex<BeaconCallback>>>,
+    timeout: Option<(Duration, Arc<Mutex<BeaconTimeoutCallback>>)>,
+    /// When the last beacon arrived, or the timeout last fired
+    last_beacon: Instant,
+}
+
+/// Give a beacon to the beacon callback or, if there is none, to the inbox
+fn dispatch_beacon(handlers: &Mutex<BeaconHandlers>, inbox: &Inbox, beacon: BeaconTelemetry) {
+    let callback = {
+        let mut handlers = handlers.lock().unwrap();
+        handlers.last_beacon = Instant::now();
+        handlers.beacon.clone()
+    };
+    match callback {
+        Some(callback) => (callback.lock().unwrap())(beacon),
+        None => inbox.push(Ok(Telemetry::Beacon(beacon))),
+    }
+}
+
+/// Call the timeout callback if a whole interval has passed without a
+/// beacon
+fn check_beacon_timeout(handlers: &Mutex<BeaconHandlers>) {
+    let callback = {
+        let mut handlers = handlers.lock().unwrap();
+        match &handlers.timeout {
+            Some((interval, callback)) if handlers.last_beacon.elapsed() >= *interval => {
+                let callback = callback.clone();
+                handlers.last_beacon = Instant::now();
+                callback
+            }
+            _ => return,
+        }
+    };
+    (callback.lock().unwrap())();
+}
+
+#[derive(Default)]
+struct InboxState {
+    items: VecDeque<TcsResult<Telemetry>>,
+    /// The background receiver has stopped
+    closed: bool,
+}
+
+/// Telemetry, other than beacons, passed from the background receiver to
+/// the client
+#[derive(Default)]
+struct Inbox {
+    state: Mutex<InboxState>,
+    ready: Condvar,
+}
+
+impl Inbox {
+    fn push(&self, item: TcsResult<Telemetry>) {
+        let mut state = self.state.lock().unwrap();
+        if state.items.len() == INBOX_DEPTH {
+            state.items.pop_front();
+        }
+        state.items.push_back(item);
+        self.ready.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_one();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().items.is_empty()
+    }
+
+    /// Take the next item, waiting up to timeout or, if None, for ever
+    fn pop(&self, timeout: Option<Duration>) -> TcsResult<Telemetry> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return item;
+            }
+            if state.closed {
+                return Err(TcsError::ConnectionClosed);
+            }
+            state = match deadline {
+                None => self.ready.wait(state).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(TcsError::Timeout);
+                    }
+                    self.ready.wait_timeout(state, remaining).unwrap().0
+                }
+            };
+        }
+    }
+}
+
+/// Threads reading the connection, and the beacon socket if there is one,
+/// so beacons are handled while the client is idle; they stop when dropped
+struct BackgroundReceiver {
+    inbox: Arc<Inbox>,
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BackgroundReceiver {
+    fn start(
+        connection: Arc<Mutex<Box<dyn Connection>>>,
+        handlers: Arc<Mutex<BeaconHandlers>>,
+        beacon_socket: Option<Arc<UdpSocket>>,
+    ) -> Self {
+        let inbox = Arc::new(Inbox::default());
+        let running = Arc::new(AtomicBool::new(true));
+        let mut threads = Vec::new();
+        if let Some(socket) = beacon_socket {
+            let (handlers, inbox, running) = (handlers.clone(), inbox.clone(), running.clone());
+            threads.push(thread::spawn(move || beacon_loop(&socket, &handlers, &inbox, &running)));
+        }
+        let (inbox_clone, running_clone) = (inbox.clone(), running.clone());
+        threads.push(thread::spawn(move || receive_loop(&connection, &handlers, &inbox_clone, &running_clone)));
+        Self { inbox, running, threads }
+    }
+}
+
+impl Drop for BackgroundReceiver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read the connection until stopped, giving beacons to the beacon callback
+/// and everything else to the inbox
+///
+/// An I/O error ends the loop, since the connection is unlikely to recover
+/// without being replaced. The connection is only read once it has data, so
+/// an idle link doesn't spend its time timing out reads.
+fn receive_loop(
+    connection: &Mutex<Box<dyn Connection>>,
+    handlers: &Mutex<BeaconHandlers>,
+    inbox: &Inbox,
+    running: &AtomicBool,
+) {
+    while running.load(Ordering::SeqCst) {
+        let result = match connection.lock() {
+            Ok(mut connection) => match connection.has_data() {
+                Ok(true) => Some(connection.receive_timeout(RECEIVER_POLL)),
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            },
+            Err(_) => break,
+        };
+
+        match result {
+            None => thread::sleep(RECEIVER_POLL),
+            Some(Ok(Telemetry::Beacon(beacon))) => dispatch_beacon(handlers, inbox, beacon),
+            Some(Err(TcsError::Timeout)) => {}
+            Some(Err(e @ (TcsError::Io(_) | TcsError::ConnectionClosed))) => {
+                inbox.push(Err(e));
+                break;
+            }
+            Some(result) => inbox.push(result),
+        }
+        check_beacon_timeout(handlers);
+    }
+    inbox.close();
+}
+
+/// Read beacons from their own socket until stopped, handling them as
+/// beacons read from the connection are
+///
+/// Anything else sent to the socket is dropped.
+fn beacon_loop(socket: &UdpSocket, handlers: &Mutex<BeaconHandlers>, inbox: &Inbox, running: &AtomicBool) {
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    while running.load(Ordering::SeqCst) {
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(_) => break,
+        };
+        if let Ok(Telemetry::Beacon(beacon)) =
+            ProtocolMessage::from_bytes(&buffer[..size]).and_then(ProtocolMessage::into_telemetry)
+        {
+            dispatch_beacon(handlers, inbox, beacon);
+        }
+    }
+}
+
 /// TCSpecial client for sending commands and receiving telemetry
 pub struct TcsClient {
-    connection: Box<dyn Connection>,
+    connection: Arc<Mutex<Box<dyn Connection>>>,
     sequence: AtomicU32,
     timeout: Duration,
+    history: Option<TelemetryHistory>,
+    framing: Framing,
+    request_id: Option<u64>,
+    max_telemetry_size: Option<usize>,
+    /// Times a command is resent after its reply times out
+    retries: u32,
+    /// Pause before each resend
+    retry_backoff: Duration,
+    /// Unsolicited telemetry received while waiting for command replies
+    unsolicited: VecDeque<Telemetry>,
+    beacon_handlers: Arc<Mutex<BeaconHandlers>>,
+    /// Where beacons are received, if not only on the connection
+    beacon_socket: Option<Arc<UdpSocket>>,
+    /// Reads the connection once a beacon callback is registered
+    receiver: Option<BackgroundReceiver>,
 }
 
 impl TcsClient {
     /// Create a new client with the given connection
     pub fn new(connection: Box<dyn Connection>) -> Self {
         Self {
-            connection,
+            connection: Arc::new(Mutex::new(connection)),
             sequence: AtomicU32::new(1),
             timeout: DEFAULT_TIMEOUT,
+            history: None,
+            framing: Framing::Json,
+            request_id: None,
+            max_telemetry_size: None,
+            retries: 0,
+            retry_backoff: Duration::ZERO,
+            unsolicited: VecDeque::new(),
+            beacon_handlers: Arc::new(Mutex::new(BeaconHandlers {
+                beacon: None,
+                timeout: None,
+                last_beacon: Instant::now(),
+            })),
+            beacon_socket: None,
+            receiver: None,
+        }
+    }
+
+    /// Create a client connected to the CI named by a URL
+    ///
+    /// See parse_url for the accepted forms.
+    pub fn from_url(url: &str) -> TcsResult<Self> {
+        let connection: Box<dyn Connection> = match parse_url(url)? {
+            (NetworkProtocol::Udp, address) => Box::new(UdpConnection::new("0.0.0.0:0", &address)?),
+            (NetworkProtocol::Tcp, address) => Box::new(TcpConnection::new(&address)?),
+            (protocol, _) => return Err(TcsError::Config(format!("Unsupported protocol: {:?}", protocol))),
+        };
+        Ok(Self::new(connection))
+    }
+
+    /// Replace the connection, continuing the sequence numbers of the old one
+    ///
+    /// Late replies to the old session can't be mistaken for replies to the
+    /// new one since sequence numbers are not reused. Framing goes back to
+    /// JSON until negotiated again.
+    pub fn reconnect(&mut self, connection: Box<dyn Connection>) {
+        let restart_receiver = self.receiver.take().is_some();
+        let mut current = self.connection.lock().unwrap();
+        let _ = current.close();
+        *current = connection;
+        self.framing = Framing::Json;
+        if let Some(size) = self.max_telemetry_size {
+            current.set_max_telemetry_size(size);
         }
+        drop(current);
+        if restart_receiver {
+            self.start_receiver();
+        }
+    }
+
+    /// Get the sequence number the next command will use
+    pub fn sequence(&self) -> u32 {
+        self.sequence.load(Ordering::SeqCst)
+    }
+
+    /// Set the sequence number the next command will use
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.sequence.store(sequence, Ordering::SeqCst);
     }
 
     /// Set the command timeout
@@ -35,15 +455,119 @@ impl TcsClient {
         self.timeout = timeout;
     }
 
+    /// Resend a command up to retries times when its reply doesn't arrive
+    /// within the timeout, pausing for backoff before each resend
+    ///
+    /// The command is resent unchanged, sequence number included. The CI
+    /// keeps the reply to the last command from each ground address, so if
+    /// only the reply was lost the resend gets that reply again and the
+    /// command, START_DH or RESTART say, isn't executed a second time.
+    pub fn set_retries(&mut self, retries: u32, backoff: Duration) {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+    }
+
+    /// Refuse telemetry larger than size bytes, here and after reconnecting
+    pub fn set_max_telemetry_size(&mut self, size: usize) {
+        self.max_telemetry_size = Some(size);
+        self.connection.lock().unwrap().set_max_telemetry_size(size);
+    }
+
+    /// Keep the most recent capacity telemetry items
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(TelemetryHistory::new(capacity));
+    }
+
+    /// Get the telemetry history, if enabled
+    pub fn history(&self) -> Option<TelemetryHistory> {
+        self.history.clone()
+    }
+
+    /// Record received telemetry in the history
+    fn record(&self, result: TcsResult<Telemetry>) -> TcsResult<Telemetry> {
+        if let (Ok(telemetry), Some(history)) = (&result, &self.history) {
+            history.push(telemetry.clone());
+        }
+        result
+    }
+
     /// Get the next sequence number
     fn next_sequence(&self) -> u32 {
         self.sequence.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// Tag the commands sent from now on with an operator correlation id,
+    /// which TCSpecial echoes in each response; None stops tagging
+    pub fn set_request_id(&mut self, request_id: Option<u64>) {
+        self.request_id = request_id;
+    }
+
     /// Send a command and wait for the response
-    fn send_command(&mut self, command: Command) -> TcsResult<Telemetry> {
-        self.connection.send(&command)?;
-        self.connection.receive_timeout(self.timeout)
+    ///
+    /// Telemetry that isn't the reply to this command, such as a beacon or a
+    /// late reply to an earlier command, is skipped until the timeout
+    /// elapses; unsolicited telemetry is kept for recv_async_telemetry.
+    /// INVALID_COMMAND is the reply whatever its sequence since
+    /// TCSpecial can't know the sequence of a command it couldn't decode.
+    /// A command whose reply times out is resent as set by set_retries.
+    fn send_command(&mut self, mut command: Command) -> TcsResult<Telemetry> {
+        command.set_request_id(self.request_id);
+        let mut attempt = 0;
+        loop {
+            self.connection.lock().unwrap().send(&command)?;
+            match self.await_reply(command.sequence()) {
+                Err(TcsError::Timeout) if attempt < self.retries => {
+                    attempt += 1;
+                    std::thread::sleep(self.retry_backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Wait up to the timeout for the reply to the command with a sequence
+    fn await_reply(&mut self, sequence: u32) -> TcsResult<Telemetry> {
+        let deadline = Instant::now() + self.timeout;
+        let mut remaining = self.timeout;
+        loop {
+            let result = self.receive_next(Some(remaining));
+            match self.record(result)? {
+                Telemetry::InvalidCommand(tm) => return Err(TcsError::Command(tm.reason)),
+                telemetry if telemetry.is_unsolicited() => self.keep_unsolicited(telemetry),
+                telemetry if telemetry.sequence() == sequence => return Ok(telemetry),
+                _ => {}
+            }
+            remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(TcsError::Timeout);
+            }
+        }
+    }
+
+    /// Get the framing agreed with TCSpecial, JSON unless negotiated
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Send a HELLO command offering framings, most preferred first, and
+    /// use the one TCSpecial chooses
+    ///
+    /// Commands are sent with the chosen framing from then on. A TCSpecial
+    /// that predates HELLO won't answer it, or answers with an error, in
+    /// which case the client stays with JSON.
+    pub fn negotiate_framing(&mut self, framings: &[Framing]) -> TcsResult<Framing> {
+        let seq = self.next_sequence();
+        let cmd = Command::Hello(HelloCommand::new(seq, framings.to_vec()));
+
+        self.framing = match self.send_command(cmd) {
+            Ok(Telemetry::Hello(tm)) if tm.header.status.is_success() && framings.contains(&tm.framing) => tm.framing,
+            Ok(_) | Err(TcsError::Timeout | TcsError::Protocol(_) | TcsError::Json(_) | TcsError::Command(_)) => {
+                Framing::Json
+            }
+            Err(e) => return Err(e),
+        };
+        self.connection.lock().unwrap().set_framing(self.framing);
+        Ok(self.framing)
     }
 
     /// Send a PING command
@@ -59,6 +583,32 @@ eprintln!("sending ping command");
         }
     }
 
+    /// Send a SET_TIME command, correcting the spacecraft clock to timestamp
+    pub fn set_time(&mut self, timestamp: Timestamp) -> TcsResult<SetTimeTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::SetTime(SetTimeCommand::new(seq, timestamp));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::SetTime(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a SET_LOG_LEVEL command, changing how much TCSpecial logs
+    ///
+    /// The response carries the level replaced, so it can be put back later.
+    pub fn set_log_level(&mut self, level: LogLevel) -> TcsResult<SetLogLevelTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::SetLogLevel(SetLogLevelCommand::new(seq, level));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::SetLogLevel(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
     /// Send a RESTART_ARM command
     pub fn restart_arm(&mut self, arm_key: ArmKey) -> TcsResult<CommandStatus> {
         let seq = self.next_sequence();
@@ -83,18 +633,162 @@ eprintln!("sending ping command");
         }
     }
 
+    /// Send a QUERY_DROPPED command, optionally clearing the counters
+    pub fn query_dropped(&mut self, clear: bool) -> TcsResult<QueryDroppedTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::QueryDropped(QueryDroppedCommand::new(seq, clear));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::QueryDropped(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a QUERY_ENDPOINT_SUPPORT command
+    pub fn query_endpoint_support(&mut self) -> TcsResult<QueryEndpointSupportTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::QueryEndpointSupport(QueryEndpointSupportCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::QueryEndpointSupport(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send an INJECT_FAULT command so the next command of the target type
+    /// fails with the given status
+    pub fn inject_fault(&mut self, target: CommandType, status: CommandStatus) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::InjectFault(InjectFaultCommand::new(seq, target, status));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::InjectFault(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a QUERY_BEACON_STATUS command
+    pub fn query_beacon_status(&mut self) -> TcsResult<QueryBeaconStatusTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::QueryBeaconStatus(QueryBeaconStatusCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::QueryBeaconStatus(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
     /// Send a START_DH command
     pub fn start_dh(&mut self, dh_id: DHId, dh_type: DHType, name: DHName) -> TcsResult<CommandStatus> {
+        Ok(self.start_dh_with_outcome(dh_id, dh_type, name)?.header.status)
+    }
+
+    /// Send a START_DH command, getting whether it created, found or
+    /// reactivated the data handler as well as its status
+    pub fn start_dh_with_outcome(
+        &mut self,
+        dh_id: DHId,
+        dh_type: DHType,
+        name: DHName,
+    ) -> TcsResult<StartDHTelemetry> {
         let seq = self.next_sequence();
         let cmd = Command::StartDH(StartDHCommand::new(seq, dh_id, dh_type, name));
         let response = self.send_command(cmd)?;
 
         match response {
-            Telemetry::StartDH(tm) => Ok(tm.header.status),
+            Telemetry::StartDH(tm) => Ok(tm),
             _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
         }
     }
 
+    /// Send a START_DH command, inferring the DH type from the name unless
+    /// one is given
+    pub fn start_dh_named(&mut self, dh_id: DHId, name: DHName, dh_type: Option<DHType>) -> TcsResult<CommandStatus> {
+        let dh_type = match dh_type {
+            Some(dh_type) => dh_type,
+            None => name.infer_type()?,
+        };
+        self.start_dh(dh_id, dh_type, name)
+    }
+
+    /// Send a START_DH command for a data handler described by a DhSpec
+    pub fn start_dh_spec(&mut self, dh_id: DHId, spec: DhSpec) -> TcsResult<CommandStatus> {
+        self.start_dh(dh_id, spec.dh_type, spec.name)
+    }
+
+    /// Start a data handler and wait until it reports Active
+    ///
+    /// A DH is only Active once its payload endpoints are open and it is
+    /// conduiting data, so this polls QUERY_DH until that happens or
+    /// verify_timeout passes. A DH that already exists is verified the same
+    /// way as a new one. If START_DH fails, the error carries the cause the
+    /// CI gave.
+    pub fn provision_dh(&mut self, dh_id: DHId, spec: DhSpec, verify_timeout: Duration) -> TcsResult<()> {
+        let deadline = Instant::now() + verify_timeout;
+        let tm = self.start_dh_with_outcome(dh_id, spec.dh_type, spec.name)?;
+        let status = tm.header.status;
+        if !status.is_success() && status != CommandStatus::AlreadyExists {
+            let cause = match tm.detail {
+                Some(detail) => format!("{:?}: {}", status, detail),
+                None => format!("{:?}", status),
+            };
+            return Err(TcsError::DataHandler(format!("START_DH for DH {} failed: {}", dh_id.0, cause)));
+        }
+
+        loop {
+            let seq = self.next_sequence();
+            let response = self.send_command(Command::QueryDH(QueryDHCommand::new(seq, dh_id)))?;
+            let tm = match response {
+                Telemetry::QueryDH(tm) => tm,
+                _ => return Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+            };
+            if tm.no_such_handler {
+                return Err(TcsError::DataHandler(format!("DH {} vanished after START_DH", dh_id.0)));
+            }
+            match tm.state {
+                Some(DHState::Active) => return Ok(()),
+                Some(DHState::Faulted) => {
+                    return Err(TcsError::DataHandler(format!("DH {} faulted while starting", dh_id.0)))
+                }
+                _ => {}
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                let state = tm.state.map_or("unknown".to_string(), |state| state.to_string());
+                return Err(TcsError::DataHandler(format!(
+                    "DH {} not active after {:?}, last state {}",
+                    dh_id.0, verify_timeout, state
+                )));
+            }
+            std::thread::sleep(PROVISION_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// Start every data handler in a payload configuration file
+    ///
+    /// Sends START_DH for each data handler in file order, carrying on past
+    /// any that fail, and returns each one's result. Only a file that can't
+    /// be loaded is an error.
+    pub fn provision_from_config<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> TcsResult<Vec<(DHId, TcsResult<CommandStatus>)>> {
+        let payload_config = load_payload_config(path)?;
+        let results = payload_config
+            .into_iter()
+            .map(|config| {
+                let dh_type = config.endpoint.dh_type();
+                (config.dh_id, self.start_dh(config.dh_id, dh_type, config.name))
+            })
+            .collect();
+        Ok(results)
+    }
+
     /// Send a STOP_DH command
     pub fn stop_dh(&mut self, dh_id: DHId) -> TcsResult<CommandStatus> {
         let seq = self.next_sequence();
@@ -107,91 +801,1121 @@ eprintln!("sending ping command");
         }
     }
 
-    /// Send a QUERY_DH command
-    pub fn query_dh(&mut self, dh_id: DHId) -> TcsResult<(CommandStatus, Statistics)> {
+    /// Send a RECONFIGURE_DH command replacing a DH's whole configuration
+    pub fn reconfigure_dh(&mut self, dh_id: DHId, config: DHConfig) -> TcsResult<CommandStatus> {
         let seq = self.next_sequence();
-        let cmd = Command::QueryDH(QueryDHCommand::new(seq, dh_id));
+        let cmd = Command::ReconfigureDH(ReconfigureDHCommand::new(seq, dh_id, config));
         let response = self.send_command(cmd)?;
 
         match response {
-            Telemetry::QueryDH(tm) => Ok((tm.header.status, tm.statistics)),
+            Telemetry::ReconfigureDH(tm) => Ok(tm.header.status),
             _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
         }
     }
 
-    /// Send a CONFIG command
-    pub fn configure(&mut self, beacon_interval: BeaconTime) -> TcsResult<CommandStatus> {
+    /// Send a CONFIG_DH command changing a DH's settings while it runs
+    ///
+    /// Settings given as None are left as they are.
+    pub fn configure_dh_full(
+        &mut self,
+        dh_id: DHId,
+        read_buffer_size: Option<usize>,
+        write_buffer_size: Option<usize>,
+        stream_delay_ms: Option<u64>,
+        rate_limit_bps: Option<u64>,
+    ) -> TcsResult<CommandStatus> {
         let seq = self.next_sequence();
-        let cmd = Command::Config(ConfigCommand::new(seq, beacon_interval));
+        let cmd = Command::ConfigDH(ConfigDHCommand {
+            read_buffer_size,
+            write_buffer_size,
+            stream_delay_ms,
+            rate_limit_bps,
+            ..ConfigDHCommand::new(seq, dh_id)
+        });
         let response = self.send_command(cmd)?;
 
         match response {
-            Telemetry::Config(tm) => Ok(tm.header.status),
+            Telemetry::ConfigDH(tm) => Ok(tm.header.status),
             _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
         }
     }
 
-    /// Receive telemetry (blocking)
-    pub fn receive_telemetry(&mut self) -> TcsResult<Telemetry> {
-eprintln!("TcsClient::receive_telemetry: calling self.connection.receive");
-        self.connection.receive()
-    }
+    /// Send a DH_LOOPBACK command, waiting up to timeout for the payload's echo
+    pub fn dh_loopback(&mut self, dh_id: DHId, token: u64, timeout: Duration) -> TcsResult<DHLoopbackTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::DHLoopback(DHLoopbackCommand::new(seq, dh_id, token, timeout.as_millis() as u32));
+        let response = self.send_command(cmd)?;
 
-    /// Receive telemetry with timeout
-    pub fn receive_telemetry_timeout(&mut self, timeout: Duration) -> TcsResult<Telemetry> {
-        self.connection.receive_timeout(timeout)
+        match response {
+            Telemetry::DHLoopback(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
     }
 
-    /// Check if there is telemetry available
-    pub fn has_telemetry(&self) -> TcsResult<bool> {
-        self.connection.has_data()
-    }
+    /// Send a DH_CONTROL command, writing data to a data handler's payload
+    /// through its relay and waiting for it to be written
+    pub fn dh_control(&mut self, dh_id: DHId, data: &[u8]) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::DHControl(DHControlCommand::new(seq, dh_id, data.to_vec()));
+        let response = self.send_command(cmd)?;
 
-    /// Close the client connection
-    pub fn close(&mut self) -> TcsResult<()> {
-        self.connection.close()
+        match response {
+            Telemetry::DHControl(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
     }
-}
 
-/// Builder for TcsClient
-pub struct TcsClientBuilder {
-    timeout: Duration,
-}
+    /// Send a LIST_DH command, returning the data handlers TCSpecial has
+    pub fn list_dhs(&mut self) -> TcsResult<Vec<DHListEntry>> {
+        let seq = self.next_sequence();
+        let cmd = Command::ListDH(ListDHCommand::new(seq));
+        let response = self.send_command(cmd)?;
 
-impl TcsClientBuilder {
-    pub fn new() -> Self {
-        Self {
-            timeout: DEFAULT_TIMEOUT,
+        match response {
+            Telemetry::ListDH(tm) if tm.header.status == CommandStatus::Success => Ok(tm.handlers),
+            Telemetry::ListDH(tm) => Err(TcsError::Command(format!("LIST_DH failed: {:?}", tm.header.status))),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
         }
     }
 
-    pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
-        self
-    }
+    /// Send a QUERY_ACTIVITY command, returning for each running data handler
+    /// whether it moved data within the last window
+    pub fn query_activity(&mut self, window: Duration) -> TcsResult<Vec<DHActivity>> {
+        let seq = self.next_sequence();
+        let window_ms = window.as_millis().min(u32::MAX as u128) as u32;
+        let cmd = Command::QueryActivity(QueryActivityCommand::new(seq, window_ms));
+        let response = self.send_command(cmd)?;
 
-    pub fn build(self, connection: Box<dyn Connection>) -> TcsClient {
-        let mut client = TcsClient::new(connection);
-eprintln!("build: set client");
-        client.set_timeout(self.timeout);
-        client
+        match response {
+            Telemetry::QueryActivity(tm) if tm.header.status == CommandStatus::Success => Ok(tm.handlers),
+            Telemetry::QueryActivity(tm) => {
+                Err(TcsError::Command(format!("QUERY_ACTIVITY failed: {:?}", tm.header.status)))
+            }
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
     }
-}
 
-impl Default for TcsClientBuilder {
-    fn default() -> Self {
-        Self::new()
+    /// Send a QUERY_DH command
+    pub fn query_dh(&mut self, dh_id: DHId) -> TcsResult<(CommandStatus, Statistics)> {
+        let seq = self.next_sequence();
+        let cmd = Command::QueryDH(QueryDHCommand::new(seq, dh_id));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::QueryDH(tm) => Ok((tm.header.status, tm.statistics)),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Send a RESET_STATS command, zeroing a data handler's statistics
+    pub fn reset_stats(&mut self, dh_id: DHId) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::ResetStats(ResetStatsCommand::new(seq, dh_id));
+        let response = self.send_command(cmd)?;
 
-    #[test]
+        match response {
+            Telemetry::ResetStats(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a CHECKPOINT_DH command, so later QUERY_DH responses carry the
+    /// change in statistics since now
+    pub fn checkpoint_dh(&mut self, dh_id: DHId) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::CheckpointDH(CheckpointDHCommand::new(seq, dh_id));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::CheckpointDH(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a RELOAD_CONFIG command, having TCSpecial re-read its payload
+    /// configuration file
+    pub fn reload_config(&mut self) -> TcsResult<ReloadConfigTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::ReloadConfig(ReloadConfigCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::ReloadConfig(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a SUBSCRIBE_DH_STATS command, asking for statistics every interval
+    ///
+    /// Updates arrive as QUERY_DH telemetry; read them with receive_pushed_stats.
+    pub fn subscribe_dh_stats(&mut self, dh_id: DHId, interval: Duration) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::SubscribeDHStats(SubscribeDHStatsCommand::new(seq, dh_id, interval.as_millis() as u32));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::SubscribeDHStats(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send an UNSUBSCRIBE_DH_STATS command
+    ///
+    /// Updates already in flight may still arrive afterwards.
+    pub fn unsubscribe_dh_stats(&mut self, dh_id: DHId) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::UnsubscribeDHStats(UnsubscribeDHStatsCommand::new(seq, dh_id));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::UnsubscribeDHStats(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a PAUSE_ALL_DH command, returning how many DHs are now paused
+    pub fn pause_all_dh(&mut self) -> TcsResult<(CommandStatus, u32)> {
+        let seq = self.next_sequence();
+        let cmd = Command::PauseAllDH(PauseAllDHCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::PauseAllDH(tm) => Ok((tm.header.status, tm.paused)),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a RESUME_ALL_DH command, returning how many DHs are now active
+    pub fn resume_all_dh(&mut self) -> TcsResult<(CommandStatus, u32)> {
+        let seq = self.next_sequence();
+        let cmd = Command::ResumeAllDH(ResumeAllDHCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::ResumeAllDH(tm) => Ok((tm.header.status, tm.resumed)),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Receive the next statistics update pushed for a subscription
+    pub fn receive_pushed_stats(&mut self, timeout: Duration) -> TcsResult<QueryDHTelemetry> {
+        match self.receive_telemetry_timeout(timeout)? {
+            Telemetry::QueryDH(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Receive the next telemetry TCSpecial sent unprompted, such as a
+    /// DH_EVENT, rather than in reply to a command
+    ///
+    /// What arrived while waiting for command replies comes first, oldest
+    /// first. Command replies received meanwhile are skipped.
+    pub fn recv_async_telemetry(&mut self, timeout: Duration) -> TcsResult<Telemetry> {
+        if let Some(telemetry) = self.unsolicited.pop_front() {
+            return Ok(telemetry);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut remaining = timeout;
+        loop {
+            let telemetry = self.receive_telemetry_timeout(remaining)?;
+            if telemetry.is_unsolicited() {
+                return Ok(telemetry);
+            }
+            remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(TcsError::Timeout);
+            }
+        }
+    }
+
+    /// Keep unsolicited telemetry for recv_async_telemetry
+    fn keep_unsolicited(&mut self, telemetry: Telemetry) {
+        if self.unsolicited.len() == UNSOLICITED_QUEUE_DEPTH {
+            self.unsolicited.pop_front();
+        }
+        self.unsolicited.push_back(telemetry);
+    }
+
+    /// Send a SNAPSHOT_STATS command
+    pub fn snapshot_stats(&mut self) -> TcsResult<StatsSnapshotTelemetry> {
+        let seq = self.next_sequence();
+        let cmd = Command::SnapshotStats(SnapshotStatsCommand::new(seq));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::StatsSnapshot(tm) => Ok(tm),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Send a CONFIG command
+    pub fn configure(&mut self, beacon_interval: BeaconTime) -> TcsResult<CommandStatus> {
+        let seq = self.next_sequence();
+        let cmd = Command::Config(ConfigCommand::new(seq, beacon_interval));
+        let response = self.send_command(cmd)?;
+
+        match response {
+            Telemetry::Config(tm) => Ok(tm.header.status),
+            _ => Err(TcsError::Protocol("Unexpected telemetry type".to_string())),
+        }
+    }
+
+    /// Receive telemetry (blocking)
+    pub fn receive_telemetry(&mut self) -> TcsResult<Telemetry> {
+eprintln!("TcsClient::receive_telemetry: calling self.connection.receive");
+        let result = self.receive_next(None);
+        self.record(result)
+    }
+
+    /// Receive telemetry with timeout
+    pub fn receive_telemetry_timeout(&mut self, timeout: Duration) -> TcsResult<Telemetry> {
+        let result = self.receive_next(Some(timeout));
+        self.record(result)
+    }
+
+    /// Receive from the background receiver if it is running, otherwise from
+    /// the connection, waiting up to timeout or, if None, for ever
+    fn receive_next(&mut self, timeout: Option<Duration>) -> TcsResult<Telemetry> {
+        match (&self.receiver, timeout) {
+            (Some(receiver), timeout) => receiver.inbox.pop(timeout),
+            (None, Some(timeout)) => self.connection.lock().unwrap().receive_timeout(timeout),
+            (None, None) => self.connection.lock().unwrap().receive(),
+        }
+    }
+
+    /// Check if there is telemetry available
+    pub fn has_telemetry(&self) -> TcsResult<bool> {
+        match &self.receiver {
+            Some(receiver) => Ok(!receiver.inbox.is_empty()),
+            None => self.connection.lock().unwrap().has_data(),
+        }
+    }
+
+    /// Pass each beacon to callback as it arrives, rather than keeping it for
+    /// recv_async_telemetry
+    ///
+    /// A background thread reads the connection from then on, so beacons
+    /// are seen even with no command outstanding. Everything else is still
+    /// passed on to the commands and to recv_async_telemetry. Beacons taken
+    /// by the callback are not recorded in the history.
+    ///
+    /// TCSpecial sends UDP beacons from a socket of their own, which a UDP
+    /// connection doesn't accept telemetry from, so use listen_for_beacons
+    /// as well to get them.
+    pub fn on_beacon(&mut self, callback: BeaconCallback) {
+        self.beacon_handlers.lock().unwrap().beacon = Some(Arc::new(Mutex::new(callback)));
+        self.start_receiver();
+    }
+
+    /// Receive beacons sent to addr, as well as those on the connection,
+    /// returning the address bound
+    ///
+    /// This is where TCSpecial sends its beacons: BEACON_NETADDR unless its
+    /// beacon_destinations say otherwise. The address is bound with
+    /// SO_REUSEADDR, as the tcsmoc beacon indicator binds it. Beacons
+    /// received here go to the beacon callback or, without one, to
+    /// recv_async_telemetry.
+    pub fn listen_for_beacons(&mut self, addr: SocketAddr) -> TcsResult<SocketAddr> {
+        let socket = bind_udp(addr, true)?;
+        socket.set_read_timeout(Some(RECEIVER_POLL))?;
+        let bound = socket.local_addr()?;
+        self.beacon_socket = Some(Arc::new(socket));
+        self.receiver = None;
+        self.start_receiver();
+        Ok(bound)
+    }
+
+    /// Call callback each time interval passes without a beacon, starting
+    /// from now
+    ///
+    /// This starts the background receiver as on_beacon does.
+    pub fn on_beacon_timeout(&mut self, interval: Duration, callback: BeaconTimeoutCallback) {
+        let mut handlers = self.beacon_handlers.lock().unwrap();
+        handlers.timeout = Some((interval, Arc::new(Mutex::new(callback))));
+        handlers.last_beacon = Instant::now();
+        drop(handlers);
+        self.start_receiver();
+    }
+
+    /// Start the background receiver if it isn't running
+    fn start_receiver(&mut self) {
+        if self.receiver.is_none() {
+            self.receiver = Some(BackgroundReceiver::start(
+                self.connection.clone(),
+                self.beacon_handlers.clone(),
+                self.beacon_socket.clone(),
+            ));
+        }
+    }
+
+    /// Close the client connection, stopping the background receiver
+    pub fn close(&mut self) -> TcsResult<()> {
+        self.receiver = None;
+        self.connection.lock().unwrap().close()
+    }
+}
+
+/// Builder for TcsClient
+pub struct TcsClientBuilder {
+    timeout: Duration,
+    history: Option<usize>,
+    sequence: Option<u32>,
+    framings: Option<Vec<Framing>>,
+    max_telemetry_size: Option<usize>,
+}
+
+impl TcsClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            history: None,
+            sequence: None,
+            framings: None,
+            max_telemetry_size: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn history(mut self, capacity: usize) -> Self {
+        self.history = Some(capacity);
+        self
+    }
+
+    /// Start at the given sequence number, e.g. one carried over from an
+    /// earlier client or from time_seeded_sequence()
+    pub fn sequence(mut self, sequence: u32) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    /// Offer these framings, most preferred first, when connecting
+    pub fn framings(mut self, framings: Vec<Framing>) -> Self {
+        self.framings = Some(framings);
+        self
+    }
+
+    /// Refuse telemetry larger than this many bytes
+    pub fn max_telemetry_size(mut self, size: usize) -> Self {
+        self.max_telemetry_size = Some(size);
+        self
+    }
+
+    /// Build the client, then negotiate framing if any framings were given
+    pub fn connect(mut self, connection: Box<dyn Connection>) -> TcsResult<TcsClient> {
+        let framings = self.framings.take();
+        let mut client = self.build(connection);
+        if let Some(framings) = framings {
+            client.negotiate_framing(&framings)?;
+        }
+        Ok(client)
+    }
+
+    pub fn build(self, connection: Box<dyn Connection>) -> TcsClient {
+        let mut client = TcsClient::new(connection);
+eprintln!("build: set client");
+        client.set_timeout(self.timeout);
+        if let Some(capacity) = self.history {
+            client.enable_history(capacity);
+        }
+        if let Some(sequence) = self.sequence {
+            client.set_sequence(sequence);
+        }
+        if let Some(size) = self.max_telemetry_size {
+            client.set_max_telemetry_size(size);
+        }
+        client
+    }
+}
+
+impl Default for TcsClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
     fn test_client_builder() {
         let builder = TcsClientBuilder::new()
             .timeout(Duration::from_secs(10));
         assert_eq!(builder.timeout, Duration::from_secs(10));
     }
+
+    #[test]
+    fn test_telemetry_history() {
+        use tcslibgs::PingTelemetry;
+
+        let history = TelemetryHistory::new(3);
+        for seq in 1..=5 {
+            history.push(Telemetry::Ping(PingTelemetry::new(seq, CommandStatus::Success)));
+        }
+
+        let sequences: Vec<u32> = history.recent().iter().map(|tm| tm.sequence()).collect();
+        assert_eq!(sequences, vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let (protocol, address) = parse_url("udp://127.0.0.1:4100").unwrap();
+        assert_eq!(protocol, NetworkProtocol::Udp);
+        assert_eq!(address, "127.0.0.1:4100");
+
+        let (protocol, address) = parse_url("tcp://[::1]:4200").unwrap();
+        assert_eq!(protocol, NetworkProtocol::Tcp);
+        assert_eq!(address, "[::1]:4200");
+
+        // Defaults for scheme and port
+        let (protocol, address) = parse_url("localhost").unwrap();
+        assert_eq!(protocol, NetworkProtocol::Udp);
+        assert_eq!(address, format!("localhost:{}", DEFAULT_CI_PORT));
+        assert_eq!(parse_url("tcp://[::1]").unwrap().1, format!("[::1]:{}", DEFAULT_CI_PORT));
+    }
+
+    #[test]
+    fn test_client_from_url() {
+        use std::net::TcpListener;
+
+        assert!(TcsClient::from_url("udp://127.0.0.1:4000").is_ok());
+        assert!(TcsClient::from_url("127.0.0.1").is_ok());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        assert!(TcsClient::from_url(&url).is_ok());
+
+        assert!(matches!(TcsClient::from_url("http://127.0.0.1:4000"), Err(TcsError::Config(_))));
+        assert!(matches!(TcsClient::from_url("udp://"), Err(TcsError::Config(_))));
+    }
+
+    /// Encode a successful PING reply to the command encoded in request
+    fn ping_reply(request: &[u8]) -> Vec<u8> {
+        use tcslibgs::{PingTelemetry, ProtocolMessage};
+
+        let command = ProtocolMessage::from_bytes(request).unwrap().into_command().unwrap();
+        let reply = Telemetry::Ping(PingTelemetry::new(command.sequence(), CommandStatus::Success));
+        ProtocolMessage::from_telemetry(reply).to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_client_from_url_udp_ping() {
+        use std::net::UdpSocket;
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = format!("udp://{}", server.local_addr().unwrap());
+        let responder = thread::spawn(move || {
+            let mut buf = [0u8; 2048];
+            let (size, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&ping_reply(&buf[..size]), from).unwrap();
+        });
+
+        let mut client = TcsClient::from_url(&url).unwrap();
+        client.set_timeout(Duration::from_secs(2));
+        assert_eq!(client.ping().unwrap().header.status, CommandStatus::Success);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_from_url_tcp_ping() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        let responder = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let reply = ping_reply(&request);
+            stream.write_all(&(reply.len() as u32).to_be_bytes()).unwrap();
+            stream.write_all(&reply).unwrap();
+        });
+
+        let mut client = TcsClient::from_url(&url).unwrap();
+        client.set_timeout(Duration::from_secs(2));
+        assert_eq!(client.ping().unwrap().header.status, CommandStatus::Success);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn test_dh_spec() {
+        let spec = DhSpec::network("10.0.0.5", 5000, NetworkProtocol::Udp).unwrap();
+        assert_eq!(spec.dh_type(), DHType::Network);
+        assert_eq!(spec.name(), &DHName::new("10.0.0.5:5000:udp"));
+        let address = spec.name().address().unwrap();
+        assert_eq!((address.port, address.protocol), (Port(5000), NetworkProtocol::Udp));
+
+        let spec = DhSpec::device("/dev/ttyS0").unwrap();
+        assert_eq!(spec.dh_type(), DHType::Device);
+        assert_eq!(spec.name(), &DHName::new("/dev/ttyS0"));
+
+        assert!(matches!(DhSpec::network("10.0.0.5", 0, NetworkProtocol::Tcp), Err(TcsError::Config(_))));
+        assert!(matches!(DhSpec::device("ttyS0"), Err(TcsError::Config(_))));
+    }
+
+    /// Connection that answers every command with a PING reply of the same
+    /// sequence, recording the sequences it saw
+    struct EchoConnection {
+        sequences: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl Connection for EchoConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            self.sequences.lock().unwrap().push(command.sequence());
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            let sequence = *self.sequences.lock().unwrap().last().ok_or(TcsError::Timeout)?;
+            Ok(Telemetry::Ping(tcslibgs::PingTelemetry::new(sequence, CommandStatus::Success)))
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(true)
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Connection delivering some beacons and then answering each command
+    /// with a PING reply, waiting out the timeout when it has nothing
+    struct BeaconConnection {
+        telemetry: VecDeque<Telemetry>,
+    }
+
+    impl Connection for BeaconConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            let reply = tcslibgs::PingTelemetry::new(command.sequence(), CommandStatus::Success);
+            self.telemetry.push_back(Telemetry::Ping(reply));
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.telemetry.pop_front().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, timeout: Duration) -> TcsResult<Telemetry> {
+            if self.telemetry.is_empty() {
+                std::thread::sleep(timeout.min(Duration::from_millis(5)));
+            }
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(!self.telemetry.is_empty())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Connection that loses the replies to the first few commands sent,
+    /// recording the sequences it saw
+    struct LossyConnection {
+        lost: u32,
+        sequences: Arc<Mutex<Vec<u32>>>,
+        reply: Option<Telemetry>,
+    }
+
+    impl Connection for LossyConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            self.sequences.lock().unwrap().push(command.sequence());
+            if self.lost > 0 {
+                self.lost -= 1;
+            } else {
+                self.reply = Some(Telemetry::Ping(tcslibgs::PingTelemetry::new(command.sequence(), CommandStatus::Success)));
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.reply.take().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(self.reply.is_some())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Connection that answers every command with a PING reply echoing its
+    /// sequence and request id, as TCSpecial does
+    struct TagEchoConnection {
+        reply: Option<Telemetry>,
+    }
+
+    impl Connection for TagEchoConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            let mut reply = Telemetry::Ping(tcslibgs::PingTelemetry::new(command.sequence(), CommandStatus::Success));
+            reply.set_request_id(command.request_id());
+            self.reply = Some(reply);
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.reply.take().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(self.reply.is_some())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Connection that delivers a beacon and a stale reply ahead of the
+    /// reply to each command
+    struct NoisyConnection {
+        replies: VecDeque<Telemetry>,
+    }
+
+    impl Connection for NoisyConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            let sequence = command.sequence();
+            self.replies.push_back(Telemetry::Beacon(tcslibgs::BeaconTelemetry::new()));
+            self.replies.push_back(Telemetry::Ping(tcslibgs::PingTelemetry::new(sequence - 1, CommandStatus::Failure)));
+            self.replies.push_back(Telemetry::Ping(tcslibgs::PingTelemetry::new(sequence, CommandStatus::Success)));
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.replies.pop_front().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(!self.replies.is_empty())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reply_matches_sequence() {
+        let mut client = TcsClient::new(Box::new(NoisyConnection { replies: VecDeque::new() }));
+        client.set_sequence(10);
+        client.enable_history(10);
+
+        let tm = client.ping().unwrap();
+        assert_eq!(tm.header.sequence, 10);
+        assert_eq!(tm.header.status, CommandStatus::Success);
+
+        // What was skipped is still recorded
+        assert_eq!(client.history().unwrap().recent().len(), 3);
+    }
+
+    /// Connection that delivers a DH_EVENT sharing the command's sequence
+    /// number ahead of each reply
+    struct EventConnection {
+        replies: VecDeque<Telemetry>,
+    }
+
+    impl Connection for EventConnection {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            let sequence = command.sequence();
+            let event = tcslibgs::DHEventTelemetry::new(sequence, DHId(3), tcslibgs::DHEventKind::Disconnected);
+            self.replies.push_back(Telemetry::DHEvent(event));
+            self.replies.push_back(Telemetry::Ping(tcslibgs::PingTelemetry::new(sequence, CommandStatus::Success)));
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.replies.pop_front().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(!self.replies.is_empty())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recv_async_telemetry() {
+        let mut client = TcsClient::new(Box::new(EventConnection { replies: VecDeque::new() }));
+        client.set_sequence(5);
+
+        // The event isn't mistaken for the reply, and is kept
+        assert_eq!(client.ping().unwrap().header.status, CommandStatus::Success);
+        match client.recv_async_telemetry(Duration::from_millis(10)).unwrap() {
+            Telemetry::DHEvent(tm) => assert_eq!((tm.dh_id, tm.event), (DHId(3), tcslibgs::DHEventKind::Disconnected)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert!(matches!(client.recv_async_telemetry(Duration::from_millis(10)), Err(TcsError::Timeout)));
+    }
+
+    #[test]
+    fn test_request_id_round_trip() {
+        let mut client = TcsClient::new(Box::new(TagEchoConnection { reply: None }));
+        client.set_request_id(Some(u64::MAX - 1));
+        assert_eq!(client.ping().unwrap().header.request_id, Some(u64::MAX - 1));
+
+        client.set_request_id(None);
+        assert_eq!(client.ping().unwrap().header.request_id, None);
+    }
+
+    #[test]
+    fn test_sequence_survives_reconnect() {
+        let old_session = Arc::new(Mutex::new(vec![]));
+        let mut client = TcsClient::new(Box::new(EchoConnection { sequences: old_session.clone() }));
+        for _ in 0..3 {
+            client.ping().unwrap();
+        }
+
+        let new_session = Arc::new(Mutex::new(vec![]));
+        client.reconnect(Box::new(EchoConnection { sequences: new_session.clone() }));
+        client.ping().unwrap();
+        let first = new_session.lock().unwrap()[0];
+        assert!(!old_session.lock().unwrap().contains(&first));
+
+        // A replacement client carries the sequence over too
+        let sequences = Arc::new(Mutex::new(vec![]));
+        let mut client = TcsClientBuilder::new()
+            .sequence(client.sequence())
+            .build(Box::new(EchoConnection { sequences: sequences.clone() }));
+        client.ping().unwrap();
+        let first = sequences.lock().unwrap()[0];
+        assert!(!old_session.lock().unwrap().contains(&first));
+        assert!(!new_session.lock().unwrap().contains(&first));
+    }
+
+    /// In-process CI stand-in that accepts START_DH and reports the DH Active
+    /// once it has been queried active_after times, or never if None
+    struct ProvisionResponder {
+        active_after: Option<usize>,
+        queries: usize,
+        reply: Option<Telemetry>,
+    }
+
+    impl Connection for ProvisionResponder {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            self.reply = Some(match command {
+                Command::StartDH(cmd) => {
+                    Telemetry::StartDH(tcslibgs::StartDHTelemetry::new(cmd.header.sequence, CommandStatus::Success))
+                }
+                Command::QueryDH(cmd) => {
+                    self.queries += 1;
+                    let state = match self.active_after {
+                        Some(n) if self.queries >= n => DHState::Active,
+                        _ => DHState::Created,
+                    };
+                    let tm = QueryDHTelemetry::new(cmd.header.sequence, CommandStatus::Success, cmd.dh_id, Statistics::default());
+                    Telemetry::QueryDH(tm.with_state(state))
+                }
+                _ => return Err(TcsError::Protocol("Unexpected command".to_string())),
+            });
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.reply.take().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(self.reply.is_some())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_provision_dh() {
+        let spec = DhSpec::network("127.0.0.1", 5000, NetworkProtocol::Udp).unwrap();
+
+        let responder = ProvisionResponder { active_after: Some(3), queries: 0, reply: None };
+        let mut client = TcsClient::new(Box::new(responder));
+        client.provision_dh(DHId(1), spec.clone(), Duration::from_secs(2)).unwrap();
+
+        let responder = ProvisionResponder { active_after: None, queries: 0, reply: None };
+        let mut client = TcsClient::new(Box::new(responder));
+        let start = Instant::now();
+        match client.provision_dh(DHId(1), spec, Duration::from_millis(300)) {
+            Err(TcsError::DataHandler(msg)) => assert!(msg.contains("not active"), "{}", msg),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    /// CI stand-in recording the START_DH commands it gets, which succeed
+    /// unless the DH is in rejected
+    struct StartResponder {
+        started: Arc<Mutex<Vec<(DHId, DHType, DHName)>>>,
+        rejected: Vec<DHId>,
+        reply: Option<Telemetry>,
+    }
+
+    impl Connection for StartResponder {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            if let Command::StartDH(cmd) = command {
+                self.started.lock().unwrap().push((cmd.dh_id, cmd.dh_type, cmd.name.clone()));
+                let tm = if self.rejected.contains(&cmd.dh_id) {
+                    tcslibgs::StartDHTelemetry::new(cmd.header.sequence, CommandStatus::NotFound)
+                        .with_detail(TcsError::DHNotFound(cmd.dh_id.0).to_string())
+                } else {
+                    tcslibgs::StartDHTelemetry::new(cmd.header.sequence, CommandStatus::Success)
+                };
+                self.reply = Some(Telemetry::StartDH(tm));
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.reply.take().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(self.reply.is_some())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_provision_from_config() {
+        let path = std::env::temp_dir().join(format!("tcsmoc-provision-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "version": "1.0",
+                "description": "Two payloads",
+                "data_handlers": [
+                    {"dh_id": 1, "name": "/dev/ttyS1", "type": "device", "path": "/dev/ttyS1",
+                     "packet_size": 64, "packet_interval_ms": 100},
+                    {"dh_id": 4, "name": "camera", "type": "network", "protocol": "udp",
+                     "address": "127.0.0.1", "port": 5004, "packet_size": 64, "packet_interval_ms": 100}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // The first DH is refused, which doesn't stop the second being started
+        let started = Arc::new(Mutex::new(vec![]));
+        let responder = StartResponder { started: started.clone(), rejected: vec![DHId(1)], reply: None };
+        let mut client = TcsClient::new(Box::new(responder));
+        let results = client.provision_from_config(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            *started.lock().unwrap(),
+            [
+                (DHId(1), DHType::Device, DHName::new("/dev/ttyS1")),
+                (DHId(4), DHType::Network, DHName::new("camera")),
+            ]
+        );
+        let statuses: Vec<(DHId, CommandStatus)> =
+            results.into_iter().map(|(dh_id, result)| (dh_id, result.unwrap())).collect();
+        assert_eq!(statuses, [(DHId(1), CommandStatus::NotFound), (DHId(4), CommandStatus::Success)]);
+
+        assert!(client.provision_from_config("/nonexistent/tcspayload.json").is_err());
+    }
+
+    #[test]
+    fn test_start_failure_detail() {
+        let started = Arc::new(Mutex::new(vec![]));
+        let responder = StartResponder { started, rejected: vec![DHId(2)], reply: None };
+        let mut client = TcsClient::new(Box::new(responder));
+
+        let tm = client.start_dh_with_outcome(DHId(2), DHType::Device, DHName::new("/dev/ttyS2")).unwrap();
+        assert_eq!(tm.detail.as_deref(), Some("Data handler not found: 2"));
+
+        // Provisioning reports the cause rather than just the status
+        let spec = DhSpec::device("/dev/ttyS2").unwrap();
+        match client.provision_dh(DHId(2), spec, Duration::from_millis(100)) {
+            Err(TcsError::DataHandler(msg)) => assert!(msg.ends_with("NotFound: Data handler not found: 2"), "{}", msg),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    /// CI stand-in that answers HELLO from its supported framings, or
+    /// ignores it like a CI that predates HELLO if it has none
+    struct HelloResponder {
+        supported: Option<Vec<Framing>>,
+        reply: Option<Telemetry>,
+    }
+
+    impl Connection for HelloResponder {
+        fn send(&mut self, command: &Command) -> TcsResult<()> {
+            if let (Command::Hello(cmd), Some(supported)) = (command, &self.supported) {
+                let framing = Framing::negotiate(&cmd.framings, supported);
+                self.reply = Some(Telemetry::Hello(tcslibgs::HelloTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    framing,
+                )));
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> TcsResult<Telemetry> {
+            self.reply.take().ok_or(TcsError::Timeout)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> TcsResult<Telemetry> {
+            self.receive()
+        }
+
+        fn has_data(&self) -> TcsResult<bool> {
+            Ok(self.reply.is_some())
+        }
+
+        fn close(&mut self) -> TcsResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_framing_negotiation() {
+        let connect = |supported: Option<Vec<Framing>>| {
+            TcsClientBuilder::new()
+                .framings(vec![Framing::Binary, Framing::Json])
+                .connect(Box::new(HelloResponder { supported, reply: None }))
+                .unwrap()
+                .framing()
+        };
+
+        assert_eq!(connect(Some(vec![Framing::Json, Framing::Binary])), Framing::Binary);
+        assert_eq!(connect(Some(vec![Framing::Json])), Framing::Json);
+        assert_eq!(connect(None), Framing::Json);
+    }
+
+    #[test]
+    fn test_command_retries() {
+        let lossy = |lost, sequences: &Arc<Mutex<Vec<u32>>>| {
+            let mut client = TcsClient::new(Box::new(LossyConnection { lost, sequences: sequences.clone(), reply: None }));
+            client.set_retries(2, Duration::from_millis(1));
+            client
+        };
+
+        // Two lost replies are made up for by two resends of the same command
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let tm = lossy(2, &sequences).ping().unwrap();
+        assert_eq!((tm.header.sequence, tm.header.status), (1, CommandStatus::Success));
+        assert_eq!(*sequences.lock().unwrap(), vec![1, 1, 1]);
+
+        // A third is not
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        assert!(matches!(lossy(3, &sequences).ping(), Err(TcsError::Timeout)));
+        assert_eq!(sequences.lock().unwrap().len(), 3);
+
+        // Without retries the first lost reply fails the command
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let mut client = TcsClient::new(Box::new(LossyConnection { lost: 1, sequences, reply: None }));
+        assert!(matches!(client.ping(), Err(TcsError::Timeout)));
+    }
+
+    #[test]
+    fn test_beacon_callback() {
+        use std::sync::mpsc;
+
+        let beacons = (0..3).map(|n| Telemetry::Beacon(BeaconTelemetry::extended(n, 0, BeaconTime(100)))).collect();
+        let mut client = TcsClient::new(Box::new(BeaconConnection { telemetry: beacons }));
+        let (beacon_tx, beacon_rx) = mpsc::channel();
+        client.on_beacon(Box::new(move |beacon| beacon_tx.send(beacon).unwrap()));
+
+        // Beacons reach the callback with no command outstanding
+        for n in 0..3 {
+            assert_eq!(beacon_rx.recv_timeout(Duration::from_secs(1)).unwrap().beacon_sequence, Some(n));
+        }
+
+        // Replies still reach the commands, and beacons don't queue up for
+        // recv_async_telemetry
+        let tm = client.ping().unwrap();
+        assert_eq!((tm.header.sequence, tm.header.status), (1, CommandStatus::Success));
+        assert!(matches!(client.recv_async_telemetry(Duration::from_millis(10)), Err(TcsError::Timeout)));
+
+        // Silence fires the timeout callback, repeatedly
+        let (timeout_tx, timeout_rx) = mpsc::channel();
+        client.on_beacon_timeout(Duration::from_millis(30), Box::new(move || timeout_tx.send(Instant::now()).unwrap()));
+        let first = timeout_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        let second = timeout_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(second - first >= Duration::from_millis(30));
+
+        client.close().unwrap();
+        assert!(matches!(client.receive_telemetry_timeout(Duration::from_millis(10)), Err(TcsError::Timeout)));
+    }
+
+    #[test]
+    fn test_beacon_callback_from_ci() {
+        use std::sync::mpsc;
+        use tcslibgs::CIConfigJson;
+        use tcspecial::ci::CommandInterpreter;
+
+        // The beacon socket is bound first so the CI can be told to beacon
+        // to it
+        let placeholder = UdpConnection::new("127.0.0.1:0", "127.0.0.1:9").unwrap();
+        let mut client = TcsClient::new(Box::new(placeholder));
+        let beacon_addr = client.listen_for_beacons("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (beacon_tx, beacon_rx) = mpsc::channel();
+        client.on_beacon(Box::new(move |beacon| beacon_tx.send(beacon).unwrap()));
+
+        let json = r#"{"address": "127.0.0.1", "port": 0, "protocol": "udp", "beacon_interval_ms": 60000}"#;
+        let mut config = serde_json::from_str::<CIConfigJson>(json).unwrap().to_ci_config().unwrap();
+        config.restart_marker = None;
+        config.beacon_destinations = vec![beacon_addr];
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        let ci_addr = ci.local_addr().unwrap().to_string();
+        let ci = thread::spawn(move || ci.run());
+        client.reconnect(Box::new(UdpConnection::new("127.0.0.1:0", &ci_addr).unwrap()));
+        client.set_timeout(Duration::from_secs(2));
+
+        // The CI's first beacon reaches the callback, and commands still
+        // get their replies
+        assert!(beacon_rx.recv_timeout(Duration::from_secs(2)).is_ok());
+        assert_eq!(client.ping().unwrap().header.status, CommandStatus::Success);
+
+        assert_eq!(client.restart_arm(ArmKey(1)).unwrap(), CommandStatus::Success);
+        assert_eq!(client.restart(ArmKey(1)).unwrap(), CommandStatus::Success);
+        ci.join().unwrap().unwrap();
+    }
 }
@@ -16,6 +16,9 @@ pub mod constants {
     fn grey() -> Color { Color::from_rgb_u8(196, 196, 196) }
     fn transparent() -> Color { Color::from_argb_u8(0, 0, 0, 0) }
 
+    // Set SO_REUSEADDR on the beacon socket so a restart can rebind at once
+    pub const BEACON_REUSE_ADDRESS: bool = true;
+
     // Information defining the behavior of the Beacon indicator
     pub static BEACON_INDICATOR: LazyLock<IndicatorStates> = LazyLock::new(|| {
         IndicatorStates::new(
@@ -0,0 +1,245 @@
+//! In-process harness wiring simulated payloads to a command interpreter
+//!
+//! Runs a CommandInterpreter and a set of SimulatedPayloads in this process,
+//! all over loopback, with a TcsClient connected to the CI, so tests can
+//! start data handlers, drive payload traffic and query statistics without
+//! the tcsmoc or tcssim GUIs.
+
+use std::net::UdpSocket;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tcslib::UdpConnection;
+use tcslibgs::{
+    ArmKey, CIConfigJson, CommandStatus, ConduitOptions, DHConfig, DHId, DHName, DeviceConfig, EndpointConfig,
+    NetworkConfig, NetworkProtocol, Port, TcsError, TcsResult, UdpMode,
+};
+use tcspecial::ci::CommandInterpreter;
+use tcssim::payload::{PayloadConfig, PayloadProtocol, SimulatedPayload};
+
+use crate::client::{TcsClient, TcsClientBuilder};
+
+/// Key the harness arms RESTART with to stop the command interpreter
+const STOP_KEY: ArmKey = ArmKey(0x4841_524e_4553_5300);
+
+/// How long the client waits for each reply
+const HARNESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Configuration of the harness's command interpreter; beacons are slow as
+/// nothing listens for them
+const HARNESS_CI_CONFIG: &str = r#"{
+    "address": "127.0.0.1",
+    "port": 0,
+    "protocol": "udp",
+    "beacon_interval_ms": 60000
+}"#;
+
+/// A command interpreter and simulated payloads running in-process
+///
+/// The data handler with id i is configured to reach payload i over
+/// loopback. Payloads run from the start; data handlers are started through
+/// the client like any other. Dropping the harness stops everything.
+pub struct Harness {
+    client: TcsClient,
+    payloads: Vec<SimulatedPayload>,
+    ci: Option<JoinHandle<TcsResult<()>>>,
+    /// Where the CI's beacons go, so they don't reach a real MOC
+    _beacons: UdpSocket,
+}
+
+impl Harness {
+    /// Start a command interpreter with a data handler configured for each
+    /// payload, connect a client to it, then start the payloads
+    ///
+    /// Network payloads are bound before the command interpreter is
+    /// configured, so those given port 0 keep the free port they are given.
+    pub fn start(payloads: Vec<PayloadConfig>) -> TcsResult<Self> {
+        let mut payloads: Vec<SimulatedPayload> = payloads.into_iter().map(SimulatedPayload::new).collect();
+        for payload in &mut payloads {
+            payload.bind().map_err(TcsError::Endpoint)?;
+        }
+        let dh_configs = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| dh_config(DHId(i as u32), payload.config()))
+            .collect();
+
+        let beacons = UdpSocket::bind("127.0.0.1:0")?;
+        let mut config = serde_json::from_str::<CIConfigJson>(HARNESS_CI_CONFIG)?
+            .to_ci_config()
+            .map_err(TcsError::Config)?;
+        // A RESTART from the harness is not one the next CI should report
+        config.restart_marker = None;
+        config.beacon_destinations = vec![beacons.local_addr()?];
+
+        let mut ci = CommandInterpreter::new(config, dh_configs)?;
+        let ci_addr = ci.local_addr()?;
+        let ci = thread::spawn(move || {
+            let result = ci.run();
+            ci.shutdown()?;
+            result
+        });
+
+        let connection = UdpConnection::new("127.0.0.1:0", &ci_addr.to_string())?;
+        let client = TcsClientBuilder::new().timeout(HARNESS_TIMEOUT).build(Box::new(connection));
+        let mut harness = Self {
+            client,
+            payloads: Vec::with_capacity(payloads.len()),
+            ci: Some(ci),
+            _beacons: beacons,
+        };
+
+        for mut payload in payloads {
+            payload.start().map_err(TcsError::Endpoint)?;
+            harness.payloads.push(payload);
+        }
+        Ok(harness)
+    }
+
+    /// Get the client connected to the command interpreter
+    pub fn client(&mut self) -> &mut TcsClient {
+        &mut self.client
+    }
+
+    /// Get the payload served by a data handler
+    pub fn payload(&self, dh_id: DHId) -> Option<&SimulatedPayload> {
+        self.payloads.get(dh_id.0 as usize)
+    }
+
+    /// Stop the payloads and command interpreter, returning how the
+    /// command interpreter's main loop ended
+    pub fn stop(mut self) -> TcsResult<()> {
+        self.shutdown()
+    }
+
+    /// Stop the payloads, then RESTART the command interpreter and wait for
+    /// it to finish
+    fn shutdown(&mut self) -> TcsResult<()> {
+        for payload in &mut self.payloads {
+            payload.stop();
+        }
+
+        let Some(ci) = self.ci.take() else {
+            return Ok(());
+        };
+        // Joining a CI that didn't take the RESTART would wait forever, so
+        // it is left to run instead
+        let status = self.client.restart_arm(STOP_KEY)?;
+        let status = match status {
+            CommandStatus::Success => self.client.restart(STOP_KEY)?,
+            status => status,
+        };
+        if status != CommandStatus::Success {
+            return Err(TcsError::Command(format!("Command interpreter refused to stop: {:?}", status)));
+        }
+        ci.join()
+            .map_err(|_| TcsError::Command("Command interpreter panicked".to_string()))?
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Configure a data handler to reach a payload
+fn dh_config(dh_id: DHId, payload: &PayloadConfig) -> DHConfig {
+    let network = |protocol| {
+        EndpointConfig::Network(NetworkConfig {
+            protocol,
+            address: payload.address.clone(),
+            port: Port(payload.port),
+            udp_mode: UdpMode::Connected,
+        })
+    };
+    let endpoint = match payload.protocol {
+        PayloadProtocol::Tcp => network(NetworkProtocol::Tcp),
+        PayloadProtocol::Udp => network(NetworkProtocol::Udp),
+        PayloadProtocol::Device => EndpointConfig::Device(DeviceConfig {
+            path: payload.address.clone(),
+        }),
+    };
+
+    DHConfig {
+        dh_id,
+        name: DHName::new(format!("DH{}", dh_id.0)),
+        endpoint,
+        packet_size: payload.packet_size.load(Ordering::SeqCst) as usize,
+        packet_interval_ms: payload.packet_interval_ms.load(Ordering::SeqCst),
+        conduit: ConduitOptions::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use tcslibgs::{DHState, DHType};
+
+    fn udp_payload(packet_size: u32) -> PayloadConfig {
+        PayloadConfig {
+            _id: 0,
+            protocol: PayloadProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: 0,
+            packet_size: Arc::new(AtomicU32::new(packet_size)),
+            segment_size: Arc::new(AtomicU32::new(packet_size)),
+            packet_interval_ms: Arc::new(AtomicU32::new(20)),
+            segment_interval_ms: Arc::new(AtomicU32::new(20)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
+        }
+    }
+
+    #[test]
+    fn test_harness_udp_payload() {
+        let mut harness = Harness::start(vec![udp_payload(16)]).unwrap();
+        let dh_id = DHId(0);
+
+        // START_DH starts the data handler on an OC port of its own
+        let client = harness.client();
+        let tm = client.start_dh_with_outcome(dh_id, DHType::Network, DHName::new("DH0")).unwrap();
+        assert_eq!(tm.header.status, CommandStatus::Success);
+        let oc_addr = format!("127.0.0.1:{}", tm.oc_port.unwrap().0);
+        assert_eq!(client.query_dh(DHId(1)).unwrap().0, CommandStatus::NotFound);
+        assert!(client.list_dhs().unwrap().iter().any(|dh| dh.dh_id == dh_id && dh.state == DHState::Active));
+
+        // Uplink reaches the payload, which answers with downlink for as
+        // long as it is left sending
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let uplink = b"\x1b[2J\x00reset\r\n";
+        for _ in 0..3 {
+            ground.send_to(uplink, &oc_addr).unwrap();
+        }
+        let uplinked = 3 * uplink.len() as u64;
+        let payload = harness.payload(dh_id).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while payload.stats().packets_sent < 5 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        payload.set_packet_interval(0);
+
+        let mut downlinked = 0;
+        let mut buf = [0u8; 64];
+        while let Ok(n) = ground.recv(&mut buf) {
+            downlinked += n as u64;
+        }
+        let stats = payload.stats();
+        assert_eq!((stats.packets_recv, stats.bytes_recv), (3, uplinked));
+        assert!(stats.packets_sent >= 5);
+        assert_eq!(downlinked, stats.bytes_sent);
+
+        // QUERY_DH counts the uplink the payload received and the downlink
+        // it sent
+        let (status, dh_stats) = harness.client().query_dh(dh_id).unwrap();
+        assert_eq!(status, CommandStatus::Success);
+        assert_eq!((dh_stats.bytes_received, dh_stats.bytes_sent), (stats.bytes_recv, stats.bytes_sent));
+
+        harness.stop().unwrap();
+    }
+}
@@ -0,0 +1,9 @@
+//! TCSpecial Mission Operations Center library
+//!
+//! The command client behind the tcsmoc GUI, and a harness running a
+//! command interpreter and simulated payloads in-process for testing
+//! without either GUI, built with the harness feature.
+
+pub mod client;
+#[cfg(any(test, feature = "harness"))]
+pub mod harness;
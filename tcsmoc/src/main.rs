@@ -2,21 +2,24 @@
 //!
 //! A GUI application for testing and visualizing tcspecial operation.
 
-pub mod client;
-
 use slint::SharedString;
+use std::env;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::process::{Child, Command, exit};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-pub use crate::client::TcsClient;
+pub use tcsmoc::client::TcsClient;
+use tcsmoc::client::{time_seeded_sequence, TcsClientBuilder};
 use tcslib::UdpConnection;
-use tcslibgs::{ArmKey, CommandStatus, DHId, DHName, DHType};
+use tcslibgs::{ArmKey, CommandStatus, DHId, DHName, DHState, DHType};
+use tcspecial::config::check_bind_conflicts;
 use tcspecial::config::constants::BEACON_NETADDR;
 
 use crate::beacon_receive::BeaconReceive;
-use crate::config::constants::BEACON_INDICATOR;
+use crate::config::constants::{BEACON_INDICATOR, BEACON_REUSE_ADDRESS};
 
 slint::include_modules!();
 
@@ -103,6 +106,29 @@ fn main() {
     let ui = MainWindow::new().unwrap();
     let ui_weak = ui.as_weak();
 
+    // TCSMOC_SOURCE_PORT pins the command source port, either a single port
+    // or a range like 5100-5110
+    let source_ports = match env::var("TCSMOC_SOURCE_PORT") {
+        Ok(ports) => match parse_port_range(&ports) {
+            Some(range) => Some(range),
+            None => {
+                eprintln!("Invalid TCSMOC_SOURCE_PORT: {}", ports);
+                exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+
+    // The CI runs on this host, so its socket and ours must all stay clear
+    // of the beacon port
+    let beacon_addr: SocketAddr = BEACON_NETADDR.parse().unwrap();
+    let mut command_addrs: Vec<SocketAddr> = vec![DEFAULT_CI_ADDRESS.parse().unwrap()];
+    command_addrs.extend(source_ports.iter().cloned().flatten().map(|port| SocketAddr::from(([0, 0, 0, 0], port))));
+    if let Err(e) = check_bind_conflicts(&command_addrs, &[beacon_addr]) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
     // Start tcspecial and tcssim subprocesses first
     let process_manager_tcspecial = Arc::new(ProcessManager::new());
     process_manager_tcspecial.start_child("tcspecial");
@@ -113,12 +139,21 @@ fn main() {
     thread::sleep(Duration::new(2, 0));
 
     // Create connection and client on startup
-    let client: Arc<Mutex<TcsClient>> = match UdpConnection::new("0.0.0.0:0", DEFAULT_CI_ADDRESS) {
+    let connection = match source_ports {
+        Some(range) => UdpConnection::with_source_ports("0.0.0.0", range, DEFAULT_CI_ADDRESS),
+        None => UdpConnection::new("0.0.0.0:0", DEFAULT_CI_ADDRESS),
+    };
+    let client: Arc<Mutex<TcsClient>> = match connection {
         Ok(conn) => {
-            eprintln!("Connected to {}", DEFAULT_CI_ADDRESS);
+            eprintln!("Connected to {} from {:?}", DEFAULT_CI_ADDRESS, conn.local_addr());
             ui.set_ci_status(SharedString::from("Connected"));
             ui.set_ci_address(SharedString::from(DEFAULT_CI_ADDRESS));
-            Arc::new(Mutex::new(TcsClient::new(Box::new(conn))))
+            // Seed the sequence from the clock so a restarted tcsmoc doesn't
+            // reuse sequence numbers the CI may still be answering
+            let client = TcsClientBuilder::new()
+                .sequence(time_seeded_sequence())
+                .build(Box::new(conn));
+            Arc::new(Mutex::new(client))
         }
         Err(e) => {
             eprintln!("Failed to connect to {}: {}", DEFAULT_CI_ADDRESS, e);
@@ -130,9 +165,9 @@ fn main() {
     };
 
     // Start receiving beacon data
-    let beacon_addr: std::net::SocketAddr = BEACON_NETADDR.parse().unwrap();
     let beacon_ui_weak = ui_weak.clone();
-    let _beacon_receive = BeaconReceive::new(beacon_ui_weak, beacon_addr, BEACON_INDICATOR.clone());
+    let beacon_receive = BeaconReceive::new(beacon_ui_weak, beacon_addr, BEACON_INDICATOR.clone(),
+        BEACON_REUSE_ADDRESS);
 
     handle_main_menu(&ui, ui_weak.clone(), client.clone());
 /*
@@ -222,6 +257,28 @@ fn main() {
 */
 
     ui.run().unwrap();
+
+    if let Some(beacon_receive) = &beacon_receive {
+        println!("Beacons: {}", beacon_receive.stats());
+    }
+}
+
+/// Parse a source port specification, either "port" or "low-high"
+fn parse_port_range(spec: &str) -> Option<RangeInclusive<u16>> {
+    match spec.split_once('-') {
+        Some((low, high)) => {
+            let low = low.trim().parse().ok()?;
+            let high = high.trim().parse().ok()?;
+            if low > high {
+                return None;
+            }
+            Some(low..=high)
+        }
+        None => {
+            let port = spec.trim().parse().ok()?;
+            Some(port..=port)
+        }
+    }
 }
 
 // Menu action handler
@@ -289,7 +346,12 @@ fn query_dh_buttons (ui: &MainWindow, ui_weak: slint::Weak<MainWindow>, client:
         let ui = ui_weak.unwrap();
         let mut guard = client.lock().unwrap();
         let mut results = Vec::new();
-        for dh_id in 0..4 {
+        // Fall back to probing for a TCSpecial too old to list its DHs
+        let dh_ids: Vec<u32> = match guard.list_dhs() {
+            Ok(handlers) => handlers.iter().map(|entry| entry.dh_id.0).collect(),
+            Err(_) => (0..4).collect(),
+        };
+        for dh_id in dh_ids {
             match guard.query_dh(DHId(dh_id)) {
                 Ok((status, stats)) => {
                     results.push(format!("DH{}: {:?} sent={} recv={}", dh_id, status, stats.bytes_sent, stats.bytes_received));
@@ -333,9 +395,14 @@ fn start_dh_handler (ui: &MainWindow, ui_weak: slint::Weak<MainWindow>, client:
         let mut guard = client.lock().unwrap();
         let id = DHId(dh_id as u32);
         let name = DHName::new(format!("DH{}", dh_id));
-        match guard.start_dh(id, DHType::Network, name) {
-            Ok(status) => {
-                let status_str = if status == CommandStatus::Success { "Active" } else { "Error" };
+        match guard.start_dh_with_outcome(id, DHType::Network, name) {
+            Ok(tm) => {
+                let status = tm.header.status;
+                let status_str = if status == CommandStatus::Success {
+                    DHState::Active.to_string()
+                } else {
+                    "Error".to_string()
+                };
                 match dh_id {
                     0 => ui.set_dh0_status(SharedString::from(status_str)),
                     1 => ui.set_dh1_status(SharedString::from(status_str)),
@@ -343,7 +410,11 @@ fn start_dh_handler (ui: &MainWindow, ui_weak: slint::Weak<MainWindow>, client:
                     3 => ui.set_dh3_status(SharedString::from(status_str)),
                     _ => {}
                 }
-                ui.set_last_response(SharedString::from(format!("START_DH {} - {:?}", dh_id, status)));
+                let response = match tm.detail {
+                    Some(detail) => format!("START_DH {} - {:?}: {}", dh_id, status, detail),
+                    None => format!("START_DH {} - {:?}", dh_id, status),
+                };
+                ui.set_last_response(SharedString::from(response));
             }
             Err(e) => {
                 ui.set_last_response(SharedString::from(format!("START_DH {} failed: {}", dh_id, e)));
@@ -362,11 +433,12 @@ fn stop_dh_handler (ui: &MainWindow, ui_weak: slint::Weak<MainWindow>, client: A
         let id = DHId(dh_id as u32);
         match guard.stop_dh(id) {
             Ok(status) => {
+                let status_str = DHState::Stopped.to_string();
                 match dh_id {
-                    0 => ui.set_dh0_status(SharedString::from("Stopped")),
-                    1 => ui.set_dh1_status(SharedString::from("Stopped")),
-                    2 => ui.set_dh2_status(SharedString::from("Stopped")),
-                    3 => ui.set_dh3_status(SharedString::from("Stopped")),
+                    0 => ui.set_dh0_status(SharedString::from(status_str)),
+                    1 => ui.set_dh1_status(SharedString::from(status_str)),
+                    2 => ui.set_dh2_status(SharedString::from(status_str)),
+                    3 => ui.set_dh3_status(SharedString::from(status_str)),
                     _ => {}
                 }
                 ui.set_last_response(SharedString::from(format!("STOP_DH {} - {:?}", dh_id, status)));
@@ -9,108 +9,258 @@
  * the interval to a very large interval will effectively do so.
  */
 
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, SystemTime};
 
-use tcslibgs::{BeaconTelemetry, TcsResult, Telemetry};
+use tcslibgs::{
+    tcs_log, BeaconDestinationStatus, BeaconFormat, BeaconHealth, BeaconTelemetry, BeaconTime, Clock, Logger,
+    ProtocolMessage, StartReason, TcsError, TcsResult, Telemetry,
+};
+
+/// A beacon destination and when it is next due
+struct Destination {
+    status:     BeaconDestinationStatus,
+    /// Interval for this destination, overriding the shared one
+    interval:   Option<Duration>,
+    due:        SystemTime,
+}
+
+/// Gathers the spacecraft health when a beacon is sent, or None if it can't
+pub type HealthSource = Box<dyn Fn() -> Option<BeaconHealth> + Send>;
 
 #[derive(Clone)]
 pub struct BeaconSend {
-    pair:       ArcCondPair<SystemTime>,
+    pair:       ArcCondPair<Vec<Destination>>,
     interval:   Arc<Mutex<Duration>>,
-    dest_addr:  std::net::SocketAddr
+    format:     BeaconFormat,
+    node_id:    u32,
+    sequence:   Arc<AtomicU32>,
+    start_reason: StartReason,
+    clock:      Clock,
+    health:     Arc<Mutex<Option<HealthSource>>>,
+    logger:     Arc<Mutex<Logger>>,
+    /// An out-of-cycle beacon has been asked for
+    immediate:  Arc<AtomicBool>,
 }
 
 impl BeaconSend {
-    pub fn new(interval: Duration, dest_addr: std::net::SocketAddr) -> Option<BeaconSend> {
+    /// Start beaconing from bind_addr, or return None if the interval is zero
+    ///
+    /// Beacons are stamped with the clock's time and report the time since
+    /// it was created as the uptime.
+    ///
+    /// The socket is bound before the beacon thread starts, so a failure to
+    /// bind is returned here rather than lost with the thread.
+    pub fn new(
+        interval:   Duration,
+        bind_addr:  SocketAddr,
+        dest_addrs: Vec<SocketAddr>,
+        format:     BeaconFormat,
+        node_id:    u32,
+        start_reason: StartReason,
+        clock:      Clock,
+    ) -> TcsResult<Option<BeaconSend>> {
         if interval == Duration::from_secs(0) {
-            return None;
+            return Ok(None);
         }
 
-        let expiration_time = SystemTime::now() + interval;
+        let socket = UdpSocket::bind(bind_addr)?;
+
+        // Every destination gets a beacon straight away
+        let now = SystemTime::now();
+        let destinations = dest_addrs
+            .into_iter()
+            .map(|address| Destination {
+                status: BeaconDestinationStatus::new(address),
+                interval: None,
+                due: now,
+            })
+            .collect();
         let pair = Arc::new(CondPair {
-            lock: Mutex::new(expiration_time),
+            lock: Mutex::new(destinations),
             cvar: Condvar::new(),
         });
 
         let b = BeaconSend {
             pair,
             interval: Arc::new(Mutex::new(interval)),
-            dest_addr,
+            format,
+            node_id,
+            sequence: Arc::new(AtomicU32::new(0)),
+            start_reason,
+            clock,
+            health: Arc::new(Mutex::new(None)),
+            logger: Arc::new(Mutex::new(Logger::from_env())),
+            immediate: Arc::new(AtomicBool::new(false)),
         };
 
         let b_clone = b.clone();
-        thread::spawn(move || {
-// FIXME: add check for error
-            let _ = b_clone.beacon_send();
-        });
+        thread::spawn(move || b_clone.beacon_send(socket));
 
-        Some(b)
+        Ok(Some(b))
     }
 
-    // FIXME: check result type
-    fn beacon_send(&self) -> TcsResult<()> {
-        // Bind to a local address
-//        let socket = UdpSocket::bind(BEACON_NETADDR)?; // 0 = let OS pick a port
-        let socket = UdpSocket::bind("0.0.0.0:0"); // 0 = let OS pick a port
-let socket = socket?;
-
-// FIXME: add check for error
-        let _ = self.send_beacon(&socket, &self.dest_addr);
-
+    fn beacon_send(&self, socket: UdpSocket) {
+        let mut destinations = self.pair.lock.lock().unwrap();
         loop {
-            let mut expiration = self.pair.lock.lock().unwrap();
-
-            // Wait until expiration time or until notified
-            while *expiration > SystemTime::now() {
-                let timeout = expiration
-                    .duration_since(SystemTime::now())
-                    .unwrap_or(Duration::from_millis(1));
-
-                let (guard, result) = self.pair.cvar.wait_timeout(expiration, timeout).unwrap();
-                expiration = guard;
-
-                if result.timed_out() {
-                    break;
+            let now = SystemTime::now();
+            if self.immediate.swap(false, Ordering::SeqCst) {
+                if let Err(e) = self.send(&socket, &mut destinations, now, false) {
+                    tcs_log!(self.logger.lock().unwrap(), Error, "beacon_send: can't encode beacon: {}", e);
                 }
             }
 
-            // Send the beacon
-// FIXME: add check for error
-            let _ = self.send_beacon(&socket, &self.dest_addr);
+            // Wait until the earliest destination is due or until notified
+            let next_due = destinations.iter().map(|dest| dest.due).min();
+            match next_due {
+                None => {
+                    destinations = self.pair.cvar.wait(destinations).unwrap();
+                    continue;
+                }
+                Some(due) if due > now => {
+                    let timeout = due.duration_since(now).unwrap_or(Duration::from_millis(1));
+                    destinations = self.pair.cvar.wait_timeout(destinations, timeout).unwrap().0;
+                    continue;
+                }
+                Some(_) => {}
+            }
 
-            // Calculate next expiration time1G
-            let interval = *self.interval.lock().unwrap();
-            let now = SystemTime::now();
-            *expiration = now + interval;
+            // Send the beacons
+            if let Err(e) = self.send(&socket, &mut destinations, now, true) {
+                tcs_log!(self.logger.lock().unwrap(), Error, "beacon_send: can't encode beacon: {}", e);
+            }
         }
     }
 
-    pub fn send_beacon(&self, socket: &UdpSocket, dest_addr: &std::net::SocketAddr) -> TcsResult<()> {
-        let beacon = Telemetry::Beacon(BeaconTelemetry::new());
-        let data = serde_json::to_vec(&beacon)?;
-eprintln!("send_beacon::sendto {:?}", dest_addr);
-        let status = socket.send_to(&data, dest_addr);
+    /// Send a beacon to each destination that is due and schedule its next
+    /// one or, if not scheduled, to every destination leaving the schedule
+    /// alone, counting successes and failures
+    fn send(
+        &self,
+        socket: &UdpSocket,
+        destinations: &mut [Destination],
+        now: SystemTime,
+        scheduled: bool,
+    ) -> TcsResult<()> {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        let shared_interval = *self.interval.lock().unwrap();
+        // Legacy beacons have no room for the health
+        let health = match self.format {
+            BeaconFormat::Extended => self.health.lock().unwrap().as_ref().and_then(|source| source()),
+            BeaconFormat::Legacy => None,
+        };
+        for dest in destinations.iter_mut().filter(|dest| !scheduled || dest.due <= now) {
+            let interval = dest.interval.unwrap_or(shared_interval);
+            if scheduled {
+                // Keep to the schedule rather than drifting by however late
+                // this send is, unless so late that missed beacons would bunch up
+                let next = dest.due + interval;
+                dest.due = if next > now { next } else { now + interval };
+            }
+
+            let mut beacon = BeaconTelemetry::with_format(
+                self.format,
+                sequence,
+                self.node_id,
+                BeaconTime(interval.as_millis() as u32),
+            )
+            .with_timestamp(self.clock.now());
+            // The first beacon lets the ground correlate a restart command with recovery
+            if sequence == 0 {
+                beacon = beacon.with_start(self.start_reason, self.clock.uptime().as_millis() as u64);
+            }
+            if let Some(health) = health {
+                beacon = beacon.with_health(health);
+            }
+            let data = ProtocolMessage::from_telemetry(Telemetry::Beacon(beacon)).to_bytes()?;
+            match socket.send_to(&data, dest.status.address) {
+                Ok(_) => dest.status.sent += 1,
+                Err(_) => dest.status.failed += 1,
+            }
+        }
         Ok(())
     }
 
+    /// Set where extended beacons get the spacecraft health from
+    pub fn set_health_source(&self, source: HealthSource) {
+        *self.health.lock().unwrap() = Some(source);
+    }
+
+    /// Log through logger rather than to stderr at the RUST_LOG level
+    pub fn set_logger(&self, logger: Logger) {
+        *self.logger.lock().unwrap() = logger;
+    }
+
+    /// Get the destinations, their interval overrides and send counts
+    pub fn status(&self) -> Vec<BeaconDestinationStatus> {
+        self.pair
+            .lock
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|dest| BeaconDestinationStatus {
+                interval_override: dest.interval.map(|interval| BeaconTime(interval.as_millis() as u32)),
+                ..dest.status
+            })
+            .collect()
+    }
+
+    /// Send a beacon to every destination now, without moving when the
+    /// periodic ones are due
+    pub fn send_now(&self) {
+        // Set under the lock so the worker can't miss it between checking
+        // and waiting
+        let destinations = self.pair.lock.lock().unwrap();
+        self.immediate.store(true, Ordering::SeqCst);
+        drop(destinations);
+
+        self.pair.cvar.notify_one();
+    }
+
     /// Reset the interval to the given value. This will result in the immediate
-    /// sending of a beacon message
-    pub fn set_interval(&mut self, interval: Duration) {
-        if interval == Duration::from_secs(0) {
-            return;
+    /// sending of a beacon message to destinations without their own interval.
+    /// Returns the interval applied; a zero interval is refused and leaves
+    /// the current one in effect.
+    pub fn set_interval(&mut self, interval: Duration) -> TcsResult<Duration> {
+        if interval.is_zero() {
+            return Err(TcsError::Config("Beacon interval must not be zero".to_string()));
         }
 
         // Update the interval
         *self.interval.lock().unwrap() = interval;
 
-        // Set expiration to now to trigger immediate beacon
-        *self.pair.lock.lock().unwrap() = SystemTime::now();
+        // Make the destinations due now to trigger immediate beacons
+        let now = SystemTime::now();
+        for dest in self.pair.lock.lock().unwrap().iter_mut().filter(|dest| dest.interval.is_none()) {
+            dest.due = now;
+        }
 
         // Wake the worker thread
         self.pair.cvar.notify_one();
+        Ok(interval)
+    }
+
+    /// Give one destination its own interval, or return it to the shared
+    /// interval with None. A beacon is sent to it immediately. Returns false
+    /// if the address is not a destination.
+    pub fn set_destination_interval(&self, address: SocketAddr, interval: Option<Duration>) -> bool {
+        if interval == Some(Duration::from_secs(0)) {
+            return false;
+        }
+
+        let mut destinations = self.pair.lock.lock().unwrap();
+        let Some(dest) = destinations.iter_mut().find(|dest| dest.status.address == address) else {
+            return false;
+        };
+        dest.interval = interval;
+        dest.due = SystemTime::now();
+        drop(destinations);
+
+        self.pair.cvar.notify_one();
+        true
     }
 }
 
@@ -120,3 +270,75 @@ struct CondPair<T> {
     lock: Mutex<T>,
     cvar: Condvar,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_beacon_schedule() {
+        let interval = Duration::from_millis(40);
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let _beacon = BeaconSend::new(interval, "127.0.0.1:0".parse().unwrap(), vec![ground.local_addr().unwrap()],
+            BeaconFormat::Legacy, 0, StartReason::ColdStart, Clock::new()).unwrap().unwrap();
+
+        // Each beacon arrives close to its slot in the schedule set by the
+        // first, so lateness doesn't build up from one beacon to the next
+        let mut buf = [0u8; 1024];
+        ground.recv(&mut buf).unwrap();
+        let first = Instant::now();
+        for n in 1..=10 {
+            ground.recv(&mut buf).unwrap();
+            let offset = first.elapsed().as_secs_f64() * 1000.0 - (interval * n).as_secs_f64() * 1000.0;
+            assert!(offset.abs() < 15.0, "beacon {} is {:.1} ms off schedule", n, offset);
+        }
+    }
+
+    #[test]
+    fn test_send_now_keeps_schedule() {
+        let interval = Duration::from_millis(100);
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let beacon = BeaconSend::new(interval, "127.0.0.1:0".parse().unwrap(), vec![ground.local_addr().unwrap()],
+            BeaconFormat::Legacy, 0, StartReason::ColdStart, Clock::new()).unwrap().unwrap();
+
+        let mut buf = [0u8; 1024];
+        ground.recv(&mut buf).unwrap();
+        let first = Instant::now();
+
+        // The out-of-cycle beacon arrives at once...
+        thread::sleep(interval / 4);
+        beacon.send_now();
+        ground.recv(&mut buf).unwrap();
+        assert!(first.elapsed() < interval / 2, "immediate beacon took {:?}", first.elapsed());
+
+        // ...and the next periodic one still comes in its slot
+        ground.recv(&mut buf).unwrap();
+        let offset = first.elapsed().as_secs_f64() * 1000.0 - interval.as_secs_f64() * 1000.0;
+        assert!(offset.abs() < 15.0, "periodic beacon is {:.1} ms off schedule", offset);
+        assert_eq!(beacon.status()[0].sent, 3);
+    }
+
+    #[test]
+    fn test_set_interval() {
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut beacon = BeaconSend::new(Duration::from_secs(60), "127.0.0.1:0".parse().unwrap(),
+            vec![ground.local_addr().unwrap()], BeaconFormat::Legacy, 0, StartReason::ColdStart, Clock::new())
+            .unwrap().unwrap();
+        let mut buf = [0u8; 1024];
+        ground.recv(&mut buf).unwrap();
+
+        // Zero is refused and nothing changes
+        assert!(matches!(beacon.set_interval(Duration::ZERO), Err(TcsError::Config(_))));
+        assert_eq!(*beacon.interval.lock().unwrap(), Duration::from_secs(60));
+
+        // A valid interval is applied, returned, and beacons at once
+        let interval = Duration::from_millis(250);
+        assert_eq!(beacon.set_interval(interval).unwrap(), interval);
+        assert_eq!(*beacon.interval.lock().unwrap(), interval);
+        ground.recv(&mut buf).unwrap();
+    }
+}
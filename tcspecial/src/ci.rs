@@ -2,86 +2,249 @@
 //!
 //! The CI processes commands from the OC and manages data handlers.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use crate::beacon_send::BeaconSend;
-use std::net::UdpSocket;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
 //use std::os::unix::io::AsRawFd;
-use std::sync::{Arc, Mutex};
-//use std::thread;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 //use std::time::{Duration, Instant};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tcslibgs::{
-    ArmKey, BeaconTelemetry, BeaconTime, CIConfig, Command, CommandStatus, ConfigTelemetry,
-    DHConfig, DHId, PingTelemetry, QueryDHTelemetry, RestartArmTelemetry, RestartTelemetry,
-    StartDHTelemetry, Statistics, StopDHTelemetry, TcsError, TcsResult, Telemetry,
+    tcs_log, ArmKey, BeaconHealth, BeaconTelemetry, BeaconTime, BeaconTrigger, BufferPool, CIConfig,
+    CheckpointDHTelemetry, Clock, Command, CommandStatus, CommandType, ConduitOptions, ConfigTelemetry, DHActivity,
+    DHConfig, DHControlCommand, DHControlTelemetry, DHEventKind, DHEventTelemetry, DHId, DHListEntry,
+    DHLoopbackCommand, DHLoopbackTelemetry, DHState, DHStatistics, ErrorCode, Fragment, Framing, HelloTelemetry,
+    InjectFaultTelemetry, InvalidCommandTelemetry, ListDHTelemetry, Logger, MessagePayload, NetworkConfig,
+    NetworkProtocol, PauseAllDHTelemetry, PingTelemetry, Port,
+    ProtocolMessage, QueryActivityTelemetry, QueryBeaconStatusTelemetry, QueryDHTelemetry, QueryDroppedTelemetry,
+    QueryEndpointSupportTelemetry, ReconfigureDHTelemetry, ReloadConfigTelemetry, ReloadSummary, ResetStatsTelemetry,
+    RestartArmTelemetry, RestartTelemetry, ResumeAllDHTelemetry, SetLogLevelTelemetry, SetTimeTelemetry,
+    StartDHOutcome, StartDHTelemetry, StartReason, Statistics, StatsSnapshotTelemetry, StopDHTelemetry,
+    SubscribeDHStatsTelemetry, TcsError, TcsResult, Telemetry, Timestamp, UdpMode, UnsubscribeDHStatsTelemetry,
+    FRAGMENT_HEADER_SIZE, MAX_MESSAGE_SIZE,
 };
 
-use crate::config::constants::{BEACON_DEFAULT_MS, BEACON_NETADDR, RESTART_ARM_TIMEOUT};
-use crate::dh::DataHandler;
+use crate::config::{check_max_data_handlers, fd_limit, load_payload_config};
+use crate::config::constants::{
+    BEACON_BIND_ADDR, BEACON_DEFAULT_MS, BEACON_NETADDR, BINARY_PEERS_MAX, CI_MIN_WAIT, CI_SERVICE_INTERVAL, CONTROL_WRITE_TIMEOUT,
+    DEFERRED_REPLY_POLL, DOWNLINK_BURST, ENDPOINT_BUFFER_SIZE,
+    LOOPBACK_TIMEOUT_MAX, REPLY_CACHE_MAX_AGE, RESTART_ARM_TIMEOUT, SUBSCRIPTION_TIMEOUT, TELEMETRY_MAX_DATAGRAM, TELEMETRY_QUEUE_DEPTH,
+};
+use crate::dh::{validate_config, DataHandler};
+use crate::endpoint::{bind_udp, OcEndpoint, SUPPORTED_DH_TYPES, SUPPORTED_PROTOCOLS};
+use crate::rate_limit::RateLimiter;
+use crate::telemetry_queue::TelemetryQueue;
+
+/// Ground station receiving periodic statistics for a DH
+struct Subscription {
+    dh_id: DHId,
+    subscriber: SocketAddr,
+    /// Sequence number of the SUBSCRIBE_DH_STATS command, used for updates
+    sequence: u32,
+    /// Correlation id of the SUBSCRIBE_DH_STATS command, echoed in updates
+    request_id: Option<u64>,
+    interval: Duration,
+    last_sent: Instant,
+    expires: Instant,
+}
+
+/// A command that can't safely be run twice and the reply it got
+struct LastReply {
+    addr: SocketAddr,
+    command: Command,
+    reply: Telemetry,
+    received: Instant,
+}
+
+/// A command whose reply is being worked on by another thread
+struct DeferredReply {
+    addr: SocketAddr,
+    command: Command,
+    reply: mpsc::Receiver<Telemetry>,
+}
 
 /// Command interpreter state
 pub struct CommandInterpreter {
-    _beacon: Option<BeaconSend>,
+    beacon: Option<BeaconSend>,
     beacon_interval: BeaconTime,
-    _config: CIConfig,
+    config: CIConfig,
     socket: UdpSocket,
     data_handlers: Arc<Mutex<BTreeMap<DHId, DataHandler>>>,
     payload_config: Vec<DHConfig>,
+    /// File the payload configuration was loaded from, re-read by RELOAD_CONFIG
+    payload_path: Option<PathBuf>,
     arm_key: Option<ArmKey>,
     arm_time: Option<Instant>,
     running: bool,
-    _global_stats: Statistics,
+    fragment_id: u32,
+    telemetry_queue: TelemetryQueue,
+    commands_dropped: u64,
+    /// Telemetry received on the command port, likely from a misconfigured peer
+    unexpected_telemetry: u64,
+    start_reason: StartReason,
+    /// Spacecraft clock, corrected by SET_TIME
+    clock: Clock,
+    last_self_poll: Instant,
+    dh_progress: BTreeMap<DHId, u64>,
+    stalled: BTreeSet<DHId>,
+    /// Failures to report for the next command of each type
+    injected_faults: Vec<(CommandType, CommandStatus)>,
+    /// Address the command being processed came from
+    client_addr: Option<SocketAddr>,
+    subscriptions: Vec<Subscription>,
+    /// Ground addresses whose last command was binary, and so get binary
+    /// telemetry; everyone else gets JSON. Only the BINARY_PEERS_MAX most
+    /// recently heard from are kept, most recent last.
+    binary_peers: VecDeque<SocketAddr>,
+    /// Last command and its reply, so a resent command is answered again
+    /// rather than executed twice; only kept for the current client, for
+    /// REPLY_CACHE_MAX_AGE, and for commands that aren't idempotent
+    last_reply: Option<LastReply>,
+    /// Commands still waiting for their replies, which are sent as they come
+    deferred: Vec<DeferredReply>,
+    /// Cap on the combined downlink of all DHs, if configured
+    downlink_limiter: Option<Arc<RateLimiter>>,
+    /// Condition of each data handler as last reported in a DH_EVENT
+    dh_conditions: BTreeMap<DHId, DHEventKind>,
+    /// Sequence number of the next DH_EVENT
+    event_sequence: u32,
+    /// Log whose level SET_LOG_LEVEL changes
+    logger: Logger,
 }
 
 impl CommandInterpreter {
     /// Create a new command interpreter
     pub fn new(config: CIConfig, payload_config: Vec<DHConfig>) -> TcsResult<Self> {
         let addr = format!("{}:{}", config.address, config.port);
-        let socket = UdpSocket::bind(&addr)?;
+        let sock_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| TcsError::Config(format!("No address found for {}", addr)))?;
+        if let Some(max) = config.max_data_handlers {
+            check_max_data_handlers(max, fd_limit()?)?;
+        }
+        let socket = bind_udp(sock_addr, config.reuse_address)?;
         socket.set_nonblocking(false)?;
+        BufferPool::global().set_capacity(config.buffer_pool_bytes);
+        let start_reason = take_restart_marker(config.restart_marker.as_deref());
+        let downlink_limiter = config
+            .downlink_bytes_per_sec
+            .map(|rate| Arc::new(RateLimiter::new(rate, DOWNLINK_BURST)));
 
         Ok(Self {
             beacon_interval: config.beacon_interval,
-            _beacon: None,
-            _config: config,
+            beacon: None,
+            config,
             socket,
             data_handlers: Arc::new(Mutex::new(BTreeMap::new())),
             payload_config,
+            payload_path: None,
             arm_key: None,
             arm_time: None,
             running: false,
-            _global_stats: Statistics::new(),
+            fragment_id: 0,
+            telemetry_queue: TelemetryQueue::new(TELEMETRY_QUEUE_DEPTH),
+            commands_dropped: 0,
+            unexpected_telemetry: 0,
+            start_reason,
+            clock: Clock::new(),
+            last_self_poll: Instant::now(),
+            dh_progress: BTreeMap::new(),
+            stalled: BTreeSet::new(),
+            injected_faults: vec![],
+            client_addr: None,
+            subscriptions: vec![],
+            binary_peers: VecDeque::new(),
+            last_reply: None,
+            deferred: Vec::new(),
+            downlink_limiter,
+            dh_conditions: BTreeMap::new(),
+            event_sequence: 0,
+            logger: Logger::from_env(),
         })
     }
 
+    /// Set the file RELOAD_CONFIG re-reads the payload configuration from
+    pub fn with_payload_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.payload_path = Some(path.into());
+        self
+    }
+
+    /// Log through logger rather than to stderr at the RUST_LOG level
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Get the address the command socket is bound to, which tells clients
+    /// where to connect when the OS picked the port
+    pub fn local_addr(&self) -> TcsResult<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
     /// Initialize data handlers from configuration
     pub fn initialize_handlers(&mut self) -> TcsResult<()> {
         let mut handlers = self.data_handlers.lock()
             .map_err(|_| TcsError::DataHandler("Lock poisoned".to_string()))?;
 
         for config in &self.payload_config {
-            let dh = DataHandler::new(config.clone())?;
-            handlers.insert(config.dh_id, dh);
+            handlers.insert(config.dh_id, self.new_dh(config)?);
         }
 
         Ok(())
     }
 
+    /// Create a data handler sharing the CI's downlink limit and clock
+    fn new_dh(&self, config: &DHConfig) -> TcsResult<DataHandler> {
+        Ok(DataHandler::new(config.clone())?
+            .with_downlink_limiter(self.downlink_limiter.clone())
+            .with_clock(self.clock.clone()))
+    }
+
     /// Process a command and return the response telemetry
     fn process_command(&mut self, command: Command) -> Telemetry {
-eprintln!("process_command: {:?}", command);
+        tcs_log!(self.logger, Debug, "process_command: {:?}", command);
+        let request_id = command.request_id();
+        let mut response = self.execute_command(command);
+        response.set_request_id(request_id);
+        response
+    }
+
+    /// Carry out a command, returning its response
+    fn execute_command(&mut self, command: Command) -> Telemetry {
+        if let Some(index) = self.injected_faults.iter().position(|(target, _)| *target == command.cmd_type()) {
+            let (_, status) = self.injected_faults.remove(index);
+            return fault_response(&command, status, self.clock.now());
+        }
+
         match command {
             Command::Ping(cmd) => {
-                Telemetry::Ping(PingTelemetry::new(cmd.header.sequence, CommandStatus::Success))
+                Telemetry::Ping(PingTelemetry::new(cmd.header.sequence, CommandStatus::Success).with_timestamp(self.clock.now()))
             }
             Command::RestartArm(cmd) => {
+                // Reject unrecognized keys without disturbing any existing arm
+                if let Some(keys) = &self.config.arm_keys {
+                    if !keys.contains(&cmd.arm_key) {
+                        return Telemetry::RestartArm(RestartArmTelemetry::new(
+                            cmd.header.sequence,
+                            CommandStatus::InvalidArmKey,
+                        ));
+                    }
+                }
                 self.arm_key = Some(cmd.arm_key);
                 self.arm_time = Some(Instant::now());
+                self.trigger_beacon(BeaconTrigger::RestartArmed);
                 Telemetry::RestartArm(RestartArmTelemetry::new(cmd.header.sequence, CommandStatus::Success))
             }
             Command::Restart(cmd) => {
                 let status = if let (Some(arm_key), Some(arm_time)) = (self.arm_key, self.arm_time) {
                     if arm_key == cmd.arm_key && arm_time.elapsed() < RESTART_ARM_TIMEOUT {
+                        if let Some(path) = &self.config.restart_marker {
+                            let _ = write_restart_marker(path);
+                        }
                         self.running = false;
                         CommandStatus::Success
                     } else {
@@ -92,96 +255,879 @@ eprintln!("process_command: {:?}", command);
                 };
                 Telemetry::Restart(RestartTelemetry::new(cmd.header.sequence, status))
             }
+            Command::QueryDropped(cmd) => {
+                let telemetry = Telemetry::QueryDropped(QueryDroppedTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    self.telemetry_queue.dropped(),
+                    self.commands_dropped,
+                    self.unexpected_telemetry,
+                ));
+                if cmd.clear {
+                    self.telemetry_queue.clear_dropped();
+                    self.commands_dropped = 0;
+                    self.unexpected_telemetry = 0;
+                }
+                telemetry
+            }
+            Command::QueryEndpointSupport(cmd) => {
+                Telemetry::QueryEndpointSupport(QueryEndpointSupportTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    SUPPORTED_DH_TYPES.to_vec(),
+                    SUPPORTED_PROTOCOLS.to_vec(),
+                ))
+            }
+            Command::InjectFault(cmd) => {
+                let status = if !self.config.fault_injection {
+                    CommandStatus::InvalidCommand
+                } else if cmd.target == CommandType::InjectFault {
+                    CommandStatus::InvalidParameter
+                } else {
+                    self.injected_faults.push((cmd.target, cmd.status));
+                    CommandStatus::Success
+                };
+                Telemetry::InjectFault(InjectFaultTelemetry::new(cmd.header.sequence, status))
+            }
+            Command::QueryBeaconStatus(cmd) => {
+                let destinations = self.beacon.as_ref().map(BeaconSend::status).unwrap_or_default();
+                Telemetry::QueryBeaconStatus(QueryBeaconStatusTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    destinations,
+                ))
+            }
+            Command::Hello(cmd) => {
+                let framing = Framing::negotiate(&cmd.framings, &self.config.framings);
+                Telemetry::Hello(HelloTelemetry::new(cmd.header.sequence, CommandStatus::Success, framing))
+            }
+            Command::SetTime(cmd) => {
+                // A rejected time leaves the clock as it was, which is what
+                // is reported as applied
+                let (status, applied, previous) = if cmd.timestamp.nanoseconds >= 1_000_000_000 {
+                    let now = self.clock.now();
+                    (CommandStatus::InvalidParameter, now, now)
+                } else {
+                    (CommandStatus::Success, cmd.timestamp, self.clock.set(cmd.timestamp))
+                };
+                Telemetry::SetTime(SetTimeTelemetry::new(cmd.header.sequence, status, applied, previous))
+            }
+            Command::SetLogLevel(cmd) => {
+                let previous = self.logger.set_level(cmd.level);
+                Telemetry::SetLogLevel(SetLogLevelTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    cmd.level,
+                    previous,
+                ))
+            }
             Command::StartDH(cmd) => {
-                let status = {
-                    let mut handlers = match self.data_handlers.lock() {
-                        Ok(h) => h,
-                        Err(_) => return Telemetry::StartDH(StartDHTelemetry::new(cmd.header.sequence, CommandStatus::Failure)),
-                    };
-
-                    if handlers.contains_key(&cmd.dh_id) {
-                        // Idempotent - already exists
-                        CommandStatus::Success
-                    } else {
-                        // Find config and create handler
-                        if let Some(config) = self.payload_config.iter().find(|c| c.dh_id == cmd.dh_id) {
-                            match DataHandler::new(config.clone()) {
-                                Ok(dh) => {
-                                    handlers.insert(cmd.dh_id, dh);
-                                    CommandStatus::Success
-                                }
-                                Err(_) => CommandStatus::Failure,
-                            }
-                        } else {
-                            CommandStatus::NotFound
+                let result = create_dh(
+                    &self.data_handlers,
+                    &self.payload_config,
+                    cmd.dh_id,
+                    self.config.start_dh_exclusive,
+                    self.config.max_data_handlers,
+                    &|config| self.new_dh(config),
+                    &self.config.address,
+                );
+                let tm = match result {
+                    Ok((outcome, oc_port)) => {
+                        let tm = StartDHTelemetry::new(cmd.header.sequence, CommandStatus::Success).with_outcome(outcome);
+                        match oc_port {
+                            Some(oc_port) => tm.with_oc_port(oc_port),
+                            None => tm,
                         }
                     }
+                    Err(e) => StartDHTelemetry::new(cmd.header.sequence, start_dh_status(&e))
+                        .with_error(ErrorCode::from(&e))
+                        .with_detail(e.to_string()),
                 };
-                Telemetry::StartDH(StartDHTelemetry::new(cmd.header.sequence, status))
+                Telemetry::StartDH(tm)
             }
             Command::StopDH(cmd) => {
-                let status = {
-                    let mut handlers = match self.data_handlers.lock() {
-                        Ok(h) => h,
-                        Err(_) => return Telemetry::StopDH(StopDHTelemetry::new(cmd.header.sequence, CommandStatus::Failure)),
-                    };
+                let poisoned = || {
+                    Telemetry::StopDH(
+                        StopDHTelemetry::new(cmd.header.sequence, CommandStatus::Failure)
+                            .with_detail("Data handler table lock poisoned"),
+                    )
+                };
+                // The DH is taken out while it stops, as draining its relays
+                // can take a while, and put back stopped
+                let dh = match self.data_handlers.lock() {
+                    Ok(mut handlers) => handlers.remove(&cmd.dh_id),
+                    Err(_) => return poisoned(),
+                };
 
-                    if let Some(dh) = handlers.get_mut(&cmd.dh_id) {
-                        match dh.stop() {
-                            Ok(_) => CommandStatus::Success,
-                            Err(_) => CommandStatus::Failure,
-                        }
-                    } else {
-                        // Idempotent - not found is also success
-                        CommandStatus::Success
+                // Idempotent - not found is also success
+                let result = match dh {
+                    Some(mut dh) => {
+                        let result = dh.stop();
+                        match self.data_handlers.lock() {
+                            Ok(mut handlers) => handlers.insert(cmd.dh_id, dh),
+                            Err(_) => return poisoned(),
+                        };
+                        result
                     }
+                    None => Ok(()),
+                };
+                let tm = match result {
+                    Ok(()) => StopDHTelemetry::new(cmd.header.sequence, CommandStatus::Success),
+                    Err(e) => StopDHTelemetry::new(cmd.header.sequence, CommandStatus::Failure).with_detail(e.to_string()),
                 };
-                Telemetry::StopDH(StopDHTelemetry::new(cmd.header.sequence, status))
+                Telemetry::StopDH(tm)
             }
+            Command::PauseAllDH(cmd) => {
+                let (status, paused) = self.for_each_running_dh(DataHandler::pause, DHState::Paused);
+                Telemetry::PauseAllDH(PauseAllDHTelemetry::new(cmd.header.sequence, status, paused))
+            }
+            Command::ResumeAllDH(cmd) => {
+                let (status, resumed) = self.for_each_running_dh(DataHandler::resume, DHState::Active);
+                Telemetry::ResumeAllDH(ResumeAllDHTelemetry::new(cmd.header.sequence, status, resumed))
+            }
+            Command::ResetStats(cmd) => {
+                let status = match self.data_handlers.lock() {
+                    Ok(mut handlers) => match handlers.get_mut(&cmd.dh_id) {
+                        Some(dh) => {
+                            dh.reset_statistics();
+                            CommandStatus::Success
+                        }
+                        None => CommandStatus::NotFound,
+                    },
+                    Err(_) => CommandStatus::Failure,
+                };
+                Telemetry::ResetStats(ResetStatsTelemetry::new(cmd.header.sequence, status, cmd.dh_id))
+            }
+            Command::CheckpointDH(cmd) => {
+                let status = match self.data_handlers.lock() {
+                    Ok(mut handlers) => match handlers.get_mut(&cmd.dh_id) {
+                        Some(dh) => {
+                            dh.checkpoint();
+                            CommandStatus::Success
+                        }
+                        None => CommandStatus::NotFound,
+                    },
+                    Err(_) => CommandStatus::Failure,
+                };
+                Telemetry::CheckpointDH(CheckpointDHTelemetry::new(cmd.header.sequence, status, cmd.dh_id))
+            }
+            Command::ListDH(cmd) => match self.data_handlers.lock() {
+                Ok(handlers) => {
+                    let entries = handlers
+                        .values()
+                        .map(|dh| DHListEntry {
+                            dh_id: dh.id(),
+                            dh_type: dh.config().endpoint.dh_type(),
+                            name: dh.name().clone(),
+                            state: dh.state(),
+                        })
+                        .collect();
+                    Telemetry::ListDH(ListDHTelemetry::new(cmd.header.sequence, CommandStatus::Success, entries))
+                }
+                Err(_) => Telemetry::ListDH(ListDHTelemetry::new(cmd.header.sequence, CommandStatus::Failure, vec![])),
+            },
             Command::QueryDH(cmd) => {
-                let (status, stats) = {
-                    let handlers = match self.data_handlers.lock() {
-                        Ok(h) => h,
-                        Err(_) => return Telemetry::QueryDH(QueryDHTelemetry::new(
-                            cmd.header.sequence,
-                            CommandStatus::Failure,
-                            cmd.dh_id,
-                            Statistics::new(),
-                        )),
-                    };
+                let handlers = match self.data_handlers.lock() {
+                    Ok(h) => h,
+                    Err(_) => return Telemetry::QueryDH(QueryDHTelemetry::new(
+                        cmd.header.sequence,
+                        CommandStatus::Failure,
+                        cmd.dh_id,
+                        Statistics::new(),
+                    )),
+                };
 
-                    if let Some(dh) = handlers.get(&cmd.dh_id) {
-                        (CommandStatus::Success, dh.statistics())
+                if let Some(dh) = handlers.get(&cmd.dh_id) {
+                    Telemetry::QueryDH(QueryDHTelemetry::new(
+                        cmd.header.sequence,
+                        CommandStatus::Success,
+                        cmd.dh_id,
+                        dh.statistics(),
+                    )
+                    .with_state(dh.state())
+                    .with_write_latency(dh.write_latency())
+                    .with_since_checkpoint(dh.since_checkpoint()))
+                } else {
+                    let valid_ids = if self.config.query_dh_valid_ids {
+                        Some(handlers.keys().copied().collect())
                     } else {
-                        (CommandStatus::NotFound, Statistics::new())
+                        None
+                    };
+                    Telemetry::QueryDH(QueryDHTelemetry::not_found(cmd.header.sequence, cmd.dh_id, valid_ids))
+                }
+            }
+            Command::SnapshotStats(cmd) => {
+                let timestamp = self.clock.now();
+                // The global entry is the sum over the data handlers
+                let mut global = Statistics::new();
+                global.timestamp = Some(timestamp);
+
+                let handlers = match self.data_handlers.lock() {
+                    Ok(h) => h,
+                    Err(_) => return Telemetry::StatsSnapshot(StatsSnapshotTelemetry::new(
+                        cmd.header.sequence,
+                        CommandStatus::Failure,
+                        timestamp,
+                        global,
+                        vec![],
+                    )),
+                };
+
+                // Stamp every entry with the capture time so the snapshot is
+                // a single point-in-time view
+                let dh_stats = handlers.iter()
+                    .map(|(dh_id, dh)| {
+                        let mut statistics = dh.statistics();
+                        statistics.timestamp = Some(timestamp);
+                        global.accumulate(&statistics);
+                        DHStatistics { dh_id: *dh_id, statistics }
+                    })
+                    .collect();
+
+                Telemetry::StatsSnapshot(StatsSnapshotTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    timestamp,
+                    global,
+                    dh_stats,
+                ))
+            }
+            Command::DHLoopback(cmd) => self
+                .start_loopback(cmd)
+                .recv()
+                .unwrap_or_else(|_| fault_response(&Command::DHLoopback(cmd), CommandStatus::Failure, self.clock.now())),
+            Command::DHControl(cmd) => {
+                let (sequence, dh_id) = (cmd.header.sequence, cmd.dh_id);
+                self.start_control(cmd).recv().unwrap_or_else(|_| {
+                    Telemetry::DHControl(DHControlTelemetry::new(sequence, CommandStatus::Failure, dh_id))
+                })
+            }
+            Command::QueryActivity(cmd) => {
+                let handlers = match self.data_handlers.lock() {
+                    Ok(h) => h,
+                    Err(_) => return fault_response(&Command::QueryActivity(cmd), CommandStatus::Failure, self.clock.now()),
+                };
+
+                let window = Duration::from_millis(cmd.window_ms as u64);
+                let now = Instant::now();
+                let activity = handlers
+                    .values()
+                    .filter(|dh| matches!(dh.state(), DHState::Active | DHState::Paused))
+                    .map(|dh| {
+                        let idle = dh.last_transfer().map(|at| now.saturating_duration_since(at));
+                        DHActivity {
+                            dh_id: dh.id(),
+                            active: idle.is_some_and(|idle| idle <= window),
+                            idle_ms: idle.map(|idle| idle.as_millis() as u64),
+                        }
+                    })
+                    .collect();
+                Telemetry::QueryActivity(QueryActivityTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    cmd.window_ms,
+                    activity,
+                ))
+            }
+            Command::ReconfigureDH(cmd) => {
+                let result = match self.data_handlers.lock() {
+                    Err(_) => Err((CommandStatus::Failure, "Data handler table lock poisoned".to_string())),
+                    Ok(mut handlers) => match handlers.get_mut(&cmd.dh_id) {
+                        None => Err((CommandStatus::NotFound, TcsError::DHNotFound(cmd.dh_id.0).to_string())),
+                        Some(_) if cmd.config.dh_id != cmd.dh_id => Err((
+                            CommandStatus::InvalidParameter,
+                            format!("Configuration is for DH {}, not DH {}", cmd.config.dh_id.0, cmd.dh_id.0),
+                        )),
+                        // Running DHs restart their relays on the OC endpoint START_DH gave them
+                        Some(dh) => validate_config(&cmd.config)
+                            .map_err(|e| (CommandStatus::InvalidParameter, e.to_string()))
+                            .and_then(|()| {
+                                dh.reconfigure(cmd.config.clone(), None)
+                                    .map_err(|e| (CommandStatus::Failure, e.to_string()))
+                            }),
+                    },
+                };
+                let tm = match result {
+                    Ok(()) => ReconfigureDHTelemetry::new(cmd.header.sequence, CommandStatus::Success, cmd.dh_id),
+                    Err((status, detail)) => {
+                        ReconfigureDHTelemetry::new(cmd.header.sequence, status, cmd.dh_id).with_detail(detail)
+                    }
+                };
+                Telemetry::ReconfigureDH(tm)
+            }
+            Command::ReloadConfig(cmd) => match self.reload_config() {
+                Ok(summary) => {
+                    let status = if summary.failed.is_empty() { CommandStatus::Success } else { CommandStatus::Failure };
+                    Telemetry::ReloadConfig(ReloadConfigTelemetry::new(cmd.header.sequence, status).with_summary(summary))
+                }
+                Err(status) => Telemetry::ReloadConfig(ReloadConfigTelemetry::new(cmd.header.sequence, status)),
+            },
+            Command::SubscribeDHStats(cmd) => {
+                let exists = self.data_handlers.lock().map(|h| h.contains_key(&cmd.dh_id)).unwrap_or(false);
+                let status = match self.client_addr {
+                    _ if !exists => CommandStatus::NotFound,
+                    _ if cmd.interval_ms == 0 => CommandStatus::InvalidParameter,
+                    None => CommandStatus::Failure,
+                    Some(subscriber) => {
+                        // Subscribing again renews the subscription
+                        self.subscriptions.retain(|s| !(s.dh_id == cmd.dh_id && s.subscriber == subscriber));
+                        let now = Instant::now();
+                        self.subscriptions.push(Subscription {
+                            dh_id: cmd.dh_id,
+                            subscriber,
+                            sequence: cmd.header.sequence,
+                            request_id: cmd.header.request_id,
+                            interval: Duration::from_millis(cmd.interval_ms as u64),
+                            last_sent: now,
+                            expires: now + SUBSCRIPTION_TIMEOUT,
+                        });
+                        CommandStatus::Success
                     }
                 };
-                Telemetry::QueryDH(QueryDHTelemetry::new(cmd.header.sequence, status, cmd.dh_id, stats))
+                Telemetry::SubscribeDHStats(SubscribeDHStatsTelemetry::new(cmd.header.sequence, status, cmd.dh_id))
+            }
+            Command::UnsubscribeDHStats(cmd) => {
+                // Idempotent - not subscribed is also success
+                let client_addr = self.client_addr;
+                self.subscriptions.retain(|s| !(s.dh_id == cmd.dh_id && Some(s.subscriber) == client_addr));
+                Telemetry::UnsubscribeDHStats(UnsubscribeDHStatsTelemetry::new(
+                    cmd.header.sequence,
+                    CommandStatus::Success,
+                    cmd.dh_id,
+                ))
             }
             Command::Config(cmd) => {
-                self.beacon_interval = cmd.beacon_interval;
-                Telemetry::Config(ConfigTelemetry::new(cmd.header.sequence, CommandStatus::Success))
+                let tm = match self.set_beacon_interval(cmd.beacon_interval) {
+                    Ok(applied) => {
+                        ConfigTelemetry::new(cmd.header.sequence, CommandStatus::Success).with_beacon_interval(applied)
+                    }
+                    Err(_) => ConfigTelemetry::new(cmd.header.sequence, CommandStatus::InvalidParameter),
+                };
+                Telemetry::Config(tm)
+            }
+            Command::ConfigDH(cmd) => {
+                let status = match self.data_handlers.lock() {
+                    Err(_) => CommandStatus::Failure,
+                    Ok(mut handlers) => match handlers.get_mut(&cmd.dh_id) {
+                        None => CommandStatus::NotFound,
+                        Some(dh) => {
+                            let current = &dh.config().conduit;
+                            let options = ConduitOptions {
+                                read_buffer_size: cmd.read_buffer_size.or(current.read_buffer_size),
+                                write_buffer_size: cmd.write_buffer_size.or(current.write_buffer_size),
+                                stream_delay_ms: cmd.stream_delay_ms.or(current.stream_delay_ms),
+                                rate_limit_bps: cmd.rate_limit_bps.or(current.rate_limit_bps),
+                                ..current.clone()
+                            };
+                            match dh.set_conduit_options(options) {
+                                Ok(()) => CommandStatus::Success,
+                                Err(_) => CommandStatus::InvalidParameter,
+                            }
+                        }
+                    },
+                };
+                Telemetry::ConfigDH(tcslibgs::ConfigDHTelemetry::new(cmd.header.sequence, status))
+            }
+        }
+    }
+
+    /// Start a command whose reply would keep the CI from other commands,
+    /// returning where the reply will come from
+    ///
+    /// Returns None for commands that are answered as soon as they are
+    /// processed, and for those with an injected fault waiting.
+    fn defer_command(&mut self, command: &Command) -> Option<mpsc::Receiver<Telemetry>> {
+        if self.injected_faults.iter().any(|(target, _)| *target == command.cmd_type()) {
+            return None;
+        }
+        match command {
+            Command::DHLoopback(cmd) => Some(self.start_loopback(*cmd)),
+            Command::DHControl(cmd) => Some(self.start_control(cmd.clone())),
+            _ => None,
+        }
+    }
+
+    /// Send a DH_LOOPBACK token through its DH's relay, returning where the
+    /// reply will come from
+    ///
+    /// The echo is waited for on a thread of its own, with the timeout
+    /// counted from now, so a payload still being connected to uses up some
+    /// of it. Refusals are replied to at once.
+    fn start_loopback(&self, cmd: DHLoopbackCommand) -> mpsc::Receiver<Telemetry> {
+        let (sender, receiver) = mpsc::channel();
+        let reply = move |status, rtt_us| {
+            Telemetry::DHLoopback(DHLoopbackTelemetry::new(cmd.header.sequence, status, cmd.dh_id, cmd.token, rtt_us))
+        };
+
+        // Only a relaying DH is looped back, as the relay carries the token
+        let loopback = match self.data_handlers.lock() {
+            Ok(handlers) => match handlers.get(&cmd.dh_id) {
+                None => Err(CommandStatus::NotFound),
+                Some(dh) if dh.state() != DHState::Active => Err(CommandStatus::InvalidParameter),
+                Some(dh) => dh.send_loopback(cmd.token).map_err(|e| match e {
+                    TcsError::Timeout => CommandStatus::Timeout,
+                    _ => CommandStatus::Failure,
+                }),
+            },
+            Err(_) => Err(CommandStatus::Failure),
+        };
+
+        match loopback {
+            Ok(loopback) => {
+                let timeout = Duration::from_millis(cmd.timeout_ms as u64).min(LOOPBACK_TIMEOUT_MAX);
+                thread::spawn(move || {
+                    let telemetry = match loopback.wait(timeout) {
+                        Ok(rtt) => reply(CommandStatus::Success, Some(rtt.as_micros() as u64)),
+                        Err(TcsError::Timeout) => reply(CommandStatus::Timeout, None),
+                        Err(_) => reply(CommandStatus::Failure, None),
+                    };
+                    let _ = sender.send(telemetry);
+                });
+            }
+            Err(status) => {
+                let _ = sender.send(reply(status, None));
+            }
+        }
+        receiver
+    }
+
+    /// Queue DH_CONTROL bytes for their DH's relay, returning where the
+    /// reply will come from
+    ///
+    /// The reply is SUCCESS once the relay has written the bytes, FAILURE if
+    /// it couldn't, and TIMEOUT if it didn't take them within
+    /// CONTROL_WRITE_TIMEOUT, in which case they are never written. The
+    /// write is waited for on a thread of its own; refusals are replied to
+    /// at once.
+    fn start_control(&self, cmd: DHControlCommand) -> mpsc::Receiver<Telemetry> {
+        let (sender, receiver) = mpsc::channel();
+        let reply = move |status| Telemetry::DHControl(DHControlTelemetry::new(cmd.header.sequence, status, cmd.dh_id));
+
+        // The bytes go through the DH's relay, so it must be running
+        let receipt = match self.data_handlers.lock() {
+            Ok(handlers) => match handlers.get(&cmd.dh_id) {
+                None => Err(CommandStatus::NotFound),
+                Some(_) if cmd.data.is_empty() || cmd.data.len() > ENDPOINT_BUFFER_SIZE => {
+                    Err(CommandStatus::InvalidParameter)
+                }
+                Some(dh) if !matches!(dh.state(), DHState::Active | DHState::Paused) => {
+                    Err(CommandStatus::InvalidParameter)
+                }
+                Some(dh) => dh.send_control(&cmd.data).map_err(|e| match e {
+                    TcsError::Timeout => CommandStatus::Timeout,
+                    _ => CommandStatus::Failure,
+                }),
+            },
+            Err(_) => Err(CommandStatus::Failure),
+        };
+
+        match receipt {
+            Ok(receipt) => {
+                thread::spawn(move || {
+                    let status = match receipt.wait(CONTROL_WRITE_TIMEOUT) {
+                        Ok(()) => CommandStatus::Success,
+                        Err(TcsError::Timeout) => CommandStatus::Timeout,
+                        Err(_) => CommandStatus::Failure,
+                    };
+                    let _ = sender.send(reply(status));
+                });
+            }
+            Err(status) => {
+                let _ = sender.send(reply(status));
+            }
+        }
+        receiver
+    }
+
+    /// Queue the replies of deferred commands that have them
+    fn push_deferred_replies(&mut self) {
+        let mut index = 0;
+        while index < self.deferred.len() {
+            let mut reply = match self.deferred[index].reply.try_recv() {
+                Ok(reply) => reply,
+                Err(mpsc::TryRecvError::Empty) => {
+                    index += 1;
+                    continue;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    fault_response(&self.deferred[index].command, CommandStatus::Failure, self.clock.now())
+                }
+            };
+            let DeferredReply { addr, command, .. } = self.deferred.remove(index);
+            reply.set_request_id(command.request_id());
+            self.remember_reply(addr, command, &reply);
+            self.telemetry_queue.push(reply, addr);
+        }
+        self.flush_telemetry();
+    }
+
+    /// Keep the reply to a command that isn't idempotent, so a resent copy
+    /// is answered with it rather than run again
+    fn remember_reply(&mut self, addr: SocketAddr, command: Command, reply: &Telemetry) {
+        self.last_reply = (!command.is_idempotent()).then(|| LastReply {
+            addr,
+            command,
+            reply: reply.clone(),
+            received: Instant::now(),
+        });
+    }
+
+    /// Run a self-poll if one is configured and due
+    fn maybe_self_poll(&mut self) {
+        if let Some(interval) = self.config.self_poll_interval {
+            if self.last_self_poll.elapsed() >= interval {
+                self.last_self_poll = Instant::now();
+                self.self_poll();
+            }
+        }
+    }
+
+    /// Check active DHs for progress since the last self-poll
+    ///
+    /// Returns the DHs that have newly stopped making progress. A DH is
+    /// reported once until it makes progress again.
+    fn self_poll(&mut self) -> Vec<DHId> {
+        let handlers = match self.data_handlers.lock() {
+            Ok(h) => h,
+            Err(_) => return vec![],
+        };
+
+        let mut newly_stalled = vec![];
+        let mut progress = BTreeMap::new();
+        for (id, dh) in handlers.iter().filter(|(_, dh)| dh.state() == DHState::Active) {
+            let stats = dh.statistics();
+            let total = stats.bytes_received + stats.bytes_sent;
+            progress.insert(*id, total);
+
+            match self.dh_progress.get(id) {
+                Some(&previous) if previous == total => {
+                    if self.stalled.insert(*id) {
+                        tcs_log!(self.logger, Warn, "self_poll: DH {} has made no progress in {:?}", id.0,
+                            self.config.self_poll_interval.unwrap_or_default());
+                        newly_stalled.push(*id);
+                    }
+                }
+                _ => {
+                    self.stalled.remove(id);
+                }
+            }
+        }
+
+        self.stalled.retain(|id| progress.contains_key(id));
+        self.dh_progress = progress;
+        newly_stalled
+    }
+
+    /// Queue statistics for subscriptions that are due, dropping lapsed ones
+    fn push_subscriptions(&mut self) {
+        let now = Instant::now();
+        self.subscriptions.retain(|s| s.expires > now);
+        if self.subscriptions.is_empty() {
+            return;
+        }
+
+        let handlers = match self.data_handlers.lock() {
+            Ok(h) => h,
+            Err(_) => return,
+        };
+
+        let mut updates = vec![];
+        for subscription in self.subscriptions.iter_mut().filter(|s| now >= s.last_sent + s.interval) {
+            subscription.last_sent = now;
+            let telemetry = match handlers.get(&subscription.dh_id) {
+                Some(dh) => QueryDHTelemetry::new(
+                    subscription.sequence,
+                    CommandStatus::Success,
+                    subscription.dh_id,
+                    dh.statistics(),
+                )
+                .with_state(dh.state()),
+                None => QueryDHTelemetry::not_found(subscription.sequence, subscription.dh_id, None),
+            };
+            let mut telemetry = Telemetry::QueryDH(telemetry);
+            telemetry.set_request_id(subscription.request_id);
+            updates.push((telemetry, subscription.subscriber));
+        }
+        drop(handlers);
+
+        if !updates.is_empty() {
+            for (telemetry, addr) in updates {
+                self.telemetry_queue.push(telemetry, addr);
+            }
+            self.flush_telemetry();
+        }
+    }
+
+    /// Connect the TCP payloads START_DH left connecting, and reconnect those
+    /// that have closed their connections
+    ///
+    /// The relays are started on the OC endpoint START_DH gave the data
+    /// handler. A connected data handler is reported as connected by the
+    /// next DH_EVENT.
+    fn reconnect_payloads(&mut self) {
+        let mut handlers = match self.data_handlers.lock() {
+            Ok(h) => h,
+            Err(_) => return,
+        };
+        for (dh_id, dh) in handlers.iter_mut() {
+            if let Err(e) = dh.reconnect_payload() {
+                tcs_log!(self.logger, Warn, "reconnect_payloads: DH {} can't reach its payload: {}", dh_id.0, e);
+            }
+        }
+    }
+
+    /// Queue a DH_EVENT for each data handler whose condition changed
+    fn push_dh_events(&mut self) {
+        let conditions = match self.data_handlers.lock() {
+            Ok(handlers) => handlers
+                .iter()
+                .filter_map(|(dh_id, dh)| dh_condition(dh).map(|condition| (*dh_id, condition)))
+                .collect(),
+            Err(_) => return,
+        };
+        self.report_dh_conditions(conditions);
+    }
+
+    /// Queue a DH_EVENT for each condition that differs from the one last
+    /// reported, reporting data handlers that have gone as stopped
+    ///
+    /// Events go to the OC that sent the most recent command or, before any
+    /// has, to the first beacon destination.
+    fn report_dh_conditions(&mut self, conditions: BTreeMap<DHId, DHEventKind>) {
+        let mut events = vec![];
+        for (dh_id, condition) in &conditions {
+            if self.dh_conditions.get(dh_id) != Some(condition) {
+                events.push((*dh_id, *condition));
             }
-            Command::ConfigDH(_cmd) => {
-                // Not yet implemented
-                Telemetry::ConfigDH(tcslibgs::ConfigDHTelemetry::new(_cmd.header.sequence, CommandStatus::Success))
+        }
+        for (dh_id, condition) in &self.dh_conditions {
+            if !conditions.contains_key(dh_id) && *condition != DHEventKind::Stopped {
+                events.push((*dh_id, DHEventKind::Stopped));
+            }
+        }
+        self.dh_conditions = conditions;
+        if events.is_empty() {
+            return;
+        }
+
+        let destination = match self.client_addr {
+            Some(addr) => addr,
+            None => match self.config.beacon_destinations.first() {
+                Some(addr) => *addr,
+                None => BEACON_NETADDR.parse().unwrap(),
+            },
+        };
+        for (_, event) in &events {
+            match event {
+                DHEventKind::Error(_) => self.trigger_beacon(BeaconTrigger::DHFaulted),
+                DHEventKind::Disconnected => self.trigger_beacon(BeaconTrigger::DHDisconnected),
+                DHEventKind::Connected | DHEventKind::Stopped => {}
+            }
+        }
+        for (dh_id, event) in events {
+            let telemetry = Telemetry::DHEvent(DHEventTelemetry::new(self.event_sequence, dh_id, event));
+            self.event_sequence = self.event_sequence.wrapping_add(1);
+            self.telemetry_queue.push(telemetry, destination);
+        }
+        self.flush_telemetry();
+    }
+
+    /// Send a beacon at once if the trigger is configured to, leaving the
+    /// periodic beacons as they were
+    fn trigger_beacon(&self, trigger: BeaconTrigger) {
+        if let Some(beacon) = &self.beacon {
+            if self.config.beacon_triggers.contains(&trigger) {
+                beacon.send_now();
+            }
+        }
+    }
+
+    /// Send queued telemetry until the queue is empty or a send fails
+    fn flush_telemetry(&mut self) {
+        while let Some(item) = self.telemetry_queue.pop() {
+            if let Err(e) = self.send_telemetry(&item.telemetry, &item.addr) {
+                tcs_log!(self.logger, Warn, "flush_telemetry: can't send to {}: {}", item.addr, e);
+                self.telemetry_queue.requeue(item);
+                break;
             }
         }
     }
 
+    /// Send telemetry, fragmenting it if it doesn't fit in one datagram
+    fn send_telemetry(&mut self, telemetry: &Telemetry, addr: &std::net::SocketAddr) -> TcsResult<()> {
+        let framing = if self.binary_peers.contains(addr) { Framing::Binary } else { Framing::Json };
+        let data = ProtocolMessage::from_telemetry(telemetry.clone()).to_bytes_with(framing)?;
+        tcs_log!(self.logger, Debug, "run::sendto {:?}", addr);
+        if data.len() <= TELEMETRY_MAX_DATAGRAM {
+            return send_datagram(&self.socket, &data, addr);
+        }
+
+        self.fragment_id = self.fragment_id.wrapping_add(1);
+        let max_data = TELEMETRY_MAX_DATAGRAM - FRAGMENT_HEADER_SIZE;
+        for fragment in Fragment::split(self.fragment_id, &data, max_data)? {
+            send_datagram(&self.socket, &fragment.to_bytes(), addr)?;
+        }
+        Ok(())
+    }
+
     /// Send a beacon telemetry message
     fn _send_beacon(&self, addr: &std::net::SocketAddr) -> TcsResult<()> {
         let beacon = Telemetry::Beacon(BeaconTelemetry::new());
-        let data = serde_json::to_vec(&beacon)?;
-eprintln!("_send_beacon::sendto {:?}", addr);
-        self.socket.send_to(&data, addr)?;
+        let data = ProtocolMessage::from_telemetry(beacon).to_bytes()?;
+        tcs_log!(self.logger, Debug, "_send_beacon::sendto {:?}", addr);
+        send_datagram(&self.socket, &data, addr)
+    }
+
+    /// Apply an action to every active or paused data handler
+    ///
+    /// Returns Failure if the action failed for any of them, along with how
+    /// many are left in the given state.
+    fn for_each_running_dh(
+        &self,
+        action: fn(&mut DataHandler) -> TcsResult<()>,
+        state: DHState,
+    ) -> (CommandStatus, u32) {
+        let mut handlers = match self.data_handlers.lock() {
+            Ok(h) => h,
+            Err(_) => return (CommandStatus::Failure, 0),
+        };
+
+        let mut status = CommandStatus::Success;
+        let mut count = 0;
+        for dh in handlers.values_mut().filter(|dh| matches!(dh.state(), DHState::Active | DHState::Paused)) {
+            if action(dh).is_err() {
+                status = CommandStatus::Failure;
+            }
+            if dh.state() == state {
+                count += 1;
+            }
+        }
+        (status, count)
+    }
+
+    /// Decode and process one command datagram, queueing the response
+    ///
+    /// Telemetry to the sender is framed the way its command was. Datagrams
+    /// that aren't valid commands are counted as dropped and, if so
+    /// configured, answered with INVALID_COMMAND telemetry. Telemetry is
+    /// counted separately and never answered, so two misconfigured peers
+    /// can't keep replying to each other.
+    fn handle_datagram(&mut self, data: &[u8], addr: SocketAddr) {
+        let response = match ProtocolMessage::from_bytes(data).map(|message| message.payload) {
+            Ok(MessagePayload::Telemetry(telemetry)) => {
+                self.unexpected_telemetry += 1;
+                if self.unexpected_telemetry == self.config.unexpected_telemetry_warning {
+                    tcs_log!(
+                        self.logger,
+                        Warn,
+                        "handle_datagram: {} telemetry messages on the command port, most recently {:?} from {}; \
+                         is a peer misconfigured?",
+                        self.unexpected_telemetry,
+                        telemetry.tm_type(),
+                        addr
+                    );
+                }
+                return;
+            }
+            Ok(MessagePayload::Command(command)) => {
+                self.binary_peers.retain(|peer| *peer != addr);
+                if Framing::detect(data) == Framing::Binary {
+                    if self.binary_peers.len() >= BINARY_PEERS_MAX {
+                        self.binary_peers.pop_front();
+                    }
+                    self.binary_peers.push_back(addr);
+                }
+                self.client_addr = Some(addr);
+                // A resent command still being worked on is answered once,
+                // when its reply is ready
+                if self.deferred.iter().any(|deferred| deferred.addr == addr && deferred.command == command) {
+                    return;
+                }
+                let resent = self.last_reply.as_ref().filter(|last| {
+                    last.addr == addr && last.command == command && last.received.elapsed() < REPLY_CACHE_MAX_AGE
+                });
+                match resent {
+                    Some(last) => last.reply.clone(),
+                    None => {
+                        if let Some(reply) = self.defer_command(&command) {
+                            self.deferred.push(DeferredReply { addr, command, reply });
+                            return;
+                        }
+                        let reply = self.process_command(command.clone());
+                        self.remember_reply(addr, command, &reply);
+                        reply
+                    }
+                }
+            }
+            Err(e) => {
+                self.commands_dropped += 1;
+                if !self.config.reply_invalid_commands {
+                    return;
+                }
+                Telemetry::InvalidCommand(InvalidCommandTelemetry::new(format!("Undecodable command: {}", e)))
+            }
+        };
+        self.telemetry_queue.push(response, addr);
+        self.flush_telemetry();
+    }
+
+    /// Apply a beacon interval from CONFIG, returning the interval now in
+    /// effect
+    ///
+    /// A zero interval is refused whether or not beacons are being sent.
+    fn set_beacon_interval(&mut self, interval: BeaconTime) -> TcsResult<BeaconTime> {
+        let requested = Duration::from_millis(interval.0 as u64);
+        let applied = match self.beacon.as_mut() {
+            Some(beacon) => beacon.set_interval(requested)?,
+            None if requested.is_zero() => {
+                return Err(TcsError::Config("Beacon interval must not be zero".to_string()))
+            }
+            None => requested,
+        };
+        self.beacon_interval = BeaconTime(applied.as_millis() as u32);
+        Ok(self.beacon_interval)
+    }
+
+    /// Start sending beacons from bind_addr
+    ///
+    /// If beaconing can't start the CI carries on without beacons, unless the
+    /// configuration requires them, in which case the error is returned.
+    fn start_beacon(&mut self, bind_addr: SocketAddr) -> TcsResult<()> {
+        let destinations = if self.config.beacon_destinations.is_empty() {
+            vec![BEACON_NETADDR.parse().unwrap()]
+        } else {
+            self.config.beacon_destinations.clone()
+        };
+        self.beacon = match BeaconSend::new(BEACON_DEFAULT_MS, bind_addr, destinations,
+            self.config.beacon_format, self.config.node_id, self.start_reason, self.clock.clone()) {
+            Ok(beacon) => beacon,
+            Err(e) => {
+                tcs_log!(self.logger, Error, "run: can't start beaconing from {}: {}", bind_addr, e);
+                if self.config.beacon_required {
+                    return Err(e);
+                }
+                None
+            }
+        };
+        if let Some(beacon) = &self.beacon {
+            let data_handlers = Arc::clone(&self.data_handlers);
+            let clock = self.clock.clone();
+            beacon.set_health_source(Box::new(move || beacon_health(&data_handlers, &clock)));
+            beacon.set_logger(self.logger.clone());
+            for (addr, interval) in &self.config.beacon_intervals {
+                if !beacon.set_destination_interval(*addr, Some(*interval)) {
+                    tcs_log!(self.logger, Warn,
+                        "run: beacon interval given for {}, which is not a beacon destination", addr);
+                }
+            }
+        }
         Ok(())
     }
 
     /// Run the command interpreter main loop
     pub fn run(&mut self) -> TcsResult<()> {
         self.running = true;
-        let mut recv_buffer = vec![0u8; 65535];
+        let mut recv_buffer = BufferPool::global().take(MAX_MESSAGE_SIZE);
         let _last_beacon = Instant::now();
         let mut _last_client_addr: Option<std::net::SocketAddr> = None;
 
@@ -189,11 +1135,19 @@ eprintln!("_send_beacon::sendto {:?}", addr);
         // Set a timeout for receiving so we can send beacons
         self.socket.set_read_timeout(Some(Duration::from_millis(100)))?;
 */
-eprintln!("run: BEACON_NETADDR {:?}", BEACON_NETADDR);
-        let _beacon = BeaconSend::new(BEACON_DEFAULT_MS, BEACON_NETADDR.parse().unwrap());
+        tcs_log!(self.logger, Debug, "run: BEACON_NETADDR {:?}", BEACON_NETADDR);
+        self.start_beacon(BEACON_BIND_ADDR.parse().unwrap())?;
 //        let _beacon = BeaconSend::new(BEACON_DEFAULT_MS, "0.0.0.0:5550".parse().unwrap());
 
         while self.running {
+            self.maybe_self_poll();
+            self.push_subscriptions();
+            self.reconnect_payloads();
+            self.push_dh_events();
+            self.push_deferred_replies();
+
+            // Wake up for the next self-poll or subscription update
+            self.socket.set_read_timeout(Some(self.read_timeout()))?;
 /*
             // Check if we need to send a beacon
             if last_beacon.elapsed() >= Duration::from_millis(self.beacon_interval.0 as u64) {
@@ -207,24 +1161,11 @@ eprintln!("run: BEACON_NETADDR {:?}", BEACON_NETADDR);
             // Try to receive a command
             match self.socket.recv_from(&mut recv_buffer) {
                 Ok((size, addr)) => {
-eprintln!("run::recv_from {:?}", addr);
+                    tcs_log!(self.logger, Debug, "run::recv_from {:?}", addr);
                     _last_client_addr = Some(addr);
-
-                    // Parse and process command
-                    match serde_json::from_slice::<Command>(&recv_buffer[..size]) {
-                        Ok(command) => {
-                            let response = self.process_command(command);
-                            if let Ok(data) = serde_json::to_vec(&response) {
-eprintln!("run::sendto {:?}", addr);
-                                let _ = self.socket.send_to(&data, addr);
-                            }
-                        }
-                        Err(_) => {
-                            // Invalid command - ignore
-                        }
-                    }
+                    self.handle_datagram(&recv_buffer[..size], addr);
                 }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
                     // Timeout - continue loop
                     continue;
                 }
@@ -237,11 +1178,89 @@ eprintln!("run::sendto {:?}", addr);
         Ok(())
     }
 
+    /// Get how long to wait for a command before periodic work is due
+    ///
+    /// The wait ends when the next self-poll or subscription update is due,
+    /// so they are neither late nor woken for early, and is never longer
+    /// than the maximum wait, or DEFERRED_REPLY_POLL while replies are being
+    /// worked on.
+    fn read_timeout(&self) -> Duration {
+        let now = Instant::now();
+        let mut due = now + self.config.max_wait.unwrap_or(CI_SERVICE_INTERVAL);
+        if let Some(interval) = self.config.self_poll_interval {
+            due = due.min(self.last_self_poll + interval);
+        }
+        for subscription in &self.subscriptions {
+            due = due.min(subscription.last_sent + subscription.interval).min(subscription.expires);
+        }
+        if !self.deferred.is_empty() {
+            due = due.min(now + DEFERRED_REPLY_POLL);
+        }
+        // A zero timeout is refused by the socket
+        due.saturating_duration_since(now).max(CI_MIN_WAIT)
+    }
+
     /// Stop the command interpreter
     pub fn stop(&mut self) {
         self.running = false;
     }
 
+    /// Re-read the payload configuration file and bring the data handlers
+    /// in line with it
+    ///
+    /// Data handlers no longer configured are stopped and dropped, new ones
+    /// are created, and changed ones are reconfigured; the rest carry on
+    /// untouched. A file that can't be read or holds an invalid configuration
+    /// changes nothing. Failures are returned as the status to report.
+    fn reload_config(&mut self) -> Result<ReloadSummary, CommandStatus> {
+        let path = self.payload_path.as_ref().ok_or(CommandStatus::Failure)?;
+        let payload_config = load_payload_config(path).map_err(|_| CommandStatus::Failure)?;
+        let ids: BTreeSet<DHId> = payload_config.iter().map(|config| config.dh_id).collect();
+        if ids.len() != payload_config.len() || payload_config.iter().any(|config| validate_config(config).is_err()) {
+            return Err(CommandStatus::InvalidParameter);
+        }
+
+        let mut summary = ReloadSummary::default();
+        let mut handlers = self.data_handlers.lock().map_err(|_| CommandStatus::Failure)?;
+
+        // Removed DHs are stopped once the lock is released, as joining
+        // their conduits can take a while
+        let mut stopping = Vec::new();
+        let removed: Vec<DHId> = handlers.keys().filter(|id| !ids.contains(id)).copied().collect();
+        for dh_id in removed {
+            stopping.extend(handlers.remove(&dh_id));
+            self.subscriptions.retain(|s| s.dh_id != dh_id);
+            self.dh_progress.remove(&dh_id);
+            self.stalled.remove(&dh_id);
+            summary.removed.push(dh_id);
+        }
+
+        for config in &payload_config {
+            match handlers.get_mut(&config.dh_id) {
+                Some(dh) if dh.config() == config => summary.unchanged.push(config.dh_id),
+                // Running DHs restart their relays on the OC endpoint START_DH gave them
+                Some(dh) => match dh.reconfigure(config.clone(), None) {
+                    Ok(()) => summary.reconfigured.push(config.dh_id),
+                    Err(_) => summary.failed.push(config.dh_id),
+                },
+                None => match self.new_dh(config) {
+                    Ok(dh) => {
+                        handlers.insert(config.dh_id, dh);
+                        summary.added.push(config.dh_id);
+                    }
+                    Err(_) => summary.failed.push(config.dh_id),
+                },
+            }
+        }
+
+        drop(handlers);
+        for mut dh in stopping {
+            let _ = dh.stop();
+        }
+        self.payload_config = payload_config;
+        Ok(summary)
+    }
+
     /// Shut down all data handlers
     pub fn shutdown(&mut self) -> TcsResult<()> {
         self.running = false;
@@ -257,21 +1276,2038 @@ eprintln!("run::sendto {:?}", addr);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tcslibgs::NetworkProtocol;
+/// Leave the restart marker for the next CI to find
+///
+/// The marker usually lives in a shared directory such as /tmp, so it is
+/// only ever created, never opened if something is already at the path:
+/// a symlink planted there can't redirect the write. It is readable by
+/// this user only.
+fn write_restart_marker(path: &str) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
 
-    #[test]
-    fn test_ci_creation() {
-        let config = CIConfig {
-            address: "127.0.0.1".to_string(),
-            port: 0, // Let OS assign port
-            protocol: NetworkProtocol::Udp,
-            beacon_interval: BeaconTime(5000),
-        };
+    std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    Ok(())
+}
 
-        let ci = CommandInterpreter::new(config, vec![]);
-        assert!(ci.is_ok());
+/// Consume the restart marker, reporting whether this start was commanded
+///
+/// Only a regular file counts as a marker; anything else at the path, a
+/// symlink say, is removed and the start treated as a cold start.
+fn take_restart_marker(path: Option<&str>) -> StartReason {
+    let Some(path) = path else {
+        return StartReason::ColdStart;
+    };
+    let is_marker = std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_file());
+    match std::fs::remove_file(path) {
+        Ok(()) if is_marker => StartReason::CommandedRestart,
+        _ => StartReason::ColdStart,
+    }
+}
+
+/// Create a data handler from its configuration and start it relaying
+///
+/// The check for an existing handler and the insert happen under one lock, so
+/// of several concurrent START_DH commands for the same id exactly one creates
+/// it. The others find it already active, or report ALREADY_EXISTS if
+/// exclusive is set. A stopped or faulted handler is replaced by a new one,
+/// and one loaded from the configuration but never started is started.
+///
+/// The CI gives each data handler it starts an OC endpoint of its own, bound
+/// to oc_address on a port the OS picks, which is returned with the outcome.
+/// Holding it lets the CI restart the relays later, to reconfigure the data
+/// handler or reconnect its payload. start_dh_status gives the status to
+/// report for a failure.
+fn create_dh(
+    data_handlers: &Mutex<BTreeMap<DHId, DataHandler>>,
+    payload_config: &[DHConfig],
+    dh_id: DHId,
+    exclusive: bool,
+    max_data_handlers: Option<usize>,
+    new_dh: &dyn Fn(&DHConfig) -> TcsResult<DataHandler>,
+    oc_address: &str,
+) -> TcsResult<(StartDHOutcome, Option<Port>)> {
+    let mut handlers = data_handlers
+        .lock()
+        .map_err(|_| TcsError::DataHandler("Data handler table lock poisoned".to_string()))?;
+
+    let outcome = match handlers.get(&dh_id).map(DataHandler::state) {
+        None | Some(DHState::Created) => StartDHOutcome::Created,
+        Some(DHState::Stopped | DHState::Faulted) => StartDHOutcome::Reactivated,
+        Some(_) if exclusive => return Err(TcsError::DHExists(dh_id.0)),
+        Some(_) => return Ok((StartDHOutcome::AlreadyActive, handlers.get(&dh_id).and_then(oc_port))),
+    };
+
+    // Only running DHs hold their endpoints open, so only they count
+    // against the limit
+    if let Some(max) = max_data_handlers {
+        let running = handlers.values().filter(|dh| matches!(dh.state(), DHState::Active | DHState::Paused)).count();
+        if running >= max {
+            return Err(TcsError::DataHandler(format!("Limit of {} running data handlers reached", max)));
+        }
+    }
+
+    let config = payload_config.iter().find(|c| c.dh_id == dh_id).ok_or(TcsError::DHNotFound(dh_id.0))?;
+    let oc = OcEndpoint::new(&NetworkConfig {
+        protocol: NetworkProtocol::Udp,
+        address: oc_address.to_string(),
+        port: Port::ANY,
+        udp_mode: UdpMode::Connected,
+    })?;
+    match handlers.get_mut(&dh_id) {
+        Some(dh) if dh.state() == DHState::Created => dh.start_oc(oc)?,
+        _ => {
+            let mut dh = new_dh(config)?;
+            dh.start_oc(oc)?;
+            handlers.insert(dh_id, dh);
+        }
+    }
+    Ok((outcome, handlers.get(&dh_id).and_then(oc_port)))
+}
+
+/// Get the port of the OC endpoint a data handler relays through, if it has
+/// one
+fn oc_port(dh: &DataHandler) -> Option<Port> {
+    let addr = dh.oc_endpoint()?.local_addr().ok()?;
+    Some(Port(addr.port()))
+}
+
+/// Get the status reporting why create_dh failed
+fn start_dh_status(error: &TcsError) -> CommandStatus {
+    match error {
+        TcsError::DHExists(_) => CommandStatus::AlreadyExists,
+        TcsError::DHNotFound(_) => CommandStatus::NotFound,
+        TcsError::InvalidConfiguration(_) => CommandStatus::InvalidParameter,
+        _ => CommandStatus::Failure,
+    }
+}
+
+/// Get the condition a DH_EVENT reports for a data handler, or None if it
+/// hasn't started
+fn dh_condition(dh: &DataHandler) -> Option<DHEventKind> {
+    match dh.state() {
+        DHState::Created => None,
+        DHState::Active | DHState::Paused if dh.payload_connected() => Some(DHEventKind::Connected),
+        DHState::Active | DHState::Paused => Some(DHEventKind::Disconnected),
+        DHState::Stopped => Some(DHEventKind::Stopped),
+        // Conduits fault on sustained I/O errors
+        DHState::Faulted => Some(DHEventKind::Error(ErrorCode::Io)),
+    }
+}
+
+/// Gather the spacecraft health for a beacon, or None if the data handlers
+/// can't be locked
+fn beacon_health(data_handlers: &Mutex<BTreeMap<DHId, DataHandler>>, clock: &Clock) -> Option<BeaconHealth> {
+    let handlers = data_handlers.lock().ok()?;
+    let mut statistics = Statistics::new();
+    for dh in handlers.values() {
+        statistics.accumulate(&dh.statistics());
+    }
+    Some(BeaconHealth {
+        active_dh_count: handlers.values().filter(|dh| dh.state() == DHState::Active).count() as u32,
+        uptime_secs: clock.uptime().as_secs(),
+        statistics,
+    })
+}
+
+/// Socket telemetry is sent on, abstracted so short sends can be simulated
+trait DatagramSocket {
+    fn send_to(&self, data: &[u8], addr: &SocketAddr) -> io::Result<usize>;
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send_to(&self, data: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, data, addr)
+    }
+}
+
+/// Send one datagram, failing if less than all of it was sent
+///
+/// UDP sends are normally all or nothing, but a short send would otherwise
+/// silently deliver truncated telemetry.
+fn send_datagram(socket: &impl DatagramSocket, data: &[u8], addr: &SocketAddr) -> TcsResult<()> {
+    let sent = socket.send_to(data, addr)?;
+    if sent != data.len() {
+        let msg = format!("Short send to {}: {} of {} bytes", addr, sent, data.len());
+        return Err(TcsError::Io(io::Error::new(io::ErrorKind::WriteZero, msg)));
+    }
+    Ok(())
+}
+
+/// Build the response to a command failed by fault injection
+///
+/// now is the CI's clock reading, reported by replies that carry a time;
+/// a failed SET_TIME reports it as both the applied and previous time, as
+/// the clock is left alone.
+fn fault_response(command: &Command, status: CommandStatus, now: Timestamp) -> Telemetry {
+    let sequence = command.sequence();
+    match command {
+        Command::Ping(_) => Telemetry::Ping(PingTelemetry::new(sequence, status)),
+        Command::RestartArm(_) => Telemetry::RestartArm(RestartArmTelemetry::new(sequence, status)),
+        Command::Restart(_) => Telemetry::Restart(RestartTelemetry::new(sequence, status)),
+        Command::QueryDropped(_) => Telemetry::QueryDropped(QueryDroppedTelemetry::new(sequence, status, 0, 0, 0)),
+        Command::QueryEndpointSupport(_) => {
+            Telemetry::QueryEndpointSupport(QueryEndpointSupportTelemetry::new(sequence, status, vec![], vec![]))
+        }
+        Command::InjectFault(_) => Telemetry::InjectFault(InjectFaultTelemetry::new(sequence, status)),
+        Command::QueryBeaconStatus(_) => {
+            Telemetry::QueryBeaconStatus(QueryBeaconStatusTelemetry::new(sequence, status, vec![]))
+        }
+        Command::Hello(_) => Telemetry::Hello(HelloTelemetry::new(sequence, status, Framing::Json)),
+        Command::SetTime(_) => Telemetry::SetTime(SetTimeTelemetry::new(sequence, status, now, now)),
+        Command::SetLogLevel(cmd) => {
+            Telemetry::SetLogLevel(SetLogLevelTelemetry::new(sequence, status, cmd.level, cmd.level))
+        }
+        Command::StartDH(_) => Telemetry::StartDH(StartDHTelemetry::new(sequence, status)),
+        Command::StopDH(_) => Telemetry::StopDH(StopDHTelemetry::new(sequence, status)),
+        Command::PauseAllDH(_) => Telemetry::PauseAllDH(PauseAllDHTelemetry::new(sequence, status, 0)),
+        Command::ResumeAllDH(_) => Telemetry::ResumeAllDH(ResumeAllDHTelemetry::new(sequence, status, 0)),
+        Command::ResetStats(cmd) => Telemetry::ResetStats(ResetStatsTelemetry::new(sequence, status, cmd.dh_id)),
+        Command::ListDH(_) => Telemetry::ListDH(ListDHTelemetry::new(sequence, status, vec![])),
+        Command::CheckpointDH(cmd) => Telemetry::CheckpointDH(CheckpointDHTelemetry::new(sequence, status, cmd.dh_id)),
+        Command::DHControl(cmd) => Telemetry::DHControl(DHControlTelemetry::new(sequence, status, cmd.dh_id)),
+        Command::QueryActivity(cmd) => {
+            Telemetry::QueryActivity(QueryActivityTelemetry::new(sequence, status, cmd.window_ms, vec![]))
+        }
+        Command::QueryDH(cmd) => {
+            Telemetry::QueryDH(QueryDHTelemetry::new(sequence, status, cmd.dh_id, Statistics::new()))
+        }
+        Command::SnapshotStats(_) => Telemetry::StatsSnapshot(StatsSnapshotTelemetry::new(
+            sequence,
+            status,
+            now,
+            Statistics::new(),
+            vec![],
+        )),
+        Command::DHLoopback(cmd) => {
+            Telemetry::DHLoopback(DHLoopbackTelemetry::new(sequence, status, cmd.dh_id, cmd.token, None))
+        }
+        Command::SubscribeDHStats(cmd) => {
+            Telemetry::SubscribeDHStats(SubscribeDHStatsTelemetry::new(sequence, status, cmd.dh_id))
+        }
+        Command::UnsubscribeDHStats(cmd) => {
+            Telemetry::UnsubscribeDHStats(UnsubscribeDHStatsTelemetry::new(sequence, status, cmd.dh_id))
+        }
+        Command::Config(_) => Telemetry::Config(ConfigTelemetry::new(sequence, status)),
+        Command::ConfigDH(_) => Telemetry::ConfigDH(tcslibgs::ConfigDHTelemetry::new(sequence, status)),
+        Command::ReconfigureDH(cmd) => Telemetry::ReconfigureDH(ReconfigureDHTelemetry::new(sequence, status, cmd.dh_id)),
+        Command::ReloadConfig(_) => Telemetry::ReloadConfig(ReloadConfigTelemetry::new(sequence, status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcslibgs::{
+        BeaconFormat, ConduitOptions, DHName, DeviceConfig, EndpointConfig, NetworkProtocol, Port, QueryDHCommand,
+        SnapshotStatsCommand, DEFAULT_POOL_CAPACITY, DEFAULT_UNEXPECTED_TELEMETRY_WARNING,
+    };
+
+    fn test_config() -> CIConfig {
+        CIConfig {
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY, // Let OS assign port
+            protocol: NetworkProtocol::Udp,
+            beacon_interval: BeaconTime(5000),
+            query_dh_valid_ids: false,
+            beacon_format: BeaconFormat::Legacy,
+            node_id: 0,
+            buffer_pool_bytes: DEFAULT_POOL_CAPACITY,
+            restart_marker: None,
+            self_poll_interval: None,
+            arm_keys: None,
+            fault_injection: false,
+            start_dh_exclusive: false,
+            beacon_destinations: vec![],
+            reuse_address: true,
+            downlink_bytes_per_sec: None,
+            beacon_intervals: BTreeMap::new(),
+            framings: vec![Framing::Json],
+            reply_invalid_commands: true,
+            unexpected_telemetry_warning: DEFAULT_UNEXPECTED_TELEMETRY_WARNING,
+            beacon_required: false,
+            max_wait: None,
+            max_data_handlers: None,
+            beacon_triggers: vec![],
+        }
+    }
+
+    fn test_payload_config(count: u32) -> Vec<DHConfig> {
+        (0..count)
+            .map(|id| DHConfig {
+                dh_id: DHId(id),
+                name: DHName::new(format!("DH{}", id)),
+                endpoint: EndpointConfig::Device(DeviceConfig {
+                    path: "/dev/null".to_string(),
+                }),
+                packet_size: 64,
+                packet_interval_ms: 100,
+                conduit: ConduitOptions::default(),
+            })
+            .collect()
+    }
+
+    /// Service the CI until a data handler's TCP payload is connected
+    fn wait_for_payload(ci: &mut CommandInterpreter, dh_id: DHId) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !ci.data_handlers.lock().unwrap()[&dh_id].payload_connected() {
+            assert!(Instant::now() < deadline, "Payload never connected");
+            ci.reconnect_payloads();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_ci_creation() {
+        let ci = CommandInterpreter::new(test_config(), vec![]);
+        assert!(ci.is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_stats() {
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(3)).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        let response = ci.process_command(Command::SnapshotStats(SnapshotStatsCommand::new(7)));
+        let snapshot = match response {
+            Telemetry::StatsSnapshot(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+
+        assert_eq!(snapshot.header.sequence, 7);
+        assert_eq!(snapshot.header.status, CommandStatus::Success);
+        assert_eq!(snapshot.global.timestamp, Some(snapshot.timestamp));
+
+        let ids: Vec<DHId> = snapshot.data_handlers.iter().map(|s| s.dh_id).collect();
+        assert_eq!(ids, vec![DHId(0), DHId(1), DHId(2)]);
+        for entry in &snapshot.data_handlers {
+            assert_eq!(entry.statistics.timestamp, Some(snapshot.timestamp));
+        }
+    }
+
+    #[test]
+    fn test_live_statistics() {
+        use crate::endpoint::OcEndpoint;
+        use std::time::Duration;
+        use tcslibgs::{NetworkConfig, QueryActivityCommand, SubscribeDHStatsCommand, UdpMode};
+
+        // DH 0's payload is a pipe, so what is relayed to it comes back
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut payload_config = test_payload_config(1);
+        payload_config[0].endpoint = EndpointConfig::Device(DeviceConfig {
+            path: format!("/proc/self/fd/{}", pipe_fds[0]),
+        });
+        let mut ci = CommandInterpreter::new(test_config(), payload_config).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let oc = OcEndpoint::new(&NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        })
+        .unwrap();
+        oc.set_destination(ground.local_addr().unwrap());
+        ci.data_handlers.lock().unwrap().get_mut(&DHId(0)).unwrap().start_oc(oc.clone()).unwrap();
+
+        // Baseline for the self-poll, then relay traffic without stopping the DH
+        ci.self_poll();
+        ground.send_to(b"live traffic", oc.local_addr().unwrap()).unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(ground.recv(&mut buf).unwrap(), 12);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let counted = |ci: &CommandInterpreter| {
+            let stats = ci.data_handlers.lock().unwrap()[&DHId(0)].statistics();
+            (stats.bytes_received, stats.bytes_sent)
+        };
+        while counted(&ci) != (12, 12) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        match ci.process_command(Command::QueryDH(QueryDHCommand::new(1, DHId(0)))) {
+            Telemetry::QueryDH(tm) => assert_eq!((tm.statistics.bytes_received, tm.statistics.bytes_sent), (12, 12)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        match ci.process_command(Command::SnapshotStats(SnapshotStatsCommand::new(2))) {
+            Telemetry::StatsSnapshot(tm) => {
+                assert_eq!(tm.data_handlers[0].statistics.bytes_sent, 12);
+                assert_eq!(tm.global.bytes_sent, 12);
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert!(ci.self_poll().is_empty());
+        assert_eq!(beacon_health(&ci.data_handlers, &ci.clock).unwrap().statistics.bytes_received, 12);
+        match ci.process_command(Command::QueryActivity(QueryActivityCommand::new(3, 1000))) {
+            Telemetry::QueryActivity(tm) => assert!(tm.handlers[0].active),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        let subscriber = UdpSocket::bind("127.0.0.1:0").unwrap();
+        subscriber.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        ci.client_addr = Some(subscriber.local_addr().unwrap());
+        let subscribe = Command::SubscribeDHStats(SubscribeDHStatsCommand::new(4, DHId(0), 10));
+        assert_eq!(ci.process_command(subscribe).status(), CommandStatus::Success);
+        std::thread::sleep(Duration::from_millis(20));
+        ci.push_subscriptions();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        let len = subscriber.recv(&mut buf).unwrap();
+        match ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap() {
+            Telemetry::QueryDH(tm) => assert_eq!(tm.statistics.bytes_received, 12),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        ci.data_handlers.lock().unwrap().get_mut(&DHId(0)).unwrap().stop().unwrap();
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_query_unknown_dh() {
+        let mut config = test_config();
+        config.query_dh_valid_ids = true;
+        let mut ci = CommandInterpreter::new(config, test_payload_config(2)).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        let response = ci.process_command(Command::QueryDH(QueryDHCommand::new(3, DHId(42))));
+        let tm = match response {
+            Telemetry::QueryDH(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+
+        assert_eq!(tm.header.status, CommandStatus::NotFound);
+        assert_eq!(tm.dh_id, DHId(42));
+        assert!(tm.no_such_handler);
+        assert_eq!(tm.valid_ids, Some(vec![DHId(0), DHId(1)]));
+    }
+
+    #[test]
+    fn test_query_dropped() {
+        use tcslibgs::QueryDroppedCommand;
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        for seq in 0..TELEMETRY_QUEUE_DEPTH as u32 + 5 {
+            ci.telemetry_queue.push(Telemetry::Ping(PingTelemetry::new(seq, CommandStatus::Success)), addr);
+        }
+
+        let query = |ci: &mut CommandInterpreter, clear| match ci.process_command(
+            Command::QueryDropped(QueryDroppedCommand::new(1, clear)),
+        ) {
+            Telemetry::QueryDropped(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+
+        assert_eq!(query(&mut ci, true).telemetry_dropped, 5);
+        assert_eq!(query(&mut ci, false).telemetry_dropped, 0);
+    }
+
+    #[test]
+    fn test_query_endpoint_support() {
+        use tcslibgs::{DHType, NetworkProtocol, QueryEndpointSupportCommand};
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let response = ci.process_command(Command::QueryEndpointSupport(QueryEndpointSupportCommand::new(1)));
+        let tm = match response {
+            Telemetry::QueryEndpointSupport(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        assert_eq!(tm.dh_types, vec![DHType::Network, DHType::Device]);
+        assert_eq!(tm.protocols, SUPPORTED_PROTOCOLS.to_vec());
+        assert!(tm.protocols.contains(&NetworkProtocol::UnixDgram));
+    }
+
+    #[test]
+    fn test_inject_fault() {
+        use tcslibgs::{DHType, InjectFaultCommand, PingCommand, StartDHCommand};
+
+        let inject = Command::InjectFault(InjectFaultCommand::new(1, CommandType::StartDH, CommandStatus::Timeout));
+        let start = |seq| Command::StartDH(StartDHCommand::new(seq, DHId(0), DHType::Device, DHName::new("DH0")));
+
+        // Refused unless enabled in the configuration
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        assert_eq!(ci.process_command(inject.clone()).status(), CommandStatus::InvalidCommand);
+        assert_eq!(ci.process_command(start(2)).status(), CommandStatus::Success);
+
+        let mut config = test_config();
+        config.fault_injection = true;
+        let mut ci = CommandInterpreter::new(config, test_payload_config(1)).unwrap();
+        assert_eq!(ci.process_command(inject).status(), CommandStatus::Success);
+
+        // Other commands are unaffected and only the next START_DH fails
+        assert_eq!(ci.process_command(Command::Ping(PingCommand::new(2))).status(), CommandStatus::Success);
+        let response = ci.process_command(start(3));
+        assert!(matches!(response, Telemetry::StartDH(_)));
+        assert_eq!((response.sequence(), response.status()), (3, CommandStatus::Timeout));
+        assert_eq!(ci.process_command(start(4)).status(), CommandStatus::Success);
+    }
+
+    #[test]
+    fn test_concurrent_start_dh() {
+        use std::sync::Barrier;
+        use std::thread;
+
+        const THREADS: usize = 8;
+
+        for exclusive in [false, true] {
+            let handlers = Arc::new(Mutex::new(BTreeMap::new()));
+            let payload_config = Arc::new(test_payload_config(1));
+            let barrier = Arc::new(Barrier::new(THREADS));
+
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let (handlers, payload_config, barrier) = (handlers.clone(), payload_config.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let new_dh = |config: &DHConfig| DataHandler::new(config.clone());
+                        create_dh(&handlers, &payload_config, DHId(0), exclusive, None, &new_dh, "127.0.0.1")
+                    })
+                })
+                .collect();
+            let results: Vec<_> = threads
+                .into_iter()
+                .map(|t| t.join().unwrap().map(|(outcome, _)| outcome).map_err(|e| start_dh_status(&e)))
+                .collect();
+
+            assert_eq!(handlers.lock().unwrap().len(), 1);
+            let count = |result| results.iter().filter(|r| **r == result).count();
+            assert_eq!(count(Ok(StartDHOutcome::Created)), 1);
+            if exclusive {
+                assert_eq!(count(Err(CommandStatus::AlreadyExists)), THREADS - 1);
+            } else {
+                assert_eq!(count(Ok(StartDHOutcome::AlreadyActive)), THREADS - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_beacon_status() {
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::QueryBeaconStatusCommand;
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        // The beacon socket is IPv4, so an IPv6 destination can never be sent to
+        let unreachable = "[::1]:5550".parse().unwrap();
+        let reachable = ground.local_addr().unwrap();
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        ci.beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![unreachable, reachable], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap();
+        let mut buf = [0u8; 1024];
+        ground.recv(&mut buf).unwrap();
+
+        let response = ci.process_command(Command::QueryBeaconStatus(QueryBeaconStatusCommand::new(1)));
+        let tm = match response {
+            Telemetry::QueryBeaconStatus(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        let counts: Vec<_> = tm.destinations.iter().map(|d| (d.address, d.sent, d.failed)).collect();
+        assert_eq!(counts, vec![(unreachable, 0, 1), (reachable, 1, 0)]);
+    }
+
+    #[test]
+    fn test_dh_loopback() {
+        use std::net::UdpSocket;
+        use std::thread;
+        use tcslibgs::{DHLoopbackCommand, DHType, NetworkConfig, PingCommand, StartDHCommand, UdpMode};
+
+        // One payload echoes what it gets, the other answers with noise
+        let echo = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let noisy = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let payload = |id, socket: &UdpSocket| DHConfig {
+            dh_id: DHId(id),
+            name: DHName::new(format!("DH{}", id)),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Udp,
+                address: "127.0.0.1".to_string(),
+                port: Port(socket.local_addr().unwrap().port()),
+                udp_mode: UdpMode::Connected,
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let payload_config = vec![payload(0, &echo), payload(1, &noisy), payload(2, &echo)];
+        for (socket, echoes) in [(echo, true), (noisy, false)] {
+            socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            thread::spawn(move || {
+                let mut buf = [0u8; 64];
+                while let Ok((n, from)) = socket.recv_from(&mut buf) {
+                    let reply = if echoes { &buf[..n] } else { b"not the token" };
+                    let _ = socket.send_to(reply, from);
+                }
+            });
+        }
+
+        // The relays only read the payloads once the OC has been heard from
+        let mut ci = CommandInterpreter::new(test_config(), payload_config).unwrap();
+        ci.initialize_handlers().unwrap();
+        let oc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        oc.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        for dh_id in [0, 1] {
+            let start = Command::StartDH(StartDHCommand::new(1, DHId(dh_id), DHType::Network, DHName::new("DH")));
+            let oc_port = match ci.process_command(start) {
+                Telemetry::StartDH(tm) => tm.oc_port.unwrap(),
+                other => panic!("Unexpected telemetry {:?}", other),
+            };
+            wait_for_payload(&mut ci, DHId(dh_id));
+            oc.send_to(b"hello", format!("127.0.0.1:{}", oc_port.0)).unwrap();
+            oc.recv(&mut buf).unwrap();
+        }
+        let loopback = |dh_id, timeout_ms| {
+            Command::DHLoopback(DHLoopbackCommand::new(2, DHId(dh_id), 0x0123_4567_89ab_cdef, timeout_ms))
+        };
+        let mut process = |command| match ci.process_command(command) {
+            Telemetry::DHLoopback(tm) => tm,
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+
+        // The token goes through the relay, and its echo is picked out of it
+        let tm = process(loopback(0, 1000));
+        assert_eq!((tm.header.status, tm.token), (CommandStatus::Success, 0x0123_4567_89ab_cdef));
+        assert!(tm.rtt_us.unwrap() < 1_000_000);
+        let len = oc.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &0x0123_4567_89ab_cdef_u64.to_be_bytes());
+
+        let tm = process(loopback(1, 100));
+        assert_eq!((tm.header.status, tm.rtt_us), (CommandStatus::Timeout, None));
+        assert_eq!(process(loopback(7, 100)).header.status, CommandStatus::NotFound);
+        assert_eq!(process(loopback(2, 100)).header.status, CommandStatus::InvalidParameter);
+
+        // Other commands are answered while a loopback waits
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let addr = ground.local_addr().unwrap();
+        let send = |ci: &mut CommandInterpreter, command| {
+            ci.handle_datagram(&ProtocolMessage::from_command(command).to_bytes().unwrap(), addr);
+        };
+        let mut receive = || {
+            let len = ground.recv(&mut buf).unwrap();
+            ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap()
+        };
+        let started = Instant::now();
+        send(&mut ci, loopback(1, 500));
+        send(&mut ci, loopback(1, 500));
+        send(&mut ci, Command::Ping(PingCommand::new(3)));
+        assert!(matches!(receive(), Telemetry::Ping(_)));
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        // The resent loopback is answered once, when it times out
+        while !ci.deferred.is_empty() {
+            ci.push_deferred_replies();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(receive().status(), CommandStatus::Timeout);
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        ground.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert!(ground.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_dh_control() {
+        use std::io::Read;
+        use std::net::TcpListener;
+        use tcslibgs::{DHControlCommand, DHType, NetworkConfig, StartDHCommand, UdpMode};
+
+        // The payload takes a single connection, as tcssim does
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = test_payload_config(2).remove(0);
+        config.endpoint = EndpointConfig::Network(NetworkConfig {
+            protocol: NetworkProtocol::Tcp,
+            address: "127.0.0.1".to_string(),
+            port: Port(listener.local_addr().unwrap().port()),
+            udp_mode: UdpMode::Connected,
+        });
+        let idle = test_payload_config(2).remove(1);
+        let mut ci = CommandInterpreter::new(test_config(), vec![config, idle]).unwrap();
+        ci.initialize_handlers().unwrap();
+        let start = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Network, DHName::new("DH0")));
+        let oc_addr = match ci.process_command(start) {
+            Telemetry::StartDH(tm) => format!("127.0.0.1:{}", tm.oc_port.unwrap().0),
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        wait_for_payload(&mut ci, DHId(0));
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.send_to(b"relayed", &oc_addr).unwrap();
+        let mut buf = [0u8; 7];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"relayed");
+
+        let mut control = |dh_id, data: &[u8]| match ci
+            .process_command(Command::DHControl(DHControlCommand::new(2, DHId(dh_id), data.to_vec())))
+        {
+            Telemetry::DHControl(tm) => tm.header.status,
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+
+        // The bytes arrive on the connection the DH is relaying over
+        let sequence = b"\x1b[2J\x00reset\r\n";
+        assert_eq!(control(0, sequence), CommandStatus::Success);
+        let mut buf = vec![0u8; sequence.len()];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, sequence);
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+
+        assert_eq!(control(0, b""), CommandStatus::InvalidParameter);
+        assert_eq!(control(1, sequence), CommandStatus::InvalidParameter);
+        assert_eq!(control(7, sequence), CommandStatus::NotFound);
+    }
+
+    #[test]
+    fn test_query_activity() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{DHControlCommand, NetworkConfig, QueryActivityCommand, UdpMode};
+
+        // DH 0's payload is busy, DH 1's is idle, and DH 2 never starts
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(3)).unwrap();
+        ci.initialize_handlers().unwrap();
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        for dh_id in [DHId(0), DHId(1)] {
+            ci.data_handlers.lock().unwrap().get_mut(&dh_id).unwrap().start(
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            ).unwrap();
+        }
+
+        let query = |ci: &mut CommandInterpreter, sequence| {
+            match ci.process_command(Command::QueryActivity(QueryActivityCommand::new(sequence, 500))) {
+                Telemetry::QueryActivity(tm) => {
+                    assert_eq!((tm.header.status, tm.window_ms), (CommandStatus::Success, 500));
+                    tm.handlers
+                }
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+
+        // Nothing has moved yet
+        let idle = DHActivity { dh_id: DHId(1), active: false, idle_ms: None };
+        assert_eq!(query(&mut ci, 1), vec![DHActivity { dh_id: DHId(0), ..idle }, idle]);
+
+        match ci.process_command(Command::DHControl(DHControlCommand::new(2, DHId(0), b"go".to_vec()))) {
+            Telemetry::DHControl(tm) => assert_eq!(tm.header.status, CommandStatus::Success),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        // SUCCESS means the relay has written the control bytes
+        let activity = query(&mut ci, 3);
+        assert_eq!(activity.len(), 2);
+        assert_eq!((activity[0].dh_id, activity[0].active), (DHId(0), true));
+        assert!(activity[0].idle_ms.unwrap() < 500);
+        assert_eq!(activity[1], idle);
+    }
+
+    #[test]
+    fn test_config_beacon_interval() {
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::ConfigCommand;
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        ci.beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![ground.local_addr().unwrap()], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap();
+        let mut config = |interval| match ci.process_command(Command::Config(ConfigCommand::new(1, BeaconTime(interval)))) {
+            Telemetry::Config(tm) => (tm.header.status, tm.beacon_interval),
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+
+        assert_eq!(config(0), (CommandStatus::InvalidParameter, None));
+        assert_eq!(config(250), (CommandStatus::Success, Some(BeaconTime(250))));
+        assert_eq!(ci.beacon_interval, BeaconTime(250));
+
+        // Without a beacon running zero is still refused
+        ci.beacon = None;
+        let tm = ci.process_command(Command::Config(ConfigCommand::new(2, BeaconTime(0))));
+        assert!(matches!(tm, Telemetry::Config(tm) if tm.header.status == CommandStatus::InvalidParameter));
+        assert_eq!(ci.beacon_interval, BeaconTime(250));
+    }
+
+    #[test]
+    fn test_event_triggered_beacon() {
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::RestartArmCommand;
+
+        let oc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = test_config();
+        config.beacon_triggers = vec![BeaconTrigger::DHFaulted];
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        ci.client_addr = Some(oc.local_addr().unwrap());
+        ci.beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![ground.local_addr().unwrap()], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap();
+
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        ground.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        ground.recv(&mut buf).unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+        // Events not configured as triggers wait for the next interval
+        ci.report_dh_conditions(BTreeMap::from([(DHId(0), DHEventKind::Disconnected)]));
+        ci.process_command(Command::RestartArm(RestartArmCommand::new(1, ArmKey(99))));
+        assert!(ground.recv(&mut buf).is_err());
+
+        // A fault sends one straight away, and only one
+        let faulted = Instant::now();
+        ci.report_dh_conditions(BTreeMap::from([(DHId(0), DHEventKind::Error(ErrorCode::Io))]));
+        ground.recv(&mut buf).unwrap();
+        assert!(faulted.elapsed() < Duration::from_millis(100));
+        assert!(ground.recv(&mut buf).is_err());
+        assert_eq!(ci.beacon.as_ref().unwrap().status()[0].sent, 2);
+    }
+
+    #[test]
+    fn test_restart_reported_in_first_beacon() {
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::{RestartArmCommand, RestartCommand};
+
+        let marker = std::env::temp_dir().join(format!("tcspecial-restart-{}", std::process::id()));
+        let mut config = test_config();
+        config.restart_marker = Some(marker.to_string_lossy().into_owned());
+
+        let mut ci = CommandInterpreter::new(config.clone(), vec![]).unwrap();
+        assert_eq!(ci.start_reason, StartReason::ColdStart);
+        ci.process_command(Command::RestartArm(RestartArmCommand::new(1, ArmKey(99))));
+        let response = ci.process_command(Command::Restart(RestartCommand::new(2, ArmKey(99))));
+        assert_eq!(response.status(), CommandStatus::Success);
+        drop(ci);
+
+        // The restarted CI sees the marker and reports it in its first beacon
+        let ci = CommandInterpreter::new(config, vec![]).unwrap();
+        assert_eq!(ci.start_reason, StartReason::CommandedRestart);
+        assert!(!marker.exists());
+
+        // A symlink at the marker path is neither followed nor taken for a
+        // marker
+        let target = marker.with_extension("target");
+        std::os::unix::fs::symlink(&target, &marker).unwrap();
+        assert!(write_restart_marker(marker.to_str().unwrap()).is_err());
+        assert!(!target.exists());
+        assert_eq!(take_restart_marker(marker.to_str()), StartReason::ColdStart);
+        assert!(std::fs::symlink_metadata(&marker).is_err());
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let _beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![ground.local_addr().unwrap()], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let size = ground.recv(&mut buf).unwrap();
+        let beacon = match ProtocolMessage::from_bytes(&buf[..size]).unwrap().into_telemetry().unwrap() {
+            Telemetry::Beacon(beacon) => beacon,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        assert_eq!(beacon.start_reason, Some(StartReason::CommandedRestart));
+        assert!(beacon.uptime_ms.unwrap() < 1000);
+    }
+
+    #[test]
+    fn test_beacon_health() {
+        use std::net::UdpSocket;
+        use std::time::Duration;
+
+        // With no data handlers the health is all zeros, not missing
+        let ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let health = beacon_health(&ci.data_handlers, &ci.clock).unwrap();
+        assert_eq!((health.active_dh_count, health.statistics), (0, Statistics::new()));
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(2)).unwrap();
+        ci.initialize_handlers().unwrap();
+        let active = ci.data_handlers.lock().unwrap().values().filter(|dh| dh.state() == DHState::Active).count();
+        assert_eq!(beacon_health(&ci.data_handlers, &ci.clock).unwrap().active_dh_count, active as u32);
+
+        // Extended beacons carry the health, legacy ones don't
+        for (format, expected) in [(BeaconFormat::Extended, true), (BeaconFormat::Legacy, false)] {
+            let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let address = ground.local_addr().unwrap();
+            let beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+                vec![address], format, 0, ci.start_reason, ci.clock.clone()).unwrap().unwrap();
+            let data_handlers = Arc::clone(&ci.data_handlers);
+            let clock = ci.clock.clone();
+            beacon.set_health_source(Box::new(move || beacon_health(&data_handlers, &clock)));
+            // The first beacon may have gone before the source was set, so
+            // ask for another and check the last one received
+            assert!(beacon.set_destination_interval(address, None));
+
+            let mut buf = [0u8; 1024];
+            ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let mut size = ground.recv(&mut buf).unwrap();
+            ground.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let mut next = [0u8; 1024];
+            while let Ok(next_size) = ground.recv(&mut next) {
+                buf = next;
+                size = next_size;
+            }
+            match ProtocolMessage::from_bytes(&buf[..size]).unwrap().into_telemetry().unwrap() {
+                Telemetry::Beacon(beacon) => assert_eq!(beacon.health.is_some(), expected, "{:?}", beacon),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_time() {
+        use std::net::UdpSocket;
+        use tcslibgs::{InjectFaultCommand, PingCommand, SetTimeCommand};
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let host = Timestamp::now();
+        let target = Timestamp { seconds: 1_000_000_000, nanoseconds: 0 };
+        let close = |a: Timestamp, b: Timestamp| a.seconds.abs_diff(b.seconds) <= 1;
+
+        match ci.process_command(Command::SetTime(SetTimeCommand::new(1, target))) {
+            Telemetry::SetTime(tm) => {
+                assert_eq!((tm.header.status, tm.applied), (CommandStatus::Success, target));
+                assert!(close(tm.previous, host), "{:?}", tm);
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        match ci.process_command(Command::Ping(PingCommand::new(2))) {
+            Telemetry::Ping(tm) => assert!(close(tm.timestamp, target), "{:?}", tm),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let _beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![ground.local_addr().unwrap()], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap();
+        let mut buf = [0u8; 1024];
+        let size = ground.recv(&mut buf).unwrap();
+        match ProtocolMessage::from_bytes(&buf[..size]).unwrap().into_telemetry().unwrap() {
+            Telemetry::Beacon(beacon) => assert!(close(beacon.timestamp, target), "{:?}", beacon),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        // A time that can't be right leaves the clock alone
+        let invalid = Timestamp { seconds: 5, nanoseconds: 1_000_000_000 };
+        match ci.process_command(Command::SetTime(SetTimeCommand::new(3, invalid))) {
+            Telemetry::SetTime(tm) => {
+                assert_eq!(tm.header.status, CommandStatus::InvalidParameter);
+                assert!(close(tm.previous, target) && close(tm.applied, target), "{:?}", tm);
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert!(close(ci.clock.now(), target));
+
+        // So does one failed by fault injection, which reports the CI's time
+        // rather than the host's
+        ci.config.fault_injection = true;
+        let inject = InjectFaultCommand::new(4, CommandType::SetTime, CommandStatus::Failure);
+        assert_eq!(ci.process_command(Command::InjectFault(inject)).status(), CommandStatus::Success);
+        let later = Timestamp { seconds: 2_000_000_000, nanoseconds: 0 };
+        match ci.process_command(Command::SetTime(SetTimeCommand::new(5, later))) {
+            Telemetry::SetTime(tm) => {
+                assert_eq!(tm.header.status, CommandStatus::Failure);
+                assert!(close(tm.previous, target) && close(tm.applied, target), "{:?}", tm);
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert!(close(ci.clock.now(), target));
+    }
+
+    #[test]
+    fn test_query_dh_reports_state() {
+        use tcslibgs::DHState;
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        let response = ci.process_command(Command::QueryDH(QueryDHCommand::new(1, DHId(0))));
+        let tm = match response {
+            Telemetry::QueryDH(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        assert_eq!(tm.state, Some(DHState::Created));
+
+        // The state survives the trip to the ground
+        let json = serde_json::to_string(&Telemetry::QueryDH(tm)).unwrap();
+        match serde_json::from_str::<Telemetry>(&json).unwrap() {
+            Telemetry::QueryDH(tm) => assert_eq!(tm.state, Some(DHState::Created)),
+            _ => panic!("Unexpected telemetry type"),
+        }
+    }
+
+    #[test]
+    fn test_self_poll_detects_silent_dh() {
+        use crate::endpoint::UdpEndpoint;
+        use std::time::Duration;
+        use tcslibgs::{NetworkConfig, UdpMode};
+
+        let mut config = test_config();
+        config.self_poll_interval = Some(Duration::from_millis(10));
+        let mut ci = CommandInterpreter::new(config, test_payload_config(1)).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        ci.data_handlers.lock().unwrap().get_mut(&DHId(0)).unwrap().start(
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+        ).unwrap();
+
+        // The first poll records a baseline, the next notices nothing moved
+        for _ in 0..2 {
+            std::thread::sleep(Duration::from_millis(20));
+            ci.maybe_self_poll();
+        }
+        assert!(ci.stalled.contains(&DHId(0)));
+
+        // Already reported, so a further poll does not warn again
+        assert!(ci.self_poll().is_empty());
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        use std::time::Duration;
+
+        // With nothing periodic to do the CI waits as long as it may
+        let mut config = test_config();
+        let mut ci = CommandInterpreter::new(config.clone(), vec![]).unwrap();
+        assert_eq!(ci.read_timeout(), CI_SERVICE_INTERVAL);
+        config.max_wait = Some(Duration::from_millis(500));
+        ci = CommandInterpreter::new(config.clone(), vec![]).unwrap();
+        assert_eq!(ci.read_timeout(), Duration::from_millis(500));
+
+        // It wakes no later than the next self-poll
+        config.self_poll_interval = Some(Duration::from_millis(200));
+        ci = CommandInterpreter::new(config, vec![]).unwrap();
+        ci.last_self_poll = Instant::now() - Duration::from_millis(150);
+        assert!(ci.read_timeout() <= Duration::from_millis(50));
+
+        // or the next subscription update, and never waits for zero time
+        ci.subscriptions.push(Subscription {
+            dh_id: DHId(0),
+            subscriber: "127.0.0.1:9".parse().unwrap(),
+            sequence: 1,
+            request_id: None,
+            interval: Duration::from_millis(10),
+            last_sent: Instant::now() - Duration::from_millis(20),
+            expires: Instant::now() + Duration::from_secs(60),
+        });
+        assert_eq!(ci.read_timeout(), CI_MIN_WAIT);
+    }
+
+    #[test]
+    fn test_hello_negotiates_framing() {
+        use tcslibgs::HelloCommand;
+
+        let offer = vec![Framing::Binary, Framing::Json];
+        let negotiated = |ci: &mut CommandInterpreter| {
+            match ci.process_command(Command::Hello(HelloCommand::new(1, offer.clone()))) {
+                Telemetry::Hello(tm) => tm.framing,
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        assert_eq!(negotiated(&mut ci), Framing::Json);
+
+        let mut config = test_config();
+        config.framings = vec![Framing::Json, Framing::Binary];
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        assert_eq!(negotiated(&mut ci), Framing::Binary);
+    }
+
+    #[test]
+    fn test_binary_peers_bounded() {
+        use tcslibgs::PingCommand;
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let ping = ProtocolMessage::from_command(Command::Ping(PingCommand::new(1)));
+        let binary = ping.to_bytes_with(Framing::Binary).unwrap();
+        let peer = |n: usize| SocketAddr::from(([127, 0, 0, 1], 40000 + n as u16));
+
+        // Spoofed source ports can't grow the table without limit
+        for n in 0..BINARY_PEERS_MAX + 10 {
+            ci.handle_datagram(&binary, peer(n));
+        }
+        assert_eq!(ci.binary_peers.len(), BINARY_PEERS_MAX);
+        assert!(!ci.binary_peers.contains(&peer(0)));
+        assert!(ci.binary_peers.contains(&peer(BINARY_PEERS_MAX + 9)));
+
+        // A peer heard from again is kept over quieter ones, until it sends JSON
+        ci.handle_datagram(&binary, peer(10));
+        ci.handle_datagram(&binary, peer(0));
+        assert!(ci.binary_peers.contains(&peer(10)));
+        assert!(!ci.binary_peers.contains(&peer(11)));
+        ci.handle_datagram(&ping.to_bytes_with(Framing::Json).unwrap(), peer(10));
+        assert!(!ci.binary_peers.contains(&peer(10)));
+    }
+
+    #[test]
+    fn test_request_id_echoed() {
+        use tcslibgs::PingCommand;
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+
+        let mut ping = Command::Ping(PingCommand::new(7));
+        ping.set_request_id(Some(0xfeed_0000_0000_beef));
+        let response = ci.process_command(ping);
+        assert_eq!(response.request_id(), Some(0xfeed_0000_0000_beef));
+        assert_eq!(response.sequence(), 7);
+
+        // Error responses are tagged too, and untagged commands stay untagged
+        let mut query = Command::QueryDH(QueryDHCommand::new(8, DHId(99)));
+        query.set_request_id(Some(42));
+        assert_eq!(ci.process_command(query).request_id(), Some(42));
+        assert_eq!(ci.process_command(Command::Ping(PingCommand::new(9))).request_id(), None);
+    }
+
+    #[test]
+    fn test_arm_key_validation() {
+        use tcslibgs::RestartArmCommand;
+
+        let mut config = test_config();
+        config.arm_keys = Some(vec![ArmKey(0x1234)]);
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+
+        let response = ci.process_command(Command::RestartArm(RestartArmCommand::new(1, ArmKey(0x4321))));
+        assert_eq!(response.status(), CommandStatus::InvalidArmKey);
+        assert_eq!(ci.arm_key, None);
+
+        let response = ci.process_command(Command::RestartArm(RestartArmCommand::new(2, ArmKey(0x1234))));
+        assert_eq!(response.status(), CommandStatus::Success);
+        assert_eq!(ci.arm_key, Some(ArmKey(0x1234)));
+    }
+
+    #[test]
+    fn test_subscribe_dh_stats() {
+        use std::net::UdpSocket;
+        use std::thread;
+        use tcslibgs::{DHType, StartDHCommand, SubscribeDHStatsCommand, UnsubscribeDHStatsCommand};
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        ci.client_addr = Some(ground.local_addr().unwrap());
+
+        let subscribe = |dh_id| Command::SubscribeDHStats(SubscribeDHStatsCommand::new(7, DHId(dh_id), 10));
+        assert_eq!(ci.process_command(subscribe(0)).status(), CommandStatus::NotFound);
+        let start = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Device, DHName::new("DH0")));
+        assert_eq!(ci.process_command(start).status(), CommandStatus::Success);
+        assert_eq!(ci.process_command(subscribe(0)).status(), CommandStatus::Success);
+
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        for _ in 0..2 {
+            thread::sleep(Duration::from_millis(20));
+            ci.push_subscriptions();
+            let len = ground.recv(&mut buf).unwrap();
+            let telemetry = ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap();
+            match telemetry {
+                Telemetry::QueryDH(tm) => {
+                    assert_eq!(tm.header.sequence, 7);
+                    assert_eq!(tm.dh_id, DHId(0));
+                }
+                _ => panic!("Unexpected telemetry type"),
+            }
+        }
+
+        let unsubscribe = Command::UnsubscribeDHStats(UnsubscribeDHStatsCommand::new(8, DHId(0)));
+        assert_eq!(ci.process_command(unsubscribe).status(), CommandStatus::Success);
+        assert!(ci.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn test_dh_events() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use tcslibgs::{NetworkConfig, StopDHCommand, UdpMode};
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        ci.client_addr = Some(ground.local_addr().unwrap());
+        ci.initialize_handlers().unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        let mut next_event = || {
+            let len = ground.recv(&mut buf).unwrap();
+            match ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap() {
+                Telemetry::DHEvent(tm) => (tm.header.sequence, tm.dh_id, tm.event),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+
+        // Data handlers that haven't started have nothing to report
+        ci.push_dh_events();
+        ci.data_handlers.lock().unwrap().get_mut(&DHId(0)).unwrap().start(
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+        )
+        .unwrap();
+        ci.push_dh_events();
+        assert_eq!(next_event(), (0, DHId(0), DHEventKind::Connected));
+
+        // A payload hanging up is reported once, and so is its return
+        let disconnected = BTreeMap::from([(DHId(0), DHEventKind::Disconnected)]);
+        ci.report_dh_conditions(disconnected.clone());
+        ci.report_dh_conditions(disconnected);
+        assert_eq!(next_event(), (1, DHId(0), DHEventKind::Disconnected));
+        ci.push_dh_events();
+        assert_eq!(next_event(), (2, DHId(0), DHEventKind::Connected));
+
+        assert_eq!(ci.process_command(Command::StopDH(StopDHCommand::new(2, DHId(0)))).status(), CommandStatus::Success);
+        ci.push_dh_events();
+        assert_eq!(next_event(), (3, DHId(0), DHEventKind::Stopped));
+
+        // Nothing changed, so nothing more is sent
+        ci.push_dh_events();
+        ground.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        assert!(ground.recv(&mut [0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_started_payload() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use tcslibgs::{DHType, NetworkConfig, StartDHCommand, UdpMode};
+
+        // The payload sends on its first connection, closes it and stops
+        // listening for a while, then takes a new connection
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let payload = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"first").unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            drop(stream);
+            drop(listener);
+
+            std::thread::sleep(Duration::from_millis(150));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"second").unwrap();
+            let mut uplink = [0u8; 6];
+            stream.read_exact(&mut uplink).unwrap();
+            uplink
+        });
+
+        let mut config = test_payload_config(1).remove(0);
+        config.endpoint = EndpointConfig::Network(NetworkConfig {
+            protocol: NetworkProtocol::Tcp,
+            address: "127.0.0.1".to_string(),
+            port: Port(port),
+            udp_mode: UdpMode::Connected,
+        });
+        let events = UdpSocket::bind("127.0.0.1:0").unwrap();
+        events.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut ci = CommandInterpreter::new(test_config(), vec![config]).unwrap();
+        ci.client_addr = Some(events.local_addr().unwrap());
+        let start = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Network, DHName::new("DH0")));
+        let oc_addr = match ci.process_command(start) {
+            Telemetry::StartDH(tm) => format!("127.0.0.1:{}", tm.oc_port.unwrap().0),
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+        wait_for_payload(&mut ci, DHId(0));
+
+        // Downlink follows the first uplink from the ground
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        ground.send_to(b"hi", &oc_addr).unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first");
+
+        // The CI's service loop reconnects the payload, reporting it going
+        // and coming back
+        let mut reported = vec![];
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !reported.ends_with(&[DHEventKind::Disconnected, DHEventKind::Connected]) {
+            assert!(Instant::now() < deadline, "Payload never reconnected: {:?}", reported);
+            ci.reconnect_payloads();
+            ci.push_dh_events();
+            while let Ok(len) = events.recv(&mut buf) {
+                match ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap() {
+                    Telemetry::DHEvent(tm) => reported.push(tm.event),
+                    other => panic!("Unexpected telemetry {:?}", other),
+                }
+            }
+        }
+        assert_eq!(reported.first(), Some(&DHEventKind::Connected));
+
+        // and relays both ways on the new connection
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"second");
+        ground.send_to(b"uplink", &oc_addr).unwrap();
+        assert_eq!(&payload.join().unwrap(), b"uplink");
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(0)].state(), DHState::Active);
+    }
+
+    #[test]
+    fn test_start_dh_payload_not_up() {
+        use std::net::TcpListener;
+        use tcslibgs::{DHType, NetworkConfig, StartDHCommand, UdpMode};
+
+        // Nothing listens on the payload's port, yet START_DH answers at
+        // once and leaves the connect retrying behind it
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut config = test_payload_config(1).remove(0);
+        config.endpoint = EndpointConfig::Network(NetworkConfig {
+            protocol: NetworkProtocol::Tcp,
+            address: "127.0.0.1".to_string(),
+            port: Port(port),
+            udp_mode: UdpMode::Connected,
+        });
+        let mut ci = CommandInterpreter::new(test_config(), vec![config]).unwrap();
+        let started = Instant::now();
+        let start = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Network, DHName::new("DH0")));
+        match ci.process_command(start) {
+            Telemetry::StartDH(tm) => assert_eq!(tm.header.status, CommandStatus::Success),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        let handlers = ci.data_handlers.lock().unwrap();
+        assert_eq!(handlers[&DHId(0)].state(), DHState::Active);
+        assert!(!handlers[&DHId(0)].payload_connected());
+        assert_eq!(dh_condition(&handlers[&DHId(0)]), Some(DHEventKind::Disconnected));
+    }
+
+    #[test]
+    fn test_start_dh_outcome() {
+        use tcslibgs::{DHType, StartDHCommand, StopDHCommand};
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(2)).unwrap();
+        let start = |ci: &mut CommandInterpreter, dh_id| {
+            let cmd = Command::StartDH(StartDHCommand::new(1, DHId(dh_id), DHType::Device, DHName::new("DH")));
+            match ci.process_command(cmd) {
+                Telemetry::StartDH(tm) => (tm.header.status, tm.outcome, tm.oc_port),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+
+        // The DH is started on an OC endpoint of its own, whose port is
+        // given again to later START_DHs
+        let (status, outcome, oc_port) = start(&mut ci, 0);
+        assert_eq!((status, outcome), (CommandStatus::Success, Some(StartDHOutcome::Created)));
+        assert!(oc_port.is_some_and(|port| !port.is_any()));
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(0)].state(), DHState::Active);
+        assert_eq!(start(&mut ci, 0), (CommandStatus::Success, Some(StartDHOutcome::AlreadyActive), oc_port));
+
+        // Stopping and starting it again replaces it
+        assert_eq!(ci.process_command(Command::StopDH(StopDHCommand::new(2, DHId(0)))).status(), CommandStatus::Success);
+        let (status, outcome, _) = start(&mut ci, 0);
+        assert_eq!((status, outcome), (CommandStatus::Success, Some(StartDHOutcome::Reactivated)));
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(0)].state(), DHState::Active);
+
+        // One loaded from the configuration is started, not created again
+        ci.initialize_handlers().unwrap();
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(1)].state(), DHState::Created);
+        assert_eq!(start(&mut ci, 1).1, Some(StartDHOutcome::Created));
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(1)].state(), DHState::Active);
+
+        assert_eq!(start(&mut ci, 7), (CommandStatus::NotFound, None, None));
+    }
+
+    #[test]
+    fn test_start_dh_unavailable_address() {
+        use tcslibgs::{DHType, NetworkConfig, StartDHCommand, UdpMode};
+
+        // 192.0.2.0/24 is reserved for documentation and is never local;
+        // unconnected, the DH binds the configured address
+        let mut payload_config = test_payload_config(1);
+        payload_config[0].endpoint = EndpointConfig::Network(NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "192.0.2.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Unconnected,
+        });
+        let mut ci = CommandInterpreter::new(test_config(), payload_config).unwrap();
+
+        let cmd = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Network, DHName::new("DH0")));
+        match ci.process_command(cmd) {
+            Telemetry::StartDH(tm) => {
+                assert_eq!(tm.header.status, CommandStatus::InvalidParameter);
+                assert_eq!(tm.error, Some(ErrorCode::InvalidConfiguration));
+                assert!(tm.detail.unwrap().contains("192.0.2.1"));
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pause_all_dh() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{NetworkConfig, PauseAllDHCommand, ResumeAllDHCommand, UdpMode};
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(4)).unwrap();
+        ci.initialize_handlers().unwrap();
+        // Three of the four DHs are running
+        for (_, dh) in ci.data_handlers.lock().unwrap().iter_mut().take(3) {
+            dh.start(
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            )
+            .unwrap();
+        }
+        let states = |ci: &CommandInterpreter| {
+            ci.data_handlers.lock().unwrap().values().map(DataHandler::state).collect::<Vec<_>>()
+        };
+
+        for _ in 0..2 {
+            match ci.process_command(Command::PauseAllDH(PauseAllDHCommand::new(1))) {
+                Telemetry::PauseAllDH(tm) => assert_eq!((tm.header.status, tm.paused), (CommandStatus::Success, 3)),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+            assert_eq!(states(&ci), [DHState::Paused, DHState::Paused, DHState::Paused, DHState::Created]);
+        }
+
+        for _ in 0..2 {
+            match ci.process_command(Command::ResumeAllDH(ResumeAllDHCommand::new(2))) {
+                Telemetry::ResumeAllDH(tm) => assert_eq!((tm.header.status, tm.resumed), (CommandStatus::Success, 3)),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+            assert_eq!(states(&ci), [DHState::Active, DHState::Active, DHState::Active, DHState::Created]);
+        }
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        use tcslibgs::ResetStatsCommand;
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(2)).unwrap();
+        ci.initialize_handlers().unwrap();
+
+        match ci.process_command(Command::ResetStats(ResetStatsCommand::new(2, DHId(0)))) {
+            Telemetry::ResetStats(tm) => assert_eq!((tm.header.status, tm.dh_id), (CommandStatus::Success, DHId(0))),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        match ci.process_command(Command::QueryDH(QueryDHCommand::new(3, DHId(0)))) {
+            Telemetry::QueryDH(tm) => assert_eq!((tm.statistics.bytes_received, tm.statistics.bytes_sent), (0, 0)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+
+        match ci.process_command(Command::ResetStats(ResetStatsCommand::new(4, DHId(9)))) {
+            Telemetry::ResetStats(tm) => assert_eq!((tm.header.status, tm.dh_id), (CommandStatus::NotFound, DHId(9))),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_dh() {
+        use tcslibgs::{CheckpointDHCommand, ResetStatsCommand};
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        ci.initialize_handlers().unwrap();
+        let query = |ci: &mut CommandInterpreter| {
+            match ci.process_command(Command::QueryDH(QueryDHCommand::new(2, DHId(0)))) {
+                Telemetry::QueryDH(tm) => tm.since_checkpoint,
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+        assert_eq!(query(&mut ci), None);
+
+        match ci.process_command(Command::CheckpointDH(CheckpointDHCommand::new(3, DHId(0)))) {
+            Telemetry::CheckpointDH(tm) => assert_eq!((tm.header.status, tm.dh_id), (CommandStatus::Success, DHId(0))),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        let delta = query(&mut ci).unwrap();
+        assert_eq!((delta.bytes_received, delta.bytes_sent), (0, 0));
+
+        // Resetting the statistics drops the checkpoint with them
+        ci.process_command(Command::ResetStats(ResetStatsCommand::new(4, DHId(0))));
+        assert_eq!(query(&mut ci), None);
+
+        match ci.process_command(Command::CheckpointDH(CheckpointDHCommand::new(5, DHId(9)))) {
+            Telemetry::CheckpointDH(tm) => assert_eq!(tm.header.status, CommandStatus::NotFound),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_log_level() {
+        use std::io::Write;
+        use tcslibgs::{LogLevel, PingCommand, SetLogLevelCommand};
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let logger = Logger::with_writer(LogLevel::Info, capture.clone());
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap().with_logger(logger);
+        let take = || String::from_utf8(std::mem::take(&mut *capture.0.lock().unwrap())).unwrap();
+
+        ci.process_command(Command::Ping(PingCommand::new(1)));
+        assert_eq!(take(), "");
+
+        match ci.process_command(Command::SetLogLevel(SetLogLevelCommand::new(2, LogLevel::Debug))) {
+            Telemetry::SetLogLevel(tm) => assert_eq!((tm.level, tm.previous), (LogLevel::Debug, LogLevel::Info)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        ci.process_command(Command::Ping(PingCommand::new(3)));
+        let logged = take();
+        assert!(logged.contains("[debug] process_command: Ping"), "{}", logged);
+
+        // Lowering the level again stops the debug lines
+        ci.process_command(Command::SetLogLevel(SetLogLevelCommand::new(4, LogLevel::Info)));
+        take();
+        ci.process_command(Command::Ping(PingCommand::new(5)));
+        assert_eq!(take(), "");
+    }
+
+    #[test]
+    fn test_failure_detail() {
+        use tcslibgs::{DHType, ReconfigureDHCommand, StartDHCommand};
+
+        let mut config = test_config();
+        config.start_dh_exclusive = true;
+        let mut ci = CommandInterpreter::new(config, test_payload_config(1)).unwrap();
+        let mut start = |dh_id: u32| {
+            let cmd = StartDHCommand::new(1, DHId(dh_id), DHType::Device, DHName::new("DH"));
+            match ci.process_command(Command::StartDH(cmd)) {
+                Telemetry::StartDH(tm) => (tm.header.status, tm.detail),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+
+        assert_eq!(start(0), (CommandStatus::Success, None));
+        assert_eq!(
+            start(0),
+            (CommandStatus::AlreadyExists, Some("Data handler already exists: 0".to_string()))
+        );
+        assert_eq!(start(7), (CommandStatus::NotFound, Some("Data handler not found: 7".to_string())));
+
+        // The cause of an invalid configuration is passed on as given
+        let mut dh_config = test_payload_config(1).remove(0);
+        dh_config.packet_size = 0;
+        let tm = ci.process_command(Command::ReconfigureDH(ReconfigureDHCommand::new(2, DHId(0), dh_config.clone())));
+        assert_eq!(tm.status(), CommandStatus::InvalidParameter);
+        assert_eq!(tm.detail(), Some(validate_config(&dh_config).unwrap_err().to_string().as_str()));
+    }
+
+    #[test]
+    fn test_reconfigure_running_dh() {
+        use std::time::Duration;
+        use tcslibgs::{DHType, NetworkConfig, ReconfigureDHCommand, StartDHCommand, UdpMode};
+
+        // DH 0 starts out echoing through a pipe
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut config = test_payload_config(1).remove(0);
+        config.endpoint = EndpointConfig::Device(DeviceConfig {
+            path: format!("/proc/self/fd/{}", pipe_fds[0]),
+        });
+        let mut ci = CommandInterpreter::new(test_config(), vec![config.clone()]).unwrap();
+        let oc_port = match ci.process_command(Command::StartDH(StartDHCommand::new(
+            1,
+            DHId(0),
+            DHType::Device,
+            DHName::new("DH0"),
+        ))) {
+            Telemetry::StartDH(tm) => tm.oc_port.unwrap(),
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+        let oc_addr = format!("127.0.0.1:{}", oc_port.0);
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 32];
+        ground.send_to(b"device", &oc_addr).unwrap();
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"device");
+
+        // Buffer sizes and protocol change in one command, on a running DH
+        let payload = UdpSocket::bind("127.0.0.1:0").unwrap();
+        payload.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut new_config = DHConfig {
+            name: DHName::new("Reconfigured"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Udp,
+                address: "127.0.0.1".to_string(),
+                port: Port(payload.local_addr().unwrap().port()),
+                udp_mode: UdpMode::Connected,
+            }),
+            ..config
+        };
+        new_config.conduit.read_buffer_size = Some(512);
+        new_config.conduit.write_buffer_size = Some(256);
+        let reconfigure = Command::ReconfigureDH(ReconfigureDHCommand::new(2, DHId(0), new_config.clone()));
+        assert_eq!(ci.process_command(reconfigure).status(), CommandStatus::Success);
+        {
+            let handlers = ci.data_handlers.lock().unwrap();
+            assert_eq!(handlers[&DHId(0)].state(), DHState::Active);
+            assert_eq!(handlers[&DHId(0)].config(), &new_config);
+        }
+
+        // The same OC port now reaches the new payload, with the statistics kept
+        ground.send_to(b"network", &oc_addr).unwrap();
+        let (n, relay) = payload.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"network");
+        payload.send_to(b"reply", relay).unwrap();
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"reply");
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let stats = loop {
+            let stats = ci.data_handlers.lock().unwrap()[&DHId(0)].statistics();
+            if stats.bytes_sent == 11 || Instant::now() >= deadline {
+                break stats;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!((stats.bytes_received, stats.bytes_sent), (13, 11));
+        assert_eq!((stats.reads_failed, stats.writes_failed), (0, 0));
+
+        drop(ci);
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_max_data_handlers() {
+        use tcslibgs::{DHType, StartDHCommand};
+
+        for max in [0, usize::MAX / 8] {
+            let mut config = test_config();
+            config.max_data_handlers = Some(max);
+            assert!(matches!(CommandInterpreter::new(config, vec![]), Err(TcsError::Config(_))));
+        }
+
+        let mut config = test_config();
+        config.max_data_handlers = Some(1);
+        let mut ci = CommandInterpreter::new(config, test_payload_config(2)).unwrap();
+        let mut start = |dh_id: u32| {
+            let cmd = StartDHCommand::new(1, DHId(dh_id), DHType::Device, DHName::new("DH"));
+            ci.process_command(Command::StartDH(cmd))
+        };
+
+        assert_eq!(start(0).status(), CommandStatus::Success);
+        let tm = start(1);
+        assert_eq!(tm.status(), CommandStatus::Failure);
+        assert_eq!(tm.detail(), Some("Data handler error: Limit of 1 running data handlers reached"));
+        // Starting one already running doesn't need another slot
+        assert_eq!(start(0).status(), CommandStatus::Success);
+    }
+
+    #[test]
+    fn test_config_dh() {
+        use tcslibgs::ConfigDHCommand;
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1)).unwrap();
+        ci.initialize_handlers().unwrap();
+        let mut config_dh = |cmd: ConfigDHCommand| match ci.process_command(Command::ConfigDH(cmd)) {
+            Telemetry::ConfigDH(tm) => tm.header.status,
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+
+        let cmd = ConfigDHCommand { read_buffer_size: Some(512), stream_delay_ms: Some(5), ..ConfigDHCommand::new(2, DHId(0)) };
+        assert_eq!(config_dh(cmd), CommandStatus::Success);
+        // Values not given are left as they are
+        let cmd = ConfigDHCommand { rate_limit_bps: Some(9600), ..ConfigDHCommand::new(3, DHId(0)) };
+        assert_eq!(config_dh(cmd), CommandStatus::Success);
+
+        for cmd in [
+            ConfigDHCommand { write_buffer_size: Some(0), ..ConfigDHCommand::new(4, DHId(0)) },
+            ConfigDHCommand { read_buffer_size: Some(ENDPOINT_BUFFER_SIZE + 1), ..ConfigDHCommand::new(5, DHId(0)) },
+            ConfigDHCommand { rate_limit_bps: Some(0), ..ConfigDHCommand::new(6, DHId(0)) },
+        ] {
+            assert_eq!(config_dh(cmd), CommandStatus::InvalidParameter);
+        }
+        assert_eq!(config_dh(ConfigDHCommand::new(7, DHId(9))), CommandStatus::NotFound);
+
+        let handlers = ci.data_handlers.lock().unwrap();
+        let options = &handlers[&DHId(0)].config().conduit;
+        assert_eq!(
+            (options.read_buffer_size, options.write_buffer_size, options.stream_delay_ms, options.rate_limit_bps),
+            (Some(512), None, Some(5), Some(9600))
+        );
+    }
+
+    #[test]
+    fn test_beacon_bind_failure() {
+        // Not an address of this host, so binding it fails
+        let unbindable: SocketAddr = "192.0.2.1:0".parse().unwrap();
+        assert!(BeaconSend::new(Duration::from_secs(60), unbindable, vec![], BeaconFormat::Legacy, 0,
+            StartReason::ColdStart, Clock::new()).is_err());
+
+        // By default the CI carries on without beacons
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        ci.start_beacon(unbindable).unwrap();
+        assert!(ci.beacon.is_none());
+        ci.start_beacon(BEACON_BIND_ADDR.parse().unwrap()).unwrap();
+        assert!(ci.beacon.is_some());
+
+        let mut config = test_config();
+        config.beacon_required = true;
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        assert!(ci.start_beacon(unbindable).is_err());
+        assert!(ci.beacon.is_none());
+    }
+
+    #[test]
+    fn test_list_dh() {
+        use tcslibgs::{DHType, ListDHCommand, StartDHCommand};
+
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(3)).unwrap();
+        let list = |ci: &mut CommandInterpreter, seq| {
+            match ci.process_command(Command::ListDH(ListDHCommand::new(seq))) {
+                Telemetry::ListDH(tm) => {
+                    assert_eq!(tm.header.status, CommandStatus::Success);
+                    tm.handlers
+                }
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        };
+        assert!(list(&mut ci, 1).is_empty());
+
+        for (seq, id) in [(2, 2), (3, 0)] {
+            let name = DHName::new(format!("DH{}", id));
+            ci.process_command(Command::StartDH(StartDHCommand::new(seq, DHId(id), DHType::Device, name)));
+        }
+
+        let handlers = list(&mut ci, 4);
+        let ids: Vec<DHId> = handlers.iter().map(|entry| entry.dh_id).collect();
+        assert_eq!(ids, [DHId(0), DHId(2)]);
+        assert_eq!(handlers[1].name, DHName::new("DH2"));
+        assert_eq!((handlers[1].dh_type, handlers[1].state), (DHType::Device, DHState::Active));
+    }
+
+    #[test]
+    fn test_reload_config() {
+        use tcslibgs::ReloadConfigCommand;
+
+        let path = std::env::temp_dir().join(format!("tcspayload-reload-{}.json", std::process::id()));
+        let mut ci =
+            CommandInterpreter::new(test_config(), test_payload_config(2)).unwrap().with_payload_path(path.clone());
+        ci.initialize_handlers().unwrap();
+
+        // DH0 goes, DH1 stays as it was and DH2 is new
+        let payload_json = r#"{
+            "version": "1.0",
+            "description": "Reloaded payloads",
+            "data_handlers": [
+                {"dh_id": 1, "name": "DH1", "type": "device", "path": "/dev/null",
+                 "packet_size": 64, "packet_interval_ms": 100},
+                {"dh_id": 2, "name": "DH2", "type": "device", "path": "/dev/null",
+                 "packet_size": 64, "packet_interval_ms": 100}
+            ]
+        }"#;
+        std::fs::write(&path, payload_json).unwrap();
+
+        let summary = match ci.process_command(Command::ReloadConfig(ReloadConfigCommand::new(1))) {
+            Telemetry::ReloadConfig(tm) => {
+                assert_eq!(tm.header.status, CommandStatus::Success);
+                tm.summary.unwrap()
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+        assert_eq!(summary.added, [DHId(2)]);
+        assert_eq!(summary.removed, [DHId(0)]);
+        assert_eq!(summary.unchanged, [DHId(1)]);
+        assert!(summary.reconfigured.is_empty() && summary.failed.is_empty());
+
+        let running: Vec<DHId> = ci.data_handlers.lock().unwrap().keys().copied().collect();
+        assert_eq!(running, [DHId(1), DHId(2)]);
+        assert_eq!(ci.payload_config, load_payload_config(&path).unwrap());
+
+        // An unreadable file changes nothing
+        std::fs::write(&path, "garbage").unwrap();
+        match ci.process_command(Command::ReloadConfig(ReloadConfigCommand::new(2))) {
+            Telemetry::ReloadConfig(tm) => assert_eq!((tm.header.status, tm.summary), (CommandStatus::Failure, None)),
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert_eq!(ci.data_handlers.lock().unwrap().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_config_running_dh() {
+        use std::io::Write;
+        use tcslibgs::{DHType, ReloadConfigCommand, StartDHCommand};
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut ci = CommandInterpreter::new(test_config(), test_payload_config(1))
+            .unwrap()
+            .with_payload_path(file.path());
+        let start = Command::StartDH(StartDHCommand::new(1, DHId(0), DHType::Device, DHName::new("DH0")));
+        let oc_port = match ci.process_command(start) {
+            Telemetry::StartDH(tm) => tm.oc_port.unwrap(),
+            other => panic!("Unexpected telemetry {:?}", other),
+        };
+
+        // DH 0 moves from /dev/null to a pipe that echoes what it is sent
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        write!(
+            file,
+            r#"{{
+                "version": "1.0",
+                "description": "Reloaded payloads",
+                "data_handlers": [
+                    {{"dh_id": 0, "name": "DH0", "type": "device", "path": "/proc/self/fd/{}",
+                     "packet_size": 64, "packet_interval_ms": 100}}
+                ]
+            }}"#,
+            pipe_fds[0]
+        )
+        .unwrap();
+
+        match ci.process_command(Command::ReloadConfig(ReloadConfigCommand::new(2))) {
+            Telemetry::ReloadConfig(tm) => {
+                assert_eq!(tm.header.status, CommandStatus::Success);
+                assert_eq!(tm.summary.unwrap().reconfigured, [DHId(0)]);
+            }
+            other => panic!("Unexpected telemetry {:?}", other),
+        }
+        assert_eq!(ci.data_handlers.lock().unwrap()[&DHId(0)].state(), DHState::Active);
+
+        // The relays were restarted on the new payload behind the same OC port
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        ground.send_to(b"reloaded", format!("127.0.0.1:{}", oc_port.0)).unwrap();
+        let mut buf = [0u8; 16];
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"reloaded");
+
+        drop(ci);
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_invalid_command_reply() {
+        use std::net::UdpSocket;
+        use tcslibgs::PROTOCOL_VERSION;
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let addr = ground.local_addr().unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let unknown = [&[PROTOCOL_VERSION][..], br#"{"payload":{"Command":{"Frobnicate":{"header":{"sequence":5}}}}}"#]
+            .concat();
+        let cases = [
+            (&unknown[..], "Frobnicate"),
+            (&[PROTOCOL_VERSION, 0x7f, 0x00][..], "Unknown message type 0x7F"),
+            (&[PROTOCOL_VERSION + 1, 0x01][..], "version mismatch"),
+        ];
+        for (data, expected) in cases {
+            ci.handle_datagram(data, addr);
+            let len = ground.recv(&mut buf).unwrap();
+            match ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap() {
+                Telemetry::InvalidCommand(tm) => {
+                    assert_eq!(tm.header.sequence, 0);
+                    assert_eq!(tm.header.status, CommandStatus::InvalidCommand);
+                    assert!(tm.reason.contains(expected), "{}", tm.reason);
+                }
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        }
+        assert_eq!(ci.commands_dropped, 3);
+
+        // Silence, as before, when replies are turned off
+        let mut config = test_config();
+        config.reply_invalid_commands = false;
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        ci.handle_datagram(&unknown, addr);
+        assert!(ground.recv(&mut buf).is_err());
+        assert_eq!(ci.commands_dropped, 1);
+    }
+
+    #[test]
+    fn test_resent_commands() {
+        use std::net::UdpSocket;
+        use tcslibgs::{DHType, PingCommand, RestartArmCommand, RestartCommand, StartDHCommand};
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let addr = ground.local_addr().unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+        let mut exchange = |ci: &mut CommandInterpreter, command: &Command| {
+            ci.handle_datagram(&ProtocolMessage::from_command(command.clone()).to_bytes().unwrap(), addr);
+            let len = ground.recv(&mut buf).unwrap();
+            ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap()
+        };
+
+        let mut config = test_config();
+        config.start_dh_exclusive = true;
+        let mut ci = CommandInterpreter::new(config, test_payload_config(1)).unwrap();
+
+        // A resent START_DH gets the first reply, not ALREADY_EXISTS
+        let start = |seq| Command::StartDH(StartDHCommand::new(seq, DHId(0), DHType::Device, DHName::new("DH0")));
+        let first = exchange(&mut ci, &start(1));
+        let Telemetry::StartDH(tm) = &first else { panic!("Unexpected telemetry {:?}", first) };
+        assert_eq!(tm.outcome, Some(StartDHOutcome::Created));
+        assert!(tm.oc_port.is_some());
+        assert_eq!(exchange(&mut ci, &start(1)), first);
+        assert_eq!(ci.data_handlers.lock().unwrap().len(), 1);
+
+        // A stale reply isn't replayed to a client that reuses the sequence
+        ci.last_reply.as_mut().unwrap().received -= REPLY_CACHE_MAX_AGE;
+        assert_eq!(exchange(&mut ci, &start(1)).status(), CommandStatus::AlreadyExists);
+
+        // A new START_DH is executed
+        assert_eq!(exchange(&mut ci, &start(2)).status(), CommandStatus::AlreadyExists);
+
+        // A resent RESTART is answered without restarting again
+        exchange(&mut ci, &Command::RestartArm(RestartArmCommand::new(3, ArmKey(99))));
+        let restart = Command::Restart(RestartCommand::new(4, ArmKey(99)));
+        let first = exchange(&mut ci, &restart);
+        assert_eq!(first.status(), CommandStatus::Success);
+        assert!(!ci.running);
+        ci.running = true;
+        assert_eq!(exchange(&mut ci, &restart), first);
+        assert!(ci.running);
+
+        // Only the last command is kept, and only if it isn't idempotent
+        exchange(&mut ci, &Command::Ping(PingCommand::new(5)));
+        assert!(ci.last_reply.is_none());
+        exchange(&mut ci, &restart);
+        assert!(!ci.running);
+    }
+
+    #[test]
+    fn test_unexpected_telemetry() {
+        use std::net::UdpSocket;
+        use tcslibgs::QueryDroppedCommand;
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let addr = ground.local_addr().unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+
+        let mut config = test_config();
+        config.unexpected_telemetry_warning = 3;
+        let mut ci = CommandInterpreter::new(config, vec![]).unwrap();
+        let telemetry = Telemetry::Ping(PingTelemetry::new(1, CommandStatus::Success));
+        for framing in [Framing::Json, Framing::Binary, Framing::Json, Framing::Json] {
+            let data = ProtocolMessage::from_telemetry(telemetry.clone()).to_bytes_with(framing).unwrap();
+            ci.handle_datagram(&data, addr);
+        }
+
+        // Counted apart from undecodable commands, and never answered
+        assert_eq!(ci.unexpected_telemetry, 4);
+        assert_eq!(ci.commands_dropped, 0);
+        assert!(ground.recv(&mut buf).is_err());
+
+        let tm = match ci.process_command(Command::QueryDropped(QueryDroppedCommand::new(1, true))) {
+            Telemetry::QueryDropped(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        assert_eq!(tm.unexpected_telemetry, 4);
+        assert_eq!(ci.unexpected_telemetry, 0);
+    }
+
+    #[test]
+    fn test_binary_framing_follows_command() {
+        use std::net::UdpSocket;
+        use tcslibgs::PingCommand;
+
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let addr = ground.local_addr().unwrap();
+        let mut buf = [0u8; TELEMETRY_MAX_DATAGRAM];
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        for framing in [Framing::Binary, Framing::Json, Framing::Binary] {
+            let command = ProtocolMessage::from_command(Command::Ping(PingCommand::new(6)));
+            ci.handle_datagram(&command.to_bytes_with(framing).unwrap(), addr);
+
+            let len = ground.recv(&mut buf).unwrap();
+            assert_eq!(Framing::detect(&buf[..len]), framing);
+            match ProtocolMessage::from_bytes(&buf[..len]).and_then(ProtocolMessage::into_telemetry).unwrap() {
+                Telemetry::Ping(tm) => assert_eq!(tm.header.sequence, 6),
+                other => panic!("Unexpected telemetry {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_send_detected() {
+        /// Socket that sends at most a fixed number of bytes
+        struct ShortSocket(usize);
+
+        impl DatagramSocket for ShortSocket {
+            fn send_to(&self, data: &[u8], _addr: &SocketAddr) -> io::Result<usize> {
+                Ok(data.len().min(self.0))
+            }
+        }
+
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let data = [0u8; 100];
+        assert!(send_datagram(&ShortSocket(100), &data, &addr).is_ok());
+        match send_datagram(&ShortSocket(60), &data, &addr) {
+            Err(TcsError::Io(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::WriteZero);
+                assert!(e.to_string().contains("60 of 100 bytes"));
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_beacon_destination_intervals() {
+        use std::net::UdpSocket;
+        use tcslibgs::{BeaconTime, QueryBeaconStatusCommand};
+
+        let console = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let archive = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (console_addr, archive_addr) = (console.local_addr().unwrap(), archive.local_addr().unwrap());
+
+        let mut ci = CommandInterpreter::new(test_config(), vec![]).unwrap();
+        let beacon = BeaconSend::new(Duration::from_secs(60), BEACON_BIND_ADDR.parse().unwrap(),
+            vec![console_addr, archive_addr], BeaconFormat::Legacy, 0, ci.start_reason, ci.clock.clone()).unwrap().unwrap();
+        assert!(beacon.set_destination_interval(console_addr, Some(Duration::from_millis(50))));
+        assert!(beacon.set_destination_interval(archive_addr, Some(Duration::from_millis(200))));
+        ci.beacon = Some(beacon);
+        std::thread::sleep(Duration::from_millis(1000));
+
+        let response = ci.process_command(Command::QueryBeaconStatus(QueryBeaconStatusCommand::new(1)));
+        let tm = match response {
+            Telemetry::QueryBeaconStatus(tm) => tm,
+            _ => panic!("Unexpected telemetry type"),
+        };
+        let overrides: Vec<_> = tm.destinations.iter().map(|d| d.interval_override).collect();
+        assert_eq!(overrides, vec![Some(BeaconTime(50)), Some(BeaconTime(200))]);
+
+        // Each destination is sent to at its own rate
+        let (fast, slow) = (tm.destinations[0].sent, tm.destinations[1].sent);
+        assert!((15..=25).contains(&fast), "console got {} beacons", fast);
+        assert!((4..=7).contains(&slow), "archive got {} beacons", slow);
     }
 }
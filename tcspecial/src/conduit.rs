@@ -2,14 +2,21 @@
 //!
 //! Conduits move data between endpoints in one direction.
 
+use std::collections::VecDeque;
 use std::os::unix::io::RawFd;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use tcslibgs::{Statistics, TcsError, TcsResult};
+use std::time::{Duration, Instant};
+use tcslibgs::{BufferPool, ConduitOptions, IoMode, Statistics, TcsError, TcsResult, WriteOrdering};
 
-use crate::config::constants::ENDPOINT_BUFFER_SIZE;
-use crate::endpoint::{EndpointReadable, EndpointWritable, WaitResult};
+use crate::config::constants::{
+    CONTROL_QUEUE_DEPTH, DOWNLINK_BURST, ENDPOINT_BUFFER_SIZE, FAULT_THRESHOLD, FAULT_WINDOW, OVERLAP_LIMIT,
+    STREAM_DRAIN_TIMEOUT, STREAM_EP_DELAY, STREAM_WRITE_TIMEOUT,
+};
+use crate::endpoint::{EndpointReadable, EndpointWaitable, EndpointWritable, WaitResult};
+use crate::latency::LatencyHistogram;
+use crate::rate_limit::RateLimiter;
 
 /// Direction of data flow in a conduit
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,8 +25,23 @@ pub enum ConduitDirection {
     GroundToPayload,
     /// Payload to ground
     PayloadToGround,
+    /// Both directions, serviced in turn by a single thread
+    Bidirectional,
 }
 
+/// Longest a non-blocking conduit waits before checking it is still running
+const POLL_MS: i32 = 1000;
+
+/// Longest a fair conduit blocks on one direction when both are idle
+const FAIR_POLL_MS: i32 = 10;
+
+/// Longest a paused conduit, or one with nowhere to send, waits for a
+/// command before checking whether it can relay again
+const PAUSE_POLL: Duration = Duration::from_millis(10);
+
+/// How long a conduit waits before retrying a write to a full stream
+const WRITE_RETRY: Duration = Duration::from_millis(1);
+
 /// Command for conduit control
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConduitCommand {
@@ -27,12 +49,368 @@ pub enum ConduitCommand {
     Stop,
     /// Get statistics
     GetStats,
+    /// Half-close the payload connection, relay what the payload still
+    /// sends, then stop
+    Drain,
+    /// Write the bytes waiting in the control queue to the payload
+    Control,
+}
+
+impl ConduitCommand {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            ConduitCommand::Stop => 0,
+            ConduitCommand::GetStats => 1,
+            ConduitCommand::Drain => 2,
+            ConduitCommand::Control => 3,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ConduitCommand::Stop),
+            1 => Some(ConduitCommand::GetStats),
+            2 => Some(ConduitCommand::Drain),
+            3 => Some(ConduitCommand::Control),
+            _ => None,
+        }
+    }
+}
+
+/// Decides when conduit I/O errors amount to a fault
+///
+/// Errors must be consecutive, number at least the threshold and persist for
+/// at least the window, so isolated errors recover silently while sustained
+/// failure faults the data handler. Any successful transfer resets the count.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultDetector {
+    threshold: u32,
+    window: Duration,
+    consecutive: u32,
+    first_error: Option<Instant>,
+}
+
+impl FaultDetector {
+    pub fn new(threshold: u32, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            consecutive: 0,
+            first_error: None,
+        }
+    }
+
+    /// Create a detector from conduit options, using defaults for unset values
+    pub fn from_options(options: &ConduitOptions) -> Self {
+        Self::new(
+            options.fault_threshold.unwrap_or(FAULT_THRESHOLD),
+            options.fault_window_ms.map_or(FAULT_WINDOW, Duration::from_millis),
+        )
+    }
+
+    /// Record the outcome of a transfer, returning true if this is a fault
+    pub fn record(&mut self, ok: bool) -> bool {
+        if ok {
+            self.consecutive = 0;
+            self.first_error = None;
+            return false;
+        }
+
+        self.consecutive += 1;
+        let first_error = *self.first_error.get_or_insert_with(Instant::now);
+        self.consecutive >= self.threshold && first_error.elapsed() >= self.window
+    }
+}
+
+impl Default for FaultDetector {
+    fn default() -> Self {
+        Self::new(FAULT_THRESHOLD, FAULT_WINDOW)
+    }
+}
+
+/// Conduit settings that can change while the conduits run
+///
+/// A data handler's conduits share one, so CONFIG_DH reaches all of them at
+/// once. Zero means no setting, leaving reads and writes their full size.
+#[derive(Default)]
+pub struct LiveSettings {
+    read_size: AtomicUsize,
+    write_size: AtomicUsize,
+    stream_delay_ms: AtomicU64,
+    coalesce_ms: AtomicU64,
+    /// Limit on the data handler's throughput and the rate it was made for
+    rate_limit: Mutex<Option<(u64, Arc<RateLimiter>)>>,
+}
+
+impl LiveSettings {
+    pub fn from_options(options: &ConduitOptions) -> Self {
+        let settings = Self::default();
+        settings.apply(options);
+        settings
+    }
+
+    /// Take up the settings in options
+    ///
+    /// An unchanged rate limit keeps its bucket, so reapplying the same
+    /// options doesn't reset a data handler's budget.
+    pub fn apply(&self, options: &ConduitOptions) {
+        self.read_size.store(options.read_buffer_size.unwrap_or(0), Ordering::SeqCst);
+        self.write_size.store(options.write_buffer_size.unwrap_or(0), Ordering::SeqCst);
+        self.stream_delay_ms.store(options.stream_delay_ms.unwrap_or(0), Ordering::SeqCst);
+        self.coalesce_ms.store(options.coalesce_ms.unwrap_or(0), Ordering::SeqCst);
+
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        if rate_limit.as_ref().map(|(rate, _)| *rate) != options.rate_limit_bps {
+            *rate_limit = options
+                .rate_limit_bps
+                .map(|rate| (rate, Arc::new(RateLimiter::new(rate, DOWNLINK_BURST))));
+        }
+    }
+
+    /// Get the most to read at once into a buffer of len bytes
+    fn read_size(&self, len: usize) -> usize {
+        match self.read_size.load(Ordering::SeqCst) {
+            0 => len,
+            size => size.min(len),
+        }
+    }
+
+    /// Get the most to give a stream in one write, if limited
+    fn write_size(&self) -> Option<usize> {
+        Some(self.write_size.load(Ordering::SeqCst)).filter(|&size| size != 0)
+    }
+
+    fn stream_delay(&self) -> Duration {
+        Duration::from_millis(self.stream_delay_ms.load(Ordering::SeqCst))
+    }
+
+    /// Get how long a stream must be quiet before its data is relayed, if
+    /// reads are coalesced
+    fn coalesce(&self) -> Option<Duration> {
+        Some(self.coalesce_ms.load(Ordering::SeqCst)).filter(|&ms| ms != 0).map(Duration::from_millis)
+    }
+
+    fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.rate_limit.lock().unwrap().as_ref().map(|(_, limiter)| limiter.clone())
+    }
+}
+
+/// Bytes waiting to be written to the payload apart from relayed data
+///
+/// Shared by a data handler and its ground-to-payload conduit, which writes
+/// them through its payload writer before its next read, so they reach the
+/// payload over the connection already relaying to it.
+#[derive(Debug, Default)]
+pub struct ControlQueue {
+    pending: Mutex<VecDeque<QueuedControl>>,
+    next_id: AtomicU64,
+}
+
+/// Bytes in a ControlQueue and where to say whether they were written
+#[derive(Debug)]
+struct QueuedControl {
+    id: u64,
+    data: Vec<u8>,
+    written: mpsc::Sender<bool>,
+}
+
+/// Word from the conduit on bytes queued by ControlQueue::push
+pub struct ControlReceipt {
+    id: u64,
+    written: mpsc::Receiver<bool>,
+    queue: Arc<ControlQueue>,
+}
+
+impl ControlQueue {
+    /// Queue data for the payload
+    ///
+    /// Returns TcsError::Timeout if CONTROL_QUEUE_DEPTH writes are already
+    /// waiting for a payload that isn't taking them.
+    pub fn push(self: &Arc<Self>, data: Vec<u8>) -> TcsResult<ControlReceipt> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= CONTROL_QUEUE_DEPTH {
+            return Err(TcsError::Timeout);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (written, receiver) = mpsc::channel();
+        pending.push_back(QueuedControl { id, data, written });
+        Ok(ControlReceipt { id, written: receiver, queue: self.clone() })
+    }
+
+    /// Take the oldest data waiting, if any
+    fn pop(&self) -> Option<QueuedControl> {
+        self.pending.lock().unwrap().pop_front()
+    }
+}
+
+impl ControlReceipt {
+    /// Wait for the bytes to be written
+    ///
+    /// Returns TcsError::Timeout, with the bytes dropped from the queue, if
+    /// no conduit has taken them in time. Once taken, the write is waited
+    /// for however long it takes, which the conduit limits, and an error is
+    /// returned if it fails.
+    pub fn wait(self, timeout: Duration) -> TcsResult<()> {
+        let written = match self.written.recv_timeout(timeout) {
+            Ok(written) => written,
+            Err(mpsc::RecvTimeoutError::Timeout) if self.withdraw() => return Err(TcsError::Timeout),
+            Err(mpsc::RecvTimeoutError::Timeout) => self.written.recv().unwrap_or(false),
+            Err(mpsc::RecvTimeoutError::Disconnected) => false,
+        };
+        if written {
+            Ok(())
+        } else {
+            Err(TcsError::Endpoint("Control bytes could not be written to the payload".to_string()))
+        }
+    }
+
+    /// Drop the bytes from the queue if no conduit has taken them yet,
+    /// returning true if they were dropped
+    pub fn withdraw(&self) -> bool {
+        let mut pending = self.queue.pending.lock().unwrap();
+        match pending.iter().position(|queued| queued.id == self.id) {
+            Some(index) => pending.remove(index).is_some(),
+            None => false,
+        }
+    }
+}
+
+/// Token a loopback is waiting for the payload to echo
+struct EchoWait {
+    token: Vec<u8>,
+    /// Tail of what the payload has sent, too short to hold the token
+    seen: Vec<u8>,
+    echoed: mpsc::Sender<Instant>,
+}
+
+/// Watch for a token in what the payload sends
+///
+/// Shared by a data handler and the conduit reading its payload, whose reads
+/// are checked for the token on their way to the OC. The token may arrive
+/// split across reads or among other data.
+#[derive(Default)]
+pub struct EchoWatch {
+    armed: AtomicBool,
+    waiting: Mutex<Option<EchoWait>>,
+}
+
+impl EchoWatch {
+    /// Start watching for token, returning a receiver given the time it is
+    /// seen
+    ///
+    /// Only one token is watched for at a time; returns an error if another
+    /// is still being waited for.
+    pub fn watch(&self, token: Vec<u8>) -> TcsResult<mpsc::Receiver<Instant>> {
+        let mut waiting = self.waiting.lock().unwrap();
+        if waiting.is_some() {
+            return Err(TcsError::DataHandler("A loopback is already waiting for its echo".to_string()));
+        }
+        let (echoed, receiver) = mpsc::channel();
+        *waiting = Some(EchoWait { token, seen: Vec::new(), echoed });
+        self.armed.store(true, Ordering::SeqCst);
+        Ok(receiver)
+    }
+
+    /// Stop watching for the token, if it hasn't been seen
+    pub fn cancel(&self) {
+        self.armed.store(false, Ordering::SeqCst);
+        *self.waiting.lock().unwrap() = None;
+    }
+
+    /// Look for the token in data read from the payload
+    fn observe(&self, data: &[u8]) {
+        if data.is_empty() || !self.armed.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut waiting = self.waiting.lock().unwrap();
+        let Some(wait) = waiting.as_mut() else { return };
+        wait.seen.extend_from_slice(data);
+        if wait.seen.windows(wait.token.len()).any(|window| window == wait.token) {
+            let _ = wait.echoed.send(Instant::now());
+            self.armed.store(false, Ordering::SeqCst);
+            *waiting = None;
+        } else {
+            let keep = wait.token.len().saturating_sub(1);
+            let excess = wait.seen.len().saturating_sub(keep);
+            wait.seen.drain(..excess);
+        }
+    }
+}
+
+/// Payload reader that shows what it reads to an EchoWatch
+pub struct EchoTap {
+    inner: Box<dyn EndpointReadable + Send>,
+    echo: Arc<EchoWatch>,
+}
+
+impl EchoTap {
+    pub fn new(inner: Box<dyn EndpointReadable + Send>, echo: Arc<EchoWatch>) -> Self {
+        Self { inner, echo }
+    }
+}
+
+impl EndpointWaitable for EchoTap {
+    fn io_fd(&self) -> RawFd {
+        self.inner.io_fd()
+    }
+
+    fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+        self.inner.wait_for_event(cmd_fd, timeout_ms)
+    }
+
+    fn set_blocking(&self, blocking: bool) -> TcsResult<()> {
+        self.inner.set_blocking(blocking)
+    }
+}
+
+impl EndpointReadable for EchoTap {
+    fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+        let n = self.inner.read(buffer)?;
+        self.echo.observe(&buffer[..n]);
+        Ok(n)
+    }
+
+    fn is_datagram(&self) -> bool {
+        self.inner.is_datagram()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
 }
 
 /// Conduit thread state
 pub struct Conduit {
     direction: ConduitDirection,
     running: Arc<AtomicBool>,
+    faulted: Arc<AtomicBool>,
+    /// The payload closed its end of the connection, ending the conduit
+    payload_closed: Arc<AtomicBool>,
+    /// Data is left waiting at the reader while set
+    paused: Arc<AtomicBool>,
+    /// Set to have the thread discard the statistics gathered so far
+    reset_stats: Arc<AtomicBool>,
+    fault_detector: FaultDetector,
+    io_mode: IoMode,
+    write_ordering: WriteOrdering,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Where the time taken by each write is recorded, if anywhere
+    write_latency: Option<Arc<Mutex<LatencyHistogram>>>,
+    /// When data was last written, which may be shared with other conduits
+    last_transfer: Arc<Mutex<Option<Instant>>>,
+    /// The thread's statistics as of its last wait for I/O
+    live_stats: Arc<Mutex<Statistics>>,
+    settings: Arc<LiveSettings>,
+    /// Bytes to write to the payload ahead of relayed data, if any
+    control: Option<Arc<ControlQueue>>,
+    /// Endpoints given to new, kept until run starts the thread with them
+    endpoints: Option<(Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>)>,
+    /// Payload-to-ground endpoints a fair conduit services alongside its own
+    downlink: Option<(Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>)>,
+    /// When a drain was requested, so stop waits for the thread to finish it
+    draining: Option<Instant>,
+    /// A stop was requested, so stop need only wait for the thread
+    stopping: bool,
     thread_handle: Option<JoinHandle<TcsResult<Statistics>>>,
     cmd_pipe_write: RawFd,
 }
@@ -41,8 +419,8 @@ impl Conduit {
     /// Create a new conduit
     pub fn new(
         direction: ConduitDirection,
-        _reader: Box<dyn EndpointReadable + Send>,
-        _writer: Box<dyn EndpointWritable + Send>,
+        reader: Box<dyn EndpointReadable + Send>,
+        writer: Box<dyn EndpointWritable + Send>,
         _cmd_pipe_read: RawFd,
         cmd_pipe_write: RawFd,
     ) -> Self {
@@ -51,60 +429,246 @@ impl Conduit {
         Self {
             direction,
             running,
+            faulted: Arc::new(AtomicBool::new(false)),
+            payload_closed: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            reset_stats: Arc::new(AtomicBool::new(false)),
+            fault_detector: FaultDetector::default(),
+            io_mode: IoMode::default(),
+            write_ordering: WriteOrdering::default(),
+            rate_limiter: None,
+            write_latency: None,
+            last_transfer: Arc::default(),
+            live_stats: Arc::default(),
+            settings: Arc::default(),
+            control: None,
+            endpoints: Some((reader, writer)),
+            downlink: None,
+            draining: None,
+            stopping: false,
             thread_handle: None,
             cmd_pipe_write,
         }
     }
 
+    /// Use the given detector to decide when I/O errors fault the conduit
+    pub fn with_fault_detector(mut self, fault_detector: FaultDetector) -> Self {
+        self.fault_detector = fault_detector;
+        self
+    }
+
+    /// Use blocking or non-blocking reads; only start honors this
+    pub fn with_io_mode(mut self, io_mode: IoMode) -> Self {
+        self.io_mode = io_mode;
+        self
+    }
+
+    /// Drain each write before the next read, or let reads overlap slow
+    /// stream writes; only start honors this
+    pub fn with_write_ordering(mut self, write_ordering: WriteOrdering) -> Self {
+        self.write_ordering = write_ordering;
+        self
+    }
+
+    /// Share a limit on throughput with other conduits; for a fair conduit
+    /// this applies to the payload-to-ground direction only
+    pub fn with_rate_limiter(mut self, rate_limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Record the time taken by each write in a histogram, which may be
+    /// shared with other conduits
+    pub fn with_write_latency(mut self, write_latency: Option<Arc<Mutex<LatencyHistogram>>>) -> Self {
+        self.write_latency = write_latency;
+        self
+    }
+
+    /// Record when data was last written in last_transfer, which may be
+    /// shared with other conduits
+    pub fn with_last_transfer(mut self, last_transfer: Arc<Mutex<Option<Instant>>>) -> Self {
+        self.last_transfer = last_transfer;
+        self
+    }
+
+    /// Take read and write sizes, stream delay, coalescing and rate limit
+    /// from settings,
+    /// which may be shared with other conduits and changed while they run
+    pub fn with_settings(mut self, settings: Arc<LiveSettings>) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Write the bytes queued in control to the payload before each read;
+    /// only a ground-to-payload or bidirectional conduit has a payload to
+    /// write them to
+    pub fn with_control(mut self, control: Arc<ControlQueue>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Give a bidirectional conduit the payload-to-ground endpoints it
+    /// services alongside those given to new
+    pub fn with_downlink(
+        mut self,
+        reader: Box<dyn EndpointReadable + Send>,
+        writer: Box<dyn EndpointWritable + Send>,
+    ) -> Self {
+        self.downlink = Some((reader, writer));
+        self
+    }
+
+    /// Start the conduit thread on the endpoints given to new, and to
+    /// with_downlink for a bidirectional conduit
+    pub fn run(&mut self, cmd_fd: RawFd) -> TcsResult<()> {
+        let (reader, writer) = self
+            .endpoints
+            .take()
+            .ok_or_else(|| TcsError::DataHandler("Conduit endpoints already used".to_string()))?;
+        match self.direction {
+            ConduitDirection::Bidirectional => {
+                let (p2g_reader, p2g_writer) = self
+                    .downlink
+                    .take()
+                    .ok_or_else(|| TcsError::DataHandler("No downlink endpoints".to_string()))?;
+                self.start_fair(reader, writer, p2g_reader, p2g_writer, cmd_fd)
+            }
+            _ => self.start(reader, writer, cmd_fd),
+        }
+    }
+
     /// Start the conduit thread
+    ///
+    /// In blocking mode the thread waits indefinitely for data or a command,
+    /// so only the command pipe written by stop wakes it to exit, except
+    /// while overlapped writes are held, when it wakes to finish them.
     pub fn start(&mut self, mut reader: Box<dyn EndpointReadable + Send>, mut writer: Box<dyn EndpointWritable + Send>, cmd_fd: RawFd) -> TcsResult<()> {
         if self.running.load(Ordering::SeqCst) {
             return Err(TcsError::DataHandler("Conduit already running".to_string()));
         }
 
+        let timeout_ms = match self.io_mode {
+            IoMode::NonBlocking => POLL_MS,
+            IoMode::Blocking => {
+                reader.set_blocking(true)?;
+                -1
+            }
+        };
+
+        if self.write_ordering == WriteOrdering::Overlap && !writer.is_datagram() {
+            writer = Box::new(OverlapWriter::new(writer));
+        }
+
         let running = self.running.clone();
         running.store(true, Ordering::SeqCst);
+        let faulted = self.faulted.clone();
+        let payload_closed = self.payload_closed.clone();
+        let mut fault_detector = self.fault_detector;
+        let rate_limiter = self.rate_limiter.clone();
+        let direction = self.direction;
+        let paused = self.paused.clone();
+        let reset_stats = self.reset_stats.clone();
+        let write_latency = self.write_latency.clone();
+        let last_transfer = self.last_transfer.clone();
+        let live_stats = self.live_stats.clone();
+        let settings = self.settings.clone();
+        let control = self.control.clone();
 
         let handle = thread::spawn(move || {
             let mut stats = Statistics::new();
-            let mut buffer = vec![0u8; ENDPOINT_BUFFER_SIZE];
+            let mut buffer = BufferPool::global().take(ENDPOINT_BUFFER_SIZE);
 
             while running.load(Ordering::SeqCst) {
+                *live_stats.lock().unwrap() = stats;
+
+                // Control bytes go out even while paused
+                if let Some(control) = &control {
+                    if !write_control(control, writer.as_mut(), &settings, &last_transfer)
+                        && fault_detector.record(false)
+                    {
+                        faulted.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                }
+
+                // With nowhere to send, data waits at the reader as if
+                // paused, and only commands are waited for
+                let hold = paused.load(Ordering::SeqCst) || !writer.is_connected();
+
+                // Finish what overlapped writes left, waking soon to carry on
+                // if the stream still won't take it all
+                let held = if hold {
+                    false
+                } else {
+                    match writer.flush() {
+                        Ok(held) => held,
+                        Err(_) => {
+                            stats.writes_failed += 1;
+                            if fault_detector.record(false) {
+                                faulted.store(true, Ordering::SeqCst);
+                                break;
+                            }
+                            false
+                        }
+                    }
+                };
+                let timeout_ms = if held { FAIR_POLL_MS } else { timeout_ms };
+
                 // Wait for I/O or command
-                match reader.wait_for_event(cmd_fd, 1000) {
+                let event = if hold { wait_for_command(cmd_fd) } else { reader.wait_for_event(cmd_fd, timeout_ms) };
+                if reset_stats.swap(false, Ordering::SeqCst) {
+                    stats = Statistics::new();
+                }
+                match event {
                     Ok(WaitResult::CommandPending) | Ok(WaitResult::Both) => {
-                        // Read command byte from pipe
-                        let mut cmd_buf = [0u8; 1];
-                        unsafe {
-                            libc::read(cmd_fd, cmd_buf.as_mut_ptr() as *mut libc::c_void, 1);
-                        }
-                        // Check if we should stop
-                        if cmd_buf[0] == 0 {
-                            break;
+                        match read_command(cmd_fd) {
+                            Some(ConduitCommand::Stop) => break,
+                            // The uplink ends by half-closing the payload; the
+                            // downlink carries on until the payload closes too,
+                            // if it has anywhere to send
+                            Some(ConduitCommand::Drain) => {
+                                if direction == ConduitDirection::GroundToPayload {
+                                    let _ = writer.shutdown_write();
+                                } else if writer.is_connected() {
+                                    drain(
+                                        reader.as_mut(),
+                                        writer.as_mut(),
+                                        &mut buffer,
+                                        &mut stats,
+                                        Throttle::new(rate_limiter.as_deref(), &running),
+                                        write_latency.as_deref(),
+                                        &settings,
+                                    );
+                                }
+                                break;
+                            }
+                            _ => {}
                         }
                     }
+                    // Data that arrived while waiting stays put if paused or
+                    // disconnected meanwhile
+                    Ok(WaitResult::IoReady) if paused.load(Ordering::SeqCst) || !writer.is_connected() => continue,
                     Ok(WaitResult::IoReady) => {
-                        // Read from source
-                        match reader.read(&mut buffer) {
-                            Ok(0) => continue,
-                            Ok(n) => {
-                                stats.bytes_received += n as u64;
-                                stats.reads_completed += 1;
-
-                                // Write to destination
-                                match writer.write(&buffer[..n]) {
-                                    Ok(written) => {
-                                        stats.bytes_sent += written as u64;
-                                        stats.writes_completed += 1;
-                                    }
-                                    Err(_) => {
-                                        stats.writes_failed += 1;
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                stats.reads_failed += 1;
-                            }
+                        let sent = stats.bytes_sent;
+                        let ok = relay_once(
+                            reader.as_mut(),
+                            writer.as_mut(),
+                            &mut buffer,
+                            &mut stats,
+                            Throttle::new(rate_limiter.as_deref(), &running),
+                            write_latency.as_deref(),
+                            &settings,
+                        );
+                        if stats.bytes_sent != sent {
+                            *last_transfer.lock().unwrap() = Some(Instant::now());
+                        }
+                        if fault_detector.record(ok) {
+                            faulted.store(true, Ordering::SeqCst);
+                            break;
+                        }
+                        if direction == ConduitDirection::PayloadToGround && reader.is_closed() {
+                            payload_closed.store(true, Ordering::SeqCst);
+                            break;
                         }
                     }
                     Ok(WaitResult::Timeout) => continue,
@@ -114,28 +678,240 @@ impl Conduit {
                 }
             }
 
-            Ok(stats.with_timestamp())
+            finish_writes(writer.as_mut());
+            if reset_stats.swap(false, Ordering::SeqCst) {
+                stats = Statistics::new();
+            }
+            Ok(stats)
+        });
+
+        self.thread_handle = Some(handle);
+        Ok(())
+    }
+
+    /// Start a single thread servicing both directions in round-robin order
+    ///
+    /// Each direction gets at most one read per round, so a saturated
+    /// direction cannot starve the other. The returned statistics carry the
+    /// receive counters of the ground-to-payload direction and the send
+    /// counters of the payload-to-ground direction.
+    pub fn start_fair(
+        &mut self,
+        mut g2p_reader: Box<dyn EndpointReadable + Send>,
+        mut g2p_writer: Box<dyn EndpointWritable + Send>,
+        mut p2g_reader: Box<dyn EndpointReadable + Send>,
+        mut p2g_writer: Box<dyn EndpointWritable + Send>,
+        cmd_fd: RawFd,
+    ) -> TcsResult<()> {
+        if self.running.load(Ordering::SeqCst) {
+            return Err(TcsError::DataHandler("Conduit already running".to_string()));
+        }
+
+        let running = self.running.clone();
+        running.store(true, Ordering::SeqCst);
+        let faulted = self.faulted.clone();
+        let payload_closed = self.payload_closed.clone();
+        let mut fault_detector = self.fault_detector;
+        let rate_limiter = self.rate_limiter.clone();
+        let paused = self.paused.clone();
+        let reset_stats = self.reset_stats.clone();
+        let write_latency = self.write_latency.clone();
+        let last_transfer = self.last_transfer.clone();
+        let live_stats = self.live_stats.clone();
+        let settings = self.settings.clone();
+        let control = self.control.clone();
+
+        let handle = thread::spawn(move || {
+            let mut g2p_stats = Statistics::new();
+            let mut p2g_stats = Statistics::new();
+            let mut buffer = BufferPool::global().take(ENDPOINT_BUFFER_SIZE);
+            let mut g2p_first = true;
+
+            'outer: while running.load(Ordering::SeqCst) {
+                *live_stats.lock().unwrap() = fair_stats(&g2p_stats, &p2g_stats);
+
+                if let Some(control) = &control {
+                    if !write_control(control, g2p_writer.as_mut(), &settings, &last_transfer)
+                        && fault_detector.record(false)
+                    {
+                        faulted.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                }
+
+                // Commands still come through while paused
+                if paused.load(Ordering::SeqCst) {
+                    if !matches!(wait_for_command(cmd_fd), Ok(WaitResult::CommandPending)) {
+                        continue;
+                    }
+                    match read_command(cmd_fd) {
+                        Some(ConduitCommand::Stop) => break,
+                        Some(ConduitCommand::Drain) => {
+                            let _ = g2p_writer.shutdown_write();
+                            if p2g_writer.is_connected() {
+                                drain(
+                                    p2g_reader.as_mut(),
+                                    p2g_writer.as_mut(),
+                                    &mut buffer,
+                                    &mut p2g_stats,
+                                    Throttle::new(rate_limiter.as_deref(), &running),
+                                    write_latency.as_deref(),
+                                    &settings,
+                                );
+                            }
+                            break;
+                        }
+                        _ => continue,
+                    }
+                }
+
+                let mut idle = true;
+                // Downlink with nowhere to send waits at the reader, and
+                // uplink is waited on in its place
+                let p2g_connected = p2g_writer.is_connected();
+
+                for turn in 0..2 {
+                    let g2p = (turn == 0) == g2p_first;
+                    if !g2p && !p2g_connected {
+                        continue;
+                    }
+                    let reader = if g2p { g2p_reader.as_mut() } else { p2g_reader.as_mut() };
+                    // Only block on the second direction, and only if the round was idle
+                    let timeout = if (turn == 1 && idle) || !p2g_connected { FAIR_POLL_MS } else { 0 };
+
+                    let event = reader.wait_for_event(cmd_fd, timeout);
+                    if reset_stats.swap(false, Ordering::SeqCst) {
+                        g2p_stats = Statistics::new();
+                        p2g_stats = Statistics::new();
+                    }
+                    match event {
+                        Ok(WaitResult::CommandPending) | Ok(WaitResult::Both) => {
+                            match read_command(cmd_fd) {
+                                Some(ConduitCommand::Stop) => break 'outer,
+                                Some(ConduitCommand::Drain) => {
+                                    let _ = g2p_writer.shutdown_write();
+                                    drain(
+                                        p2g_reader.as_mut(),
+                                        p2g_writer.as_mut(),
+                                        &mut buffer,
+                                        &mut p2g_stats,
+                                        Throttle::new(rate_limiter.as_deref(), &running),
+                                        write_latency.as_deref(),
+                                        &settings,
+                                    );
+                                    break 'outer;
+                                }
+                                _ => {}
+                            }
+                        }
+                        Ok(WaitResult::IoReady) if paused.load(Ordering::SeqCst) => continue 'outer,
+                        Ok(WaitResult::IoReady) if !g2p && !p2g_writer.is_connected() => continue 'outer,
+                        Ok(WaitResult::IoReady) => {
+                            idle = false;
+                            let sent = g2p_stats.bytes_sent + p2g_stats.bytes_sent;
+                            let ok = if g2p {
+                                relay_once(
+                                    reader,
+                                    g2p_writer.as_mut(),
+                                    &mut buffer,
+                                    &mut g2p_stats,
+                                    Throttle::new(None, &running),
+                                    write_latency.as_deref(),
+                                    &settings,
+                                )
+                            } else {
+                                relay_once(
+                                    reader,
+                                    p2g_writer.as_mut(),
+                                    &mut buffer,
+                                    &mut p2g_stats,
+                                    Throttle::new(rate_limiter.as_deref(), &running),
+                                    write_latency.as_deref(),
+                                    &settings,
+                                )
+                            };
+                            if g2p_stats.bytes_sent + p2g_stats.bytes_sent != sent {
+                                *last_transfer.lock().unwrap() = Some(Instant::now());
+                            }
+                            if fault_detector.record(ok) {
+                                faulted.store(true, Ordering::SeqCst);
+                                break 'outer;
+                            }
+                            if !g2p && reader.is_closed() {
+                                payload_closed.store(true, Ordering::SeqCst);
+                                break 'outer;
+                            }
+                        }
+                        Ok(WaitResult::Timeout) => {}
+                        Ok(WaitResult::Error) | Err(_) => {
+                            break 'outer;
+                        }
+                    }
+                }
+
+                g2p_first = !g2p_first;
+            }
+
+            if reset_stats.swap(false, Ordering::SeqCst) {
+                g2p_stats = Statistics::new();
+                p2g_stats = Statistics::new();
+            }
+
+            Ok(fair_stats(&g2p_stats, &p2g_stats))
         });
 
         self.thread_handle = Some(handle);
         Ok(())
     }
 
+    /// Ask the conduit thread to finish gracefully
+    ///
+    /// The thread half-closes the payload connection and, on the downlink,
+    /// relays whatever the payload sends until it closes its side or
+    /// STREAM_DRAIN_TIMEOUT passes. Conduits sharing a command pipe must all
+    /// be asked before any is stopped, since any thread may take the request.
+    /// Stop then waits for the drain to finish, but for no longer than the
+    /// drain and its last write can take.
+    pub fn begin_drain(&mut self) {
+        self.resume();
+        self.draining = Some(Instant::now());
+        self.send_command(ConduitCommand::Drain);
+    }
+
+    /// Ask the conduit thread to stop without waiting for it
+    ///
+    /// Conduits sharing a command pipe must all be asked before any is
+    /// stopped, since any thread may take the request; a thread blocked
+    /// waiting for data would otherwise never see one of its own.
+    pub fn request_stop(&mut self) {
+        if self.draining.is_none() && !self.stopping {
+            self.stopping = true;
+            self.running.store(false, Ordering::SeqCst);
+            self.send_command(ConduitCommand::Stop);
+        }
+    }
+
     /// Stop the conduit thread
     pub fn stop(&mut self) -> TcsResult<Statistics> {
-        self.running.store(false, Ordering::SeqCst);
-
-        // Send stop command through pipe
-        let cmd = [0u8; 1];
-        unsafe {
-            libc::write(self.cmd_pipe_write, cmd.as_ptr() as *const libc::c_void, 1);
+        if let (Some(asked), Some(handle)) = (self.draining, &self.thread_handle) {
+            let deadline = asked + STREAM_DRAIN_TIMEOUT + STREAM_WRITE_TIMEOUT;
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(PAUSE_POLL);
+            }
+            // A thread that never took the drain is stopped outright
+            if !handle.is_finished() {
+                self.draining = None;
+            }
         }
+        self.request_stop();
 
-        if let Some(handle) = self.thread_handle.take() {
+        let stats = if let Some(handle) = self.thread_handle.take() {
             handle.join().map_err(|_| TcsError::DataHandler("Thread join failed".to_string()))?
         } else {
             Ok(Statistics::new())
-        }
+        };
+        self.running.store(false, Ordering::SeqCst);
+        stats
     }
 
     /// Check if the conduit is running
@@ -143,26 +919,1664 @@ impl Conduit {
         self.running.load(Ordering::SeqCst)
     }
 
+    /// Stop relaying, leaving data waiting at the reader until resumed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Relay again after a pause
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Discard the statistics gathered so far
+    ///
+    /// The thread zeroes its counters after its current wait, so what it
+    /// relays from then on is counted and what it relayed before is not.
+    pub fn reset_stats(&self) {
+        self.reset_stats.store(true, Ordering::SeqCst);
+        *self.live_stats.lock().unwrap() = Statistics::new();
+    }
+
+    /// Get the statistics of a running conduit as of its last wait for I/O
+    ///
+    /// Those of a stopped conduit are returned by stop instead.
+    pub fn statistics(&self) -> Statistics {
+        *self.live_stats.lock().unwrap()
+    }
+
+    /// Check if the conduit is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Check if sustained I/O errors stopped the conduit
+    pub fn is_faulted(&self) -> bool {
+        self.faulted.load(Ordering::SeqCst)
+    }
+
+    /// Check if the payload closing its end of the connection stopped the
+    /// conduit
+    pub fn is_payload_closed(&self) -> bool {
+        self.payload_closed.load(Ordering::SeqCst)
+    }
+
+    /// Get when the conduit, or another sharing its timestamp, last wrote
+    /// data, if it ever has
+    pub fn last_transfer(&self) -> Option<Instant> {
+        *self.last_transfer.lock().unwrap()
+    }
+
     /// Get the conduit direction
     pub fn direction(&self) -> ConduitDirection {
         self.direction
     }
-}
 
-impl Drop for Conduit {
-    fn drop(&mut self) {
-        if self.is_running() {
-            let _ = self.stop();
+    /// Wake the conduit thread to write what is waiting in its control queue
+    pub fn notify_control(&self) {
+        self.send_command(ConduitCommand::Control);
+    }
+
+    /// Send a command to the conduit thread through the pipe
+    fn send_command(&self, command: ConduitCommand) {
+        let cmd = [command.to_u8()];
+        unsafe {
+            libc::write(self.cmd_pipe_write, cmd.as_ptr() as *const libc::c_void, 1);
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Combine the statistics of a fair conduit's two directions: receive
+/// counters from ground-to-payload and send counters from payload-to-ground
+fn fair_stats(g2p_stats: &Statistics, p2g_stats: &Statistics) -> Statistics {
+    Statistics {
+        bytes_received: g2p_stats.bytes_received,
+        reads_completed: g2p_stats.reads_completed,
+        reads_failed: g2p_stats.reads_failed,
+        messages_received: g2p_stats.messages_received,
+        bytes_sent: p2g_stats.bytes_sent,
+        writes_completed: p2g_stats.writes_completed,
+        writes_failed: p2g_stats.writes_failed,
+        messages_sent: p2g_stats.messages_sent,
+        ..Statistics::new()
+    }
+}
 
-    #[test]
-    fn test_conduit_direction() {
-        assert_ne!(ConduitDirection::GroundToPayload, ConduitDirection::PayloadToGround);
+/// Shared rate limit a conduit waits on before each write, if any
+///
+/// The wait ends early once running is cleared, so a conduit held back by a
+/// low limit still stops promptly.
+#[derive(Clone, Copy)]
+struct Throttle<'a> {
+    rate_limiter: Option<&'a RateLimiter>,
+    running: &'a AtomicBool,
+}
+
+impl<'a> Throttle<'a> {
+    fn new(rate_limiter: Option<&'a RateLimiter>, running: &'a AtomicBool) -> Self {
+        Self { rate_limiter, running }
+    }
+
+    /// Wait until bytes may be written under the shared limit and the one in
+    /// settings, returning false if told to stop first
+    fn wait(&self, bytes: usize, settings: &LiveSettings) -> bool {
+        self.rate_limiter.is_none_or(|limiter| limiter.acquire(bytes, self.running))
+            && settings.rate_limiter().is_none_or(|limiter| limiter.acquire(bytes, self.running))
+    }
+}
+
+/// Wait up to PAUSE_POLL for a command, for a conduit holding its data
+/// at the reader
+fn wait_for_command(cmd_fd: RawFd) -> TcsResult<WaitResult> {
+    let mut poll_fd = libc::pollfd {
+        fd: cmd_fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut poll_fd, 1, PAUSE_POLL.as_millis() as i32) } {
+        0 => Ok(WaitResult::Timeout),
+        n if n > 0 && poll_fd.revents & libc::POLLIN != 0 => Ok(WaitResult::CommandPending),
+        n if n > 0 => Ok(WaitResult::Error),
+        _ => match std::io::Error::last_os_error() {
+            e if e.kind() == std::io::ErrorKind::Interrupted => Ok(WaitResult::Timeout),
+            e => Err(TcsError::Io(e)),
+        },
+    }
+}
+
+/// Read the command waiting in a conduit's command pipe
+fn read_command(cmd_fd: RawFd) -> Option<ConduitCommand> {
+    let mut cmd_buf = [0u8; 1];
+    if unsafe { libc::read(cmd_fd, cmd_buf.as_mut_ptr() as *mut libc::c_void, 1) } != 1 {
+        return None;
+    }
+    ConduitCommand::from_u8(cmd_buf[0])
+}
+
+/// Relay what the reader still has until it reaches end of file, fails, or
+/// STREAM_DRAIN_TIMEOUT passes
+///
+/// Only the reader is waited on, so requests for other conduits left in a
+/// shared command pipe are not consumed.
+fn drain(
+    reader: &mut (dyn EndpointReadable + Send),
+    writer: &mut (dyn EndpointWritable + Send),
+    buffer: &mut [u8],
+    stats: &mut Statistics,
+    throttle: Throttle,
+    write_latency: Option<&Mutex<LatencyHistogram>>,
+    settings: &LiveSettings,
+) {
+    let deadline = Instant::now() + STREAM_DRAIN_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd: reader.io_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll_fd, 1, remaining.as_millis() as i32) } <= 0 {
+            return;
+        }
+
+        // A readable stream that yields nothing has been closed by the peer
+        let reads = stats.reads_completed;
+        if !relay_once(reader, writer, buffer, stats, throttle, write_latency, settings)
+            || stats.reads_completed == reads
+        {
+            return;
+        }
+    }
+}
+
+/// Move one read's worth of data from reader to writer, updating statistics
+///
+/// Waits for the rate limits in throttle and settings, if any, before
+/// writing, and times the write itself into write_latency, if given. Returns
+/// false if the read or write failed.
+fn relay_once(
+    reader: &mut (dyn EndpointReadable + Send),
+    writer: &mut (dyn EndpointWritable + Send),
+    buffer: &mut [u8],
+    stats: &mut Statistics,
+    throttle: Throttle,
+    write_latency: Option<&Mutex<LatencyHistogram>>,
+    settings: &LiveSettings,
+) -> bool {
+    let read_size = settings.read_size(buffer.len());
+    match reader.read(&mut buffer[..read_size]) {
+        Ok(0) => true,
+        Ok(n) => {
+            stats.bytes_received += n as u64;
+            stats.reads_completed += 1;
+            let n = if reader.is_datagram() {
+                stats.messages_received += 1;
+                n
+            } else {
+                coalesce(reader, &mut buffer[..read_size], n, stats, settings)
+            };
+
+            // Told to stop while held back, the data is dropped as if it
+            // arrived after the stop
+            if !throttle.wait(n, settings) {
+                return true;
+            }
+
+            // Write to destination
+            let started = Instant::now();
+            let result = write_all(writer, &buffer[..n], settings);
+            if let Some(write_latency) = write_latency {
+                write_latency.lock().unwrap().record(started.elapsed());
+            }
+            match result {
+                Ok(written) => {
+                    stats.bytes_sent += written as u64;
+                    stats.writes_completed += 1;
+                    if writer.is_datagram() {
+                        stats.messages_sent += 1;
+                    }
+                    true
+                }
+                Err(_) => {
+                    stats.writes_failed += 1;
+                    false
+                }
+            }
+        }
+        Err(_) => {
+            stats.reads_failed += 1;
+            false
+        }
+    }
+}
+
+/// Keep reading a stream after the first n bytes in buffer until it has been
+/// quiet for the coalescing time in settings, buffer is full or
+/// STREAM_EP_DELAY has passed, returning how many bytes buffer then holds
+///
+/// Without a coalescing time the n bytes are relayed as they are. A read
+/// that fails or finds the stream closed ends coalescing and is left for the
+/// next read to report.
+fn coalesce(
+    reader: &mut (dyn EndpointReadable + Send),
+    buffer: &mut [u8],
+    mut n: usize,
+    stats: &mut Statistics,
+    settings: &LiveSettings,
+) -> usize {
+    let Some(quiet) = settings.coalesce() else {
+        return n;
+    };
+
+    let deadline = Instant::now() + STREAM_EP_DELAY;
+    while n < buffer.len() {
+        let wait = quiet.min(deadline.saturating_duration_since(Instant::now()));
+        if wait.is_zero() {
+            break;
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd: reader.io_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll_fd, 1, wait.as_millis().max(1) as i32) } <= 0 {
+            break;
+        }
+        match reader.read(&mut buffer[n..]) {
+            Ok(0) | Err(_) => break,
+            Ok(more) => {
+                n += more;
+                stats.bytes_received += more as u64;
+                stats.reads_completed += 1;
+            }
+        }
+    }
+    n
+}
+
+/// Write the data waiting in control to the payload writer, returning false
+/// if any of it couldn't be written
+///
+/// Whoever queued each piece of data is told whether it was written.
+/// Control bytes aren't relayed data, so they leave the statistics alone.
+fn write_control(
+    control: &ControlQueue,
+    writer: &mut (dyn EndpointWritable + Send),
+    settings: &LiveSettings,
+    last_transfer: &Mutex<Option<Instant>>,
+) -> bool {
+    let mut ok = true;
+    while let Some(QueuedControl { data, written, .. }) = control.pop() {
+        let done = write_all(writer, &data, settings).is_ok();
+        if done {
+            *last_transfer.lock().unwrap() = Some(Instant::now());
+        } else {
+            ok = false;
+        }
+        let _ = written.send(done);
+    }
+    ok
+}
+
+/// Write all of data, finishing short writes
+///
+/// Only streams write short; a datagram is sent whole or not at all, so its
+/// result is returned as is. A stream is given at most the write size in
+/// settings at once, with the stream delay after each write, and one that
+/// takes nothing more for STREAM_WRITE_TIMEOUT fails the write.
+fn write_all(writer: &mut (dyn EndpointWritable + Send), data: &[u8], settings: &LiveSettings) -> TcsResult<usize> {
+    if writer.is_datagram() {
+        return writer.write(data);
+    }
+
+    let mut written = 0;
+    let mut stalled_since = None;
+    while written < data.len() {
+        let end = settings.write_size().map_or(data.len(), |size| data.len().min(written + size));
+        match writer.write(&data[written..end])? {
+            0 => {
+                if stalled_since.get_or_insert_with(Instant::now).elapsed() >= STREAM_WRITE_TIMEOUT {
+                    return Err(TcsError::Endpoint(format!(
+                        "Stream write stalled after {} of {} bytes",
+                        written,
+                        data.len()
+                    )));
+                }
+                thread::sleep(WRITE_RETRY);
+            }
+            n => {
+                written += n;
+                stalled_since = None;
+                thread::sleep(settings.stream_delay());
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Writer letting reads overlap writes to a slow stream
+///
+/// What the stream won't take straight away is held, up to OVERLAP_LIMIT,
+/// and counted as written; only once the limit is reached does a write wait
+/// for the stream. flush writes what is held as the stream takes it.
+struct OverlapWriter {
+    inner: Box<dyn EndpointWritable + Send>,
+    held: Vec<u8>,
+}
+
+impl OverlapWriter {
+    fn new(inner: Box<dyn EndpointWritable + Send>) -> Self {
+        Self { inner, held: Vec::new() }
+    }
+}
+
+impl EndpointWaitable for OverlapWriter {
+    fn io_fd(&self) -> RawFd {
+        self.inner.io_fd()
+    }
+
+    fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+        self.inner.wait_for_event(cmd_fd, timeout_ms)
+    }
+}
+
+impl EndpointWritable for OverlapWriter {
+    fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+        self.flush()?;
+        let n = data.len().min(OVERLAP_LIMIT.saturating_sub(self.held.len()));
+        self.held.extend_from_slice(&data[..n]);
+        self.flush()?;
+        Ok(n)
+    }
+
+    fn shutdown_write(&mut self) -> TcsResult<()> {
+        finish_writes(self);
+        self.inner.shutdown_write()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn flush(&mut self) -> TcsResult<bool> {
+        while !self.held.is_empty() {
+            match self.inner.write(&self.held)? {
+                0 => break,
+                n => {
+                    self.held.drain(..n);
+                }
+            }
+        }
+        Ok(!self.held.is_empty())
+    }
+}
+
+/// Write out whatever the writer holds, giving up on it after
+/// STREAM_WRITE_TIMEOUT
+fn finish_writes(writer: &mut (dyn EndpointWritable + Send)) {
+    let deadline = Instant::now() + STREAM_WRITE_TIMEOUT;
+    while let Ok(true) = writer.flush() {
+        if Instant::now() >= deadline {
+            return;
+        }
+        thread::sleep(WRITE_RETRY);
+    }
+}
+
+impl Drop for Conduit {
+    fn drop(&mut self) {
+        if self.is_running() {
+            let _ = self.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::EndpointWaitable;
+
+    #[test]
+    fn test_conduit_direction() {
+        assert_ne!(ConduitDirection::GroundToPayload, ConduitDirection::PayloadToGround);
+    }
+
+    #[test]
+    fn test_fault_detector() {
+        // Isolated errors never add up to a fault
+        let mut detector = FaultDetector::new(3, Duration::ZERO);
+        for _ in 0..10 {
+            assert!(!detector.record(false));
+            assert!(!detector.record(false));
+            assert!(!detector.record(true));
+        }
+
+        // Sustained errors fault once the threshold is reached
+        assert!(!detector.record(false));
+        assert!(!detector.record(false));
+        assert!(detector.record(false));
+
+        // and, with a window, only once the errors have persisted for it
+        let mut detector = FaultDetector::new(2, Duration::from_millis(50));
+        assert!(!detector.record(false));
+        assert!(!detector.record(false));
+        thread::sleep(Duration::from_millis(60));
+        assert!(detector.record(false));
+    }
+
+    /// Endpoint that is always ready and fails the first `failures` reads
+    struct FailingEndpoint {
+        failures: Option<u32>,
+        fd: RawFd,
+    }
+
+    impl EndpointWaitable for FailingEndpoint {
+        fn io_fd(&self) -> RawFd {
+            self.fd
+        }
+
+        fn wait_for_event(&self, cmd_fd: RawFd, _timeout_ms: i32) -> TcsResult<WaitResult> {
+            let mut cmd_pending = 0;
+            unsafe {
+                libc::ioctl(cmd_fd, libc::FIONREAD, &mut cmd_pending);
+            }
+            thread::sleep(Duration::from_millis(1));
+            Ok(if cmd_pending > 0 { WaitResult::CommandPending } else { WaitResult::IoReady })
+        }
+    }
+
+    impl EndpointReadable for FailingEndpoint {
+        fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+            match self.failures.as_mut() {
+                Some(0) => Ok(buffer.len().min(1)),
+                Some(n) => {
+                    *n -= 1;
+                    Err(TcsError::Endpoint("Injected read error".to_string()))
+                }
+                None => Err(TcsError::Endpoint("Injected read error".to_string())),
+            }
+        }
+    }
+
+    impl EndpointWritable for FailingEndpoint {
+        fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn test_conduit_faults_on_sustained_errors() {
+        // A couple of errors followed by recovery, then errors that never stop
+        for (failures, expect_fault) in [(Some(2), false), (None, true)] {
+            let mut pipe_fds = [0i32; 2];
+            assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+            let endpoint = |failures| FailingEndpoint { failures, fd: pipe_fds[0] };
+
+            let mut conduit = Conduit::new(
+                ConduitDirection::GroundToPayload,
+                Box::new(endpoint(None)),
+                Box::new(endpoint(None)),
+                pipe_fds[0],
+                pipe_fds[1],
+            )
+            .with_fault_detector(FaultDetector::new(5, Duration::from_millis(20)));
+            conduit.start(Box::new(endpoint(failures)), Box::new(endpoint(None)), pipe_fds[0]).unwrap();
+            thread::sleep(Duration::from_millis(200));
+
+            assert_eq!(conduit.is_faulted(), expect_fault);
+            let stats = conduit.stop().unwrap();
+            if !expect_fault {
+                assert_eq!(stats.reads_failed, 2);
+            }
+
+            unsafe {
+                libc::close(pipe_fds[0]);
+                libc::close(pipe_fds[1]);
+            }
+        }
+    }
+
+    /// Writer that stalls on every `stall_every`th write
+    struct StallingWriter {
+        writes: u32,
+        stall_every: u32,
+        fd: RawFd,
+    }
+
+    impl EndpointWaitable for StallingWriter {
+        fn io_fd(&self) -> RawFd {
+            self.fd
+        }
+
+        fn wait_for_event(&self, _cmd_fd: RawFd, _timeout_ms: i32) -> TcsResult<WaitResult> {
+            Ok(WaitResult::IoReady)
+        }
+    }
+
+    impl EndpointWritable for StallingWriter {
+        fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+            self.writes += 1;
+            if self.writes.is_multiple_of(self.stall_every) {
+                thread::sleep(Duration::from_millis(20));
+            }
+            Ok(data.len())
+        }
+    }
+
+    /// Stream writer taking at most `limit` bytes per write, and nothing
+    /// every other write, as a full socket buffer would
+    struct ShortWriter {
+        limit: usize,
+        full: bool,
+        written: Arc<Mutex<Vec<u8>>>,
+        fd: RawFd,
+    }
+
+    impl EndpointWaitable for ShortWriter {
+        fn io_fd(&self) -> RawFd {
+            self.fd
+        }
+
+        fn wait_for_event(&self, _cmd_fd: RawFd, _timeout_ms: i32) -> TcsResult<WaitResult> {
+            Ok(WaitResult::IoReady)
+        }
+    }
+
+    impl EndpointWritable for ShortWriter {
+        fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+            self.full = !self.full;
+            if self.full {
+                return Ok(0);
+            }
+            let n = data.len().min(self.limit);
+            self.written.lock().unwrap().extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_short_writes() {
+        use crate::endpoint::DeviceEndpoint;
+        use tcslibgs::DeviceConfig;
+
+        let mut payload_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(payload_fds.as_mut_ptr()) }, 0);
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let device = DeviceConfig { path: format!("/proc/self/fd/{}", payload_fds[0]) };
+        let written = Arc::new(Mutex::new(vec![]));
+        let writer = || ShortWriter { limit: 3, full: false, written: written.clone(), fd: pipe_fds[0] };
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(DeviceEndpoint::new(&device).unwrap()),
+            Box::new(writer()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(DeviceEndpoint::new(&device).unwrap()), Box::new(writer()), pipe_fds[0]).unwrap();
+
+        let data = b"no byte of this is lost";
+        let sent = unsafe { libc::write(payload_fds[1], data.as_ptr() as *const libc::c_void, data.len()) };
+        assert_eq!(sent, data.len() as isize);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while written.lock().unwrap().len() < data.len() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let stats = conduit.stop().unwrap();
+        assert_eq!(&written.lock().unwrap()[..], &data[..]);
+        assert_eq!((stats.bytes_received, stats.bytes_sent), (data.len() as u64, data.len() as u64));
+        assert_eq!(stats.writes_completed, stats.reads_completed);
+        assert_eq!(stats.writes_failed, 0);
+
+        unsafe {
+            for fd in payload_fds.into_iter().chain(pipe_fds) {
+                libc::close(fd);
+            }
+        }
+    }
+
+    /// Endpoint that is always ready with another `size` bytes, counting
+    /// what it has given out
+    struct FloodReader {
+        size: usize,
+        read: Arc<AtomicUsize>,
+        fd: RawFd,
+    }
+
+    impl EndpointWaitable for FloodReader {
+        fn io_fd(&self) -> RawFd {
+            self.fd
+        }
+
+        fn wait_for_event(&self, cmd_fd: RawFd, _timeout_ms: i32) -> TcsResult<WaitResult> {
+            let mut cmd_pending = 0;
+            unsafe {
+                libc::ioctl(cmd_fd, libc::FIONREAD, &mut cmd_pending);
+            }
+            Ok(if cmd_pending > 0 { WaitResult::CommandPending } else { WaitResult::IoReady })
+        }
+    }
+
+    impl EndpointReadable for FloodReader {
+        fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+            let n = buffer.len().min(self.size);
+            self.read.fetch_add(n, Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_write_ordering() {
+        const READ_SIZE: usize = 1000;
+
+        for ordering in [WriteOrdering::Drain, WriteOrdering::Overlap] {
+            let mut pipe_fds = [0i32; 2];
+            assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+            let read = Arc::new(AtomicUsize::new(0));
+            let written = Arc::new(Mutex::new(vec![]));
+            let reader = FloodReader { size: READ_SIZE, read: read.clone(), fd: pipe_fds[0] };
+            let writer = || ShortWriter { limit: 100, full: false, written: written.clone(), fd: pipe_fds[0] };
+            let mut conduit = Conduit::new(
+                ConduitDirection::PayloadToGround,
+                Box::new(FloodReader { size: READ_SIZE, read: read.clone(), fd: pipe_fds[0] }),
+                Box::new(writer()),
+                pipe_fds[0],
+                pipe_fds[1],
+            )
+            .with_write_ordering(ordering);
+            conduit.start(Box::new(reader), Box::new(writer()), pipe_fds[0]).unwrap();
+
+            // The reader is far faster than the writer
+            let mut most_in_flight = 0;
+            let deadline = Instant::now() + Duration::from_millis(300);
+            while Instant::now() < deadline {
+                // Reading the read count first means a read and write landing
+                // between the two loads can't inflate the figure
+                let read = read.load(Ordering::SeqCst);
+                most_in_flight = most_in_flight.max(read.saturating_sub(written.lock().unwrap().len()));
+                thread::sleep(Duration::from_millis(1));
+            }
+            conduit.stop().unwrap();
+
+            match ordering {
+                // Never more than the read being written
+                WriteOrdering::Drain => assert!(most_in_flight <= READ_SIZE, "{}", most_in_flight),
+                // Reads run ahead, but only as far as the limit
+                WriteOrdering::Overlap => {
+                    assert!(most_in_flight > READ_SIZE, "{}", most_in_flight);
+                    assert!(most_in_flight <= OVERLAP_LIMIT + READ_SIZE, "{}", most_in_flight);
+                }
+            }
+
+            unsafe {
+                libc::close(pipe_fds[0]);
+                libc::close(pipe_fds[1]);
+            }
+        }
+    }
+
+    /// Stream writer recording the size of each write
+    struct ChunkWriter {
+        chunks: Arc<Mutex<Vec<usize>>>,
+        fd: RawFd,
+    }
+
+    impl EndpointWaitable for ChunkWriter {
+        fn io_fd(&self) -> RawFd {
+            self.fd
+        }
+
+        fn wait_for_event(&self, _cmd_fd: RawFd, _timeout_ms: i32) -> TcsResult<WaitResult> {
+            Ok(WaitResult::IoReady)
+        }
+    }
+
+    impl EndpointWritable for ChunkWriter {
+        fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+            self.chunks.lock().unwrap().push(data.len());
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn test_control_receipt() {
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ChunkWriter { chunks: chunks.clone(), fd: 0 };
+        let settings = LiveSettings::from_options(&ConduitOptions::default());
+        let last_transfer = Mutex::new(None);
+        let control = Arc::new(ControlQueue::default());
+
+        // Bytes no conduit takes in time are dropped rather than written late
+        let late = control.push(b"late".to_vec()).unwrap();
+        let written = control.push(b"written".to_vec()).unwrap();
+        assert!(matches!(late.wait(Duration::from_millis(10)), Err(TcsError::Timeout)));
+        assert!(write_control(&control, &mut writer, &settings, &last_transfer));
+        assert!(written.wait(Duration::from_millis(10)).is_ok());
+        assert_eq!(*chunks.lock().unwrap(), vec![7]);
+        assert!(last_transfer.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_live_settings() {
+        use crate::endpoint::DeviceEndpoint;
+        use tcslibgs::DeviceConfig;
+
+        let mut payload_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(payload_fds.as_mut_ptr()) }, 0);
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let device = DeviceConfig { path: format!("/proc/self/fd/{}", payload_fds[0]) };
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let writer = || ChunkWriter { chunks: chunks.clone(), fd: pipe_fds[0] };
+        let settings = Arc::new(LiveSettings::default());
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(DeviceEndpoint::new(&device).unwrap()),
+            Box::new(writer()),
+            pipe_fds[0],
+            pipe_fds[1],
+        )
+        .with_settings(settings.clone());
+        conduit.start(Box::new(DeviceEndpoint::new(&device).unwrap()), Box::new(writer()), pipe_fds[0]).unwrap();
+
+        let send = |data: &[u8], total: usize| {
+            let sent = unsafe { libc::write(payload_fds[1], data.as_ptr() as *const libc::c_void, data.len()) };
+            assert_eq!(sent, data.len() as isize);
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while chunks.lock().unwrap().iter().sum::<usize>() < total && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        send(b"0123456789", 10);
+        assert_eq!(*chunks.lock().unwrap(), [10]);
+
+        // The running conduit reads 6 bytes at a time and writes 4 at a time
+        settings.apply(&ConduitOptions {
+            read_buffer_size: Some(6),
+            write_buffer_size: Some(4),
+            ..ConduitOptions::default()
+        });
+        send(b"0123456789", 20);
+        assert_eq!(*chunks.lock().unwrap(), [10, 4, 2, 4]);
+
+        let stats = conduit.stop().unwrap();
+        assert_eq!(stats.reads_completed, 3);
+        assert_eq!(stats.bytes_sent, 20);
+
+        unsafe {
+            for fd in payload_fds.into_iter().chain(pipe_fds) {
+                libc::close(fd);
+            }
+        }
+    }
+
+    #[test]
+    fn test_coalesce_stream_reads() {
+        use crate::endpoint::DeviceEndpoint;
+        use tcslibgs::DeviceConfig;
+
+        let mut payload_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(payload_fds.as_mut_ptr()) }, 0);
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let device = DeviceConfig { path: format!("/proc/self/fd/{}", payload_fds[0]) };
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let writer = || ChunkWriter { chunks: chunks.clone(), fd: pipe_fds[0] };
+        let options = ConduitOptions { coalesce_ms: Some(20), ..ConduitOptions::default() };
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(DeviceEndpoint::new(&device).unwrap()),
+            Box::new(writer()),
+            pipe_fds[0],
+            pipe_fds[1],
+        )
+        .with_settings(Arc::new(LiveSettings::from_options(&options)));
+        conduit.start(Box::new(DeviceEndpoint::new(&device).unwrap()), Box::new(writer()), pipe_fds[0]).unwrap();
+
+        // A byte-at-a-time source, a few bytes in each burst
+        for burst in 0..10u8 {
+            let data = [burst; 3];
+            let sent = unsafe { libc::write(payload_fds[1], data.as_ptr() as *const libc::c_void, data.len()) };
+            assert_eq!(sent, 3);
+            thread::sleep(Duration::from_millis(2));
+        }
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while chunks.lock().unwrap().iter().sum::<usize>() < 30 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let stats = conduit.stop().unwrap();
+        let chunks = chunks.lock().unwrap();
+        assert_eq!(chunks.iter().sum::<usize>(), 30);
+        assert!(chunks.len() <= 3, "{:?}", chunks);
+        assert_eq!(stats.writes_completed, chunks.len() as u64);
+        assert!(stats.reads_completed > stats.writes_completed, "{:?}", stats);
+
+        unsafe {
+            for fd in payload_fds.into_iter().chain(pipe_fds) {
+                libc::close(fd);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_latency() {
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let reader = || FailingEndpoint { failures: Some(0), fd: pipe_fds[0] };
+        let writer = || StallingWriter { writes: 0, stall_every: 20, fd: pipe_fds[0] };
+
+        let histogram = Arc::new(Mutex::new(LatencyHistogram::new()));
+        let mut conduit = Conduit::new(
+            ConduitDirection::GroundToPayload,
+            Box::new(reader()),
+            Box::new(writer()),
+            pipe_fds[0],
+            pipe_fds[1],
+        )
+        .with_write_latency(Some(histogram.clone()));
+        conduit.start(Box::new(reader()), Box::new(writer()), pipe_fds[0]).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        let stats = conduit.stop().unwrap();
+
+        // One write in twenty stalls, which the median hides but p99 shows
+        let latency = histogram.lock().unwrap().summary();
+        assert_eq!(latency.samples, stats.writes_completed);
+        assert!(latency.samples >= 40, "{:?}", latency);
+        assert!(latency.p50_us < 5_000, "{:?}", latency);
+        assert!(latency.p99_us >= 20_000, "{:?}", latency);
+        assert!(latency.max_us >= 20_000, "{:?}", latency);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_blocking_device_conduit() {
+        use crate::endpoint::{DeviceEndpoint, UdpEndpoint};
+        use std::ffi::CString;
+        use std::fs::OpenOptions;
+        use std::io::Write;
+        use std::net::UdpSocket;
+        use tcslibgs::{DeviceConfig, NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        // A FIFO stands in for a slow character device
+        let path = std::env::temp_dir().join(format!("tcspecial-blocking-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let device = DeviceConfig { path: path.to_str().unwrap().to_string() };
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sink.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let writer = UdpEndpoint::new(&local).unwrap();
+        writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(DeviceEndpoint::new(&device).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        )
+        .with_io_mode(IoMode::Blocking);
+        conduit.start(Box::new(DeviceEndpoint::new(&device).unwrap()), Box::new(writer), pipe_fds[0]).unwrap();
+
+        let mut payload = OpenOptions::new().write(true).open(&path).unwrap();
+        payload.write_all(b"telemetry").unwrap();
+        let mut buf = [0u8; 16];
+        let n = sink.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"telemetry");
+
+        // Nothing more arrives, yet stop is not held up by the blocking read
+        let start = Instant::now();
+        let stats = conduit.stop().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(stats.bytes_sent, 9);
+
+        let _ = std::fs::remove_file(&path);
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_oc_endpoint_retarget() {
+        use crate::endpoint::{OcEndpoint, UdpEndpoint};
+        use std::net::UdpSocket;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let grounds: Vec<UdpSocket> = (0..2).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+        for ground in &grounds {
+            ground.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        }
+        let oc = OcEndpoint::new(&local).unwrap();
+        oc.set_destination(grounds[0].local_addr().unwrap());
+
+        let reader = UdpEndpoint::new(&local).unwrap();
+        let reader_addr = reader.local_addr().unwrap();
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(oc.clone()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(reader), Box::new(oc.clone()), pipe_fds[0]).unwrap();
+
+        let payload = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0u8; 16];
+        payload.send_to(b"before", reader_addr).unwrap();
+        let n = grounds[0].recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"before");
+
+        // The running conduit follows the new destination
+        oc.set_destination(grounds[1].local_addr().unwrap());
+        payload.send_to(b"after", reader_addr).unwrap();
+        let n = grounds[1].recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"after");
+        assert!(grounds[0].recv(&mut buf).is_err());
+
+        conduit.stop().unwrap();
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_oc_disconnect_pauses_downlink() {
+        use crate::endpoint::{OcEndpoint, UdpEndpoint};
+        use std::net::UdpSocket;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let ground_addr = ground.local_addr().unwrap();
+        let oc = OcEndpoint::new(&local).unwrap();
+        oc.set_destination(ground_addr);
+
+        let reader = UdpEndpoint::new(&local).unwrap();
+        let reader_addr = reader.local_addr().unwrap();
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(oc.clone()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(reader), Box::new(oc.clone()), pipe_fds[0]).unwrap();
+
+        let payload = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0u8; 16];
+        payload.send_to(b"before", reader_addr).unwrap();
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"before");
+
+        // Downlink is held rather than failing while the OC is gone
+        oc.disconnect();
+        assert!(!oc.is_connected());
+        payload.send_to(b"held", reader_addr).unwrap();
+        assert!(ground.recv(&mut buf).is_err());
+        assert!(!conduit.is_faulted());
+
+        // and what was held goes out once it is back
+        oc.set_destination(ground_addr);
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"held");
+
+        // Silence disconnects the OC too, and uplink from it reconnects it
+        oc.set_silence_timeout(Some(Duration::from_millis(200)));
+        thread::sleep(Duration::from_millis(250));
+        assert!(!oc.is_connected());
+        payload.send_to(b"quiet", reader_addr).unwrap();
+        assert!(ground.recv(&mut buf).is_err());
+        ground.send_to(b"uplink", oc.local_addr().unwrap()).unwrap();
+        let mut oc_reader = oc.clone();
+        let mut uplink = [0u8; 16];
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while oc_reader.read(&mut uplink).unwrap() == 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(PAUSE_POLL);
+        }
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"quiet");
+
+        let stats = conduit.stop().unwrap();
+        assert_eq!(stats.messages_sent, 3);
+        assert_eq!(stats.writes_failed, 0);
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_held_conduit_takes_commands() {
+        use crate::endpoint::{OcEndpoint, UdpEndpoint};
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let pending = |fd: RawFd| {
+            let mut pending: libc::c_int = 0;
+            unsafe { libc::ioctl(fd, libc::FIONREAD, &mut pending) };
+            pending
+        };
+
+        // The OC has never been heard from, so the downlink has nowhere to send
+        let oc = OcEndpoint::new(&local).unwrap();
+        assert!(!oc.is_connected());
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(oc.clone()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(UdpEndpoint::new(&local).unwrap()), Box::new(oc), pipe_fds[0]).unwrap();
+
+        // Commands are still read, while paused too
+        conduit.notify_control();
+        conduit.pause();
+        conduit.notify_control();
+        thread::sleep(PAUSE_POLL * 5);
+        assert_eq!(pending(pipe_fds[0]), 0);
+
+        // so a drain ends at once rather than leaving stop waiting for ever
+        let (stopped_tx, stopped_rx) = mpsc::channel();
+        thread::spawn(move || {
+            conduit.begin_drain();
+            stopped_tx.send(conduit.stop().is_ok()).unwrap();
+        });
+        assert!(stopped_rx.recv_timeout(Duration::from_secs(2)).expect("Conduit didn't stop"));
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_shared_downlink_limit() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::sync::atomic::AtomicBool;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        const RATE: u64 = 20_000;
+        const BURST: usize = 1000;
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let limiter = Arc::new(RateLimiter::new(RATE, BURST));
+        let flooding = Arc::new(AtomicBool::new(true));
+
+        // Two DHs' downlinks, each with a payload sending as fast as it can
+        let mut conduits = vec![];
+        let mut floods = vec![];
+        let mut sinks = vec![];
+        for _ in 0..2 {
+            let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let reader = UdpEndpoint::new(&local).unwrap();
+            let writer = UdpEndpoint::new(&local).unwrap();
+            writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+            let reader_addr = reader.local_addr().unwrap();
+
+            let mut pipe_fds = [0i32; 2];
+            assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+            let mut conduit = Conduit::new(
+                ConduitDirection::PayloadToGround,
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                pipe_fds[0],
+                pipe_fds[1],
+            )
+            .with_rate_limiter(Some(limiter.clone()));
+            conduit.start(Box::new(reader), Box::new(writer), pipe_fds[0]).unwrap();
+            conduits.push((conduit, pipe_fds));
+            sinks.push(sink);
+
+            let flooding = flooding.clone();
+            floods.push(thread::spawn(move || {
+                let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+                while flooding.load(Ordering::SeqCst) {
+                    let _ = sender.send_to(&[0x5a; 500], reader_addr);
+                    thread::sleep(Duration::from_micros(100));
+                }
+            }));
+        }
+
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(500));
+        let sent: Vec<u64> = conduits
+            .iter_mut()
+            .map(|(conduit, _)| conduit.stop().unwrap().bytes_sent)
+            .collect();
+        let elapsed = start.elapsed();
+        flooding.store(false, Ordering::SeqCst);
+        for flood in floods {
+            flood.join().unwrap();
+        }
+
+        let total: u64 = sent.iter().sum();
+        let ceiling = (RATE as f64 * elapsed.as_secs_f64()) as u64 + BURST as u64;
+        assert!(total <= ceiling, "sent {} bytes, ceiling {}", total, ceiling);
+        for dh_sent in &sent {
+            assert!(*dh_sent >= total / 4, "unfair split {:?}", sent);
+        }
+
+        for (_, pipe_fds) in conduits {
+            unsafe {
+                libc::close(pipe_fds[0]);
+                libc::close(pipe_fds[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_bps() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::sync::atomic::AtomicBool;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        const RATE: u64 = 10_000;
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let reader = UdpEndpoint::new(&local).unwrap();
+        let writer = UdpEndpoint::new(&local).unwrap();
+        writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+        let reader_addr = reader.local_addr().unwrap();
+
+        let options = ConduitOptions { rate_limit_bps: Some(RATE), ..ConduitOptions::default() };
+        let settings = Arc::new(LiveSettings::from_options(&options));
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        )
+        .with_settings(settings.clone());
+        conduit.start(Box::new(reader), Box::new(writer), pipe_fds[0]).unwrap();
+        assert_eq!(conduit.last_transfer(), None);
+
+        // A payload sending far faster than the cap
+        let flooding = Arc::new(AtomicBool::new(true));
+        let flood = {
+            let flooding = flooding.clone();
+            thread::spawn(move || {
+                let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+                while flooding.load(Ordering::SeqCst) {
+                    let _ = sender.send_to(&[0x5a; 500], reader_addr);
+                    thread::sleep(Duration::from_micros(100));
+                }
+            })
+        };
+
+        // Over a second no more than the rate and the initial burst get
+        // through, give or take the packet that overdraws the bucket
+        sink.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let mut buf = [0u8; 1024];
+        let mut received = 0;
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if let Ok(n) = sink.recv(&mut buf) {
+                received += n as u64;
+            }
+        }
+        let elapsed = start.elapsed();
+        let ceiling = (RATE as f64 * elapsed.as_secs_f64()) as u64 + DOWNLINK_BURST as u64 + 500;
+        assert!(received <= ceiling, "{} bytes got through in {:?}, ceiling {}", received, elapsed, ceiling);
+        assert!(received >= RATE / 2, "only {} bytes got through in {:?}", received, elapsed);
+        assert!(conduit.last_transfer().unwrap().elapsed() < Duration::from_millis(200));
+
+        // A conduit held back for seconds by a lower cap still stops at once
+        settings.apply(&ConduitOptions { rate_limit_bps: Some(10), ..options });
+        thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        conduit.stop().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200), "stop took {:?}", start.elapsed());
+
+        flooding.store(false, Ordering::SeqCst);
+        flood.join().unwrap();
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_stream_drain_on_stop() {
+        use crate::endpoint::{TcpEndpoint, UdpEndpoint};
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, UdpSocket};
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        // The payload flushes its last bytes only once the ground stops sending
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let payload_config = NetworkConfig {
+            protocol: NetworkProtocol::Tcp,
+            address: "127.0.0.1".to_string(),
+            port: Port(listener.local_addr().unwrap().port()),
+            udp_mode: UdpMode::Connected,
+        };
+        let payload = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut uplink = vec![];
+            stream.read_to_end(&mut uplink).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(b"final bytes").unwrap();
+        });
+
+        let payload_reader = TcpEndpoint::new_client(&payload_config).unwrap();
+        let payload_writer = payload_reader.try_clone().unwrap();
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sink.set_nonblocking(true).unwrap();
+        let oc_writer = UdpEndpoint::new(&local).unwrap();
+        oc_writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let conduit = |direction| {
+            Conduit::new(
+                direction,
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                pipe_fds[0],
+                pipe_fds[1],
+            )
+        };
+        let mut g2p = conduit(ConduitDirection::GroundToPayload);
+        let mut p2g = conduit(ConduitDirection::PayloadToGround);
+        g2p.start(Box::new(UdpEndpoint::new(&local).unwrap()), Box::new(payload_writer), pipe_fds[0]).unwrap();
+        p2g.start(Box::new(payload_reader), Box::new(oc_writer), pipe_fds[0]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        g2p.begin_drain();
+        p2g.begin_drain();
+        g2p.stop().unwrap();
+        let stats = p2g.stop().unwrap();
+        payload.join().unwrap();
+
+        // Already delivered by the time stop returned
+        let mut buf = [0u8; 32];
+        let n = sink.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"final bytes");
+        assert_eq!(stats.bytes_sent, 11);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_payload_close_detected() {
+        use crate::endpoint::{TcpEndpoint, UdpEndpoint};
+        use std::io::Write;
+        use std::net::{TcpListener, UdpSocket};
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let payload_config = NetworkConfig {
+            protocol: NetworkProtocol::Tcp,
+            address: "127.0.0.1".to_string(),
+            port: Port(listener.local_addr().unwrap().port()),
+            udp_mode: UdpMode::Connected,
+        };
+        let payload_reader = TcpEndpoint::new_client(&payload_config).unwrap();
+        let (mut payload, _) = listener.accept().unwrap();
+
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sink.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let oc_writer = UdpEndpoint::new(&local).unwrap();
+        oc_writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(payload_reader), Box::new(oc_writer), pipe_fds[0]).unwrap();
+
+        // Data still flows until the payload hangs up
+        payload.write_all(b"last").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(sink.recv(&mut buf).unwrap(), 4);
+        assert!(!conduit.is_payload_closed());
+
+        drop(payload);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !conduit.is_payload_closed() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(conduit.is_payload_closed());
+        assert!(!conduit.is_faulted());
+        assert_eq!(conduit.stop().unwrap().bytes_sent, 4);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_pause_and_resume_conduits() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        // Three DHs' downlinks
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut relays = vec![];
+        for _ in 0..3 {
+            let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+            sink.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+            let reader = UdpEndpoint::new(&local).unwrap();
+            let writer = UdpEndpoint::new(&local).unwrap();
+            writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+            let reader_addr = reader.local_addr().unwrap();
+
+            let mut pipe_fds = [0i32; 2];
+            assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+            let mut conduit = Conduit::new(
+                ConduitDirection::PayloadToGround,
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                Box::new(UdpEndpoint::new(&local).unwrap()),
+                pipe_fds[0],
+                pipe_fds[1],
+            );
+            conduit.start(Box::new(reader), Box::new(writer), pipe_fds[0]).unwrap();
+            relays.push((conduit, reader_addr, sink, pipe_fds));
+        }
+
+        for (conduit, reader_addr, _, _) in &relays {
+            conduit.pause();
+            sender.send_to(b"held", reader_addr).unwrap();
+        }
+        let mut buf = [0u8; 16];
+        for (_, _, sink, _) in &relays {
+            assert!(sink.recv(&mut buf).is_err());
+        }
+
+        // Data held while paused is delivered on resuming
+        for (conduit, _, _, _) in &relays {
+            conduit.resume();
+        }
+        for (_, _, sink, _) in &relays {
+            sink.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let n = sink.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"held");
+        }
+
+        for (mut conduit, _, _, pipe_fds) in relays {
+            conduit.stop().unwrap();
+            unsafe {
+                libc::close(pipe_fds[0]);
+                libc::close(pipe_fds[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_conduit_message_counters() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let reader = UdpEndpoint::new(&local).unwrap();
+        let writer = UdpEndpoint::new(&local).unwrap();
+        writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+        let reader_addr = reader.local_addr().unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(reader), Box::new(writer), pipe_fds[0]).unwrap();
+
+        // Datagram sizes chosen so they would coalesce in a stream
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for size in [1usize, 100, ENDPOINT_BUFFER_SIZE / 2] {
+            sender.send_to(&vec![0x5a; size], reader_addr).unwrap();
+        }
+        thread::sleep(Duration::from_millis(200));
+
+        let stats = conduit.stop().unwrap();
+        assert_eq!(stats.messages_received, 3);
+        assert_eq!(stats.messages_sent, 3);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::time::Duration;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let reader = UdpEndpoint::new(&local).unwrap();
+        let writer = UdpEndpoint::new(&local).unwrap();
+        writer.connect(&sink.local_addr().unwrap().to_string()).unwrap();
+        let reader_addr = reader.local_addr().unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let mut conduit = Conduit::new(
+            ConduitDirection::PayloadToGround,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit.start(Box::new(reader), Box::new(writer), pipe_fds[0]).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"before", reader_addr).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // The thread zeroes its counts before relaying the next datagram
+        conduit.reset_stats();
+        sender.send_to(b"after!!", reader_addr).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let stats = conduit.stop().unwrap();
+        assert_eq!((stats.bytes_received, stats.messages_received), (7, 1));
+        assert_eq!((stats.bytes_sent, stats.messages_sent), (7, 1));
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_fair_conduit_uplink_not_starved() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::sync::atomic::AtomicBool;
+        use std::time::{Duration, Instant};
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        let uplink_sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let downlink_sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let g2p_reader = UdpEndpoint::new(&local).unwrap();
+        let g2p_writer = UdpEndpoint::new(&local).unwrap();
+        g2p_writer.connect(&uplink_sink.local_addr().unwrap().to_string()).unwrap();
+        let p2g_reader = UdpEndpoint::new(&local).unwrap();
+        let p2g_writer = UdpEndpoint::new(&local).unwrap();
+        p2g_writer.connect(&downlink_sink.local_addr().unwrap().to_string()).unwrap();
+        let g2p_addr = g2p_reader.local_addr().unwrap();
+        let p2g_addr = p2g_reader.local_addr().unwrap();
+
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+
+        let mut conduit = Conduit::new(
+            ConduitDirection::Bidirectional,
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            Box::new(UdpEndpoint::new(&local).unwrap()),
+            pipe_fds[0],
+            pipe_fds[1],
+        );
+        conduit
+            .start_fair(
+                Box::new(g2p_reader),
+                Box::new(g2p_writer),
+                Box::new(p2g_reader),
+                Box::new(p2g_writer),
+                pipe_fds[0],
+            )
+            .unwrap();
+
+        // Keep the downlink saturated for the duration of the test
+        let flooding = Arc::new(AtomicBool::new(true));
+        let flood = {
+            let flooding = flooding.clone();
+            thread::spawn(move || {
+                let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let data = vec![0xa5; 1024];
+                while flooding.load(Ordering::SeqCst) {
+                    let _ = sender.send_to(&data, p2g_addr);
+                }
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        UdpSocket::bind("127.0.0.1:0").unwrap().send_to(&[0x42], g2p_addr).unwrap();
+        uplink_sink.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut buf = [0u8; 16];
+        let received = uplink_sink.recv(&mut buf);
+        let elapsed = start.elapsed();
+
+        flooding.store(false, Ordering::SeqCst);
+        flood.join().unwrap();
+        let stats = conduit.stop().unwrap();
+
+        assert_eq!(received.unwrap(), 1);
+        assert_eq!(buf[0], 0x42);
+        assert!(elapsed < Duration::from_millis(500));
+        assert!(stats.messages_sent > 0);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
     }
 }
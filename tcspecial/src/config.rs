@@ -2,9 +2,11 @@
 
 use std::fs::File;
 use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::Path;
 
-use tcslibgs::{CIConfig, DHConfig, PayloadConfig, TcsError, TcsResult};
+use serde::Deserialize;
+use tcslibgs::{CIConfig, DHConfig, DHConfigJson, PayloadConfig, TcsError, TcsResult};
 
 /// Load tcspecial configuration from a JSON file
 pub fn load_tcspecial_config<P: AsRef<Path>>(path: P) -> TcsResult<CIConfig> {
@@ -12,12 +14,89 @@ pub fn load_tcspecial_config<P: AsRef<Path>>(path: P) -> TcsResult<CIConfig> {
     let reader = BufReader::new(file);
     let tcspecial_tcspecial_config: PayloadConfig = serde_json::from_reader(reader)?;
 
-    let tcspecial_config = tcspecial_tcspecial_config.tcspecial_config.to_tcspecial_config()
-        .map_err(|e| TcsError::Config(e))?;
+    let tcspecial_config = tcspecial_tcspecial_config.ci_config.to_ci_config()
+        .map_err(TcsError::Config)?;
 
     Ok(tcspecial_config)
 }
 
+/// The part of a payload configuration file describing the data handlers
+#[derive(Deserialize)]
+struct PayloadFile {
+    data_handlers: Vec<DHConfigJson>,
+}
+
+/// Load the data handler configurations from a payload configuration file
+pub fn load_payload_config<P: AsRef<Path>>(path: P) -> TcsResult<Vec<DHConfig>> {
+    let file = File::open(path)?;
+    let payload_file: PayloadFile = serde_json::from_reader(BufReader::new(file))?;
+
+    payload_file
+        .data_handlers
+        .iter()
+        .map(|dh| dh.to_dh_config().map_err(TcsError::Config))
+        .collect()
+}
+
+/// Check that no beacon socket binds an address a command socket also binds
+///
+/// Two addresses overlap if they share a port and their IPs are equal or
+/// either is the wildcard address. Port 0 lets the OS choose and never
+/// overlaps. The error lists every conflict found.
+pub fn check_bind_conflicts(command_addrs: &[SocketAddr], beacon_addrs: &[SocketAddr]) -> TcsResult<()> {
+    let overlaps = |a: &SocketAddr, b: &SocketAddr| {
+        a.port() != 0
+            && a.port() == b.port()
+            && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+    };
+
+    let conflicts: Vec<String> = command_addrs
+        .iter()
+        .flat_map(|command| beacon_addrs.iter().map(move |beacon| (command, beacon)))
+        .filter(|(command, beacon)| overlaps(command, beacon))
+        .map(|(command, beacon)| format!("command socket {} and beacon socket {}", command, beacon))
+        .collect();
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(TcsError::Config(format!("Overlapping bind addresses: {}", conflicts.join(", "))))
+    }
+}
+
+/// Get the process's limit on open file descriptors
+pub fn fd_limit() -> TcsResult<u64> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit only writes to the rlimit it is given
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(TcsError::Io(std::io::Error::last_os_error()));
+    }
+    Ok(limit.rlim_cur)
+}
+
+/// Check that max data handlers can run at once within fd_limit file
+/// descriptors
+///
+/// Each running DH holds several descriptors, and the CI needs some of its
+/// own, so a limit that only fits on paper is refused rather than failing
+/// when the last DHs start.
+pub fn check_max_data_handlers(max: usize, fd_limit: u64) -> TcsResult<()> {
+    use constants::{FDS_PER_DH, FDS_RESERVED};
+
+    if max == 0 {
+        return Err(TcsError::Config("Maximum data handlers must not be zero".to_string()));
+    }
+
+    let fit = fd_limit.saturating_sub(FDS_RESERVED) / FDS_PER_DH;
+    if max as u64 > fit {
+        return Err(TcsError::Config(format!(
+            "Maximum of {} data handlers needs more than the {} file descriptor limit allows; at most {} fit",
+            max, fd_limit, fit
+        )));
+    }
+    Ok(())
+}
+
 /// Configuration constants
 pub mod constants {
     use std::time::Duration;
@@ -27,14 +106,17 @@ pub mod constants {
     // FIXME: use getaddrinfo()
     pub const BEACON_NETADDR: &str = "0.0.0.0:5550";
 
+    /// Local address beacons are sent from; port 0 lets the OS pick
+    pub const BEACON_BIND_ADDR: &str = "0.0.0.0:0";
+
     /// Initial delay for endpoint retry
     pub const ENDPOINT_DELAY_INIT: Duration = Duration::from_millis(100);
 
     /// Maximum delay for endpoint retry
     pub const ENDPOINT_DELAY_MAX: Duration = Duration::from_secs(10);
 
-    /// Maximum number of endpoint retries
-    pub const ENDPOINT_MAX_RETRIES: u32 = 10;
+    /// Longest an endpoint keeps retrying before giving up
+    pub const ENDPOINT_RETRY_LIMIT: Duration = Duration::from_secs(10);
 
     /// Stream endpoint delay for collecting bytes
     pub const STREAM_EP_DELAY: Duration = Duration::from_millis(50);
@@ -44,13 +126,136 @@ pub mod constants {
 
     /// Restart arm timeout
     pub const RESTART_ARM_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Largest telemetry datagram sent before fragmenting
+    pub const TELEMETRY_MAX_DATAGRAM: usize = 1400;
+
+    /// Telemetry held for sending before the oldest is dropped
+    pub const TELEMETRY_QUEUE_DEPTH: usize = 64;
+
+    /// Longest the CI waits for a command before doing periodic work,
+    /// unless configured otherwise
+    pub const CI_SERVICE_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Shortest the CI waits for a command, even with periodic work overdue
+    pub const CI_MIN_WAIT: Duration = Duration::from_millis(1);
+
+    /// Time a statistics subscription lasts unless renewed
+    pub const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+    /// Bytes the downlink rate limiter lets through at once after being idle
+    pub const DOWNLINK_BURST: usize = ENDPOINT_BUFFER_SIZE;
+
+    /// Longest a stopping stream DH waits for the payload's final bytes
+    /// after half-closing the connection
+    pub const STREAM_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Longest a conduit keeps retrying a write to a stream that won't take
+    /// any more data
+    pub const STREAM_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Most data a conduit holds for a slow stream when writes overlap reads
+    pub const OVERLAP_LIMIT: usize = 4 * ENDPOINT_BUFFER_SIZE;
+
+    /// Longest a reply is kept for answering a resent command
+    pub const REPLY_CACHE_MAX_AGE: Duration = Duration::from_secs(30);
+
+    /// Ground addresses whose binary framing is remembered, the least
+    /// recently heard from being forgotten first
+    pub const BINARY_PEERS_MAX: usize = 32;
+
+    /// DH_CONTROL writes held for a payload before more are refused
+    pub const CONTROL_QUEUE_DEPTH: usize = 16;
+
+    /// Longest DH_CONTROL waits for the relay to start writing its bytes
+    pub const CONTROL_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Longest DH_LOOPBACK waits for its token, whatever timeout it asks for
+    pub const LOOPBACK_TIMEOUT_MAX: Duration = Duration::from_secs(5);
+
+    /// Longest the CI waits for a command while a reply is being worked on
+    /// elsewhere, so it is sent soon after it is ready
+    pub const DEFERRED_REPLY_POLL: Duration = Duration::from_millis(5);
+
+    /// Consecutive conduit I/O errors before a DH is faulted
+    pub const FAULT_THRESHOLD: u32 = 5;
+
+    /// Least time conduit I/O errors must persist before a DH is faulted
+    pub const FAULT_WINDOW: Duration = Duration::from_secs(1);
+
+    /// File descriptors a running DH may hold: a listener and a connection
+    /// for each of its reader and writer endpoints
+    pub const FDS_PER_DH: u64 = 4;
+
+    /// File descriptors kept for the CI's own sockets, files and stdio
+    pub const FDS_RESERVED: u64 = 64;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use constants::BEACON_NETADDR;
     use std::io::Write;
     use tempfile::NamedTempFile;
+    use tcslibgs::Port;
+
+    #[test]
+    fn test_check_max_data_handlers() {
+        assert!(matches!(check_max_data_handlers(0, 1024), Err(TcsError::Config(_))));
+        assert!(check_max_data_handlers(1, 1024).is_ok());
+        assert!(check_max_data_handlers(240, 1024).is_ok());
+
+        match check_max_data_handlers(1_000_000, 1024) {
+            Err(TcsError::Config(msg)) => assert!(msg.contains("at most 240"), "{}", msg),
+            other => panic!("Accepted an absurd maximum: {:?}", other),
+        }
+        // Even one DH won't fit if the CI's own descriptors use up the limit
+        assert!(check_max_data_handlers(1, 16).is_err());
+        assert!(fd_limit().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_check_bind_conflicts() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let beacon = [addr(BEACON_NETADDR)];
+
+        assert!(check_bind_conflicts(&[addr("127.0.0.1:4000"), addr("0.0.0.0:5551")], &beacon).is_ok());
+        assert!(check_bind_conflicts(&[addr("0.0.0.0:0")], &[addr("0.0.0.0:0")]).is_ok());
+        assert!(check_bind_conflicts(&[addr("127.0.0.1:5550")], &[addr("127.0.0.2:5550")]).is_ok());
+
+        // The wildcard beacon address overlaps any command address on its port
+        match check_bind_conflicts(&[addr("127.0.0.1:4000"), addr("127.0.0.1:5550")], &beacon) {
+            Err(TcsError::Config(msg)) => assert_eq!(
+                msg,
+                "Overlapping bind addresses: command socket 127.0.0.1:5550 and beacon socket 0.0.0.0:5550"
+            ),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_payload_config() {
+        let payload_json = r#"{
+            "version": "1.0",
+            "description": "Test payloads",
+            "data_handlers": [
+                {"dh_id": 0, "name": "DH0", "type": "device", "path": "/dev/null",
+                 "packet_size": 12, "packet_interval_ms": 1000},
+                {"dh_id": 3, "name": "DH3", "type": "network", "protocol": "udp", "address": "localhost",
+                 "port": 5000, "packet_size": 12, "packet_interval_ms": 1000}
+            ]
+        }"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(payload_json.as_bytes()).unwrap();
+
+        let payload_config = load_payload_config(temp_file.path()).unwrap();
+        let ids: Vec<u32> = payload_config.iter().map(|config| config.dh_id.0).collect();
+        assert_eq!(ids, [0, 3]);
+
+        temp_file.write_all(b"garbage").unwrap();
+        assert!(load_payload_config(temp_file.path()).is_err());
+    }
 
     #[test]
     fn test_load_tcspecial_config() {
@@ -69,7 +274,7 @@ mod tests {
                     "packet_interval_ms": 1000
                 }
             ],
-            "tcspecial_config": {
+            "ci_config": {
                 "address": "0.0.0.0",
                 "port": 4000,
                 "protocol": "udp",
@@ -80,11 +285,15 @@ mod tests {
         let mut temp_file = NamedTempFile::new().unwrap();
         temp_file.write_all(config_json.as_bytes()).unwrap();
 
-        let result = load_tcspecial_config(temp_file.path());
-        assert!(result.is_ok());
-
-        let (tcspecial_config, payload_config) = result.unwrap();
-        assert_eq!(tcspecial_config.port, 4000);
+        let tcspecial_config = load_tcspecial_config(temp_file.path()).unwrap();
+        assert_eq!(tcspecial_config.port, Port(4000));
+        let payload_config = load_payload_config(temp_file.path()).unwrap();
         assert_eq!(payload_config.len(), 1);
+
+        // A downlink rate of 0 would never let anything through
+        let config_json = config_json.replace(r#""beacon_interval_ms": 5000"#, r#""beacon_interval_ms": 5000, "downlink_bytes_per_sec": 0"#);
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(config_json.as_bytes()).unwrap();
+        assert!(matches!(load_tcspecial_config(temp_file.path()), Err(TcsError::Config(_))));
     }
 }
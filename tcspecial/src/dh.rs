@@ -4,22 +4,26 @@
 
 use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tcslibgs::{DHConfig, DHId, DHName, Statistics, TcsError, TcsResult};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tcslibgs::{
+    Clock, ConduitOptions, DHConfig, DHId, DHName, DHState, DeviceConfig, EndpointConfig, NetworkConfig, NetworkProtocol,
+    SerialConfig, Statistics, TcsError, TcsResult, UdpMode, UnixConfig, WriteLatency,
+};
 
-use crate::endpoint::{create_reader_endpoint, create_writer_endpoint, EndpointReadable, EndpointWritable};
-use crate::conduit::{Conduit, ConduitDirection};
+use crate::config::constants::{ENDPOINT_BUFFER_SIZE, STREAM_EP_DELAY};
+use crate::endpoint::{
+    create_reader_endpoint, create_writer_endpoint, EndpointReadable, EndpointWritable, OcEndpoint, TcpEndpoint, UdpEndpoint, UnixEndpoint, NETWORK_ENDPOINT_PROTOCOLS,
+};
+use crate::conduit::{
+    Conduit, ConduitDirection, ControlQueue, ControlReceipt, EchoTap, EchoWatch, FaultDetector, LiveSettings,
+};
+use crate::latency::LatencyHistogram;
+use crate::rate_limit::RateLimiter;
 
-/// Data handler state
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DHState {
-    /// Created but not activated
-    Created,
-    /// Active and conduiting data
-    Active,
-    /// Stopped
-    Stopped,
-}
+/// Reader and writer for one end of a data handler's relays
+type EndpointPair = (Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>);
 
 /// Data handler
 pub struct DataHandler {
@@ -30,34 +34,84 @@ pub struct DataHandler {
     ground_to_payload: Option<Conduit>,
     payload_to_ground: Option<Conduit>,
     stats: Statistics,
+    /// Statistics kept by the last checkpoint, if any
+    checkpoint: Option<Statistics>,
+    /// When the data handler last became active
+    activated: Option<Instant>,
+    /// How long the data handler was active before it stopped
+    active_duration: Duration,
     running: Arc<AtomicBool>,
-    cmd_pipe: Option<(RawFd, RawFd)>,
+    /// Command pipes of the ground-to-payload and payload-to-ground conduits,
+    /// the first serving a bidirectional conduit on its own
+    cmd_pipes: Option<[(RawFd, RawFd); 2]>,
+    /// Limit shared with other DHs on payload-to-ground throughput
+    downlink_limiter: Option<Arc<RateLimiter>>,
+    /// Clock the statistics are timestamped with
+    clock: Clock,
+    /// Time taken by the conduits' writes, if tracked
+    write_latency: Option<Arc<Mutex<LatencyHistogram>>>,
+    /// When the conduits, or DH_CONTROL, last moved data
+    last_transfer: Arc<Mutex<Option<Instant>>>,
+    /// OC endpoint shared by the conduits, if started with one
+    oc_endpoint: Option<Arc<OcEndpoint>>,
+    /// Settings shared by the conduits that can change while they run
+    settings: Arc<LiveSettings>,
+    /// DH_CONTROL bytes waiting for the ground-to-payload conduit to write
+    control: Arc<ControlQueue>,
+    /// DH_LOOPBACK token the payload-to-ground relay is watching for
+    echo: Arc<EchoWatch>,
+    /// Connection being made to a TCP payload not yet connected to, or that
+    /// closed the last one
+    reconnecting: Option<JoinHandle<TcsResult<EndpointPair>>>,
+    /// OC endpoints held for the relays until the TCP payload is connected
+    waiting_oc: Option<EndpointPair>,
 }
 
 impl DataHandler {
     /// Create a new data handler
     pub fn new(config: DHConfig) -> TcsResult<Self> {
-        // Create command pipe
-        let mut pipe_fds = [0i32; 2];
-        unsafe {
-            if libc::pipe(pipe_fds.as_mut_ptr()) != 0 {
-                return Err(TcsError::Io(std::io::Error::last_os_error()));
-            }
-        }
+        // Create command pipes
+        let g2p_pipe = create_pipe()?;
+        let p2g_pipe = create_pipe().inspect_err(|_| close_pipe(g2p_pipe))?;
 
         Ok(Self {
             id: config.dh_id,
             name: config.name.clone(),
+            settings: Arc::new(LiveSettings::from_options(&config.conduit)),
             config,
             state: DHState::Created,
             ground_to_payload: None,
             payload_to_ground: None,
             stats: Statistics::new(),
+            checkpoint: None,
+            activated: None,
+            active_duration: Duration::ZERO,
             running: Arc::new(AtomicBool::new(false)),
-            cmd_pipe: Some((pipe_fds[0], pipe_fds[1])),
+            cmd_pipes: Some([g2p_pipe, p2g_pipe]),
+            downlink_limiter: None,
+            clock: Clock::new(),
+            write_latency: None,
+            last_transfer: Arc::default(),
+            oc_endpoint: None,
+            control: Arc::default(),
+            echo: Arc::default(),
+            reconnecting: None,
+            waiting_oc: None,
         })
     }
 
+    /// Share a limit on payload-to-ground throughput with other DHs
+    pub fn with_downlink_limiter(mut self, downlink_limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.downlink_limiter = downlink_limiter;
+        self
+    }
+
+    /// Timestamp statistics with the given clock, e.g. the CI's corrected one
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// Get the data handler ID
     pub fn id(&self) -> DHId {
         self.id
@@ -70,91 +124,496 @@ impl DataHandler {
 
     /// Get the current state
     pub fn state(&self) -> DHState {
-        self.state
+        let faulted = [&self.ground_to_payload, &self.payload_to_ground]
+            .into_iter()
+            .flatten()
+            .any(Conduit::is_faulted);
+        if self.state == DHState::Active && faulted {
+            DHState::Faulted
+        } else {
+            self.state
+        }
     }
 
-    /// Get the statistics
+    /// Check the payload is still connected
+    ///
+    /// False once the payload has closed its end of the connection, while a
+    /// TCP payload is still being connected to, and while the data handler
+    /// isn't relaying.
+    pub fn payload_connected(&self) -> bool {
+        matches!(self.state, DHState::Active | DHState::Paused)
+            && self.ground_to_payload.is_some()
+            && ![&self.ground_to_payload, &self.payload_to_ground]
+                .into_iter()
+                .flatten()
+                .any(Conduit::is_payload_closed)
+    }
+
+    /// Get the statistics, including what the running relays have moved
     pub fn statistics(&self) -> Statistics {
-        self.stats.clone().with_timestamp()
+        let mut stats = self.stats;
+        for conduit in [&self.ground_to_payload, &self.payload_to_ground].into_iter().flatten() {
+            fold_conduit_stats(&mut stats, conduit.direction(), &conduit.statistics());
+        }
+        let mut stats = stats.with_timestamp(self.clock.now());
+        let active = match self.activated {
+            Some(activated) => activated.elapsed(),
+            None => self.active_duration,
+        };
+        stats.active_duration_ms = active.as_millis() as u64;
+        stats
+    }
+
+    /// Keep the current statistics to compare later ones against
+    pub fn checkpoint(&mut self) {
+        self.checkpoint = Some(self.statistics());
+    }
+
+    /// Get the change in statistics since the last checkpoint, if there was one
+    pub fn since_checkpoint(&self) -> Option<Statistics> {
+        self.checkpoint.as_ref().map(|checkpoint| self.statistics().diff(checkpoint))
+    }
+
+    /// Get when the data handler last moved data, if it ever has
+    pub fn last_transfer(&self) -> Option<Instant> {
+        *self.last_transfer.lock().unwrap()
+    }
+
+    /// Get the write latency, if the configuration asks for it to be tracked
+    pub fn write_latency(&self) -> Option<WriteLatency> {
+        self.write_latency.as_ref().map(|histogram| histogram.lock().unwrap().summary())
+    }
+
+    /// Zero the statistics and write latency and drop any checkpoint,
+    /// leaving the data handler in its current state
+    pub fn reset_statistics(&mut self) {
+        self.stats = Statistics::new();
+        self.checkpoint = None;
+        for conduit in [&self.ground_to_payload, &self.payload_to_ground].into_iter().flatten() {
+            conduit.reset_stats();
+        }
+        if let Some(histogram) = &self.write_latency {
+            *histogram.lock().unwrap() = LatencyHistogram::new();
+        }
     }
 
     /// Start the data handler
+    ///
+    /// A TCP payload is connected to on a thread of its own, so the data
+    /// handler is active at once but relays nothing until reconnect_payload
+    /// finds the connection made.
     pub fn start(&mut self, oc_reader: Box<dyn EndpointReadable + Send>, oc_writer: Box<dyn EndpointWritable + Send>) -> TcsResult<()> {
         if self.state != DHState::Created {
             return Err(TcsError::DataHandler("Invalid state for start".to_string()));
         }
 
-        let (cmd_read, cmd_write) = self.cmd_pipe.ok_or_else(|| TcsError::DataHandler("No command pipe".to_string()))?;
-
         // Create payload endpoint
-        let payload_reader = create_reader_endpoint(&self.config.endpoint)?;
-        let payload_writer = create_writer_endpoint(&self.config.endpoint)?;
-
-        // Create conduits
-        let g2p_conduit = Conduit::new(
-            ConduitDirection::GroundToPayload,
-            oc_reader,
-            payload_writer,
-            cmd_read,
-            cmd_write,
-        );
-
-        let p2g_conduit = Conduit::new(
-            ConduitDirection::PayloadToGround,
-            payload_reader,
-            oc_writer,
-            cmd_read,
-            cmd_write,
-        );
+        let payload = open_payload(&self.config.endpoint)?;
+        self.attach_payload(oc_reader, oc_writer, payload)?;
 
-        // Note: In a full implementation, we would start the conduits here
-        // For now, we just update state
         self.state = DHState::Active;
+        self.activated = Some(Instant::now());
         self.running.store(true, Ordering::SeqCst);
 
+        Ok(())
+    }
+
+    /// Start the data handler relaying to and from a shared OC endpoint
+    ///
+    /// Downlink follows the endpoint's destination, so retargeting it
+    /// through oc_endpoint moves all downlink to the new address.
+    pub fn start_oc(&mut self, oc_endpoint: Arc<OcEndpoint>) -> TcsResult<()> {
+        self.start(Box::new(oc_endpoint.clone()), Box::new(oc_endpoint.clone()))?;
+        self.oc_endpoint = Some(oc_endpoint);
+        Ok(())
+    }
+
+    /// Get the OC endpoint the data handler was started with, if any
+    pub fn oc_endpoint(&self) -> Option<&Arc<OcEndpoint>> {
+        self.oc_endpoint.as_ref()
+    }
+
+    /// Create the conduits between the OC and payload endpoints
+    ///
+    /// Write latency tracking starts or stops here to follow the
+    /// configuration; latencies already recorded are kept.
+    fn create_conduits(
+        &mut self,
+        oc_reader: Box<dyn EndpointReadable + Send>,
+        oc_writer: Box<dyn EndpointWritable + Send>,
+        payload_reader: Box<dyn EndpointReadable + Send>,
+        payload_writer: Box<dyn EndpointWritable + Send>,
+        cmd_pipes: [(RawFd, RawFd); 2],
+    ) -> (Conduit, Option<Conduit>) {
+        match (self.config.conduit.track_write_latency, &self.write_latency) {
+            (true, None) => self.write_latency = Some(Arc::new(Mutex::new(LatencyHistogram::new()))),
+            (false, Some(_)) => self.write_latency = None,
+            _ => {}
+        }
+
+        let payload_reader = Box::new(EchoTap::new(payload_reader, self.echo.clone()));
+
+        // Fair scheduling services both directions from one thread
+        let fault_detector = FaultDetector::from_options(&self.config.conduit);
+        let [(g2p_read, g2p_write), (p2g_read, p2g_write)] = cmd_pipes;
+        if self.config.conduit.fair_scheduling {
+            let conduit = Conduit::new(
+                ConduitDirection::Bidirectional,
+                oc_reader,
+                payload_writer,
+                g2p_read,
+                g2p_write,
+            )
+            .with_downlink(payload_reader, oc_writer)
+            .with_fault_detector(fault_detector)
+            .with_rate_limiter(self.downlink_limiter.clone())
+            .with_write_latency(self.write_latency.clone())
+            .with_last_transfer(self.last_transfer.clone())
+            .with_settings(self.settings.clone())
+            .with_control(self.control.clone());
+            (conduit, None)
+        } else {
+            let g2p_conduit = Conduit::new(
+                ConduitDirection::GroundToPayload,
+                oc_reader,
+                payload_writer,
+                g2p_read,
+                g2p_write,
+            )
+            .with_fault_detector(fault_detector)
+            .with_io_mode(self.config.conduit.io_mode)
+            .with_write_ordering(self.config.conduit.write_ordering)
+            .with_write_latency(self.write_latency.clone())
+            .with_last_transfer(self.last_transfer.clone())
+            .with_settings(self.settings.clone())
+            .with_control(self.control.clone());
+
+            let p2g_conduit = Conduit::new(
+                ConduitDirection::PayloadToGround,
+                payload_reader,
+                oc_writer,
+                p2g_read,
+                p2g_write,
+            )
+            .with_fault_detector(fault_detector)
+            .with_io_mode(self.config.conduit.io_mode)
+            .with_write_ordering(self.config.conduit.write_ordering)
+            .with_rate_limiter(self.downlink_limiter.clone())
+            .with_write_latency(self.write_latency.clone())
+            .with_last_transfer(self.last_transfer.clone())
+            .with_settings(self.settings.clone());
+            (g2p_conduit, Some(p2g_conduit))
+        }
+    }
+
+    /// Replace the configuration in one step, keeping the statistics
+    ///
+    /// An active or paused data handler has its relays stopped and restarted
+    /// on the new payload endpoints, for which OC endpoints must be given as
+    /// for start unless it was started with a shared OC endpoint; a paused
+    /// one stays paused.
+    /// Invalid configurations are rejected before anything changes, and if
+    /// the new payload endpoints can't be created the data handler carries on
+    /// with its old configuration. A TCP payload is connected to as by start,
+    /// so failing to reach it isn't seen here.
+    pub fn reconfigure(
+        &mut self,
+        config: DHConfig,
+        oc_endpoints: Option<(Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>)>,
+    ) -> TcsResult<()> {
+        if config.dh_id != self.id {
+            return Err(TcsError::Config(format!("Configuration is for DH {}, not DH {}", config.dh_id.0, self.id.0)));
+        }
+        validate_config(&config)?;
+
+        if !matches!(self.state, DHState::Active | DHState::Paused) {
+            self.name = config.name.clone();
+            self.settings.apply(&config.conduit);
+            self.config = config;
+            return Ok(());
+        }
+
+        let (oc_reader, oc_writer): (Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>) =
+            match (oc_endpoints, &self.oc_endpoint) {
+                (Some(endpoints), _) => {
+                    self.oc_endpoint = None;
+                    endpoints
+                }
+                (None, Some(oc)) => (Box::new(oc.clone()), Box::new(oc.clone())),
+                (None, None) => {
+                    return Err(TcsError::DataHandler("OC endpoints needed to restart relays".to_string()))
+                }
+            };
+        // Pause, folding the relays' statistics into ours
+        self.stop_conduits();
+        self.reconnecting = None;
+        self.waiting_oc = None;
+
+        let old_config = std::mem::replace(&mut self.config, config);
+        let (payload, outcome) = match open_payload(&self.config.endpoint) {
+            Ok(payload) => (payload, Ok(())),
+            Err(e) => {
+                // Resume as we were
+                self.config = old_config;
+                match open_payload(&self.config.endpoint) {
+                    Ok(payload) => (payload, Err(e)),
+                    Err(_) => {
+                        self.state = DHState::Faulted;
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        self.settings.apply(&self.config.conduit);
+        self.attach_payload(oc_reader, oc_writer, payload)?;
+        self.name = self.config.name.clone();
+
+        outcome
+    }
+
+    /// Start relaying between the OC endpoints and the payload or, while a
+    /// TCP payload is being connected to, hold the OC endpoints until it is
+    fn attach_payload(
+        &mut self,
+        oc_reader: Box<dyn EndpointReadable + Send>,
+        oc_writer: Box<dyn EndpointWritable + Send>,
+        payload: PayloadConnection,
+    ) -> TcsResult<()> {
+        match payload {
+            PayloadConnection::Ready((payload_reader, payload_writer)) => {
+                self.start_conduits(oc_reader, oc_writer, payload_reader, payload_writer)
+            }
+            PayloadConnection::Connecting(connecting) => {
+                self.waiting_oc = Some((oc_reader, oc_writer));
+                self.reconnecting = Some(connecting);
+                Ok(())
+            }
+        }
+    }
+
+    /// Start conduits relaying between the given endpoints in place of any
+    /// stopped ones, leaving them paused if the data handler is
+    fn start_conduits(
+        &mut self,
+        oc_reader: Box<dyn EndpointReadable + Send>,
+        oc_writer: Box<dyn EndpointWritable + Send>,
+        payload_reader: Box<dyn EndpointReadable + Send>,
+        payload_writer: Box<dyn EndpointWritable + Send>,
+    ) -> TcsResult<()> {
+        let cmd_pipes = self.cmd_pipes.ok_or_else(|| TcsError::DataHandler("No command pipes".to_string()))?;
+        let (mut g2p_conduit, mut p2g_conduit) =
+            self.create_conduits(oc_reader, oc_writer, payload_reader, payload_writer, cmd_pipes);
+        for (conduit, (cmd_read, _)) in [Some(&mut g2p_conduit), p2g_conduit.as_mut()].into_iter().zip(cmd_pipes) {
+            if let Some(conduit) = conduit {
+                if self.state == DHState::Paused {
+                    conduit.pause();
+                }
+                conduit.run(cmd_read)?;
+            }
+        }
         self.ground_to_payload = Some(g2p_conduit);
-        self.payload_to_ground = Some(p2g_conduit);
+        self.payload_to_ground = p2g_conduit;
+        Ok(())
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &DHConfig {
+        &self.config
+    }
 
+    /// Replace the conduit options without restarting the relays
+    ///
+    /// The read and write sizes, stream delay and rate limit take effect on
+    /// running relays at once; the other options wait until the relays are
+    /// next started.
+    pub fn set_conduit_options(&mut self, options: ConduitOptions) -> TcsResult<()> {
+        validate_conduit_options(&options)?;
+        self.settings.apply(&options);
+        self.config.conduit = options;
         Ok(())
     }
 
+    /// Pause an active data handler, leaving its connections open but moving
+    /// no data; pausing a paused data handler does nothing
+    pub fn pause(&mut self) -> TcsResult<()> {
+        match self.state {
+            DHState::Paused => Ok(()),
+            DHState::Active => {
+                for conduit in [&self.ground_to_payload, &self.payload_to_ground].into_iter().flatten() {
+                    conduit.pause();
+                }
+                self.state = DHState::Paused;
+                Ok(())
+            }
+            state => Err(TcsError::DataHandler(format!("Can't pause a {} data handler", state))),
+        }
+    }
+
+    /// Resume a paused data handler; resuming an active one does nothing
+    pub fn resume(&mut self) -> TcsResult<()> {
+        match self.state {
+            DHState::Active => Ok(()),
+            DHState::Paused => {
+                for conduit in [&self.ground_to_payload, &self.payload_to_ground].into_iter().flatten() {
+                    conduit.resume();
+                }
+                self.state = DHState::Active;
+                Ok(())
+            }
+            state => Err(TcsError::DataHandler(format!("Can't resume a {} data handler", state))),
+        }
+    }
+
     /// Stop the data handler
+    ///
+    /// A TCP payload connection is half-closed first and whatever the
+    /// payload still sends is relayed to the ground before the connection is
+    /// closed, so the payload's final buffered bytes are not lost. A data
+    /// handler left faulted by reconfigure is torn down too.
     pub fn stop(&mut self) -> TcsResult<()> {
-        if self.state != DHState::Active {
+        if !matches!(self.state, DHState::Active | DHState::Paused | DHState::Faulted) {
             // Idempotent - already stopped
             return Ok(());
         }
 
         self.running.store(false, Ordering::SeqCst);
-
-        // Stop conduits and collect statistics
-        if let Some(mut conduit) = self.ground_to_payload.take() {
-            if let Ok(stats) = conduit.stop() {
-                self.stats.bytes_received += stats.bytes_received;
-                self.stats.reads_completed += stats.reads_completed;
-                self.stats.reads_failed += stats.reads_failed;
+        if matches!(&self.config.endpoint, EndpointConfig::Network(net) if net.protocol == NetworkProtocol::Tcp) {
+            for conduit in [&mut self.ground_to_payload, &mut self.payload_to_ground].into_iter().flatten() {
+                conduit.begin_drain();
             }
         }
+        self.stop_conduits();
+        self.reconnecting = None;
+        self.waiting_oc = None;
+
+        self.state = DHState::Stopped;
+        self.oc_endpoint = None;
+        if let Some(activated) = self.activated.take() {
+            self.active_duration = activated.elapsed();
+        }
+
+        // Close command pipes
+        for pipe in self.cmd_pipes.take().into_iter().flatten() {
+            close_pipe(pipe);
+        }
 
-        if let Some(mut conduit) = self.payload_to_ground.take() {
+        Ok(())
+    }
+
+    /// Stop the conduits, adding their statistics to ours
+    ///
+    /// Both are asked to stop before either is waited for, so one slow to
+    /// finish doesn't hold up the other. Commands they didn't read are then
+    /// discarded so the next conduits don't see them.
+    fn stop_conduits(&mut self) {
+        let mut conduits: Vec<Conduit> =
+            [self.ground_to_payload.take(), self.payload_to_ground.take()].into_iter().flatten().collect();
+        for conduit in &mut conduits {
+            conduit.request_stop();
+        }
+        for mut conduit in conduits {
             if let Ok(stats) = conduit.stop() {
-                self.stats.bytes_sent += stats.bytes_sent;
-                self.stats.writes_completed += stats.writes_completed;
-                self.stats.writes_failed += stats.writes_failed;
+                fold_conduit_stats(&mut self.stats, conduit.direction(), &stats);
             }
         }
+        for (cmd_read, _) in self.cmd_pipes.into_iter().flatten() {
+            drain_pipe(cmd_read);
+        }
+    }
 
-        self.state = DHState::Stopped;
+    /// Connect a TCP payload not yet connected to, or that closed its
+    /// connection
+    ///
+    /// The connection is made on a thread of its own, retrying with the
+    /// endpoint backoff, so the caller isn't held up: call this periodically
+    /// to begin connecting and, once connected, to start the relays on the
+    /// new connection. A failed attempt is returned and another begins on
+    /// the next call. Only payloads reached over TCP are connected this way,
+    /// and only a data handler started with a shared OC endpoint can restart
+    /// its relays once they have run. Returns true once the relays have been
+    /// started.
+    pub fn reconnect_payload(&mut self) -> TcsResult<bool> {
+        let net_config = match &self.config.endpoint {
+            EndpointConfig::Network(net_config) if net_config.protocol == NetworkProtocol::Tcp => net_config.clone(),
+            _ => return Ok(false),
+        };
+        if !matches!(self.state, DHState::Active | DHState::Paused)
+            || self.payload_connected()
+            || (self.oc_endpoint.is_none() && self.waiting_oc.is_none())
+        {
+            return Ok(false);
+        }
 
-        // Close command pipe
-        if let Some((read_fd, write_fd)) = self.cmd_pipe.take() {
-            unsafe {
-                libc::close(read_fd);
-                libc::close(write_fd);
+        let connecting = match self.reconnecting.take() {
+            Some(connecting) => connecting,
+            None => {
+                self.reconnecting = Some(thread::spawn(move || connect_network_payload(&net_config)));
+                return Ok(false);
             }
+        };
+        if !connecting.is_finished() {
+            self.reconnecting = Some(connecting);
+            return Ok(false);
         }
+        let (payload_reader, payload_writer) = connecting
+            .join()
+            .map_err(|_| TcsError::DataHandler("Reconnect thread panicked".to_string()))??;
+        let (oc_reader, oc_writer): EndpointPair = match (self.waiting_oc.take(), &self.oc_endpoint) {
+            (Some(endpoints), _) => endpoints,
+            (None, Some(oc)) => (Box::new(oc.clone()), Box::new(oc.clone())),
+            (None, None) => return Ok(false),
+        };
 
-        Ok(())
+        // Fold the old relays' statistics into ours and carry on with new ones
+        self.stop_conduits();
+        self.start_conduits(oc_reader, oc_writer, payload_reader, payload_writer)?;
+        Ok(true)
+    }
+
+    /// Send a token to the payload through the running relay, to be
+    /// echoed back
+    ///
+    /// The token is written like DH_CONTROL bytes, and the payload-to-ground
+    /// relay watches what it reads for the echo, which goes on to the OC like
+    /// any other payload data. That relay doesn't read until the OC has been
+    /// heard from, so neither is the echo. A TCP payload still being
+    /// connected to gets the token once it is. Only an active data handler
+    /// can be looped back, one token at a time.
+    pub fn send_loopback(&self, token: u64) -> TcsResult<Loopback> {
+        if self.state() != DHState::Active {
+            return Err(TcsError::DataHandler(format!("Can't loop back a {} data handler", self.state())));
+        }
+        let token = token.to_be_bytes().to_vec();
+        let echoed = self.echo.watch(token.clone())?;
+        let sent = Instant::now();
+        let receipt = self.control.push(token).inspect_err(|_| self.echo.cancel())?;
+        if let Some(conduit) = &self.ground_to_payload {
+            conduit.notify_control();
+        }
+        Ok(Loopback { sent, echoed, echo: self.echo.clone(), receipt })
+    }
+
+    /// Write control bytes to the payload through the running relay
+    ///
+    /// The bytes are queued for the ground-to-payload relay, which writes
+    /// them between reads over the payload connection it already has, even
+    /// while paused. They aren't counted in the statistics. The receipt
+    /// returned tells whether they were written. Returns TcsError::Timeout
+    /// if too many earlier writes are still waiting for the payload, and an
+    /// error if the data handler isn't relaying.
+    pub fn send_control(&self, data: &[u8]) -> TcsResult<ControlReceipt> {
+        let conduit = match &self.ground_to_payload {
+            Some(conduit) if matches!(self.state, DHState::Active | DHState::Paused) => conduit,
+            _ => {
+                return Err(TcsError::DataHandler(format!(
+                    "Can't send control bytes to a {} data handler",
+                    self.state()
+                )))
+            }
+        };
+        let receipt = self.control.push(data.to_vec())?;
+        conduit.notify_control();
+        Ok(receipt)
     }
 
     /// Check if the data handler is running
@@ -163,18 +622,204 @@ impl DataHandler {
     }
 }
 
+/// Token sent by DataHandler::send_loopback, waiting for its echo
+///
+/// Needs no lock on the data handler, so it can be waited for on a thread
+/// of its own.
+pub struct Loopback {
+    sent: Instant,
+    echoed: mpsc::Receiver<Instant>,
+    echo: Arc<EchoWatch>,
+    receipt: ControlReceipt,
+}
+
+impl Loopback {
+    /// Wait for the echo, returning the round-trip time, or
+    /// TcsError::Timeout if the token isn't echoed within timeout of being
+    /// sent
+    ///
+    /// A token timed out before it was written is never written.
+    pub fn wait(self, timeout: Duration) -> TcsResult<Duration> {
+        match self.echoed.recv_timeout(timeout.saturating_sub(self.sent.elapsed())) {
+            Ok(echoed) => Ok(echoed.saturating_duration_since(self.sent)),
+            Err(_) => {
+                self.echo.cancel();
+                self.receipt.withdraw();
+                Err(TcsError::Timeout)
+            }
+        }
+    }
+}
+
+/// Check a data handler configuration for values that can never work
+pub fn validate_config(config: &DHConfig) -> TcsResult<()> {
+    if config.packet_size == 0 || config.packet_size > ENDPOINT_BUFFER_SIZE {
+        return Err(TcsError::Config(format!(
+            "Packet size {} is outside 1 to {}",
+            config.packet_size, ENDPOINT_BUFFER_SIZE
+        )));
+    }
+    validate_conduit_options(&config.conduit)?;
+
+    match &config.endpoint {
+        EndpointConfig::Network(net_config) if !NETWORK_ENDPOINT_PROTOCOLS.contains(&net_config.protocol) => {
+            Err(TcsError::Config(format!("Unsupported network protocol {}", net_config.protocol)))
+        }
+        EndpointConfig::Device(DeviceConfig { path }) | EndpointConfig::Serial(SerialConfig { path, .. })
+            if path.is_empty() =>
+        {
+            Err(TcsError::Config("Empty device path".to_string()))
+        }
+        EndpointConfig::Unix(UnixConfig { path, .. }) if path.is_empty() => {
+            Err(TcsError::Config("Empty socket path".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_conduit_options(options: &ConduitOptions) -> TcsResult<()> {
+    for (what, size) in [("Read", options.read_buffer_size), ("Write", options.write_buffer_size)] {
+        if let Some(size) = size.filter(|&size| size == 0 || size > ENDPOINT_BUFFER_SIZE) {
+            return Err(TcsError::Config(format!(
+                "{} buffer size {} is outside 1 to {}",
+                what, size, ENDPOINT_BUFFER_SIZE
+            )));
+        }
+    }
+    if let Some(coalesce_ms) = options.coalesce_ms.filter(|&ms| ms > STREAM_EP_DELAY.as_millis() as u64) {
+        return Err(TcsError::Config(format!(
+            "Coalescing time {}ms is longer than the {}ms a stream read is held",
+            coalesce_ms,
+            STREAM_EP_DELAY.as_millis()
+        )));
+    }
+    if options.rate_limit_bps == Some(0) {
+        return Err(TcsError::Config("Rate limit of 0 bytes per second".to_string()));
+    }
+    Ok(())
+}
+
+/// Payload endpoints, or the thread still connecting to a TCP payload
+enum PayloadConnection {
+    Ready(EndpointPair),
+    Connecting(JoinHandle<TcsResult<EndpointPair>>),
+}
+
+/// Open a payload endpoint, connecting to a TCP payload on a thread of its
+/// own so a payload that isn't up yet doesn't hold up the caller
+fn open_payload(config: &EndpointConfig) -> TcsResult<PayloadConnection> {
+    match config {
+        EndpointConfig::Network(net_config) if net_config.protocol == NetworkProtocol::Tcp => {
+            let net_config = net_config.clone();
+            Ok(PayloadConnection::Connecting(thread::spawn(move || connect_network_payload(&net_config))))
+        }
+        _ => create_payload_endpoints(config).map(PayloadConnection::Ready),
+    }
+}
+
+/// Create the reader and writer for a payload endpoint other than a TCP one
+fn create_payload_endpoints(config: &EndpointConfig) -> TcsResult<EndpointPair> {
+    match config {
+        EndpointConfig::Unix(unix_config) => {
+            // A path can only be bound once, so both directions share the socket
+            let reader = UnixEndpoint::new(unix_config)?;
+            let writer = reader.try_clone()?;
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        // Both directions share a socket, connected to the payload's address
+        // or, unconnected, bound to it and replying to whoever last sent
+        EndpointConfig::Network(net_config) if net_config.protocol == NetworkProtocol::Udp => {
+            let reader = match net_config.udp_mode {
+                UdpMode::Connected => UdpEndpoint::new_client(net_config)?,
+                UdpMode::Unconnected => UdpEndpoint::new(net_config)?,
+            };
+            let writer = reader.try_clone()?;
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        _ => Ok((create_reader_endpoint(config)?, create_writer_endpoint(config)?)),
+    }
+}
+
+/// Connect to a payload listening for TCP connections, retrying with backoff
+/// while it isn't, with both directions sharing the connection
+fn connect_network_payload(config: &NetworkConfig) -> TcsResult<EndpointPair> {
+    let reader = TcpEndpoint::connect(config)?;
+    let writer = reader.try_clone()?;
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+/// Add a conduit's statistics to a data handler's
+///
+/// The data handler receives what the ground-to-payload conduit reads from
+/// the OC and sends what the payload-to-ground conduit writes to it; a
+/// bidirectional conduit has already combined the two.
+fn fold_conduit_stats(total: &mut Statistics, direction: ConduitDirection, stats: &Statistics) {
+    match direction {
+        ConduitDirection::Bidirectional => total.accumulate(stats),
+        ConduitDirection::GroundToPayload => {
+            total.bytes_received += stats.bytes_received;
+            total.reads_completed += stats.reads_completed;
+            total.reads_failed += stats.reads_failed;
+            total.messages_received += stats.messages_received;
+        }
+        ConduitDirection::PayloadToGround => {
+            total.bytes_sent += stats.bytes_sent;
+            total.writes_completed += stats.writes_completed;
+            total.writes_failed += stats.writes_failed;
+            total.messages_sent += stats.messages_sent;
+        }
+    }
+}
+
+/// Create a command pipe, returning its read and write ends
+fn create_pipe() -> TcsResult<(RawFd, RawFd)> {
+    let mut pipe_fds = [0i32; 2];
+    if unsafe { libc::pipe(pipe_fds.as_mut_ptr()) } != 0 {
+        return Err(TcsError::Io(std::io::Error::last_os_error()));
+    }
+    Ok((pipe_fds[0], pipe_fds[1]))
+}
+
+fn close_pipe((read_fd, write_fd): (RawFd, RawFd)) {
+    unsafe {
+        libc::close(read_fd);
+        libc::close(write_fd);
+    }
+}
+
+/// Discard commands left in the pipe by a conduit that has stopped
+fn drain_pipe(fd: RawFd) {
+    let mut pending: libc::c_int = 0;
+    unsafe {
+        libc::ioctl(fd, libc::FIONREAD, &mut pending);
+    }
+    let mut buf = [0u8; 64];
+    while pending > 0 {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len().min(pending as usize)) };
+        if n <= 0 {
+            break;
+        }
+        pending -= n as libc::c_int;
+    }
+}
+
 impl Drop for DataHandler {
     fn drop(&mut self) {
-        if self.state == DHState::Active {
+        if matches!(self.state, DHState::Active | DHState::Paused | DHState::Faulted) {
             let _ = self.stop();
         }
+        // A data handler that never started, or whose stop failed, still
+        // holds its command pipes
+        for pipe in self.cmd_pipes.take().into_iter().flatten() {
+            close_pipe(pipe);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tcslibgs::{DeviceConfig, EndpointConfig, DHName};
+    use tcslibgs::{ConduitOptions, DeviceConfig, EndpointConfig, DHName};
 
     #[test]
     fn test_dh_creation() {
@@ -186,6 +831,7 @@ mod tests {
             }),
             packet_size: 64,
             packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
         };
 
         let dh = DataHandler::new(config);
@@ -194,4 +840,573 @@ mod tests {
         let dh = dh.unwrap();
         assert_eq!(dh.state(), DHState::Created);
     }
+
+    #[test]
+    fn test_dh_drop_closes_pipes() {
+        let open_fds = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Device(DeviceConfig {
+                path: "/dev/null".to_string(),
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+
+        // Other tests open fds concurrently, so only look for a steady leak:
+        // four per cycle if the pipes of a created DH outlived it
+        const CYCLES: usize = 50;
+        let before = open_fds();
+        for _ in 0..CYCLES {
+            drop(DataHandler::new(config.clone()).unwrap());
+        }
+        let after = open_fds();
+        assert!(after < before + CYCLES, "{} fds open before, {} after", before, after);
+    }
+
+    #[test]
+    fn test_dh_unavailable_address() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        // 192.0.2.0/24 is reserved for documentation and is never local;
+        // unconnected, the DH binds the configured address
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Udp,
+                address: "192.0.2.1".to_string(),
+                port: Port::ANY,
+                udp_mode: UdpMode::Unconnected,
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        let mut dh = DataHandler::new(config).unwrap();
+        let result = dh.start(
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+        );
+
+        match result {
+            Err(TcsError::InvalidConfiguration(msg)) => assert!(msg.contains("192.0.2.1")),
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Start succeeded on a non-local address"),
+        }
+        assert_eq!(dh.state(), DHState::Created);
+    }
+
+    #[test]
+    fn test_dh_buffers_recycled() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{BufferPool, NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        const HANDLERS: u64 = 20;
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let before = BufferPool::global().stats();
+
+        for id in 0..HANDLERS as u32 {
+            let config = DHConfig {
+                dh_id: DHId(id),
+                name: DHName::new("Test"),
+                endpoint: EndpointConfig::Device(DeviceConfig {
+                    path: "/dev/null".to_string(),
+                }),
+                packet_size: 64,
+                packet_interval_ms: 100,
+                conduit: ConduitOptions::default(),
+            };
+            let mut dh = DataHandler::new(config).unwrap();
+            dh.start(
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            )
+            .unwrap();
+            dh.stop().unwrap();
+        }
+
+        // Each handler uses four endpoint buffers; all but the first set are reused
+        let after = BufferPool::global().stats();
+        assert!(after.hits - before.hits >= HANDLERS);
+    }
+
+    /// Call reconnect_payload until the relays start on a connected payload
+    fn wait_for_payload(dh: &mut DataHandler) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !dh.reconnect_payload().unwrap() {
+            assert!(Instant::now() < deadline, "Payload never connected");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_dh_late_payload() {
+        use std::io::Read;
+        use std::net::{TcpListener, UdpSocket};
+        use tcslibgs::{NetworkConfig, Port, UdpMode};
+
+        // Find a free port, then leave it closed until the payload starts
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let payload = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(250));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut uplink = [0u8; 6];
+            stream.read_exact(&mut uplink).unwrap();
+            uplink
+        });
+
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Tcp,
+                address: "127.0.0.1".to_string(),
+                port: Port(port),
+                udp_mode: UdpMode::Connected,
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let oc = OcEndpoint::new(&NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        })
+        .unwrap();
+        let mut dh = DataHandler::new(config).unwrap();
+
+        // Starting doesn't wait for the payload
+        let started = Instant::now();
+        dh.start_oc(oc.clone()).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert_eq!(dh.state(), DHState::Active);
+        assert!(!dh.payload_connected());
+
+        // The first connects are refused; the retries reach the payload once it is up
+        wait_for_payload(&mut dh);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(dh.payload_connected());
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.send_to(b"uplink", oc.local_addr().unwrap()).unwrap();
+        assert_eq!(&payload.join().unwrap(), b"uplink");
+
+        dh.stop().unwrap();
+    }
+
+    #[test]
+    fn test_dh_stop_before_uplink() {
+        use std::io::Read;
+        use std::net::TcpListener;
+        use tcslibgs::{NetworkConfig, Port, UdpMode};
+
+        // The payload sends nothing and closes once the DH half-closes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Tcp,
+                address: "127.0.0.1".to_string(),
+                port: Port(port),
+                udp_mode: UdpMode::Connected,
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let oc = OcEndpoint::new(&NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        })
+        .unwrap();
+
+        // No uplink arrives, so the OC is never heard from
+        let mut dh = DataHandler::new(config).unwrap();
+        dh.start_oc(oc).unwrap();
+        wait_for_payload(&mut dh);
+
+        let (stopped_tx, stopped_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || stopped_tx.send(dh.stop()).unwrap());
+        let stopped = stopped_rx.recv_timeout(Duration::from_secs(5)).expect("DH didn't stop");
+        assert!(stopped.is_ok());
+    }
+
+    #[test]
+    fn test_dh_payload_reconnect() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, UdpSocket};
+        use tcslibgs::{Port, UdpMode};
+
+        // The payload sends once, goes away, then comes back on the same port
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let payload = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"first").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            drop(stream);
+            drop(listener);
+
+            thread::sleep(Duration::from_millis(150));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"second").unwrap();
+            let mut uplink = [0u8; 6];
+            stream.read_exact(&mut uplink).unwrap();
+            uplink
+        });
+
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                protocol: NetworkProtocol::Tcp,
+                address: "127.0.0.1".to_string(),
+                port: Port(port),
+                udp_mode: UdpMode::Connected,
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let oc = OcEndpoint::new(&NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        })
+        .unwrap();
+        oc.set_destination(ground.local_addr().unwrap());
+
+        let mut dh = DataHandler::new(config).unwrap();
+        dh.start_oc(oc.clone()).unwrap();
+        wait_for_payload(&mut dh);
+        let mut buf = [0u8; 16];
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first");
+
+        // Connected again once the payload is back, relaying both ways
+        wait_for_payload(&mut dh);
+        assert!(dh.payload_connected());
+        assert_eq!(dh.statistics().bytes_sent, 5);
+        let n = ground.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"second");
+        ground.send_to(b"uplink", oc.local_addr().unwrap()).unwrap();
+        assert_eq!(&payload.join().unwrap(), b"uplink");
+        assert_eq!(dh.state(), DHState::Active);
+
+        dh.stop().unwrap();
+    }
+
+    #[test]
+    fn test_dh_active_duration() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Device(DeviceConfig {
+                path: "/dev/null".to_string(),
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+
+        let mut dh = DataHandler::new(config).unwrap();
+        assert_eq!(dh.statistics().active_duration_ms, 0);
+
+        let started = Instant::now();
+        dh.start(
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let reported = dh.statistics().active_duration_ms;
+        let elapsed = started.elapsed().as_millis() as u64;
+        assert!((100..=elapsed).contains(&reported), "reported {} ms, elapsed {} ms", reported, elapsed);
+
+        // The duration stops growing once the DH is stopped
+        dh.stop().unwrap();
+        let stopped = dh.statistics().active_duration_ms;
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(dh.statistics().active_duration_ms, stopped);
+    }
+
+    #[test]
+    fn test_dh_stop_blocked_relays() {
+        use crate::endpoint::UdpEndpoint;
+        use std::net::UdpSocket;
+        use std::sync::mpsc;
+        use tcslibgs::{IoMode, NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        // A payload that never sends, so both relays block waiting for data
+        let payload = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Network(NetworkConfig {
+                port: Port(payload.local_addr().unwrap().port()),
+                ..oc_config.clone()
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions { io_mode: IoMode::Blocking, ..ConduitOptions::default() },
+        };
+
+        // Either relay may take either stop request, so try it a few times
+        for _ in 0..10 {
+            let mut dh = DataHandler::new(config.clone()).unwrap();
+            dh.start(
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            )
+            .unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+
+            let (stopped_tx, stopped_rx) = mpsc::channel();
+            thread::spawn(move || stopped_tx.send(dh.stop()).unwrap());
+            let stopped = stopped_rx.recv_timeout(Duration::from_secs(5)).expect("DH didn't stop");
+            assert!(stopped.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_dh_checkpoint() {
+        use std::net::UdpSocket;
+        use tcslibgs::{NetworkConfig, Port, UdpMode};
+
+        // The payload is a pipe, so whatever is written to it is read back
+        let mut pipe_fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Device(DeviceConfig {
+                path: format!("/proc/self/fd/{}", pipe_fds[0]),
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let oc = OcEndpoint::new(&NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        })
+        .unwrap();
+        oc.set_destination(ground.local_addr().unwrap());
+
+        let mut dh = DataHandler::new(config).unwrap();
+        dh.start_oc(oc.clone()).unwrap();
+        assert_eq!(dh.since_checkpoint(), None);
+
+        // Relay data through the payload and back, waiting until it is counted
+        let echo = |dh: &DataHandler, data: &[u8]| {
+            let before = dh.statistics();
+            ground.send_to(data, oc.local_addr().unwrap()).unwrap();
+            let mut buf = [0u8; 64];
+            let n = ground.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], data);
+            let len = data.len() as u64;
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while Instant::now() < deadline {
+                let stats = dh.statistics();
+                if stats.bytes_received >= before.bytes_received + len && stats.bytes_sent >= before.bytes_sent + len {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        echo(&dh, b"before checkpoint");
+        dh.checkpoint();
+        echo(&dh, b"after");
+
+        let delta = dh.since_checkpoint().unwrap();
+        assert_eq!((delta.bytes_received, delta.bytes_sent), (5, 5));
+        assert_eq!(dh.statistics().bytes_received, 22);
+
+        // A new checkpoint starts the count again, and a reset drops it
+        dh.checkpoint();
+        assert_eq!(dh.since_checkpoint().unwrap().bytes_received, 0);
+        dh.reset_statistics();
+        assert_eq!(dh.since_checkpoint(), None);
+        assert_eq!(dh.statistics().bytes_sent, 0);
+
+        // Stopping keeps what the relays moved
+        echo(&dh, b"stop");
+        dh.stop().unwrap();
+        assert_eq!((dh.statistics().bytes_received, dh.statistics().bytes_sent), (4, 4));
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::close(pipe_fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_dh_reconfigure() {
+        use crate::endpoint::UdpEndpoint;
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+        let oc_endpoints = || -> Option<(Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>)> {
+            Some((
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+                Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            ))
+        };
+        let config = DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Device(DeviceConfig {
+                path: "/dev/null".to_string(),
+            }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+
+        let mut dh = DataHandler::new(config.clone()).unwrap();
+        let (oc_reader, oc_writer) = oc_endpoints().unwrap();
+        dh.start(oc_reader, oc_writer).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let before = dh.statistics();
+
+        // Packet size and protocol change together
+        let new_config = DHConfig {
+            name: DHName::new("Reconfigured"),
+            endpoint: EndpointConfig::Network(NetworkConfig { udp_mode: UdpMode::Unconnected, ..oc_config.clone() }),
+            packet_size: 1024,
+            ..config.clone()
+        };
+        dh.reconfigure(new_config.clone(), oc_endpoints()).unwrap();
+        assert_eq!(dh.state(), DHState::Active);
+        assert_eq!(dh.config(), &new_config);
+        assert_eq!(dh.name(), &DHName::new("Reconfigured"));
+        assert!(dh.statistics().active_duration_ms >= before.active_duration_ms);
+
+        // Invalid configurations change nothing
+        let invalid = [
+            DHConfig { packet_size: 0, ..config.clone() },
+            DHConfig { dh_id: DHId(1), ..config.clone() },
+            DHConfig {
+                endpoint: EndpointConfig::Network(NetworkConfig {
+                    protocol: NetworkProtocol::UnixDgram,
+                    ..oc_config.clone()
+                }),
+                ..config.clone()
+            },
+        ];
+        for invalid in invalid {
+            assert!(matches!(dh.reconfigure(invalid, oc_endpoints()), Err(TcsError::Config(_))));
+            assert_eq!(dh.config(), &new_config);
+            assert_eq!(dh.state(), DHState::Active);
+        }
+
+        // Relays can't be restarted without OC endpoints
+        assert!(dh.reconfigure(config.clone(), None).is_err());
+        assert_eq!(dh.config(), &new_config);
+
+        dh.stop().unwrap();
+        dh.reconfigure(config.clone(), None).unwrap();
+        assert_eq!(dh.config(), &config);
+    }
+
+    #[test]
+    fn test_dh_stop_faulted() {
+        use crate::endpoint::UdpEndpoint;
+        use std::ffi::CString;
+        use tcslibgs::{NetworkConfig, Port, UdpMode};
+
+        // A FIFO payload that is gone by the time the relays are restarted
+        let path = std::env::temp_dir().join(format!("tcspecial-faulted-{}", std::process::id()));
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let device = |path: &str| DHConfig {
+            dh_id: DHId(0),
+            name: DHName::new("Test"),
+            endpoint: EndpointConfig::Device(DeviceConfig { path: path.to_string() }),
+            packet_size: 64,
+            packet_interval_ms: 100,
+            conduit: ConduitOptions::default(),
+        };
+        let oc_config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Connected,
+        };
+
+        let mut dh = DataHandler::new(device(path.to_str().unwrap())).unwrap();
+        dh.start(
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+            Box::new(UdpEndpoint::new(&oc_config).unwrap()),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Neither the new payload nor the old one can be opened
+        let oc = UdpEndpoint::new(&oc_config).unwrap();
+        let endpoints: (Box<dyn EndpointReadable + Send>, Box<dyn EndpointWritable + Send>) =
+            (Box::new(oc.try_clone().unwrap()), Box::new(oc));
+        assert!(dh.reconfigure(device("/nonexistent/tcspecial"), Some(endpoints)).is_err());
+        assert_eq!(dh.state(), DHState::Faulted);
+
+        dh.stop().unwrap();
+        assert_eq!(dh.state(), DHState::Stopped);
+        assert!(dh.cmd_pipes.is_none());
+        assert!(!dh.is_running());
+    }
 }
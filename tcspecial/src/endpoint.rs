@@ -4,14 +4,25 @@
 
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
-use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::linux::net::SocketAddrExt;
 use std::os::unix::io::{AsRawFd, RawFd};
-//use std::time::Duration;
+use std::os::unix::net::{SocketAddr as UnixAddr, UnixDatagram, UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{self, BaudRate, ControlFlags, SetArg, Termios};
 use std::os::fd::BorrowedFd;
-use tcslibgs::{DeviceConfig, EndpointConfig, NetworkConfig, NetworkProtocol, TcsError, TcsResult};
+use tcslibgs::{
+    BufferPool, DHType, DeviceConfig, EndpointConfig, NetworkConfig, NetworkProtocol, Parity, PooledBuffer, Port,
+    SerialConfig, TcsError, TcsResult, UdpMode, UnixConfig, UnixMode,
+};
 
-use crate::config::constants::{ENDPOINT_BUFFER_SIZE, /*ENDPOINT_DELAY_INIT, ENDPOINT_DELAY_MAX, ENDPOINT_MAX_RETRIES*/};
+use crate::config::constants::{ENDPOINT_BUFFER_SIZE, ENDPOINT_DELAY_INIT, ENDPOINT_DELAY_MAX, ENDPOINT_RETRY_LIMIT};
+use crate::endpoint_network::{ip_socket_config, open_socket};
 
 /// Trait for endpoints that can wait for events
 pub trait EndpointWaitable {
@@ -20,6 +31,11 @@ pub trait EndpointWaitable {
 
     /// Wait for an event on this endpoint
     fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult>;
+
+    /// Put the I/O file descriptor in blocking or non-blocking mode
+    fn set_blocking(&self, blocking: bool) -> TcsResult<()> {
+        set_fd_blocking(self.io_fd(), blocking)
+    }
 }
 
 /// Result of waiting for an event
@@ -41,63 +57,225 @@ pub enum WaitResult {
 pub trait EndpointReadable: EndpointWaitable {
     /// Read data from the endpoint
     fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize>;
+
+    /// True if each successful read returns exactly one message
+    fn is_datagram(&self) -> bool {
+        false
+    }
+
+    /// True once the peer has closed the connection, so nothing more will
+    /// be read
+    fn is_closed(&self) -> bool {
+        false
+    }
 }
 
 /// Trait for writable endpoints
 pub trait EndpointWritable: EndpointWaitable {
     /// Write data to the endpoint
     fn write(&mut self, data: &[u8]) -> TcsResult<usize>;
+
+    /// Stop sending, telling the peer no more data is coming while still
+    /// allowing it to reply; does nothing for endpoints with no half-close
+    fn shutdown_write(&mut self) -> TcsResult<()> {
+        Ok(())
+    }
+
+    /// True if each successful write sends exactly one message
+    fn is_datagram(&self) -> bool {
+        false
+    }
+
+    /// False while there is nowhere to send to, so writes would only fail
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    /// Write what the endpoint can of data it accepted but held back,
+    /// returning true if some is still held; endpoints that hold nothing
+    /// back return false
+    fn flush(&mut self) -> TcsResult<bool> {
+        Ok(false)
+    }
 }
 
-/// Helper function to wait for events on file descriptors
-fn wait_for_fds(io_fd: RawFd, cmd_fd: RawFd, io_events: PollFlags, timeout_ms: i32) -> TcsResult<WaitResult> {
-    let io_borrowed = unsafe { BorrowedFd::borrow_raw(io_fd) };
-    let cmd_borrowed = unsafe { BorrowedFd::borrow_raw(cmd_fd) };
+/// Set or clear O_NONBLOCK on a file descriptor
+fn set_fd_blocking(fd: RawFd, blocking: bool) -> TcsResult<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(TcsError::Io(io::Error::last_os_error()));
+    }
+    let flags = if blocking { flags & !libc::O_NONBLOCK } else { flags | libc::O_NONBLOCK };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(TcsError::Io(io::Error::last_os_error()));
+    }
+    Ok(())
+}
 
-    let mut poll_fds = [
-        PollFd::new(&io_borrowed, io_events),
-        PollFd::new(&cmd_borrowed, PollFlags::POLLIN),
-    ];
+/// Result of waiting for events on several I/O file descriptors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadyFds {
+    /// I/O file descriptors that are readable, in the order given
+    pub io: Vec<RawFd>,
+    /// True if a command is pending
+    pub command: bool,
+    /// File descriptors reporting only an error or hangup
+    pub failed: Vec<RawFd>,
+}
 
+impl ReadyFds {
+    /// True if nothing happened before the timeout
+    pub fn is_timeout(&self) -> bool {
+        self.io.is_empty() && !self.command && self.failed.is_empty()
+    }
+}
+
+/// Wait until any of several I/O file descriptors is readable or a command
+/// is pending, so a conduit can watch multiple endpoints in one poll
+pub fn wait_for_fds_multi(io_fds: &[RawFd], cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<ReadyFds> {
+    let borrowed: Vec<BorrowedFd> = io_fds
+        .iter()
+        .chain(std::iter::once(&cmd_fd))
+        .map(|fd| unsafe { BorrowedFd::borrow_raw(*fd) })
+        .collect();
+    let mut poll_fds: Vec<PollFd> = borrowed.iter().map(|fd| PollFd::new(fd, PollFlags::POLLIN)).collect();
+
+    let mut ready = ReadyFds::default();
     match poll(&mut poll_fds, timeout_ms) {
-        Ok(0) => Ok(WaitResult::Timeout),
+        Ok(0) => {}
         Ok(_) => {
-            let io_ready = poll_fds[0].revents().map_or(false, |r| r.intersects(io_events));
-            let cmd_ready = poll_fds[1].revents().map_or(false, |r| r.contains(PollFlags::POLLIN));
-
-            match (io_ready, cmd_ready) {
-                (true, true) => Ok(WaitResult::Both),
-                (true, false) => Ok(WaitResult::IoReady),
-                (false, true) => Ok(WaitResult::CommandPending),
-                (false, false) => Ok(WaitResult::Error),
+            for (i, poll_fd) in poll_fds.iter().enumerate() {
+                let fd = io_fds.get(i).copied().unwrap_or(cmd_fd);
+                let revents = poll_fd.revents().unwrap_or(PollFlags::empty());
+                if !revents.contains(PollFlags::POLLIN) {
+                    if !revents.is_empty() {
+                        ready.failed.push(fd);
+                    }
+                } else if i < io_fds.len() {
+                    ready.io.push(fd);
+                } else {
+                    ready.command = true;
+                }
             }
         }
-        Err(e) => Err(TcsError::Io(io::Error::from_raw_os_error(e as i32))),
+        Err(e) => return Err(TcsError::Io(io::Error::from_raw_os_error(e as i32))),
+    }
+    Ok(ready)
+}
+
+/// Helper function to wait for events on one I/O file descriptor and the
+/// command file descriptor
+fn wait_for_fds(io_fd: RawFd, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+    let ready = wait_for_fds_multi(&[io_fd], cmd_fd, timeout_ms)?;
+
+    match (!ready.io.is_empty(), ready.command) {
+        (true, true) => Ok(WaitResult::Both),
+        (true, false) => Ok(WaitResult::IoReady),
+        (false, true) => Ok(WaitResult::CommandPending),
+        (false, false) if ready.failed.is_empty() => Ok(WaitResult::Timeout),
+        (false, false) => Ok(WaitResult::Error),
+    }
+}
+
+/// Convert a bind failure into an error, naming the address if it isn't
+/// present on this host
+fn bind_error(addr: &str, e: io::Error) -> TcsError {
+    if e.kind() == io::ErrorKind::AddrNotAvailable {
+        TcsError::InvalidConfiguration(format!("Local address {} is not available on this host", addr))
+    } else {
+        TcsError::Io(e)
     }
 }
 
+/// Bind a UDP socket, first setting SO_REUSEADDR if asked so that a quickly
+/// restarted process can rebind its address at once
+///
+/// SO_REUSEPORT is not set since it would let a second process bind the same
+/// port and silently take part of the traffic.
+pub fn bind_udp(addr: SocketAddr, reuse_address: bool) -> io::Result<UdpSocket> {
+    let config = ip_socket_config(NetworkProtocol::Udp, &addr).map_err(io::Error::other)?;
+    let socket = open_socket(&config)?;
+    if reuse_address {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
 /// UDP endpoint for network communication
+///
+/// In unconnected mode the endpoint sends to the address it last received
+/// from, so payloads that reply from varying source ports still work.
 pub struct UdpEndpoint {
     socket: UdpSocket,
-    _buffer: Vec<u8>,
+    _buffer: PooledBuffer,
+    mode: UdpMode,
+    /// Peer last heard from in unconnected mode, shared with clones
+    peer: Arc<Mutex<Option<SocketAddr>>>,
 }
 
 impl UdpEndpoint {
     pub fn new(config: &NetworkConfig) -> TcsResult<Self> {
         let addr = format!("{}:{}", config.address, config.port);
-        let socket = UdpSocket::bind(&addr)?;
+        let socket = UdpSocket::bind(&addr).map_err(|e| bind_error(&addr, e))?;
         socket.set_nonblocking(true)?;
 
         Ok(Self {
             socket,
-            _buffer: vec![0u8; ENDPOINT_BUFFER_SIZE],
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+            mode: config.udp_mode,
+            peer: Arc::default(),
         })
     }
 
+    /// Create an endpoint sending to and receiving from the peer at the
+    /// configured address, from a port the OS picks
+    pub fn new_client(config: &NetworkConfig) -> TcsResult<Self> {
+        config.port.connectable()?;
+        let ipv6 = config.address.contains(':');
+        let local = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: if ipv6 { "::" } else { "0.0.0.0" }.to_string(),
+            port: Port::ANY,
+            udp_mode: config.udp_mode,
+        };
+        let endpoint = Self::new(&local)?;
+        let peer = if ipv6 {
+            format!("[{}]:{}", config.address, config.port)
+        } else {
+            format!("{}:{}", config.address, config.port)
+        };
+        endpoint.connect(&peer)?;
+        Ok(endpoint)
+    }
+
+    /// Create another endpoint on the same socket and peer
+    pub fn try_clone(&self) -> TcsResult<Self> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+            mode: self.mode,
+            peer: Arc::clone(&self.peer),
+        })
+    }
+
+    /// Set the peer; in unconnected mode this is only the initial peer
     pub fn connect(&self, addr: &str) -> TcsResult<()> {
-        self.socket.connect(addr)?;
+        match self.mode {
+            UdpMode::Connected => self.socket.connect(addr)?,
+            UdpMode::Unconnected => {
+                let peer = addr
+                    .parse()
+                    .map_err(|e| TcsError::Config(format!("Invalid peer address {}: {}", addr, e)))?;
+                *self.peer.lock().unwrap() = Some(peer);
+            }
+        }
         Ok(())
     }
+
+    pub fn local_addr(&self) -> TcsResult<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
 }
 
 impl EndpointWaitable for UdpEndpoint {
@@ -106,71 +284,231 @@ impl EndpointWaitable for UdpEndpoint {
     }
 
     fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
-        wait_for_fds(self.io_fd(), cmd_fd, PollFlags::POLLIN, timeout_ms)
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
     }
 }
 
 impl EndpointReadable for UdpEndpoint {
     fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
-        match self.socket.recv(buffer) {
+        let result = match self.mode {
+            UdpMode::Connected => self.socket.recv(buffer),
+            UdpMode::Unconnected => self.socket.recv_from(buffer).map(|(n, from)| {
+                *self.peer.lock().unwrap() = Some(from);
+                n
+            }),
+        };
+        match result {
             Ok(n) => Ok(n),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
             Err(e) => Err(TcsError::Io(e)),
         }
     }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
 }
 
 impl EndpointWritable for UdpEndpoint {
     fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
-        match self.socket.send(data) {
+        let result = match self.mode {
+            UdpMode::Connected => self.socket.send(data),
+            UdpMode::Unconnected => match *self.peer.lock().unwrap() {
+                Some(peer) => self.socket.send_to(data, peer),
+                None => return Err(TcsError::Endpoint("No UDP peer address known yet".to_string())),
+            },
+        };
+        match result {
             Ok(n) => Ok(n),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
             Err(e) => Err(TcsError::Io(e)),
         }
     }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+}
+
+/// The OC side of a data handler: the socket facing the ground and the
+/// address downlink currently goes to
+///
+/// One OcEndpoint is shared by all of a data handler's conduits, so changing
+/// the destination retargets downlink from every conduit at once. Uplink is
+/// read from whichever ground station sends it; with no destination set,
+/// downlink goes to the one that sent the first uplink.
+///
+/// The OC counts as disconnected once disconnect is called or, with a silence
+/// timeout set, once nothing has been heard from it for that long. Setting a
+/// destination or receiving uplink connects it again.
+pub struct OcEndpoint {
+    socket: UdpSocket,
+    destination: Mutex<Option<SocketAddr>>,
+    /// When the OC was last set as the destination or sent uplink
+    last_heard: Mutex<Instant>,
+    silence_timeout: Mutex<Option<Duration>>,
+}
+
+impl OcEndpoint {
+    pub fn new(config: &NetworkConfig) -> TcsResult<Arc<Self>> {
+        let addr = format!("{}:{}", config.address, config.port);
+        let socket = UdpSocket::bind(&addr).map_err(|e| bind_error(&addr, e))?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Arc::new(Self {
+            socket,
+            destination: Mutex::new(None),
+            last_heard: Mutex::new(Instant::now()),
+            silence_timeout: Mutex::new(None),
+        }))
+    }
+
+    /// Send downlink to addr from the next write on
+    pub fn set_destination(&self, addr: SocketAddr) {
+        *self.destination.lock().unwrap() = Some(addr);
+        self.heard();
+    }
+
+    /// Forget the destination, holding downlink until another is set
+    pub fn disconnect(&self) {
+        *self.destination.lock().unwrap() = None;
+    }
+
+    /// Treat the OC as disconnected after hearing nothing from it for
+    /// timeout, or never if None
+    pub fn set_silence_timeout(&self, timeout: Option<Duration>) {
+        *self.silence_timeout.lock().unwrap() = timeout;
+    }
+
+    /// Check whether there is an OC to send downlink to
+    pub fn is_connected(&self) -> bool {
+        let silent = self
+            .silence_timeout
+            .lock()
+            .unwrap()
+            .is_some_and(|timeout| self.last_heard.lock().unwrap().elapsed() >= timeout);
+        self.destination().is_some() && !silent
+    }
+
+    fn heard(&self) {
+        *self.last_heard.lock().unwrap() = Instant::now();
+    }
+
+    /// Get the address downlink is sent to, if set
+    pub fn destination(&self) -> Option<SocketAddr> {
+        *self.destination.lock().unwrap()
+    }
+
+    pub fn local_addr(&self) -> TcsResult<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+impl EndpointWaitable for Arc<OcEndpoint> {
+    fn io_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
+    }
+}
+
+impl EndpointReadable for Arc<OcEndpoint> {
+    fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+        match self.socket.recv_from(buffer) {
+            Ok((n, from)) => {
+                self.destination.lock().unwrap().get_or_insert(from);
+                self.heard();
+                Ok(n)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+}
+
+impl EndpointWritable for Arc<OcEndpoint> {
+    fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+        let destination = self
+            .destination()
+            .ok_or_else(|| TcsError::Endpoint("No OC destination set".to_string()))?;
+        match self.socket.send_to(data, destination) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+
+    fn is_connected(&self) -> bool {
+        OcEndpoint::is_connected(self)
+    }
 }
 
 /// TCP endpoint for stream communication
 pub struct TcpEndpoint {
     stream: Option<TcpStream>,
     listener: Option<TcpListener>,
-    _buffer: Vec<u8>,
+    /// The peer closed the connection
+    closed: bool,
+    _buffer: PooledBuffer,
     _is_server: bool,
 }
 
 impl TcpEndpoint {
     pub fn new_server(config: &NetworkConfig) -> TcsResult<Self> {
         let addr = format!("{}:{}", config.address, config.port);
-        let listener = TcpListener::bind(&addr)?;
+        let listener = TcpListener::bind(&addr).map_err(|e| bind_error(&addr, e))?;
         listener.set_nonblocking(true)?;
 
         Ok(Self {
             stream: None,
             listener: Some(listener),
-            _buffer: vec![0u8; ENDPOINT_BUFFER_SIZE],
+            closed: false,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
             _is_server: true,
         })
     }
 
     pub fn new_client(config: &NetworkConfig) -> TcsResult<Self> {
-        let addr = format!("{}:{}", config.address, config.port);
+        let addr = format!("{}:{}", config.address, config.port.connectable()?);
         let stream = TcpStream::connect(&addr)?;
         stream.set_nonblocking(true)?;
 
         Ok(Self {
             stream: Some(stream),
             listener: None,
-            _buffer: vec![0u8; ENDPOINT_BUFFER_SIZE],
+            closed: false,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
             _is_server: false,
         })
     }
 
+    /// Connect to a payload that may not be listening yet, retrying with
+    /// backoff before giving up
+    pub fn connect(config: &NetworkConfig) -> TcsResult<Self> {
+        // No amount of retrying will connect to port 0
+        config.port.connectable()?;
+        retry_with_backoff(ENDPOINT_DELAY_INIT, ENDPOINT_DELAY_MAX, ENDPOINT_RETRY_LIMIT, || {
+            Self::new_client(config)
+        })
+    }
+
     pub fn accept(&mut self) -> TcsResult<bool> {
         if let Some(ref listener) = self.listener {
             match listener.accept() {
                 Ok((stream, _)) => {
                     stream.set_nonblocking(true)?;
                     self.stream = Some(stream);
+                    self.closed = false;
                     Ok(true)
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
@@ -184,6 +522,18 @@ impl TcpEndpoint {
     pub fn is_connected(&self) -> bool {
         self.stream.is_some()
     }
+
+    /// Create another endpoint on the same connection, so one conduit can
+    /// read it while another writes it
+    pub fn try_clone(&self) -> TcsResult<Self> {
+        Ok(Self {
+            stream: self.stream.as_ref().map(TcpStream::try_clone).transpose()?,
+            listener: self.listener.as_ref().map(TcpListener::try_clone).transpose()?,
+            closed: self.closed,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+            _is_server: self._is_server,
+        })
+    }
 }
 
 impl EndpointWaitable for TcpEndpoint {
@@ -198,7 +548,7 @@ impl EndpointWaitable for TcpEndpoint {
     }
 
     fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
-        wait_for_fds(self.io_fd(), cmd_fd, PollFlags::POLLIN, timeout_ms)
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
     }
 }
 
@@ -206,6 +556,10 @@ impl EndpointReadable for TcpEndpoint {
     fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
         if let Some(ref mut stream) = self.stream {
             match stream.read(buffer) {
+                Ok(0) if !buffer.is_empty() => {
+                    self.closed = true;
+                    Ok(0)
+                }
                 Ok(n) => Ok(n),
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
                 Err(e) => Err(TcsError::Io(e)),
@@ -214,6 +568,10 @@ impl EndpointReadable for TcpEndpoint {
             Ok(0)
         }
     }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
 }
 
 impl EndpointWritable for TcpEndpoint {
@@ -228,12 +586,19 @@ impl EndpointWritable for TcpEndpoint {
             Ok(0)
         }
     }
+
+    fn shutdown_write(&mut self) -> TcsResult<()> {
+        match self.stream.as_ref().map(|stream| stream.shutdown(Shutdown::Write)) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotConnected => Err(TcsError::Io(e)),
+            _ => Ok(()),
+        }
+    }
 }
 
 /// Device endpoint for device file I/O
 pub struct DeviceEndpoint {
     file: File,
-    _buffer: Vec<u8>,
+    _buffer: PooledBuffer,
 }
 
 impl DeviceEndpoint {
@@ -245,7 +610,7 @@ impl DeviceEndpoint {
 
         Ok(Self {
             file,
-            _buffer: vec![0u8; ENDPOINT_BUFFER_SIZE],
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
         })
     }
 }
@@ -256,7 +621,7 @@ impl EndpointWaitable for DeviceEndpoint {
     }
 
     fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
-        wait_for_fds(self.io_fd(), cmd_fd, PollFlags::POLLIN, timeout_ms)
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
     }
 }
 
@@ -278,6 +643,363 @@ impl EndpointWritable for DeviceEndpoint {
     }
 }
 
+/// Socket behind a Unix endpoint
+enum UnixSocket {
+    /// Listening for the payload, with its connection once accepted
+    Listener { listener: UnixListener, stream: Option<UnixStream> },
+    /// Connected to a payload that is listening
+    Stream(UnixStream),
+    /// Datagrams, sent to the address last received from
+    Datagram { socket: UnixDatagram, peer: Option<UnixAddr> },
+}
+
+/// Socket shared by a Unix endpoint and its clones
+struct UnixShared {
+    socket: Mutex<UnixSocket>,
+    /// Path the socket is bound to, removed with the socket
+    bound: Option<PathBuf>,
+}
+
+impl Drop for UnixShared {
+    fn drop(&mut self) {
+        if let Some(path) = &self.bound {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Unix domain socket endpoint for payloads on the same host
+///
+/// A stream endpoint accepts the payload's connection once it arrives and,
+/// after the payload closes it, accepts the next one. A datagram endpoint
+/// replies to the address it last received from, so a payload that wants
+/// downlink must send from a bound socket.
+pub struct UnixEndpoint {
+    shared: Arc<UnixShared>,
+    mode: UnixMode,
+    /// The peer closed the connection
+    closed: bool,
+    _buffer: PooledBuffer,
+}
+
+impl UnixEndpoint {
+    /// Bind the socket at the configured path for the payload to connect to
+    pub fn new(config: &UnixConfig) -> TcsResult<Self> {
+        let socket = match config.mode {
+            UnixMode::Stream => {
+                let listener = UnixListener::bind(&config.path).map_err(|e| bind_error(&config.path, e))?;
+                listener.set_nonblocking(true)?;
+                UnixSocket::Listener { listener, stream: None }
+            }
+            UnixMode::Datagram => {
+                let socket = UnixDatagram::bind(&config.path).map_err(|e| bind_error(&config.path, e))?;
+                socket.set_nonblocking(true)?;
+                UnixSocket::Datagram { socket, peer: None }
+            }
+        };
+        Ok(Self::from_socket(socket, config.mode, Some(PathBuf::from(&config.path))))
+    }
+
+    /// Connect to a payload bound at the configured path
+    ///
+    /// A datagram endpoint binds an abstract address of its own so the
+    /// payload can reply.
+    pub fn connect(config: &UnixConfig) -> TcsResult<Self> {
+        let socket = match config.mode {
+            UnixMode::Stream => {
+                let stream = UnixStream::connect(&config.path)?;
+                stream.set_nonblocking(true)?;
+                UnixSocket::Stream(stream)
+            }
+            UnixMode::Datagram => {
+                static NEXT_CLIENT: AtomicU32 = AtomicU32::new(0);
+                let name = format!("tcspecial-{}-{}", std::process::id(), NEXT_CLIENT.fetch_add(1, Ordering::Relaxed));
+                let socket = UnixDatagram::bind_addr(&UnixAddr::from_abstract_name(name)?)?;
+                socket.set_nonblocking(true)?;
+                UnixSocket::Datagram { socket, peer: Some(UnixAddr::from_pathname(&config.path)?) }
+            }
+        };
+        Ok(Self::from_socket(socket, config.mode, None))
+    }
+
+    fn from_socket(socket: UnixSocket, mode: UnixMode, bound: Option<PathBuf>) -> Self {
+        Self {
+            shared: Arc::new(UnixShared { socket: Mutex::new(socket), bound }),
+            mode,
+            closed: false,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+        }
+    }
+
+    /// Create another endpoint on the same socket, so one conduit can read
+    /// it while another writes it
+    pub fn try_clone(&self) -> TcsResult<Self> {
+        Ok(Self {
+            shared: Arc::clone(&self.shared),
+            mode: self.mode,
+            closed: self.closed,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+        })
+    }
+}
+
+/// Accept the payload's connection if there is none yet
+fn accept_unix(listener: &UnixListener, stream: &mut Option<UnixStream>) -> TcsResult<()> {
+    if stream.is_none() {
+        match listener.accept() {
+            Ok((accepted, _)) => {
+                accepted.set_nonblocking(true)?;
+                *stream = Some(accepted);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(TcsError::Io(e)),
+        }
+    }
+    Ok(())
+}
+
+impl EndpointWaitable for UnixEndpoint {
+    fn io_fd(&self) -> RawFd {
+        match &*self.shared.socket.lock().unwrap() {
+            UnixSocket::Listener { stream: Some(stream), .. } => stream.as_raw_fd(),
+            UnixSocket::Listener { listener, stream: None } => listener.as_raw_fd(),
+            UnixSocket::Stream(stream) => stream.as_raw_fd(),
+            UnixSocket::Datagram { socket, .. } => socket.as_raw_fd(),
+        }
+    }
+
+    fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
+    }
+}
+
+impl EndpointReadable for UnixEndpoint {
+    fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+        let mut socket = self.shared.socket.lock().unwrap();
+        let result = match &mut *socket {
+            UnixSocket::Listener { listener, stream } => {
+                accept_unix(listener, stream)?;
+                match stream {
+                    Some(connection) => match connection.read(buffer) {
+                        Ok(0) if !buffer.is_empty() => {
+                            // Make way for the payload to connect again
+                            *stream = None;
+                            self.closed = true;
+                            return Ok(0);
+                        }
+                        result => {
+                            self.closed = false;
+                            result
+                        }
+                    },
+                    None => return Ok(0),
+                }
+            }
+            UnixSocket::Stream(stream) => match stream.read(buffer) {
+                Ok(0) if !buffer.is_empty() => {
+                    self.closed = true;
+                    return Ok(0);
+                }
+                result => result,
+            },
+            UnixSocket::Datagram { socket, peer } => socket.recv_from(buffer).map(|(n, from)| {
+                if !from.is_unnamed() {
+                    *peer = Some(from);
+                }
+                n
+            }),
+        };
+        match result {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+
+    fn is_datagram(&self) -> bool {
+        self.mode == UnixMode::Datagram
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+impl EndpointWritable for UnixEndpoint {
+    fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+        let mut socket = self.shared.socket.lock().unwrap();
+        let result = match &mut *socket {
+            UnixSocket::Listener { listener, stream } => {
+                accept_unix(listener, stream)?;
+                match stream {
+                    Some(stream) => stream.write(data),
+                    None => return Ok(0),
+                }
+            }
+            UnixSocket::Stream(stream) => stream.write(data),
+            UnixSocket::Datagram { socket, peer } => match peer {
+                Some(peer) => socket.send_to_addr(data, peer),
+                None => return Err(TcsError::Endpoint("No Unix peer address known yet".to_string())),
+            },
+        };
+        match result {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+
+    fn is_datagram(&self) -> bool {
+        self.mode == UnixMode::Datagram
+    }
+}
+
+/// Serial endpoint for a tty, set to raw mode with the configured line settings
+pub struct SerialEndpoint {
+    file: File,
+    _buffer: PooledBuffer,
+}
+
+impl SerialEndpoint {
+    pub fn new(config: &SerialConfig) -> TcsResult<Self> {
+        // The port must not become the controlling terminal, and opening it
+        // must not wait for carrier detect
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(&config.path)?;
+        configure_serial(&file, config)?;
+
+        Ok(Self {
+            file,
+            _buffer: BufferPool::global().take(ENDPOINT_BUFFER_SIZE),
+        })
+    }
+}
+
+/// Put a tty in raw mode at the configured baud rate, parity and stop bits
+fn configure_serial(file: &File, config: &SerialConfig) -> TcsResult<()> {
+    let mut termios = termios::tcgetattr(file).map_err(|e| TcsError::Io(e.into()))?;
+    serial_settings(&mut termios, config)?;
+    termios::tcsetattr(file, SetArg::TCSANOW, &termios).map_err(|e| TcsError::Io(e.into()))
+}
+
+fn serial_settings(termios: &mut Termios, config: &SerialConfig) -> TcsResult<()> {
+    let baud_rate = serial_baud_rate(config.baud_rate)?;
+    termios::cfmakeraw(termios);
+    termios::cfsetspeed(termios, baud_rate).map_err(|e| TcsError::Io(e.into()))?;
+
+    let flags = &mut termios.control_flags;
+    flags.insert(ControlFlags::CLOCAL | ControlFlags::CREAD);
+    flags.remove(ControlFlags::PARENB | ControlFlags::PARODD | ControlFlags::CSTOPB);
+    match config.parity {
+        Parity::None => {}
+        Parity::Even => flags.insert(ControlFlags::PARENB),
+        Parity::Odd => flags.insert(ControlFlags::PARENB | ControlFlags::PARODD),
+    }
+    match config.stop_bits {
+        1 => {}
+        2 => flags.insert(ControlFlags::CSTOPB),
+        bits => return Err(TcsError::Config(format!("Invalid stop bits {} for {}", bits, config.path))),
+    }
+    Ok(())
+}
+
+fn serial_baud_rate(baud_rate: u32) -> TcsResult<BaudRate> {
+    match baud_rate {
+        1200 => Ok(BaudRate::B1200),
+        2400 => Ok(BaudRate::B2400),
+        4800 => Ok(BaudRate::B4800),
+        9600 => Ok(BaudRate::B9600),
+        19200 => Ok(BaudRate::B19200),
+        38400 => Ok(BaudRate::B38400),
+        57600 => Ok(BaudRate::B57600),
+        115200 => Ok(BaudRate::B115200),
+        230400 => Ok(BaudRate::B230400),
+        460800 => Ok(BaudRate::B460800),
+        921600 => Ok(BaudRate::B921600),
+        _ => Err(TcsError::Config(format!("Unsupported baud rate {}", baud_rate))),
+    }
+}
+
+impl EndpointWaitable for SerialEndpoint {
+    fn io_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    fn wait_for_event(&self, cmd_fd: RawFd, timeout_ms: i32) -> TcsResult<WaitResult> {
+        wait_for_fds(self.io_fd(), cmd_fd, timeout_ms)
+    }
+}
+
+impl EndpointReadable for SerialEndpoint {
+    fn read(&mut self, buffer: &mut [u8]) -> TcsResult<usize> {
+        match self.file.read(buffer) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+}
+
+impl EndpointWritable for SerialEndpoint {
+    fn write(&mut self, data: &[u8]) -> TcsResult<usize> {
+        match self.file.write(data) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(TcsError::Io(e)),
+        }
+    }
+}
+
+/// Try an operation until it succeeds or limit has passed, sleeping
+/// between tries
+///
+/// The delay starts at init and doubles after each failure, up to max, and
+/// is cut short so the last try is made at the limit. The error from the
+/// last try is returned if none succeed.
+pub fn retry_with_backoff<T>(
+    init: Duration,
+    max: Duration,
+    limit: Duration,
+    mut op: impl FnMut() -> TcsResult<T>,
+) -> TcsResult<T> {
+    let started = std::time::Instant::now();
+    let mut delay = init;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let remaining = limit.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    return Err(e);
+                }
+                std::thread::sleep(delay.min(remaining));
+                delay = (delay * 2).min(max);
+            }
+        }
+    }
+}
+
+/// Data handler types the endpoint factories can create; serial ports are
+/// device data handlers and UNIX domain sockets network ones
+pub const SUPPORTED_DH_TYPES: [DHType; 2] = [DHType::Network, DHType::Device];
+
+/// Protocols the endpoint factories accept in a Network endpoint
+/// configuration; keep in step with create_reader_endpoint and
+/// create_writer_endpoint
+pub const NETWORK_ENDPOINT_PROTOCOLS: [NetworkProtocol; 2] = [NetworkProtocol::Tcp, NetworkProtocol::Udp];
+
+/// Network protocols the endpoint factories can create, UNIX domain sockets
+/// included
+pub const SUPPORTED_PROTOCOLS: [NetworkProtocol; 4] = [
+    NetworkProtocol::Tcp,
+    NetworkProtocol::Udp,
+    NetworkProtocol::UnixStream,
+    NetworkProtocol::UnixDgram,
+];
+
 /// Factory for creating endpoints from configuration
 pub fn create_reader_endpoint(config: &EndpointConfig) -> TcsResult<Box<dyn EndpointReadable + Send>> {
     match config {
@@ -295,6 +1017,12 @@ pub fn create_reader_endpoint(config: &EndpointConfig) -> TcsResult<Box<dyn Endp
         EndpointConfig::Device(dev_config) => {
             Ok(Box::new(DeviceEndpoint::new(dev_config)?))
         }
+        EndpointConfig::Serial(serial_config) => {
+            Ok(Box::new(SerialEndpoint::new(serial_config)?))
+        }
+        EndpointConfig::Unix(unix_config) => {
+            Ok(Box::new(UnixEndpoint::new(unix_config)?))
+        }
     }
 }
 
@@ -315,6 +1043,12 @@ pub fn create_writer_endpoint(config: &EndpointConfig) -> TcsResult<Box<dyn Endp
         EndpointConfig::Device(dev_config) => {
             Ok(Box::new(DeviceEndpoint::new(dev_config)?))
         }
+        EndpointConfig::Serial(serial_config) => {
+            Ok(Box::new(SerialEndpoint::new(serial_config)?))
+        }
+        EndpointConfig::Unix(unix_config) => {
+            Ok(Box::new(UnixEndpoint::new(unix_config)?))
+        }
     }
 }
 
@@ -327,6 +1061,309 @@ mod tests {
         assert_eq!(WaitResult::IoReady, WaitResult::IoReady);
         assert_ne!(WaitResult::IoReady, WaitResult::Timeout);
     }
+
+    #[test]
+    fn test_wait_for_fds_multi() {
+        use std::os::unix::net::UnixStream;
+
+        let pipes: Vec<(UnixStream, UnixStream)> = (0..3).map(|_| UnixStream::pair().unwrap()).collect();
+        let (cmd_reader, _cmd_writer) = UnixStream::pair().unwrap();
+        let fds: Vec<RawFd> = pipes.iter().map(|(reader, _)| reader.as_raw_fd()).collect();
+
+        let ready = wait_for_fds_multi(&fds, cmd_reader.as_raw_fd(), 0).unwrap();
+        assert!(ready.is_timeout());
+
+        (&pipes[0].1).write_all(b"a").unwrap();
+        (&pipes[2].1).write_all(b"c").unwrap();
+        let ready = wait_for_fds_multi(&fds, cmd_reader.as_raw_fd(), 1000).unwrap();
+        assert_eq!(ready.io, vec![fds[0], fds[2]]);
+        assert!(!ready.command);
+        assert!(ready.failed.is_empty());
+
+        // The two-fd wrapper agrees
+        assert_eq!(wait_for_fds(fds[1], cmd_reader.as_raw_fd(), 0).unwrap(), WaitResult::Timeout);
+        assert_eq!(wait_for_fds(fds[2], cmd_reader.as_raw_fd(), 0).unwrap(), WaitResult::IoReady);
+    }
+
+    #[test]
+    fn test_supported_protocols() {
+        // Exactly the protocols of network endpoints can be created from a
+        // Network configuration
+        for protocol in [NetworkProtocol::Tcp, NetworkProtocol::Udp, NetworkProtocol::UnixStream, NetworkProtocol::UnixDgram] {
+            let config = EndpointConfig::Network(NetworkConfig {
+                protocol,
+                address: "127.0.0.1".to_string(),
+                port: Port::ANY,
+                udp_mode: UdpMode::Connected,
+            });
+            let supported = NETWORK_ENDPOINT_PROTOCOLS.contains(&protocol);
+            assert_eq!(create_reader_endpoint(&config).is_ok(), supported, "{}", protocol);
+            assert_eq!(create_writer_endpoint(&config).is_ok(), supported, "{}", protocol);
+        }
+    }
+
+    #[test]
+    fn test_supported_lists_match_factories() {
+        let dir = std::env::temp_dir().join(format!("tcspecial-supported-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Every configuration the factories can create, with the protocol
+        // it is advertised under if it has one
+        let mut created = Vec::new();
+        for protocol in NETWORK_ENDPOINT_PROTOCOLS {
+            let config = EndpointConfig::Network(NetworkConfig {
+                protocol,
+                address: "127.0.0.1".to_string(),
+                port: Port::ANY,
+                udp_mode: UdpMode::Connected,
+            });
+            created.push((config, Some(protocol)));
+        }
+        for (mode, protocol) in [(UnixMode::Stream, NetworkProtocol::UnixStream), (UnixMode::Datagram, NetworkProtocol::UnixDgram)] {
+            let path = dir.join(protocol.to_string()).to_string_lossy().into_owned();
+            created.push((EndpointConfig::Unix(UnixConfig { path, mode }), Some(protocol)));
+        }
+        created.push((EndpointConfig::Device(DeviceConfig { path: "/dev/null".to_string() }), None));
+
+        for (config, _) in &created {
+            assert!(create_reader_endpoint(config).is_ok(), "{:?}", config);
+            assert!(SUPPORTED_DH_TYPES.contains(&config.dh_type()), "{:?}", config);
+        }
+        let protocols: Vec<NetworkProtocol> = created.iter().filter_map(|(_, protocol)| *protocol).collect();
+        assert_eq!(protocols, SUPPORTED_PROTOCOLS.to_vec());
+
+        // Serial ports are device data handlers
+        let serial = EndpointConfig::Serial(SerialConfig {
+            path: "/dev/ttyS0".to_string(),
+            baud_rate: 9600,
+            parity: Parity::default(),
+            stop_bits: 1,
+        });
+        assert!(SUPPORTED_DH_TYPES.contains(&serial.dh_type()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bind_udp_reuse_address() {
+        let socket = bind_udp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        assert!(socket2::SockRef::from(&socket).reuse_address().unwrap());
+        let addr = socket.local_addr().unwrap();
+        drop(socket);
+
+        // A restart rebinds the same port straight away
+        let socket = bind_udp(addr, true).unwrap();
+        assert_eq!(socket.local_addr().unwrap(), addr);
+
+        let socket = bind_udp("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        assert!(!socket2::SockRef::from(&socket).reuse_address().unwrap());
+    }
+
+    #[test]
+    fn test_udp_unconnected_reply_port() {
+        use std::time::Duration;
+
+        let config = NetworkConfig {
+            protocol: NetworkProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: Port::ANY,
+            udp_mode: UdpMode::Unconnected,
+        };
+        let mut endpoint = UdpEndpoint::new(&config).unwrap();
+        let endpoint_addr = endpoint.local_addr().unwrap();
+
+        // The payload listens on one port and replies from another
+        let payload_listen = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let payload_reply = UdpSocket::bind("127.0.0.1:0").unwrap();
+        payload_listen.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        payload_reply.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        endpoint.connect(&payload_listen.local_addr().unwrap().to_string()).unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(endpoint.write(b"cmd").unwrap(), 3);
+        let (n, from) = payload_listen.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..n], from), (&b"cmd"[..], endpoint_addr));
+
+        payload_reply.send_to(b"reply", endpoint_addr).unwrap();
+        assert_eq!(endpoint.wait_for_event(payload_reply.as_raw_fd(), 1000).unwrap(), WaitResult::IoReady);
+        let n = endpoint.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"reply");
+
+        // Subsequent data goes to the port the payload replied from
+        assert_eq!(endpoint.write(b"next").unwrap(), 4);
+        let n = payload_reply.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"next");
+    }
+
+    #[test]
+    fn test_serial_endpoint_pty() {
+        use nix::pty::openpty;
+        use nix::sys::termios::{cfgetospeed, tcgetattr};
+        use nix::unistd::ttyname;
+        use std::os::unix::net::UnixStream;
+
+        let pty = openpty(None, None).unwrap();
+        let mut config = SerialConfig {
+            path: ttyname(pty.slave.as_raw_fd()).unwrap().to_string_lossy().into_owned(),
+            baud_rate: 115200,
+            parity: Parity::Even,
+            stop_bits: 2,
+        };
+        let mut endpoint = SerialEndpoint::new(&config).unwrap();
+
+        let mut termios = tcgetattr(&endpoint.file).unwrap();
+        assert_eq!(cfgetospeed(&termios), BaudRate::B115200);
+        // A pty ignores the line's framing bits, so check what would be set
+        serial_settings(&mut termios, &config).unwrap();
+        let flags = termios.control_flags;
+        assert!(flags.contains(ControlFlags::PARENB | ControlFlags::CSTOPB | ControlFlags::CS8));
+        assert!(!flags.contains(ControlFlags::PARODD));
+
+        // Raw mode passes bytes through untouched, without echoing them back
+        let mut master = File::from(pty.master);
+        let (cmd_reader, _cmd_writer) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(endpoint.read(&mut buf).unwrap(), 0);
+        master.write_all(b"\x03uplink\n").unwrap();
+        assert_eq!(endpoint.wait_for_event(cmd_reader.as_raw_fd(), 1000).unwrap(), WaitResult::IoReady);
+        let n = endpoint.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"\x03uplink\n");
+
+        assert_eq!(endpoint.write(b"downlink\r").unwrap(), 9);
+        let n = master.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"downlink\r");
+
+        // Settings the line can't take are refused
+        config.stop_bits = 3;
+        assert!(matches!(SerialEndpoint::new(&config), Err(TcsError::Config(_))));
+        config.stop_bits = 1;
+        config.baud_rate = 12345;
+        assert!(matches!(SerialEndpoint::new(&config), Err(TcsError::Config(_))));
+    }
+
+    #[test]
+    fn test_unix_stream_endpoint() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("tcspecial-unix-stream-{}.sock", std::process::id()));
+        let config = UnixConfig { path: path.to_string_lossy().into_owned(), mode: UnixMode::Stream };
+        let mut reader = UnixEndpoint::new(&config).unwrap();
+        let mut writer = reader.try_clone().unwrap();
+        assert!(!EndpointReadable::is_datagram(&reader));
+        assert!(matches!(UnixEndpoint::new(&config), Err(TcsError::Io(_))));
+
+        // Nothing to read or write to until the payload connects
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(writer.write(b"early").unwrap(), 0);
+
+        let (cmd_reader, _cmd_writer) = UnixStream::pair().unwrap();
+        let mut payload = UnixStream::connect(&path).unwrap();
+        payload.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        payload.write_all(b"uplink").unwrap();
+        let mut received = Vec::new();
+        while received.len() < 6 {
+            assert_ne!(reader.wait_for_event(cmd_reader.as_raw_fd(), 1000).unwrap(), WaitResult::Timeout);
+            let n = reader.read(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, b"uplink");
+
+        // The clone writes to the connection the reader accepted
+        assert_eq!(writer.write(b"downlink").unwrap(), 8);
+        let n = payload.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"downlink");
+
+        drop(payload);
+        assert_eq!(reader.wait_for_event(cmd_reader.as_raw_fd(), 1000).unwrap(), WaitResult::IoReady);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.is_closed());
+
+        // The socket file goes with the last endpoint using it
+        drop(reader);
+        assert!(path.exists());
+        drop(writer);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_unix_datagram_endpoint() {
+        let path = std::env::temp_dir().join(format!("tcspecial-unix-dgram-{}.sock", std::process::id()));
+        let config = UnixConfig { path: path.to_string_lossy().into_owned(), mode: UnixMode::Datagram };
+        let mut reader = UnixEndpoint::new(&config).unwrap();
+        let mut writer = reader.try_clone().unwrap();
+        assert!(EndpointReadable::is_datagram(&reader) && EndpointWritable::is_datagram(&writer));
+
+        // Downlink has nowhere to go until the payload sends something
+        assert!(matches!(writer.write(b"early"), Err(TcsError::Endpoint(_))));
+
+        let mut payload = UnixEndpoint::connect(&config).unwrap();
+        let (cmd_reader, _cmd_writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert_eq!(payload.write(b"uplink").unwrap(), 6);
+        assert_eq!(reader.wait_for_event(cmd_reader.as_raw_fd(), 1000).unwrap(), WaitResult::IoReady);
+        let mut buf = [0u8; 16];
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"uplink");
+
+        // Replies go back to the payload's own address
+        assert_eq!(writer.write(b"downlink").unwrap(), 8);
+        assert_eq!(payload.wait_for_event(cmd_reader.as_raw_fd(), 1000).unwrap(), WaitResult::IoReady);
+        let n = payload.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"downlink");
+
+        drop((reader, writer));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let delay = Duration::from_millis(1);
+
+        // Succeeds once the operation stops failing
+        let mut tries = 0;
+        let result = retry_with_backoff(delay, delay * 4, Duration::from_secs(5), || {
+            tries += 1;
+            if tries < 3 { Err(TcsError::Timeout) } else { Ok(tries) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up at the limit with the last try's error, however few tries
+        // the delays left room for
+        let mut tries = 0;
+        let started = std::time::Instant::now();
+        let result: TcsResult<()> = retry_with_backoff(delay * 40, delay * 80, delay * 100, || {
+            tries += 1;
+            Err(TcsError::Endpoint(format!("attempt {}", tries)))
+        });
+        match result {
+            Err(TcsError::Endpoint(msg)) => assert_eq!(msg, "attempt 3"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(started.elapsed() >= delay * 100);
+        assert!(started.elapsed() < delay * 500);
+    }
+
+    #[test]
+    fn test_connect_port_zero() {
+        use tcslibgs::{NetworkConfig, NetworkProtocol, Port, UdpMode};
+
+        // Port 0 can only be bound, so connecting to it fails at once
+        for protocol in [NetworkProtocol::Tcp, NetworkProtocol::Udp] {
+            let config = NetworkConfig {
+                protocol,
+                address: "127.0.0.1".to_string(),
+                port: Port::ANY,
+                udp_mode: UdpMode::Connected,
+            };
+            let started = std::time::Instant::now();
+            let result = match protocol {
+                NetworkProtocol::Tcp => TcpEndpoint::connect(&config).map(drop),
+                _ => UdpEndpoint::new_client(&config).map(drop),
+            };
+            assert!(matches!(result, Err(TcsError::Config(_))));
+            assert!(started.elapsed() < ENDPOINT_DELAY_INIT);
+        }
+    }
 }
 
 /*
@@ -1,85 +1,160 @@
-mod EndpointNetwork {
+//! Network protocol families for TCSpecial endpoints
+//!
+//! Maps the family names configuration may use to the canonical address
+//! family and the socket types that family supports, so sockets of less
+//! common families (unix, vsock, packet, ...) can be created by name.
+//! Sockets for the IP protocols a configuration names are created through
+//! the same table; see ip_socket_config.
+
 use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::LazyLock;
 
-enum LinkType {
-    Packet,
-    Stream,
+use socket2::{Domain, Socket, Type};
+use tcslibgs::{AddressFamily, NetworkProtocol, SocketConfig, SocketType, TcsError, TcsResult};
+
+/// A protocol family known by one or more names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolFamily {
+    /// Names accepted in configuration, the first being the usual one
+    pub names: &'static [&'static str],
+    pub family: AddressFamily,
+    /// Supported socket types, the first being the default; empty if the
+    /// family has no socket types TCSpecial knows how to use
+    pub socket_types: &'static [SocketType],
 }
 
+const FAMILIES: &[ProtocolFamily] = &[
+    family(&["unix", "local"], AddressFamily::Unix, &[SocketType::Stream, SocketType::Dgram, SocketType::Seqpacket]),
+    family(&["inet"], AddressFamily::Inet, &[SocketType::Stream, SocketType::Dgram, SocketType::Raw]),
+    family(&["inet6"], AddressFamily::Inet6, &[SocketType::Stream, SocketType::Dgram, SocketType::Raw]),
+    family(&["ax25"], AddressFamily::Ax25, &[]),
+    family(&["ipx"], AddressFamily::Ipx, &[]),
+    family(&["appletalk"], AddressFamily::Appletalk, &[SocketType::Dgram, SocketType::Raw]),
+    family(&["x25"], AddressFamily::X25, &[SocketType::Seqpacket]),
+    family(&["decnet"], AddressFamily::Decnet, &[]),
+    family(&["key"], AddressFamily::Key, &[]),
+    family(&["netlink"], AddressFamily::Netlink, &[SocketType::Dgram, SocketType::Raw]),
+    family(&["packet"], AddressFamily::Packet, &[SocketType::Dgram, SocketType::Raw]),
+    family(&["rds"], AddressFamily::Rds, &[]),
+    family(&["pppox"], AddressFamily::Pppox, &[]),
+    family(&["llc"], AddressFamily::Llc, &[]),
+    family(&["ib"], AddressFamily::Ib, &[]),
+    family(&["mpls"], AddressFamily::Mpls, &[]),
+    family(&["can"], AddressFamily::Can, &[]),
+    family(&["tipc"], AddressFamily::Tipc, &[]),
+    family(&["bluetooth"], AddressFamily::Bluetooth, &[]),
+    family(&["alg"], AddressFamily::Alg, &[]),
+    family(&["vsock"], AddressFamily::Vsock, &[SocketType::Stream, SocketType::Seqpacket, SocketType::Dgram]),
+    family(&["xdp"], AddressFamily::Xdp, &[]),
+];
+
+const fn family(
+    names: &'static [&'static str],
+    family: AddressFamily,
+    socket_types: &'static [SocketType],
+) -> ProtocolFamily {
+    ProtocolFamily {
+        names,
+        family,
+        socket_types,
+    }
+}
 
-static protocols: LazyLock<BTreeMap<&[&'static str], i32>> = LazyLock::new(|| {
-    BTreeMap::from([
-        (&["unix", "local"], 12),
-		(&["inet"], 23),
-		(&["ax25"], 23),
-		(&["ipx"], 23),
-		(&["appletalk"], 23),
-		(&["x25"], 23),
-		(&["inet6"], 23),
-		(&["decnet"], 23),
-		(&["key"], 23),
-		(&["netlink"], 23),
-		(&["packet"], 23),
-		(&["rds"], 23),
-		(&["pppox"], 23),
-		(&["llc"], 23),
-		(&["ib"], 23),
-		(&["mpls"], 23),
-		(&["can"], 23),
-		(&["tipc"], 23),
-		(&["bluetooth"], 23),
-		(&["alg"], 23),
-		(&["vsock"], 23),
-		(&["xdp"], 23),
-    ])
+/// Families indexed by each of their names
+static PROTOCOL_FAMILIES: LazyLock<BTreeMap<&'static str, &'static ProtocolFamily>> = LazyLock::new(|| {
+    FAMILIES
+        .iter()
+        .flat_map(|family| family.names.iter().map(move |name| (*name, family)))
+        .collect()
 });
 
-struct Protocol<'a> {
-    name:   &'a [&'static str],
+/// Look up a protocol family by name, ignoring case
+pub fn lookup_family(name: &str) -> Option<&'static ProtocolFamily> {
+    PROTOCOL_FAMILIES.get(name.to_ascii_lowercase().as_str()).copied()
 }
 
-impl Protocol<'_> {
-    fn new<'a>(name: &'a[&'static str]) -> Protocol<'a>  {
-        Protocol {
-            name,
+/// Build the socket configuration for a named family
+///
+/// Uses the family's default socket type unless one is given, which must be
+/// one the family supports.
+pub fn socket_config(name: &str, socket_type: Option<SocketType>) -> TcsResult<SocketConfig> {
+    let family = lookup_family(name)
+        .ok_or_else(|| TcsError::Config(format!("Unknown protocol family {}", name)))?;
+
+    let socket_type = match socket_type {
+        Some(socket_type) if family.socket_types.contains(&socket_type) => socket_type,
+        Some(socket_type) => {
+            return Err(TcsError::Config(format!(
+                "Protocol family {} does not support {:?} sockets", name, socket_type
+            )))
         }
-    }
+        None => *family.socket_types.first().ok_or_else(|| {
+            TcsError::Config(format!("Protocol family {} has no supported socket types", name))
+        })?,
+    };
+
+    Ok(SocketConfig {
+        family: family.family,
+        socket_type,
+        protocol: 0,
+    })
+}
+
+/// Build the socket configuration for an IP protocol, in the inet or inet6
+/// family to suit the address it is to be bound or connected to
+pub fn ip_socket_config(protocol: NetworkProtocol, addr: &SocketAddr) -> TcsResult<SocketConfig> {
+    let socket_type = match protocol {
+        NetworkProtocol::Tcp => SocketType::Stream,
+        NetworkProtocol::Udp => SocketType::Dgram,
+        protocol => return Err(TcsError::Config(format!("{} is not an IP protocol", protocol))),
+    };
+    socket_config(if addr.is_ipv4() { "inet" } else { "inet6" }, Some(socket_type))
+}
+
+/// Create an unbound socket from a socket configuration
+pub fn open_socket(config: &SocketConfig) -> io::Result<Socket> {
+    let domain = Domain::from(config.family.to_os());
+    let socket_type = Type::from(config.socket_type.to_os());
+    let protocol = match config.protocol {
+        0 => None,
+        protocol => Some(protocol.into()),
+    };
+    Socket::new(domain, socket_type, protocol)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_family() {
+        let unix = socket_config("unix", None).unwrap();
+        assert_eq!((unix.family, unix.socket_type), (AddressFamily::Unix, SocketType::Stream));
+        assert_eq!(unix.family.to_os(), libc::AF_UNIX);
+        assert_eq!(lookup_family("local"), lookup_family("unix"));
+
+        let inet = socket_config("INET", Some(SocketType::Dgram)).unwrap();
+        assert_eq!((inet.family, inet.socket_type), (AddressFamily::Inet, SocketType::Dgram));
+        assert_eq!(inet.family.to_os(), libc::AF_INET);
+        assert_eq!(inet.socket_type.to_os(), libc::SOCK_DGRAM);
+        assert!(open_socket(&inet).is_ok());
 
-/*
-AF_UNIX or AF_LOCAL	SOCK_STREAM	0	stream
-SOCK_DGRAM	0	datagram
-SOCK_SEQPACKET	0	datagram
-AF_INET	SOCK_STREAM	0	stream
-SOCK_DGRAM	0	datagram
-SOCK_RAW	yes	datagram
-AF_AX25	TBD	TBD	TBD
-AF_IPX	TBD	TBD	TBD
-AF_APPLETALK	SOCK_DGRAM	yes	datagram
-SOCK_RAW	yes	datagram
-AF_X25	SOCK_SEQPACKET	0	datagram
-AF_INET6	SOCK_STREAM	yes	stream
-SOCK_DGRAM	yes	datagram
-SOCK_RAW	yes	datagram
-AF_DECnet	TBD	TBD	TBD
-AF_KEY	TBD	TBD	TBD
-AF_NETLINK	SOCK_DGRAM	yes	datagram
-SOCK_RAW	yes	datagram
-AF_PACKET	SOCK_DGRAM	yes	datagram
-SOCK_RAW	yes	datagram
-AF_RDS	TBD	TBD	TBD
-AF_PPPOX	TBD	TBD	TBD
-AF_LLC	TBD	TBD	TBD
-AF_IB	TBD	TBD	TBD
-AF_MPLS	TBD	TBD	TBD
-AF_CAN	TBD	TBD	TBD
-AF_TIPC	TBD	TBD	TBD
-AF_BLUETOOTH	TBD	TBD	TBD
-AF_ALG	TBD	TBD	TBD
-AF_VSOCK	SOCK_DGRAM	yes	datagram
-SOCK_RAW	yes	datagram
-AF_XDP	TBD	TBD	TBD
-*/
+        assert!(socket_config("x25", Some(SocketType::Stream)).is_err());
+        assert!(socket_config("ipx", None).is_err());
+        assert!(lookup_family("carrier-pigeon").is_none());
+    }
+
+    #[test]
+    fn test_ip_socket_config() {
+        let v4: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let v6: SocketAddr = "[::1]:0".parse().unwrap();
+
+        let udp = ip_socket_config(NetworkProtocol::Udp, &v4).unwrap();
+        assert_eq!((udp.family, udp.socket_type), (AddressFamily::Inet, SocketType::Dgram));
+        let tcp = ip_socket_config(NetworkProtocol::Tcp, &v6).unwrap();
+        assert_eq!((tcp.family, tcp.socket_type), (AddressFamily::Inet6, SocketType::Stream));
+        assert!(matches!(ip_socket_config(NetworkProtocol::UnixDgram, &v4), Err(TcsError::Config(_))));
+    }
 }
@@ -0,0 +1,139 @@
+//! Write latency tracking for TCSpecial
+//!
+//! Average throughput hides a payload that occasionally stalls the write
+//! side, so conduits can time each write into a histogram. Buckets are
+//! HDR-style: each power of two is split into a few linear sub-buckets, so
+//! memory is fixed and every recorded value is known to within an eighth.
+
+use std::time::Duration;
+use tcslibgs::WriteLatency;
+
+/// Sub-buckets per power of two, as a power of two
+const SUB_BUCKET_BITS: u32 = 3;
+
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Enough buckets for any u64 microsecond count
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Histogram of write latencies in microseconds
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    samples: u64,
+    min_us: u64,
+    max_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            samples: 0,
+            min_us: u64::MAX,
+            max_us: 0,
+        }
+    }
+
+    /// Record how long one write took
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[bucket_of(us)] += 1;
+        self.samples += 1;
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Get the latency below which percent of the writes completed
+    ///
+    /// Reports the top of the bucket holding that write, so a percentile is
+    /// never understated, but never more than the largest latency seen.
+    pub fn percentile(&self, percent: f64) -> u64 {
+        if self.samples == 0 {
+            return 0;
+        }
+
+        let rank = ((percent / 100.0 * self.samples as f64).ceil() as u64).clamp(1, self.samples);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_top(bucket).clamp(self.min_us, self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    /// Summarize the recorded latencies
+    pub fn summary(&self) -> WriteLatency {
+        if self.samples == 0 {
+            return WriteLatency::default();
+        }
+
+        WriteLatency {
+            samples: self.samples,
+            min_us: self.min_us,
+            max_us: self.max_us,
+            p50_us: self.percentile(50.0),
+            p99_us: self.percentile(99.0),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bucket holding a value; small values get a bucket each
+fn bucket_of(us: u64) -> usize {
+    if us < SUB_BUCKETS as u64 {
+        return us as usize;
+    }
+    let shift = 63 - us.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = (us >> shift) as usize & (SUB_BUCKETS - 1);
+    (shift as usize + 1) * SUB_BUCKETS + sub
+}
+
+/// Largest value held by a bucket
+fn bucket_top(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = (bucket / SUB_BUCKETS - 1) as u32;
+    let bottom = ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift;
+    bottom + ((1u64 << shift) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for us in [0, 7, 8, 9, 15, 16, 1000, 123_456, u64::MAX] {
+            let bucket = bucket_of(us);
+            assert!(bucket < BUCKETS);
+            assert!(bucket_top(bucket) >= us);
+            assert!(bucket_top(bucket) - us <= us / 8, "{} in bucket topped at {}", us, bucket_top(bucket));
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = LatencyHistogram::new();
+        assert_eq!(histogram.summary(), WriteLatency::default());
+
+        for _ in 0..98 {
+            histogram.record(Duration::from_micros(100));
+        }
+        histogram.record(Duration::from_millis(20));
+        histogram.record(Duration::from_millis(30));
+
+        let summary = histogram.summary();
+        assert_eq!((summary.samples, summary.min_us, summary.max_us), (100, 100, 30_000));
+        assert!((100..=112).contains(&summary.p50_us), "{:?}", summary);
+        assert!(summary.p99_us >= 20_000, "{:?}", summary);
+    }
+}
@@ -10,6 +10,9 @@ pub mod dh;
 pub mod endpoint;
 pub mod endpoint_network;
 pub mod conduit;
+pub mod latency;
+pub mod rate_limit;
+pub mod telemetry_queue;
 
 pub use beacon_send::*;
 pub use ci::*;
@@ -18,3 +21,6 @@ pub use dh::*;
 pub use endpoint::*;
 pub use endpoint_network::*;
 pub use conduit::*;
+pub use latency::*;
+pub use rate_limit::*;
+pub use telemetry_queue::*;
@@ -0,0 +1,169 @@
+//! Downlink rate limiting for TCSpecial
+//!
+//! The RF link bounds the total downlink, not each data handler's share of
+//! it, so payload-to-ground conduits share one token bucket. Conduits wait
+//! their turn in order, so each active data handler gets a fair share of the
+//! budget and data is delayed rather than dropped when the link is saturated.
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest a sender waits before checking whether it should give up
+const GIVE_UP_POLL: Duration = Duration::from_millis(10);
+
+struct Bucket {
+    /// Bytes that may be sent now; negative after a send larger than the burst
+    tokens: f64,
+    last_refill: Instant,
+    /// Ticket of the next sender allowed to take tokens
+    serving: u64,
+    next_ticket: u64,
+    /// Tickets of senders that gave up before their turn
+    abandoned: BTreeSet<u64>,
+}
+
+impl Bucket {
+    /// Move the turn to the next sender still waiting
+    fn advance(&mut self) {
+        self.serving += 1;
+        while self.abandoned.remove(&self.serving) {
+            self.serving += 1;
+        }
+    }
+}
+
+/// Token bucket shared by conduits to cap their combined throughput
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+    turn: Condvar,
+}
+
+impl RateLimiter {
+    /// Allow bytes_per_sec on average, with up to burst bytes sent at once
+    ///
+    /// Configurations with a rate of 0 are refused when loaded; should one
+    /// get here anyway it means no limit rather than no sending at all.
+    pub fn new(bytes_per_sec: u64, burst: usize) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            burst: burst as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: burst as f64,
+                last_refill: Instant::now(),
+                serving: 0,
+                next_ticket: 0,
+                abandoned: BTreeSet::new(),
+            }),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Block until bytes may be sent, taking turns with other senders
+    ///
+    /// A send larger than the burst waits for a full bucket and then leaves
+    /// it in debt, so later sends wait until the budget is repaid. Returns
+    /// false without taking any tokens if running is cleared while waiting,
+    /// so a sender held back by a low rate can still stop promptly.
+    pub fn acquire(&self, bytes: usize, running: &AtomicBool) -> bool {
+        if self.bytes_per_sec == 0.0 {
+            return running.load(Ordering::SeqCst);
+        }
+
+        let needed = (bytes as f64).min(self.burst);
+        let mut bucket = self.bucket.lock().unwrap();
+        let ticket = bucket.next_ticket;
+        bucket.next_ticket += 1;
+
+        loop {
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst);
+            bucket.last_refill = now;
+
+            if !running.load(Ordering::SeqCst) {
+                if bucket.serving == ticket {
+                    bucket.advance();
+                } else {
+                    bucket.abandoned.insert(ticket);
+                }
+                self.turn.notify_all();
+                return false;
+            }
+
+            if bucket.serving == ticket && bucket.tokens >= needed {
+                bucket.tokens -= bytes as f64;
+                bucket.advance();
+                self.turn.notify_all();
+                return true;
+            }
+
+            // Sleep until our turn, then until enough tokens should have
+            // accrued, looking in now and then to see if we should give up
+            let wait = if bucket.serving == ticket {
+                Duration::from_secs_f64((needed - bucket.tokens) / self.bytes_per_sec).min(GIVE_UP_POLL)
+            } else {
+                GIVE_UP_POLL
+            };
+            bucket = self.turn.wait_timeout(bucket, wait).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_rate() {
+        let limiter = RateLimiter::new(10_000, 1000);
+        let start = Instant::now();
+        // The first 1000 bytes are the burst, the rest accrue at the rate
+        for _ in 0..6 {
+            assert!(limiter.acquire(1000, &AtomicBool::new(true)));
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "took {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(700), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_rate_limiter_zero_rate() {
+        let limiter = RateLimiter::new(0, 1000);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter.acquire(1000, &AtomicBool::new(true)));
+        }
+        assert!(start.elapsed() < Duration::from_millis(100), "took {:?}", start.elapsed());
+        assert!(!limiter.acquire(1000, &AtomicBool::new(false)));
+    }
+
+    #[test]
+    fn test_rate_limiter_give_up() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let limiter = Arc::new(RateLimiter::new(1000, 1000));
+        assert!(limiter.acquire(1000, &AtomicBool::new(true)));
+
+        // A send that would wait for a second gives up soon after it is told to
+        let running = Arc::new(AtomicBool::new(true));
+        let waiting = {
+            let (limiter, running) = (limiter.clone(), running.clone());
+            thread::spawn(move || limiter.acquire(1000, &running))
+        };
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        running.store(false, Ordering::SeqCst);
+        assert!(!waiting.join().unwrap());
+        assert!(start.elapsed() < Duration::from_millis(100), "took {:?}", start.elapsed());
+
+        // and leaves its turn, and the tokens, to the next sender
+        let start = Instant::now();
+        assert!(limiter.acquire(50, &AtomicBool::new(true)));
+        assert!(start.elapsed() < Duration::from_millis(100), "took {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,122 @@
+//! Outgoing telemetry queue for TCSpecial
+//!
+//! Telemetry waits here until it can be sent. When the queue is full the
+//! oldest telemetry is dropped so the most recent responses reach the ground.
+//! Telemetry that fails to send goes to the back of the queue so it doesn't
+//! hold up what is behind it, and is dropped after MAX_SEND_ATTEMPTS tries.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use tcslibgs::Telemetry;
+
+/// Times telemetry is sent before it is dropped
+pub const MAX_SEND_ATTEMPTS: u32 = 3;
+
+/// Telemetry waiting to be sent and the address it is destined for
+pub struct QueuedTelemetry {
+    pub telemetry: Telemetry,
+    pub addr: SocketAddr,
+    /// Sends that have failed so far
+    attempts: u32,
+}
+
+/// Bounded queue of telemetry and the addresses it is destined for
+pub struct TelemetryQueue {
+    capacity: usize,
+    items: VecDeque<QueuedTelemetry>,
+    dropped: u64,
+}
+
+impl TelemetryQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: VecDeque::with_capacity(capacity),
+            dropped: 0,
+        }
+    }
+
+    /// Queue telemetry, dropping the oldest entry if full
+    pub fn push(&mut self, telemetry: Telemetry, addr: SocketAddr) {
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+            self.dropped += 1;
+        }
+        self.items.push_back(QueuedTelemetry { telemetry, addr, attempts: 0 });
+    }
+
+    /// Put telemetry that could not be sent at the back of the queue, or
+    /// drop it if it has failed MAX_SEND_ATTEMPTS times or the queue is full
+    pub fn requeue(&mut self, mut item: QueuedTelemetry) {
+        item.attempts += 1;
+        if item.attempts >= MAX_SEND_ATTEMPTS || self.items.len() >= self.capacity {
+            self.dropped += 1;
+            return;
+        }
+        self.items.push_back(item);
+    }
+
+    /// Take the oldest telemetry
+    pub fn pop(&mut self) -> Option<QueuedTelemetry> {
+        self.items.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Number of telemetry items dropped since the last clear
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn clear_dropped(&mut self) {
+        self.dropped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcslibgs::{CommandStatus, PingTelemetry};
+
+    #[test]
+    fn test_queue_drops_oldest() {
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let mut queue = TelemetryQueue::new(2);
+        for seq in 1..=3 {
+            queue.push(Telemetry::Ping(PingTelemetry::new(seq, CommandStatus::Success)), addr);
+        }
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop().unwrap().telemetry.sequence(), 2);
+    }
+
+    #[test]
+    fn test_queue_requeue_at_back() {
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let mut queue = TelemetryQueue::new(4);
+        for seq in 1..=2 {
+            queue.push(Telemetry::Ping(PingTelemetry::new(seq, CommandStatus::Success)), addr);
+        }
+
+        // A failed send doesn't hold up the telemetry behind it
+        let failed = queue.pop().unwrap();
+        queue.requeue(failed);
+        assert_eq!(queue.pop().unwrap().telemetry.sequence(), 2);
+
+        // and is dropped once it has failed MAX_SEND_ATTEMPTS times
+        for _ in 1..MAX_SEND_ATTEMPTS {
+            let failed = queue.pop().unwrap();
+            assert_eq!(failed.telemetry.sequence(), 1);
+            queue.requeue(failed);
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.dropped(), 1);
+    }
+}
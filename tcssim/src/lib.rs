@@ -0,0 +1,6 @@
+//! TCSpecial Payload Simulator library
+//!
+//! The simulated payloads behind the tcssim GUI, usable on their own to
+//! drive a data handler without it.
+
+pub mod payload;
@@ -6,9 +6,7 @@ use slint::SharedString;
 use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
 
-mod payload;
-
-use payload::{PayloadConfig, PayloadProtocol, SimulatedPayload};
+use tcssim::payload::{PayloadConfig, PayloadProtocol, SimulatedPayload, MAX_PACKET_SIZE};
 
 slint::include_modules!();
 
@@ -29,6 +27,8 @@ fn main() {
             segment_size: Arc::new(AtomicU32::new(12)),
             packet_interval_ms: Arc::new(AtomicU32::new(1000)),
             segment_interval_ms: Arc::new(AtomicU32::new(1000)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
         },
         PayloadConfig {
             _id: 1,
@@ -39,6 +39,8 @@ fn main() {
             segment_size: Arc::new(AtomicU32::new(11)),
             packet_interval_ms: Arc::new(AtomicU32::new(1000)),
             segment_interval_ms: Arc::new(AtomicU32::new(1000)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
         },
         PayloadConfig {
             _id: 2,
@@ -49,6 +51,8 @@ fn main() {
             segment_size: Arc::new(AtomicU32::new(1)),
             packet_interval_ms: Arc::new(AtomicU32::new(0)),
             segment_interval_ms: Arc::new(AtomicU32::new(0)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
         },
         PayloadConfig {
             _id: 3,
@@ -59,8 +63,15 @@ fn main() {
             segment_size: Arc::new(AtomicU32::new(15)),
             packet_interval_ms: Arc::new(AtomicU32::new(500)),
             segment_interval_ms: Arc::new(AtomicU32::new(500)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
         },
     ];
+    for config in &configs {
+        for warning in config.clamp_sizes(MAX_PACKET_SIZE) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
 
     // Create simulated payloads
     let payloads: Arc<Mutex<Vec<SimulatedPayload>>> = Arc::new(Mutex::new(
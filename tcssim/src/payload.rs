@@ -1,11 +1,11 @@
 //! Simulated payload implementation
 
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use rand::Rng;
 
 /// Payload configuration
@@ -19,6 +19,43 @@ pub struct PayloadConfig {
     pub segment_size: Arc<AtomicU32>,
     pub packet_interval_ms: Arc<AtomicU32>,
     pub segment_interval_ms: Arc<AtomicU32>,
+    /// Send KEEPALIVE_PACKET after this long without sending; 0 disables
+    pub keepalive_interval_ms: Arc<AtomicU32>,
+    /// Initial UDP peer, replaced by the source of each received packet
+    pub peer: Option<SocketAddr>,
+}
+
+/// Contents of a keepalive packet
+pub const KEEPALIVE_PACKET: &[u8] = b"KEEPALIVE";
+
+/// Largest packet or segment a payload sends by default, matching the data
+/// handler's read buffer (ENDPOINT_BUFFER_SIZE in tcspecial) so a packet is
+/// never split across reads
+pub const MAX_PACKET_SIZE: u32 = 4096;
+
+impl PayloadConfig {
+    /// Clamp the packet and segment sizes to max_size, returning a warning
+    /// for each that was larger
+    pub fn clamp_sizes(&self, max_size: u32) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (what, size) in [("packet", &self.packet_size), ("segment", &self.segment_size)] {
+            let requested = size.load(Ordering::SeqCst);
+            if requested > max_size {
+                size.store(max_size, Ordering::SeqCst);
+                warnings.push(format!(
+                    "Payload {}: {} size {} is over the maximum of {}, using {}",
+                    self._id, what, requested, max_size, max_size
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// Check whether a keepalive is due given the time of the last send
+fn keepalive_due(config: &PayloadConfig, last_send: Instant) -> bool {
+    let interval = config.keepalive_interval_ms.load(Ordering::SeqCst);
+    interval > 0 && last_send.elapsed() >= Duration::from_millis(interval as u64)
 }
 
 /// Payload protocol type
@@ -38,12 +75,19 @@ pub struct PayloadStats {
     pub bytes_recv: u64,
 }
 
+/// Socket a payload bound before it was started
+enum BoundSocket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
 /// Simulated payload
 pub struct SimulatedPayload {
     config: PayloadConfig,
     running: Arc<AtomicBool>,
     thread_handle: Option<JoinHandle<()>>,
     stats: Arc<std::sync::Mutex<PayloadStats>>,
+    bound: Option<BoundSocket>,
 }
 
 impl SimulatedPayload {
@@ -54,9 +98,41 @@ impl SimulatedPayload {
             running: Arc::new(AtomicBool::new(false)),
             thread_handle: None,
             stats: Arc::new(std::sync::Mutex::new(PayloadStats::default())),
+            bound: None,
         }
     }
 
+    /// Bind the payload's address now rather than when it starts, returning
+    /// the port bound
+    ///
+    /// A network payload configured with port 0 gets a free port, recorded
+    /// in its configuration, that stays bound until the payload uses it.
+    /// Device payloads bind nothing.
+    pub fn bind(&mut self) -> Result<u16, String> {
+        let addr = format!("{}:{}", self.config.address, self.config.port);
+        let (bound, local_addr) = match self.config.protocol {
+            PayloadProtocol::Tcp => {
+                let listener = TcpListener::bind(&addr).map_err(|e| format!("Failed to bind TCP listener: {}", e))?;
+                let local_addr = listener.local_addr();
+                (BoundSocket::Tcp(listener), local_addr)
+            }
+            PayloadProtocol::Udp => {
+                let socket = UdpSocket::bind(&addr).map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+                let local_addr = socket.local_addr();
+                (BoundSocket::Udp(socket), local_addr)
+            }
+            PayloadProtocol::Device => return Ok(self.config.port),
+        };
+        self.config.port = local_addr.map_err(|e| format!("Failed to get bound address: {}", e))?.port();
+        self.bound = Some(bound);
+        Ok(self.config.port)
+    }
+
+    /// Get the payload's configuration
+    pub fn config(&self) -> &PayloadConfig {
+        &self.config
+    }
+
     /// Start the payload simulation
     pub fn start(&mut self) -> Result<(), String> {
         if self.running.load(Ordering::SeqCst) {
@@ -67,11 +143,16 @@ impl SimulatedPayload {
         let running = self.running.clone();
         let config = self.config.clone();
         let stats = self.stats.clone();
+        let (listener, socket) = match self.bound.take() {
+            Some(BoundSocket::Tcp(listener)) => (Some(listener), None),
+            Some(BoundSocket::Udp(socket)) => (None, Some(socket)),
+            None => (None, None),
+        };
 
         let handle = thread::spawn(move || {
             match config.protocol {
-                PayloadProtocol::Tcp => run_tcp_payload(config, running, stats),
-                PayloadProtocol::Udp => run_udp_payload(config, running, stats),
+                PayloadProtocol::Tcp => run_tcp_payload(config, listener, running, stats),
+                PayloadProtocol::Udp => run_udp_payload(config, socket, running, stats),
                 PayloadProtocol::Device => run_device_payload(config, running, stats),
             }
         });
@@ -131,12 +212,17 @@ impl Drop for SimulatedPayload {
     }
 }
 
-/// Run TCP payload simulation
-fn run_tcp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<std::sync::Mutex<PayloadStats>>) {
+/// Run TCP payload simulation, on listener if it was bound beforehand
+fn run_tcp_payload(
+    config: PayloadConfig,
+    listener: Option<TcpListener>,
+    running: Arc<AtomicBool>,
+    stats: Arc<std::sync::Mutex<PayloadStats>>,
+) {
     let addr = format!("{}:{}", config.address, config.port);
 
     // Try to bind as server
-    let listener = match TcpListener::bind(&addr) {
+    let listener = match listener.map_or_else(|| TcpListener::bind(&addr), Ok) {
         Ok(l) => l,
         Err(e) => {
             eprintln!("Failed to bind TCP listener: {}", e);
@@ -148,6 +234,7 @@ fn run_tcp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<s
 
     let mut connection: Option<TcpStream> = None;
     let mut rng = rand::thread_rng();
+    let mut last_send = Instant::now();
 
     while running.load(Ordering::SeqCst) {
         // Accept new connections
@@ -168,12 +255,17 @@ fn run_tcp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<s
             if packet_interval > 0 {
                 let data: Vec<u8> = (0..packet_size).map(|_| rng.gen()).collect();
                 if let Ok(n) = stream.write(&data) {
+                    last_send = Instant::now();
                     let mut guard = stats.lock().unwrap();
                     guard.packets_sent += 1;
                     guard.bytes_sent += n as u64;
                 }
             }
 
+            if keepalive_due(&config, last_send) && stream.write_all(KEEPALIVE_PACKET).is_ok() {
+                last_send = Instant::now();
+            }
+
             // Try to receive data
             let mut buf = vec![0u8; 4096];
             if let Ok(n) = stream.read(&mut buf) {
@@ -194,11 +286,16 @@ fn run_tcp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<s
     }
 }
 
-/// Run UDP payload simulation
-fn run_udp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<std::sync::Mutex<PayloadStats>>) {
+/// Run UDP payload simulation, on socket if it was bound beforehand
+fn run_udp_payload(
+    config: PayloadConfig,
+    socket: Option<UdpSocket>,
+    running: Arc<AtomicBool>,
+    stats: Arc<std::sync::Mutex<PayloadStats>>,
+) {
     let addr = format!("{}:{}", config.address, config.port);
 
-    let socket = match UdpSocket::bind(&addr) {
+    let socket = match socket.map_or_else(|| UdpSocket::bind(&addr), Ok) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Failed to bind UDP socket: {}", e);
@@ -209,7 +306,8 @@ fn run_udp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<s
     socket.set_nonblocking(true).ok();
 
     let mut rng = rand::thread_rng();
-    let mut last_peer: Option<std::net::SocketAddr> = None;
+    let mut last_peer: Option<SocketAddr> = config.peer;
+    let mut last_send = Instant::now();
 
     while running.load(Ordering::SeqCst) {
         // Try to receive data
@@ -232,11 +330,16 @@ fn run_udp_payload(config: PayloadConfig, running: Arc<AtomicBool>, stats: Arc<s
                 let data: Vec<u8> = (0..packet_size).map(|_| rng.gen()).collect();
 eprintln!("run_udp_payload::sendto {:?}", peer);
                 if let Ok(n) = socket.send_to(&data, peer) {
+                    last_send = Instant::now();
                     let mut guard = stats.lock().unwrap();
                     guard.packets_sent += 1;
                     guard.bytes_sent += n as u64;
                 }
             }
+
+            if keepalive_due(&config, last_send) && socket.send_to(KEEPALIVE_PACKET, peer).is_ok() {
+                last_send = Instant::now();
+            }
         }
 
         let packet_interval = config.packet_interval_ms.load(Ordering::SeqCst);
@@ -281,7 +384,7 @@ mod tests {
     #[test]
     fn test_payload_config() {
         let config = PayloadConfig {
-            id: 0,
+            _id: 0,
             protocol: PayloadProtocol::Udp,
             address: "127.0.0.1".to_string(),
             port: 5000,
@@ -289,7 +392,68 @@ mod tests {
             segment_size: Arc::new(AtomicU32::new(12)),
             packet_interval_ms: Arc::new(AtomicU32::new(1000)),
             segment_interval_ms: Arc::new(AtomicU32::new(1000)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
         };
-        assert_eq!(config.id, 0);
+        assert_eq!(config._id, 0);
+    }
+
+    #[test]
+    fn test_clamp_sizes() {
+        let config = PayloadConfig {
+            _id: 1,
+            protocol: PayloadProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: 5001,
+            packet_size: Arc::new(AtomicU32::new(MAX_PACKET_SIZE + 1)),
+            segment_size: Arc::new(AtomicU32::new(12)),
+            packet_interval_ms: Arc::new(AtomicU32::new(1000)),
+            segment_interval_ms: Arc::new(AtomicU32::new(1000)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(0)),
+            peer: None,
+        };
+
+        let warnings = config.clamp_sizes(MAX_PACKET_SIZE);
+        assert_eq!(config.packet_size.load(Ordering::SeqCst), MAX_PACKET_SIZE);
+        assert_eq!(config.segment_size.load(Ordering::SeqCst), 12);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("packet size 4097"), "{}", warnings[0]);
+
+        // Sizes within the maximum are left alone
+        assert!(config.clamp_sizes(MAX_PACKET_SIZE).is_empty());
+    }
+
+    #[test]
+    fn test_udp_keepalive() {
+        let ground = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ground.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        // No data packets, so only keepalives are sent
+        let config = PayloadConfig {
+            _id: 0,
+            protocol: PayloadProtocol::Udp,
+            address: "127.0.0.1".to_string(),
+            port: 0,
+            packet_size: Arc::new(AtomicU32::new(12)),
+            segment_size: Arc::new(AtomicU32::new(12)),
+            packet_interval_ms: Arc::new(AtomicU32::new(0)),
+            segment_interval_ms: Arc::new(AtomicU32::new(0)),
+            keepalive_interval_ms: Arc::new(AtomicU32::new(50)),
+            peer: Some(ground.local_addr().unwrap()),
+        };
+        let mut payload = SimulatedPayload::new(config);
+        payload.start().unwrap();
+
+        let mut buf = [0u8; 64];
+        let start = Instant::now();
+        for _ in 0..3 {
+            let n = ground.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], KEEPALIVE_PACKET);
+        }
+        let elapsed = start.elapsed();
+        payload.stop();
+
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_millis(500));
     }
 }